@@ -0,0 +1,402 @@
+// Copyright 2016 AgilData
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http:// www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The primitives every encryption scheme is built from, behind a trait so the library providing
+//! them is chosen with a cargo feature. Backends must produce identical bytes for identical
+//! inputs, so values written under one backend decrypt under any other.
+
+/// AES-256-GCM, AES, HMAC-SHA256, HKDF-SHA256 and PBKDF2-HMAC-SHA256
+pub trait CryptoBackend {
+    fn name(&self) -> &'static str;
+
+    /// AES-256-GCM encryption, the ciphertext followed by the 16 byte tag
+    fn aead_seal(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Vec<u8>;
+
+    /// Opens a ciphertext followed by its tag, None when it does not authenticate
+    fn aead_open(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Option<Vec<u8>>;
+
+    /// AES-128 or AES-256 by the length of the key, the deterministic primitive of SIV, OPE and FF1
+    fn block_cipher(&self, key: &[u8]) -> Box<BlockCipher>;
+
+    /// HMAC-SHA256 of the concatenated parts
+    fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; 32];
+
+    /// 32 bytes of HKDF-SHA256 output
+    fn hkdf_sha256(&self, salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32];
+
+    /// 32 bytes of PBKDF2-HMAC-SHA256 output
+    fn pbkdf2_sha256(&self, password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32];
+}
+
+pub trait BlockCipher {
+    fn encrypt_block(&self, block: &[u8; 16]) -> [u8; 16];
+}
+
+/// The backend in use, the first enabled of ring, aes-gcm and rust-crypto
+#[cfg(feature = "backend-ring")]
+pub fn backend() -> &'static CryptoBackend {
+    &ring::RING
+}
+
+#[cfg(all(feature = "backend-aes-gcm", not(feature = "backend-ring")))]
+pub fn backend() -> &'static CryptoBackend {
+    &aes_gcm::AES_GCM
+}
+
+#[cfg(all(feature = "backend-rust-crypto", not(any(feature = "backend-ring", feature = "backend-aes-gcm"))))]
+pub fn backend() -> &'static CryptoBackend {
+    &rust_crypto::RUST_CRYPTO
+}
+
+/// Every backend built in
+#[allow(dead_code)]
+pub fn backends() -> Vec<&'static CryptoBackend> {
+    let mut all: Vec<&'static CryptoBackend> = Vec::new();
+    #[cfg(feature = "backend-ring")]
+    all.push(&ring::RING);
+    #[cfg(feature = "backend-aes-gcm")]
+    all.push(&aes_gcm::AES_GCM);
+    #[cfg(feature = "backend-rust-crypto")]
+    all.push(&rust_crypto::RUST_CRYPTO);
+    all
+}
+
+/// Compares without returning early, so the time taken does not reveal where a MAC differs
+pub fn fixed_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0u8, |d, (x, y)| d | (x ^ y)) == 0
+}
+
+#[cfg(feature = "backend-rust-crypto")]
+mod rust_crypto {
+    extern crate crypto;
+    use self::crypto::aes::KeySize;
+    use self::crypto::aes_gcm::AesGcm;
+    use self::crypto::aead::{AeadEncryptor, AeadDecryptor};
+    use self::crypto::aessafe::{AesSafe128Encryptor, AesSafe256Encryptor};
+    use self::crypto::hkdf::{hkdf_extract, hkdf_expand};
+    use self::crypto::hmac::Hmac;
+    use self::crypto::mac::Mac;
+    use self::crypto::pbkdf2::pbkdf2;
+    use self::crypto::sha2::Sha256;
+    use self::crypto::symmetriccipher::BlockEncryptor;
+    use super::{CryptoBackend, BlockCipher};
+
+    pub struct RustCrypto;
+
+    pub static RUST_CRYPTO: RustCrypto = RustCrypto;
+
+    impl CryptoBackend for RustCrypto {
+        fn name(&self) -> &'static str {
+            "rust-crypto"
+        }
+
+        fn aead_seal(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Vec<u8> {
+            let mut cipher = AesGcm::new(KeySize::KeySize256, key, nonce, aad);
+            let mut out = vec![0u8; buf.len() + 16];
+            {
+                let (ciphertext, tag) = out.split_at_mut(buf.len());
+                cipher.encrypt(buf, ciphertext, tag);
+            }
+            out
+        }
+
+        fn aead_open(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Option<Vec<u8>> {
+            if buf.len() < 16 {
+                return None
+            }
+            let len = buf.len() - 16;
+            let mut decipher = AesGcm::new(KeySize::KeySize256, key, nonce, aad);
+            let mut out = vec![0u8; len];
+            if decipher.decrypt(&buf[..len], &mut out, &buf[len..]) {
+                Some(out)
+            } else {
+                None
+            }
+        }
+
+        fn block_cipher(&self, key: &[u8]) -> Box<BlockCipher> {
+            if key.len() == 16 {
+                Box::new(Aes128(AesSafe128Encryptor::new(key)))
+            } else {
+                Box::new(Aes256(AesSafe256Encryptor::new(key)))
+            }
+        }
+
+        fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+            let mut mac = Hmac::new(Sha256::new(), key);
+            for p in parts.iter() {
+                mac.input(p);
+            }
+            let mut out = [0u8; 32];
+            out.copy_from_slice(mac.result().code());
+            out
+        }
+
+        fn hkdf_sha256(&self, salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+            let mut prk = [0u8; 32];
+            hkdf_extract(Sha256::new(), salt, ikm, &mut prk);
+            let mut out = [0u8; 32];
+            hkdf_expand(Sha256::new(), &prk, info, &mut out);
+            out
+        }
+
+        fn pbkdf2_sha256(&self, password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+            let mut mac = Hmac::new(Sha256::new(), password);
+            let mut out = [0u8; 32];
+            pbkdf2(&mut mac, salt, iterations, &mut out);
+            out
+        }
+    }
+
+    struct Aes128(AesSafe128Encryptor);
+
+    struct Aes256(AesSafe256Encryptor);
+
+    impl BlockCipher for Aes128 {
+        fn encrypt_block(&self, block: &[u8; 16]) -> [u8; 16] {
+            let mut out = [0u8; 16];
+            self.0.encrypt_block(block, &mut out);
+            out
+        }
+    }
+
+    impl BlockCipher for Aes256 {
+        fn encrypt_block(&self, block: &[u8; 16]) -> [u8; 16] {
+            let mut out = [0u8; 16];
+            self.0.encrypt_block(block, &mut out);
+            out
+        }
+    }
+}
+
+// the AES block cipher of the aes crate, as ring has no raw block cipher
+#[cfg(any(feature = "backend-aes-gcm", feature = "backend-ring"))]
+mod aes_block {
+    extern crate aes;
+    use self::aes::{Aes128, Aes256};
+    use self::aes::cipher::{BlockEncrypt, KeyInit};
+    use self::aes::cipher::generic_array::GenericArray;
+    use super::BlockCipher;
+
+    pub fn block_cipher(key: &[u8]) -> Box<BlockCipher> {
+        if key.len() == 16 {
+            Box::new(Aes128::new_from_slice(key).unwrap())
+        } else {
+            Box::new(Aes256::new_from_slice(key).unwrap())
+        }
+    }
+
+    impl BlockCipher for Aes128 {
+        fn encrypt_block(&self, block: &[u8; 16]) -> [u8; 16] {
+            let mut b = GenericArray::clone_from_slice(block);
+            BlockEncrypt::encrypt_block(self, &mut b);
+            let mut out = [0u8; 16];
+            out.copy_from_slice(&b);
+            out
+        }
+    }
+
+    impl BlockCipher for Aes256 {
+        fn encrypt_block(&self, block: &[u8; 16]) -> [u8; 16] {
+            let mut b = GenericArray::clone_from_slice(block);
+            BlockEncrypt::encrypt_block(self, &mut b);
+            let mut out = [0u8; 16];
+            out.copy_from_slice(&b);
+            out
+        }
+    }
+}
+
+// the RustCrypto crates
+#[cfg(feature = "backend-aes-gcm")]
+mod aes_gcm {
+    extern crate aes_gcm;
+    extern crate hmac;
+    extern crate sha2;
+    extern crate hkdf;
+    extern crate pbkdf2;
+    use self::aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+    use self::aes_gcm::aead::{Aead, Payload};
+    use self::hmac::{Hmac, Mac};
+    use self::sha2::Sha256;
+    use self::hkdf::Hkdf;
+    use super::{CryptoBackend, BlockCipher, aes_block};
+
+    pub struct AesGcm;
+
+    pub static AES_GCM: AesGcm = AesGcm;
+
+    impl CryptoBackend for AesGcm {
+        fn name(&self) -> &'static str {
+            "aes-gcm"
+        }
+
+        fn aead_seal(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Vec<u8> {
+            let cipher = Aes256Gcm::new_from_slice(key).unwrap();
+            cipher.encrypt(Nonce::from_slice(nonce), Payload{msg: buf, aad: aad}).unwrap()
+        }
+
+        fn aead_open(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Option<Vec<u8>> {
+            let cipher = Aes256Gcm::new_from_slice(key).unwrap();
+            cipher.decrypt(Nonce::from_slice(nonce), Payload{msg: buf, aad: aad}).ok()
+        }
+
+        fn block_cipher(&self, key: &[u8]) -> Box<BlockCipher> {
+            aes_block::block_cipher(key)
+        }
+
+        fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+            for p in parts.iter() {
+                mac.update(p);
+            }
+            let mut out = [0u8; 32];
+            out.copy_from_slice(&mac.finalize().into_bytes());
+            out
+        }
+
+        fn hkdf_sha256(&self, salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+            let mut out = [0u8; 32];
+            Hkdf::<Sha256>::new(Some(salt), ikm).expand(info, &mut out).unwrap();
+            out
+        }
+
+        fn pbkdf2_sha256(&self, password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+            let mut out = [0u8; 32];
+            pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut out);
+            out
+        }
+    }
+}
+
+#[cfg(feature = "backend-ring")]
+mod ring {
+    extern crate ring;
+    use self::ring::aead::{LessSafeKey, UnboundKey, Nonce, Aad, AES_256_GCM};
+    use self::ring::{hkdf, hmac, pbkdf2};
+    use std::num::NonZeroU32;
+    use super::{CryptoBackend, BlockCipher, aes_block};
+
+    pub struct Ring;
+
+    pub static RING: Ring = Ring;
+
+    impl CryptoBackend for Ring {
+        fn name(&self) -> &'static str {
+            "ring"
+        }
+
+        fn aead_seal(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Vec<u8> {
+            let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).unwrap());
+            let mut out = buf.to_vec();
+            key.seal_in_place_append_tag(Nonce::assume_unique_for_key(*nonce), Aad::from(aad), &mut out).unwrap();
+            out
+        }
+
+        fn aead_open(&self, key: &[u8], nonce: &[u8; 12], aad: &[u8], buf: &[u8]) -> Option<Vec<u8>> {
+            let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).unwrap());
+            let mut out = buf.to_vec();
+            match key.open_in_place(Nonce::assume_unique_for_key(*nonce), Aad::from(aad), &mut out) {
+                Ok(plaintext) => Some(plaintext.to_vec()),
+                Err(_) => None
+            }
+        }
+
+        fn block_cipher(&self, key: &[u8]) -> Box<BlockCipher> {
+            aes_block::block_cipher(key)
+        }
+
+        fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+            let mut ctx = hmac::Context::with_key(&hmac::Key::new(hmac::HMAC_SHA256, key));
+            for p in parts.iter() {
+                ctx.update(p);
+            }
+            let mut out = [0u8; 32];
+            out.copy_from_slice(ctx.sign().as_ref());
+            out
+        }
+
+        fn hkdf_sha256(&self, salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+            let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(ikm);
+            let mut out = [0u8; 32];
+            prk.expand(&[info], hkdf::HKDF_SHA256).unwrap().fill(&mut out).unwrap();
+            out
+        }
+
+        fn pbkdf2_sha256(&self, password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+            let mut out = [0u8; 32];
+            pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, NonZeroU32::new(iterations).unwrap(), salt, password, &mut out);
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use encrypt::hex_decode;
+
+    fn hex(s: &str) -> Vec<u8> {
+        hex_decode(s).unwrap()
+    }
+
+    // every backend built in must give the published answers
+    #[test]
+    fn test_known_answers() {
+        assert!(!backends().is_empty());
+        for b in backends() {
+            // GCM specification test case 16
+            let key = hex("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308");
+            let mut nonce = [0u8; 12];
+            nonce.copy_from_slice(&hex("cafebabefacedbaddecaf888"));
+            let aad = hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+            let plaintext = hex("d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39");
+            let sealed = hex("522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662\
+                              76fc6ece0f4e1768cddf8853bb2d551b");
+            assert_eq!(sealed, b.aead_seal(&key, &nonce, &aad, &plaintext), "{}", b.name());
+            assert_eq!(Some(plaintext), b.aead_open(&key, &nonce, &aad, &sealed), "{}", b.name());
+            assert_eq!(None, b.aead_open(&key, &nonce, b"other", &sealed), "{}", b.name());
+            assert_eq!(None, b.aead_open(&key, &nonce, &aad, &sealed[..15]), "{}", b.name());
+
+            // FIPS-197 appendix C
+            let mut block = [0u8; 16];
+            block.copy_from_slice(&hex("00112233445566778899aabbccddeeff"));
+            let cipher = b.block_cipher(&hex("000102030405060708090a0b0c0d0e0f"));
+            assert_eq!(hex("69c4e0d86a7b0430d8cdb78070b4c55a"), cipher.encrypt_block(&block).to_vec(), "{}", b.name());
+            let cipher = b.block_cipher(&hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"));
+            assert_eq!(hex("8ea2b7ca516745bfeafc49904b496089"), cipher.encrypt_block(&block).to_vec(), "{}", b.name());
+
+            // RFC 4231 test case 2, split across parts
+            assert_eq!(hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+                       b.hmac_sha256(b"Jefe", &[b"what do ya ", b"want for nothing?"]).to_vec(), "{}", b.name());
+
+            // RFC 5869 test case 1, first 32 bytes
+            assert_eq!(hex("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"),
+                       b.hkdf_sha256(&hex("000102030405060708090a0b0c"), &[0x0b; 22], &hex("f0f1f2f3f4f5f6f7f8f9")).to_vec(), "{}", b.name());
+
+            assert_eq!(hex("120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"),
+                       b.pbkdf2_sha256(b"password", b"salt", 1).to_vec(), "{}", b.name());
+            assert_eq!(hex("c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"),
+                       b.pbkdf2_sha256(b"password", b"salt", 4096).to_vec(), "{}", b.name());
+        }
+    }
+
+    #[test]
+    fn test_fixed_time_eq() {
+        assert!(fixed_time_eq(b"abc", b"abc"));
+        assert!(!fixed_time_eq(b"abc", b"abd"));
+        assert!(!fixed_time_eq(b"abc", b"ab"));
+    }
+}
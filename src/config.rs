@@ -13,20 +13,24 @@
 // limitations under the License.
 
 extern crate xml;
+extern crate toml;
 use std::fs::{File, read_dir};
 use std::io::{Read, Error};
-use std::collections::HashMap;
-use std::process;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::path::Path;
 use self::xml::Xml;
 
 use encrypt::*;
 
-use query::{Tokenizer, ASTNode, MySQLColumnQualifier};
+use query::{Tokenizer, ASTNode, MySQLColumnQualifier, LiteralToken};
 use query::MySQLDataType::*;
 use query::dialects::ansisql::*;
 use query::dialects::mysqlsql::*;
 use error::ZeroError;
+use key_provider::{KeyProvider, create_key_provider, load_master_key, unwrap_key};
+use secret::SecretKey;
+use paillier;
 
 // Supported qualifiers
 #[derive(Debug, PartialEq)]
@@ -36,216 +40,763 @@ pub enum NativeTypeQualifier {
     OTHER
 }
 
-// parses a default config and any configs contained within an override directory and reconciles to one Config
-pub fn parse_configs(default_path: &str, dir: &str) -> Config {
+// a problem found in a configuration file, line and column are 0 when not known
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Pos {
+    line: usize,
+    column: usize
+}
+
+// an element of either config format, with where it and its attributes start
+#[derive(Debug)]
+struct Node {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Node>,
+    pos: Pos,
+    attribute_pos: HashMap<String, Pos>
+}
+
+impl Node {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(|a| a as &str)
+    }
+
+    fn required(&self, name: &str) -> Result<String, ConfigError> {
+        match self.get(name) {
+            Some(v) => Ok(v.to_string()),
+            None => Err(self.error(name, format!("Missing attribute {} on {}", name, self.name)))
+        }
+    }
+
+    // at the attribute when it is present, otherwise at the element, the file is filled in by the ConfigBuilder
+    fn error(&self, attribute: &str, message: String) -> ConfigError {
+        let pos = self.attribute_pos.get(attribute).cloned().unwrap_or(self.pos);
+        ConfigError{file: String::new(), line: pos.line, column: pos.column, message: message}
+    }
+}
+
+// parses a default config and the .xml and .toml configs of an override directory, in file name order, and
+// merges them column by column into one Config with later files taking precedence, or reports every problem found
+pub fn parse_configs(default_path: &str, dir: &str) -> Result<Config, Vec<ConfigError>> {
     debug!("parse_configs() default: {}, override dir: {}", default_path, dir);
     let mut b = ConfigBuilder::new();
 
-    let mut xml = _load_xml_file(default_path);
-    _parse_config(&xml, &mut b);
+    _parse_config_file(default_path, false, &mut b);
 
     // If override dir exists, load any available configs
     if Path::new(dir).exists() {
-        let paths = read_dir(dir).unwrap();
-        for p in paths {
-            xml = _load_xml_file(p.unwrap().path().to_str().unwrap());
-            _parse_config(&xml, &mut b);
+        match read_dir(dir) {
+            Ok(paths) => {
+                let mut paths: Vec<_> = paths.filter_map(|p| p.ok()).map(|p| p.path()).collect();
+                paths.sort();
+                for p in paths {
+                    match p.extension().and_then(|e| e.to_str()) {
+                        Some("xml") | Some("toml") => _parse_config_file(&p.to_string_lossy(), true, &mut b),
+                        _ => warn!("Skipping {}, not an .xml or .toml config", p.display())
+                    }
+                }
+            },
+            Err(e) => b.error(ConfigError{file: dir.to_string(), line: 0, column: 0,
+                message: format!("Unable to read override directory: {}", e)})
         }
     }
 
     b.build()
 }
 
-fn _load_xml_file(path: &str) -> String {
-    let mut rdr = match File::open(path) {
-        Ok(file) => file,
-        Err(err) => {
-            println!("Unable to open configuration file '{}': {}", path, err);
-            process::exit(1);
-        }
-    };
+// parses a single xml or toml config
+pub fn parse_config(path: &str) -> Result<Config, Vec<ConfigError>> {
+    debug!("parse_config() path: {}", path);
+    let mut b = ConfigBuilder::new();
 
-    let mut string = String::new();
-    if let Err(err) = rdr.read_to_string(&mut string) {
-        error!("Reading failed: {}", err);
-        process::exit(1);
-    };
+    _parse_config_file(path, false, &mut b);
+
+    b.build()
+}
+
+// .toml files are read as toml, anything else as xml
+fn _parse_config_file(path: &str, is_override: bool, builder: &mut ConfigBuilder) {
+    builder.set_file(path, is_override);
+    match _load_config_file(path).and_then(|content| _parse_nodes(path, &content)) {
+        Ok(nodes) => parse_client_config(builder, nodes),
+        Err(e) => builder.error(e)
+    }
+}
 
-    string
+fn _parse_nodes(path: &str, content: &str) -> Result<Vec<Node>, ConfigError> {
+    if Path::new(path).extension().map_or(false, |e| e == "toml") {
+        _toml_nodes(content)
+    } else {
+        _xml_nodes(content)
+    }
 }
 
-fn _parse_config(xml: &String, builder: &mut ConfigBuilder) {
+fn _load_config_file(path: &str) -> Result<String, ConfigError> {
+    let mut string = String::new();
+    match File::open(path).and_then(|mut rdr| rdr.read_to_string(&mut string)) {
+        Ok(_) => Ok(string),
+        Err(err) => Err(ConfigError{file: path.to_string(), line: 0, column: 0,
+            message: format!("Unable to read configuration file: {}", err)})
+    }
+}
+
+// the children of the zero-config element
+fn _xml_nodes(xml: &str) -> Result<Vec<Node>, ConfigError> {
     let mut p = xml::Parser::new();
     let mut e = xml::ElementBuilder::new();
+    let mut positions = _xml_positions(xml).into_iter();
+    let mut nodes = Vec::new();
+    let mut found = false;
 
     p.feed_str(xml);
     for event in p.filter_map(|x| e.handle_event(x)) {
-        match event {
-            Ok(e) => {
-                match e {
-                    xml::Element{name: n, children: c, .. } => match &n as &str {
-                        "zero-config" => parse_client_config(builder, c),
-                        _ => panic!("Unrecognized parent XML element {}", n)
+        let root = match event {
+            Ok(root) => _xml_node(root, &mut positions),
+            Err(xml::BuilderError::Parser(err)) => return Err(ConfigError{file: String::new(),
+                line: err.line as usize, column: err.col as usize, message: err.msg.to_string()}),
+            Err(err) => return Err(ConfigError{file: String::new(), line: 0, column: 0, message: err.to_string()})
+        };
+        match &root.name as &str {
+            "zero-config" => nodes.extend(root.children),
+            _ => return Err(root.error("", format!("Unrecognized parent XML element {}", root.name)))
+        }
+        found = true;
+    }
+
+    if !found {
+        return Err(ConfigError{file: String::new(), line: 0, column: 0, message: "No zero-config element found".into()})
+    }
+    Ok(nodes)
+}
+
+// elements are built in the order their start tags were scanned
+fn _xml_node(e: xml::Element, positions: &mut Iterator<Item=(Pos, HashMap<String, Pos>)>) -> Node {
+    let (pos, attribute_pos) = positions.next().unwrap_or_default();
+    let mut children = Vec::new();
+    for c in e.children {
+        if let Xml::ElementNode(c) = c {
+            children.push(_xml_node(c, positions));
+        }
+    }
+    Node {
+        name: e.name,
+        attributes: e.attributes.into_iter().map(|((k, _), v)| (k, v)).collect(),
+        children: children,
+        pos: pos,
+        attribute_pos: attribute_pos
+    }
+}
+
+// where each start tag and its attributes begin, in document order
+fn _xml_positions(xml: &str) -> Vec<(Pos, HashMap<String, Pos>)> {
+    let chars = xml.chars().collect::<Vec<char>>();
+    let len = chars.len();
+    let mut at = Vec::with_capacity(len);
+    let (mut line, mut column) = (1, 0);
+    for c in chars.iter() {
+        column += 1;
+        at.push(Pos{line: line, column: column});
+        if *c == '\n' {
+            line += 1;
+            column = 0;
+        }
+    }
+
+    let starts_with = |i: usize, s: &str| s.chars().enumerate().all(|(j, c)| chars.get(i + j) == Some(&c));
+    let skip_to = |i: usize, end: &str| (i..len).find(|&j| starts_with(j, end)).map_or(len, |j| j + end.len());
+
+    let mut positions = Vec::new();
+    let mut i = 0;
+    while i < len {
+        if chars[i] != '<' {
+            i += 1;
+        } else if starts_with(i, "<!--") {
+            i = skip_to(i, "-->");
+        } else if starts_with(i, "<![CDATA[") {
+            i = skip_to(i, "]]>");
+        } else if starts_with(i, "<?") {
+            i = skip_to(i, "?>");
+        } else if starts_with(i, "<!") || starts_with(i, "</") {
+            i = skip_to(i, ">");
+        } else {
+            let pos = at[i];
+            let mut attributes = HashMap::new();
+            i += 1;
+            while i < len && !chars[i].is_whitespace() && chars[i] != '>' && chars[i] != '/' {
+                i += 1;
+            }
+            loop {
+                while i < len && (chars[i].is_whitespace() || chars[i] == '/') {
+                    i += 1;
+                }
+                if i >= len || chars[i] == '>' {
+                    break;
+                }
+                let start = i;
+                while i < len && chars[i] != '=' && chars[i] != '>' && !chars[i].is_whitespace() {
+                    i += 1;
+                }
+                attributes.insert(chars[start..i].iter().collect::<String>(), at[start]);
+                while i < len && chars[i] != '"' && chars[i] != '\'' && chars[i] != '>' {
+                    i += 1;
+                }
+                if i < len && chars[i] != '>' {
+                    let quote = chars[i];
+                    i += 1;
+                    while i < len && chars[i] != quote {
+                        i += 1;
                     }
+                    i += 1;
                 }
+            }
+            positions.push((pos, attributes));
+        }
+    }
+    positions
+}
+
+// toml configs are read into the same nodes as the equivalent xml:
+// tables are property sections, arrays of tables are elements and their values attributes
+fn _toml_nodes(content: &str) -> Result<Vec<Node>, ConfigError> {
+    let mut p = toml::Parser::new(content);
+    let table = match p.parse() {
+        Some(t) => t,
+        None => {
+            let err = &p.errors[0];
+            let (line, col) = p.to_linecol(err.lo);
+            return Err(ConfigError{file: String::new(), line: line + 1, column: col + 1,
+                message: format!("Invalid TOML: {}", err.desc)})
+        }
+    };
+
+    let mut positions = TomlPositions::new(content);
+    let mut nodes = Vec::new();
+    for (k, v) in table.iter() {
+        match v {
+            &toml::Value::Table(ref props) => {
+                let (pos, key_pos) = positions.next(k);
+                let mut section = Node{name: k.clone(), attributes: HashMap::new(), children: Vec::new(),
+                    pos: pos, attribute_pos: HashMap::new()};
+                for (name, value) in props.iter() {
+                    let at = key_pos.get(name).cloned().unwrap_or(pos);
+                    let mut property = Node{name: "property".into(), attributes: HashMap::new(), children: Vec::new(),
+                        pos: at, attribute_pos: HashMap::new()};
+                    property.attributes.insert("name".into(), name.clone());
+                    property.attributes.insert("value".into(), _toml_scalar(name, value, at)?);
+                    section.children.push(property);
+                }
+                nodes.push(section);
             },
-            Err(e) => error!("{}", e),
+            &toml::Value::Array(ref items) => nodes.extend(_toml_elements(k, k, items, &mut positions)?),
+            _ => {
+                let pos = positions.key(k);
+                return Err(ConfigError{file: String::new(), line: pos.line, column: pos.column,
+                    message: format!("Unexpected TOML key {}, expected a table or an array of tables", k)})
+            }
         }
     }
+    Ok(nodes)
 }
 
-// parses a single xml config
-pub fn parse_config(path: &str) -> Config {
-    debug!("parse_config() path: {}", path);
-    let mut b = ConfigBuilder::new();
+fn _toml_elements(name: &str, path: &str, items: &toml::Array, positions: &mut TomlPositions) -> Result<Vec<Node>, ConfigError> {
+    let mut nodes = Vec::new();
+    for item in items.iter() {
+        let (pos, key_pos) = positions.next(path);
+        let t = match item {
+            &toml::Value::Table(ref t) => t,
+            _ => return Err(ConfigError{file: String::new(), line: pos.line, column: pos.column,
+                message: format!("Expected an array of tables for {}, found {}", name, item.type_str())})
+        };
+        let mut e = Node{name: name.to_string(), attributes: HashMap::new(), children: Vec::new(),
+            pos: pos, attribute_pos: key_pos};
+        for (k, v) in t.iter() {
+            match v {
+                &toml::Value::Array(ref children) => {
+                    let children = _toml_elements(k, &format!("{}.{}", path, k), children, positions)?;
+                    e.children.extend(children);
+                },
+                _ => {
+                    let at = e.attribute_pos.get(k).cloned().unwrap_or(pos);
+                    e.attributes.insert(k.clone(), _toml_scalar(k, v, at)?);
+                }
+            }
+        }
+        nodes.push(e);
+    }
+    Ok(nodes)
+}
 
-    let mut xml = _load_xml_file(path);
-    _parse_config(&xml, &mut b);
+fn _toml_scalar(name: &str, value: &toml::Value, pos: Pos) -> Result<String, ConfigError> {
+    match value {
+        &toml::Value::String(ref s) => Ok(s.clone()),
+        &toml::Value::Integer(i) => Ok(i.to_string()),
+        &toml::Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(ConfigError{file: String::new(), line: pos.line, column: pos.column,
+            message: format!("Unsupported TOML value for {}, found {}", name, value.type_str())})
+    }
+}
 
-    b.build()
+// the parser keeps no positions, so table headers and keys are found by scanning the lines,
+// the nth [[a.b]] header being the nth a.b table
+struct TomlPositions {
+    headers: HashMap<String, Vec<(Pos, HashMap<String, Pos>)>>,
+    top: HashMap<String, Pos>,
+    taken: HashMap<String, usize>
 }
 
-fn parse_client_config(builder: &mut ConfigBuilder, children: Vec<Xml>) {
+impl TomlPositions {
+    fn new(content: &str) -> TomlPositions {
+        let mut headers: HashMap<String, Vec<(Pos, HashMap<String, Pos>)>> = HashMap::new();
+        let mut top = HashMap::new();
+        let mut current: Option<String> = None;
+        for (n, line) in content.lines().enumerate() {
+            let trimmed = line.trim_left();
+            let pos = Pos{line: n + 1, column: line.len() - trimmed.len() + 1};
+            if trimmed.starts_with('[') {
+                let path = trimmed.trim_left_matches('[').split(']').next().unwrap_or("")
+                    .split('.').map(|p| p.trim().trim_matches('"')).collect::<Vec<&str>>().join(".");
+                headers.entry(path.clone()).or_insert_with(Vec::new).push((pos, HashMap::new()));
+                current = Some(path);
+            } else if !trimmed.starts_with('#') && trimmed.contains('=') {
+                let key = trimmed.split('=').next().unwrap().trim().trim_matches('"').to_string();
+                match current {
+                    Some(ref path) => { headers.get_mut(path).unwrap().last_mut().unwrap().1.entry(key).or_insert(pos); },
+                    None => { top.entry(key).or_insert(pos); }
+                }
+            }
+        }
+        TomlPositions{headers: headers, top: top, taken: HashMap::new()}
+    }
 
-    for node in children {
-        match node {
-            Xml::ElementNode(e) => {
-                match &e.name as &str {
-                    "schema" => {
-                        let mut sb = SchemaConfigBuilder::new();
-                        sb.set_name(get_attr_or_fail("name", &e));
-                        parse_schema_config(&mut sb, e.children);
-                        builder.add_schema(sb.build());
-                    },
-                    "connection" => {
-                        for prop in e.children {
-                            match prop {
-                                Xml::ElementNode(n) => match &n.name as &str {
-                                    "property" => {
-                                        let key = get_attr_or_fail("name", &n);
-                                        let val = get_attr_or_fail("value", &n);
-                                        builder.add_conn_prop(key, val)
-                                    },
-                                    _ => panic!("expected property, received {}", n.name)
-                                },
-                                _ => {} // dont care yet
-                            }
-                        }
-                    },
-                    "client" => {
-                        for prop in e.children {
-                            match prop {
-                                Xml::ElementNode(n) => match &n.name as &str {
-                                    "property" => {
-                                        let key = get_attr_or_fail("name", &n);
-                                        let val = get_attr_or_fail("value", &n);
-                                        builder.add_client_prop(key, val)
-                                    },
-                                    _ => panic!("expected property, received {}", n.name)
-                                },
-                                _ => {} // dont care yet
-                            }
-                        }
-                    },
-                    "parsing" => {
-                        for prop in e.children {
-                            match prop {
-                                Xml::ElementNode(n) => match &n.name as &str {
-                                    "property" => {
-                                        let key = get_attr_or_fail("name", &n);
-                                        let val = get_attr_or_fail("value", &n);
-                                        builder.add_parsing_prop(key, val)
-                                    },
-                                    _ => panic!("expected property, received {}", n.name)
-                                },
-                                _ => {} // dont care yet
-                            }
-                        }
-                    },
-                    _ => panic!("Unexpected element tag {}", e.name)
+    fn next(&mut self, path: &str) -> (Pos, HashMap<String, Pos>) {
+        let n = self.taken.entry(path.to_string()).or_insert(0);
+        *n += 1;
+        self.headers.get(path).and_then(|h| h.get(*n - 1)).cloned().unwrap_or_default()
+    }
+
+    fn key(&self, key: &str) -> Pos {
+        self.top.get(key).cloned().unwrap_or_default()
+    }
+}
+
+// converts an xml config to toml, comments are not carried over
+pub fn xml_to_toml(path: &str) -> Result<String, ConfigError> {
+    let nodes = _load_config_file(path).and_then(|xml| _xml_nodes(&xml)).map_err(|mut e| {
+        e.file = path.to_string();
+        e
+    })?;
+
+    let mut out = String::new();
+    for section in nodes.iter().filter(|e| e.name != "schema") {
+        out.push_str(&format!("[{}]\n", _toml_key(&section.name)));
+        for p in section.children.iter().filter(|p| p.name == "property") {
+            out.push_str(&format!("{} = {}\n", _toml_key(p.get("name").unwrap_or("")),
+                toml::Value::String(p.get("value").unwrap_or("").to_string())));
+        }
+        out.push('\n');
+    }
+    for schema in nodes.iter().filter(|e| e.name == "schema") {
+        _write_toml_element(&mut out, &mut vec![], schema);
+    }
+    Ok(out)
+}
+
+fn _write_toml_element(out: &mut String, path: &mut Vec<String>, e: &Node) {
+    path.push(_toml_key(&e.name));
+    out.push_str(&format!("[[{}]]\n", path.join(".")));
+
+    // name first, then the rest in a stable order
+    let mut attrs = e.attributes.iter().collect::<Vec<(&String, &String)>>();
+    attrs.sort_by_key(|&(k, _)| (k != "name", k.clone()));
+    for (k, v) in attrs {
+        out.push_str(&format!("{} = {}\n", _toml_key(k), toml::Value::String(v.clone())));
+    }
+    out.push('\n');
+
+    for c in e.children.iter() {
+        _write_toml_element(out, path, c);
+    }
+    path.pop();
+}
+
+fn _toml_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
+    }
+}
+
+fn parse_client_config(builder: &mut ConfigBuilder, children: Vec<Node>) {
+    for e in children {
+        match &e.name as &str {
+            "schema" => parse_schema_config(builder, e),
+            "connection" | "client" | "parsing" | "key-provider" | "master-key" => {
+                for n in e.children.iter() {
+                    if n.name != "property" {
+                        builder.error(n.error("", format!("expected property, received {}", n.name)));
+                        continue;
+                    }
+                    match (n.required("name"), n.required("value")) {
+                        (Ok(key), Ok(val)) => match &e.name as &str {
+                            "connection" => builder.add_conn_prop(key, val),
+                            "client" => builder.add_client_prop(key, val),
+                            "parsing" => builder.add_parsing_prop(key, val),
+                            "key-provider" => builder.add_key_provider_prop(key, val),
+                            _ => builder.add_master_key_prop(key, val)
+                        },
+                        (Err(err), _) | (_, Err(err)) => builder.error(err)
+                    }
                 }
             },
-            _ => {} // dont care
+            _ => builder.error(e.error("", format!("Unexpected element tag {}", e.name)))
         }
     }
 }
 
-fn parse_schema_config(builder: &mut SchemaConfigBuilder, children: Vec<Xml>) {
-    for node in children {
-        match node {
-            Xml::ElementNode(e) => match &e.name as &str {
-                "table" => {
-                    let mut tb = TableConfigBuilder::new();
-                    tb.set_name(get_attr_or_fail("name", &e));
-                    parse_table_config(&mut tb, e.children);
-                    builder.add_table(tb.build());
+fn parse_schema_config(builder: &mut ConfigBuilder, e: Node) {
+    let name = match e.required("name") {
+        Ok(n) => n,
+        Err(err) => return builder.error(err)
+    };
+    builder.add_schema(&name);
 
-                },
-                _ => panic!("Unexpected element tag {}", e.name)
-            },
-            _ => {} // dont' care yet
+    for t in e.children.iter() {
+        match &t.name as &str {
+            "table" => parse_table_config(builder, &name, t),
+            _ => builder.error(t.error("", format!("Unexpected element tag {}", t.name)))
+        }
+    }
+}
+
+// columns are merged into any earlier definition of the table, which is checked as a whole once every file is read
+fn parse_table_config(builder: &mut ConfigBuilder, schema: &str, e: &Node) {
+    let name = match e.required("name") {
+        Ok(n) => n,
+        Err(err) => return builder.error(err)
+    };
+    let tbl_name: String = name.to_uppercase();  // TODO do we need the schema name as well?
+    let tenant = match determine_tenant_key(e, &name) {
+        Ok(tenant) => tenant,
+        Err(err) => {
+            builder.error(err);
+            builder.invalidate(schema, &name);
+            None
+        }
+    };
+    let tenant = builder.add_table(schema, &name, tenant, e.pos);
+
+    for c in e.children.iter() {
+        if c.name != "column" {
+            builder.error(c.error("", format!("Unexpected element tag {}", c.name)));
+            builder.invalidate(schema, &name);
+            continue;
+        }
+        match parse_column_config(&tbl_name, tenant, c) {
+            Ok(column) => builder.add_column(schema, &name, column, c),
+            Err(err) => {
+                builder.error(err);
+                builder.invalidate(schema, &name);
+            }
         }
     }
 }
 
-fn parse_table_config(builder: &mut TableConfigBuilder, children: Vec<Xml>) {
+fn parse_column_config(tbl_name: &str, tenant: bool, e: &Node) -> Result<ColumnConfig, ConfigError> {
     use std::env;
-    let tbl_name: String = builder.name.clone().unwrap().to_uppercase();  // TODO do we need the schema name as well?
-
-    for node in children {
-        match node {
-            Xml::ElementNode(e) => match &e.name as &str {
-                "column" => {
-                    let name = get_attr_or_fail("name", &e);
-                    let native_type = get_attr_or_fail("type", &e);
-                    let encryption = get_attr_or_fail("encryption", &e);
-                    let key = if encryption.to_uppercase() != "NONE" {
-                                  determine_key(&
-                                      env::var(format!("ZERO_{}_{}", &tbl_name, &name.to_uppercase()))
-                                        .ok()
-                                        .unwrap_or_else(|| get_attr_or_fail("key", &e))
-                                  )
-                              } else {
-                                  [0u8; 32]
-                              };
-                    let dt = match determine_native_type(&native_type) {
-                        Ok(t) => t,
-                        Err(e) => panic!("Failed to determine data type for {}.{} : {}", tbl_name, name, e)
-                    };
-
-                    let iv = match e.get_attribute("iv", None) {
-                        Some(hex) => Some(iv_from_hex(hex)),
-                        None => None
-                    };
-                    let encrypt_type = determine_encryption(&encryption, iv);
-                    if encrypt_type != EncryptionType::NA && !dt.is_supported() {
-                        panic!("Column: {}.{} Native Type {:?} is not supported for encryption {:?}",
-                            tbl_name, name, native_type, encrypt_type
-                        )
-                    }
 
-                    builder.add_column(ColumnConfig{
-                        name: name,
-                        native_type: dt,
-                        encryption: encrypt_type,
-                        key: key,
-                    });
-                },
-                _ => panic!("Unexpected element tag {}", e.name)
+    let name = e.required("name")?;
+    let native_type = e.required("type")?;
+    let encryption = e.required("encryption")?;
+    let key_ref = e.get("key-ref").map(|r| r.to_string());
+    let wrapped_key = e.get("wrapped-key").map(|k| k.to_string());
+    // columns of tables encrypted per tenant use the tenants' keys
+    let key_bytes = if encryption.to_uppercase() != "NONE" && key_ref.is_none() && wrapped_key.is_none() && !tenant {
+                  match env::var(format!("ZERO_{}_{}", tbl_name, &name.to_uppercase())) {
+                      Ok(k) => determine_key(&k).map_err(|m| e.error("name",
+                          format!("Column: {}.{} ZERO_{}_{} {}", tbl_name, name, tbl_name, name.to_uppercase(), m)))?,
+                      Err(_) => determine_key(&e.required("key")?).map_err(|m| e.error("key",
+                          format!("Column: {}.{} key {}", tbl_name, name, m)))?
+                  }
+              } else {
+                  SecretKey::new([0u8; 32])
+              };
+    let dt = match determine_native_type(&native_type) {
+        Ok(t) => t,
+        Err(err) => return Err(e.error("type", format!("Failed to determine data type for {}.{} : {}", tbl_name, name, err)))
+    };
+
+    let iv = match e.get("iv") {
+        Some(hex) => Some(iv_from_hex(hex).map_err(|m| e.error("iv", format!("Column: {}.{} iv {}", tbl_name, name, m)))?),
+        None => None
+    };
+    let fpe = match e.get("fpe-alphabet") {
+        Some(alphabet) => Some(determine_fpe_format(alphabet, e.get("fpe-pattern"), tbl_name, &name)
+            .map_err(|m| e.error("fpe-alphabet", m))?),
+        None => match e.get("fpe-pattern") {
+            Some(_) => return Err(e.error("fpe-pattern", format!("Column: {}.{} fpe-pattern attribute requires fpe-alphabet", tbl_name, name))),
+            None => None
+        }
+    };
+    let encrypt_type = determine_encryption(&encryption, iv, fpe)
+        .map_err(|m| e.error("encryption", format!("Column: {}.{} {}", tbl_name, name, m)))?;
+    if encrypt_type != EncryptionType::NA && !dt.is_supported() {
+        return Err(e.error("type", format!("Column: {}.{} Native Type {:?} is not supported for encryption {:?}",
+            tbl_name, name, native_type, encrypt_type
+        )))
+    }
+    if !encrypt_type.supports(&dt) {
+        return Err(e.error("encryption", format!("Column: {}.{} Native Type {:?} is not supported by encryption {:?}",
+            tbl_name, name, native_type, encrypt_type
+        )))
+    }
+    // decimals are ordered as doubles, which only hold 15 significant digits exactly
+    if encrypt_type == EncryptionType::Ope && dt == NativeType::D128 {
+        let precision = determine_decimal_precision(&native_type)
+            .map_err(|err| e.error("type", format!("Failed to determine data type for {}.{} : {}", tbl_name, name, err)))?;
+        if precision > 15 {
+            return Err(e.error("encryption", format!("Column: {}.{} encryption OPE supports DECIMAL of at most 15 digits, found {}",
+                tbl_name, name, precision
+            )))
+        }
+    }
+
+    let key_derivation = match e.get("key-derivation") {
+        Some(d) => determine_key_derivation(d).map_err(|m| e.error("key-derivation", format!("Column: {}.{} {}", tbl_name, name, m)))?,
+        None => KeyDerivation::Column
+    };
+    // deterministic and homomorphic schemes need one key across rows
+    if key_derivation == KeyDerivation::Row && encrypt_type != EncryptionType::AesGcm {
+        return Err(e.error("key-derivation", format!("Column: {}.{} key-derivation row requires aes_gcm encryption", tbl_name, name)))
+    }
+
+    let aad = match e.get("aad") {
+        Some(policy) => determine_aad(policy).map_err(|m| e.error("aad", format!("Column: {}.{} {}", tbl_name, name, m)))?,
+        // keys derived per row need the row's primary key, as row bound values do
+        None if key_derivation == KeyDerivation::Row => AadPolicy::Row,
+        None => AadPolicy::NA
+    };
+    if key_derivation == KeyDerivation::Row && aad != AadPolicy::Row {
+        return Err(e.error("aad", format!("Column: {}.{} key-derivation row binds values to their row, aad must be row", tbl_name, name)))
+    }
+    if aad != AadPolicy::NA && encrypt_type == EncryptionType::NA {
+        return Err(e.error("aad", format!("Column: {}.{} aad attribute requires an encrypted column", tbl_name, name)))
+    }
+    // binding ciphertexts to aad would stop them adding up
+    if aad != AadPolicy::NA && encrypt_type == EncryptionType::Paillier {
+        return Err(e.error("aad", format!("Column: {}.{} aad attribute is not supported by encryption {:?}", tbl_name, name, encrypt_type)))
+    }
+
+    let pk_ordinal = match e.get("pkOrdinal") {
+        Some(o) => match o.parse::<u32>() {
+            Ok(o) => Some(o),
+            Err(_) => return Err(e.error("pkOrdinal", format!("Column: {}.{} invalid pkOrdinal {}", tbl_name, name, o)))
+        },
+        None => None
+    };
+
+    if key_ref.is_some() && encrypt_type == EncryptionType::NA {
+        return Err(e.error("key-ref", format!("Column: {}.{} key-ref attribute requires an encrypted column", tbl_name, name)))
+    }
+    if wrapped_key.is_some() && encrypt_type == EncryptionType::NA {
+        return Err(e.error("wrapped-key", format!("Column: {}.{} wrapped-key attribute requires an encrypted column", tbl_name, name)))
+    }
+    if key_ref.is_some() && wrapped_key.is_some() {
+        return Err(e.error("wrapped-key", format!("Column: {}.{} cannot have both key-ref and wrapped-key", tbl_name, name)))
+    }
+
+    // older key versions stay readable, new values are written with key-version
+    let key_version = match e.get("key-version") {
+        Some(v) => determine_key_version(v, tbl_name, &name).map_err(|m| e.error("key-version", m))?,
+        None => 0
+    };
+    let mut key = KeyRing::new(key_version, key_bytes);
+    let mut previous_keys: Vec<KeyVersionConfig> = Vec::new();
+    for p in e.children.iter() {
+        match &p.name as &str {
+            "previous-key" => {
+                if encrypt_type == EncryptionType::NA {
+                    return Err(p.error("", format!("Column: {}.{} previous-key requires an encrypted column", tbl_name, name)))
+                }
+                // format preserving ciphertexts have no header naming their key version
+                if let EncryptionType::Fpe(_) = encrypt_type {
+                    return Err(p.error("", format!("Column: {}.{} previous-key is not supported by encryption FPE", tbl_name, name)))
+                }
+                let version = determine_key_version(&p.required("version")?, tbl_name, &name).map_err(|m| p.error("version", m))?;
+                if version >= key_version || previous_keys.iter().any(|k| k.version == version) {
+                    return Err(p.error("version", format!("Column: {}.{} previous-key version {} must be unique and older than key-version {}",
+                        tbl_name, name, version, key_version)))
+                }
+                let p_key_ref = p.get("key-ref").map(|r| r.to_string());
+                let p_wrapped_key = p.get("wrapped-key").map(|k| k.to_string());
+                if p_key_ref.is_none() && p_wrapped_key.is_none() {
+                    key.add(version, determine_key(&p.required("key")?).map_err(|m| p.error("key",
+                        format!("Column: {}.{} previous-key version {} key {}", tbl_name, name, version, m)))?);
+                }
+                previous_keys.push(KeyVersionConfig{version: version, key_ref: p_key_ref, wrapped_key: p_wrapped_key});
             },
-            _ => {} // dont' care yet
+            _ => return Err(p.error("", format!("Unexpected element tag {}", p.name)))
         }
     }
-}
 
-fn get_attr_or_fail(name: &str, element: &xml::Element) -> String {
-    match element.get_attribute(name, None) {
-        Some(v) => v.to_string(),
-        None => panic!("Missing attribute {}", name)
+    // columns of values written before headers were introduced keep writing them without, until rotated
+    match e.get("header").map(|h| h.to_lowercase()) {
+        Some(ref h) if h == "false" => {
+            if !encrypt_type.is_deterministic() {
+                return Err(e.error("header", format!("Column: {}.{} header=\"false\" requires encryption AES or AES_SIV", tbl_name, name)))
+            }
+            key.set_header(false);
+        },
+        Some(ref h) if h == "true" => {},
+        Some(h) => return Err(e.error("header", format!("Column: {}.{} header must be true or false, found {}", tbl_name, name, h))),
+        None => {}
     }
+
+    let blind_index = match e.get("blind-index") {
+        Some(column) => {
+            if encrypt_type == EncryptionType::NA {
+                return Err(e.error("blind-index", format!("Column: {}.{} blind-index attribute requires an encrypted column", tbl_name, name)))
+            }
+            let length = match e.get("blind-index-length") {
+                Some(l) => match l.parse::<u32>() {
+                    Ok(l) if l >= 4 && l <= 32 => l,
+                    _ => return Err(e.error("blind-index-length", format!("Column: {}.{} blind-index-length must be between 4 and 32 bytes, found {}", tbl_name, name, l)))
+                },
+                None => DEFAULT_BLIND_INDEX_LENGTH
+            };
+            Some(BlindIndex{column: column.to_string(), length: length})
+        },
+        None => None
+    };
+
+    let like_index = match e.get("like-index") {
+        Some(column) => {
+            match (&encrypt_type, &dt) {
+                (&EncryptionType::NA, _) => return Err(e.error("like-index", format!("Column: {}.{} like-index attribute requires an encrypted column", tbl_name, name))),
+                (_, &NativeType::Varchar(_)) | (_, &NativeType::Char(_)) | (_, &NativeType::LONGTEXT(_)) => {},
+                _ => return Err(e.error("like-index", format!("Column: {}.{} like-index attribute requires a character column, found {:?}", tbl_name, name, dt)))
+            }
+            let (mode, default, range) = match e.get("like-index-mode").map(|m| m.to_uppercase()) {
+                Some(ref m) if m == "NGRAM" => (LikeMode::NGram, DEFAULT_NGRAM_LENGTH, (2, 8)),
+                Some(ref m) if m == "PREFIX" => (LikeMode::Prefix, DEFAULT_PREFIX_LENGTH, (1, 32)),
+                None => (LikeMode::NGram, DEFAULT_NGRAM_LENGTH, (2, 8)),
+                Some(m) => return Err(e.error("like-index-mode", format!("Column: {}.{} like-index-mode must be ngram or prefix, found {}", tbl_name, name, m)))
+            };
+            let length = match e.get("like-index-length") {
+                Some(l) => match l.parse::<u32>() {
+                    Ok(l) if l >= range.0 && l <= range.1 => l,
+                    _ => return Err(e.error("like-index-length", format!("Column: {}.{} like-index-length must be between {} and {} for {:?}, found {}", tbl_name, name, range.0, range.1, mode, l)))
+                },
+                None => default
+            };
+            Some(LikeIndex{column: column.to_string(), mode: mode, length: length})
+        },
+        None => None
+    };
+
+    let padding = match e.get("padding").map(|p| p.to_uppercase()) {
+        Some(p) => {
+            // only randomized ciphertexts, equal values must still encrypt alike under the deterministic schemes
+            if encrypt_type != EncryptionType::AesGcm {
+                return Err(e.error("padding", format!("Column: {}.{} padding attribute requires encryption AES_GCM", tbl_name, name)))
+            }
+            match (&p as &str, &dt) {
+                ("BUCKET", &NativeType::Varchar(_)) | ("BUCKET", &NativeType::Char(_)) | ("BUCKET", &NativeType::LONGTEXT(_)) => {
+                    match e.get("padding-bucket") {
+                        Some(b) => match b.parse::<u32>() {
+                            Ok(b) if b > 0 => Padding::Bucket(b),
+                            _ => return Err(e.error("padding-bucket", format!("Column: {}.{} padding-bucket must be a positive number of bytes, found {}", tbl_name, name, b)))
+                        },
+                        None => Padding::Bucket(DEFAULT_PADDING_BUCKET)
+                    }
+                },
+                ("MAX", &NativeType::Varchar(l)) | ("MAX", &NativeType::Char(l)) => Padding::Max(l),
+                ("BUCKET", _) | ("MAX", _) => return Err(e.error("padding", format!("Column: {}.{} padding {} is not supported for {:?}", tbl_name, name, p, dt))),
+                _ => return Err(e.error("padding", format!("Column: {}.{} padding must be bucket or max, found {}", tbl_name, name, p)))
+            }
+        },
+        None => {
+            if e.get("padding-bucket").is_some() {
+                return Err(e.error("padding-bucket", format!("Column: {}.{} padding-bucket attribute requires padding=\"bucket\"", tbl_name, name)))
+            }
+            Padding::NA
+        }
+    };
+
+    let normalization = match e.get("normalize") {
+        Some(policy) => {
+            // only deterministic ciphertexts are compared by the database
+            match (&encrypt_type, &dt) {
+                (&EncryptionType::Aes(_), &NativeType::Varchar(_)) | (&EncryptionType::Aes(_), &NativeType::Char(_)) |
+                (&EncryptionType::AesSiv, &NativeType::Varchar(_)) | (&EncryptionType::AesSiv, &NativeType::Char(_)) => {},
+                (&EncryptionType::Aes(_), _) | (&EncryptionType::AesSiv, _) =>
+                    return Err(e.error("normalize", format!("Column: {}.{} normalize attribute requires a character column, found {:?}", tbl_name, name, dt))),
+                _ => return Err(e.error("normalize", format!("Column: {}.{} normalize attribute requires encryption AES or AES_SIV", tbl_name, name)))
+            }
+            if aad != AadPolicy::NA {
+                return Err(e.error("normalize", format!("Column: {}.{} normalize attribute is not supported with aad", tbl_name, name)))
+            }
+            if blind_index.is_some() || like_index.is_some() {
+                return Err(e.error("normalize", format!("Column: {}.{} normalize attribute is not supported on indexed columns", tbl_name, name)))
+            }
+            let mut n = Normalization{case_fold: false, nfc: false, trim: false,
+                display: e.get("display-column").map(|d| d.to_string())};
+            for p in policy.split(',').map(|p| p.trim().to_uppercase()) {
+                match &p as &str {
+                    "CASE" => n.case_fold = true,
+                    "NFC" => n.nfc = true,
+                    "TRIM" => n.trim = true,
+                    _ => return Err(e.error("normalize", format!("Column: {}.{} normalize must be a list of case, nfc and trim, found {}", tbl_name, name, p)))
+                }
+            }
+            Some(n)
+        },
+        None => {
+            if e.get("display-column").is_some() {
+                return Err(e.error("display-column", format!("Column: {}.{} display-column attribute requires normalize", tbl_name, name)))
+            }
+            None
+        }
+    };
+
+    Ok(ColumnConfig{
+        name: name,
+        native_type: dt,
+        encryption: encrypt_type,
+        key: key,
+        key_ref: key_ref,
+        wrapped_key: wrapped_key,
+        previous_keys: previous_keys,
+        aad: aad,
+        key_derivation: key_derivation,
+        blind_index: blind_index,
+        like_index: like_index,
+        padding: padding,
+        normalization: normalization,
+        pk_ordinal: pk_ordinal,
+    })
 }
 
-pub fn reconcile_native_type(data_type: &ASTNode, qualifiers: &Vec<NativeTypeQualifier>) -> Result<NativeType, Box<ZeroError>> {
+pub fn reconcile_native_type(data_type: &ASTNode, qualifiers: &Vec<NativeTypeQualifier>, literals: &Vec<LiteralToken>) -> Result<NativeType, Box<ZeroError>> {
     Ok(match data_type {
         &ASTNode::MySQLDataType(ref dt) => match dt {
             &Bit{..} | &TinyInt{..} |
@@ -284,14 +835,31 @@ pub fn reconcile_native_type(data_type: &ASTNode, qualifiers: &Vec<NativeTypeQua
             &TinyText => NativeType::Varchar(2_u32.pow(8)),
             &MediumText => NativeType::LONGTEXT(2_u64.pow(24)),
             &LongText => NativeType::LONGTEXT(2_u64.pow(32)),
-
-            _ => return Err(ZeroError::SchemaError{message: format!("Unsupported data type {:?}", dt), code: "123".into()}.into())
+            &Enum{box ref values} => NativeType::ENUM(reconcile_members(values, literals, false)?),
+            &Set{box ref values} => NativeType::SET(reconcile_members(values, literals, true)?),
         },
         _ => return Err(ZeroError::SchemaError{message: format!("Unexpected native type expression {:?}", data_type), code: "123".into()}.into())
 
     })
 }
 
+// the quoted members of an ENUM or SET definition
+fn reconcile_members(values: &ASTNode, literals: &Vec<LiteralToken>, set: bool) -> Result<Vec<String>, Box<ZeroError>> {
+    let items = match values {
+        &ASTNode::SQLExprList(ref items) => items,
+        _ => return Err(ZeroError::SchemaError{message: format!("Expected ENUM or SET members, received {:?}", values), code: "123".into()}.into())
+    };
+    items.iter().map(|i| match (i, set) {
+        (&ASTNode::SQLLiteral(n), _) => match literals.get(n) {
+            Some(&LiteralToken::LiteralString(_, ref v)) if set && v.contains(',') =>
+                Err(ZeroError::SchemaError{message: format!("SET member {} cannot contain a comma", v), code: "123".into()}.into()),
+            Some(&LiteralToken::LiteralString(_, ref v)) => Ok(v.clone()),
+            l => Err(ZeroError::SchemaError{message: format!("Expected a quoted ENUM or SET member, received {:?}", l), code: "123".into()}.into())
+        },
+        _ => Err(ZeroError::SchemaError{message: format!("Expected a quoted ENUM or SET member, received {:?}", i), code: "123".into()}.into())
+    }).collect()
+}
+
 // Should fail in config parse, but not as part of get table meta
 pub fn reconcile_column_qualifiers(qualifiers: &Vec<ASTNode>, fail: bool) -> Result<Vec<NativeTypeQualifier>, Box<ZeroError>> {
     // Iterate over qualifiers and propagate error on unsupported
@@ -316,7 +884,7 @@ pub fn reconcile_column_qualifiers(qualifiers: &Vec<ASTNode>, fail: bool) -> Res
 fn determine_native_type(native_type: &String) -> Result<NativeType, Box<ZeroError>> {
     let ansi = AnsiSQLDialect::new();
     let dialect = MySQLDialect::new(&ansi);
-    let tokens = native_type.tokenize(&dialect).unwrap();
+    let tokens = native_type.tokenize(&dialect)?;
 
     let data_type = dialect.parse_data_type(&tokens)?;
 
@@ -325,68 +893,239 @@ fn determine_native_type(native_type: &String) -> Result<NativeType, Box<ZeroErr
 
     let qualifiers = reconcile_column_qualifiers(&parsed_qs, true)?;
 
-    reconcile_native_type(&data_type, &qualifiers)
+    reconcile_native_type(&data_type, &qualifiers, &tokens.literals)
 }
 
-fn determine_encryption(encryption: &String, iv: Option<[u8;12]>) -> EncryptionType {
-    match &encryption.to_uppercase() as &str {
+// the precision of a DECIMAL type, MySQL defaults it to 10
+fn determine_decimal_precision(native_type: &String) -> Result<u32, Box<ZeroError>> {
+    let ansi = AnsiSQLDialect::new();
+    let dialect = MySQLDialect::new(&ansi);
+    let tokens = native_type.tokenize(&dialect)?;
+
+    Ok(match dialect.parse_data_type(&tokens)? {
+        ASTNode::MySQLDataType(Decimal{precision, ..}) => precision.unwrap_or(10),
+        _ => 0
+    })
+}
+
+fn determine_encryption(encryption: &String, iv: Option<[u8;12]>, fpe: Option<FpeFormat>) -> Result<EncryptionType, String> {
+    let encryption = encryption.to_uppercase();
+    if fpe.is_some() && encryption != "FPE" {
+        return Err("fpe-alphabet attribute requires FPE encryption".into())
+    }
+    Ok(match &encryption as &str {
         "AES" => {
             match iv {
                 Some(nonce)=> EncryptionType::Aes(nonce),
-                None => panic!("iv attribute required for AES encryption")
+                None => return Err("iv attribute required for AES encryption".into())
             }
         },
         // "AES-SALTED" => EncryptionType::AES_SALT,
         "AES_GCM" => EncryptionType::AesGcm,
+        "AES_SIV" => EncryptionType::AesSiv,
+        "OPE" => EncryptionType::Ope,
+        "PAILLIER" => EncryptionType::Paillier,
+        "FPE" => match fpe {
+            Some(format) => EncryptionType::Fpe(format),
+            None => return Err("fpe-alphabet attribute required for FPE encryption".into())
+        },
         "NONE" => EncryptionType::NA,
-        _ => panic!("Unsupported encryption type {}", encryption)
+        _ => return Err(format!("Unsupported encryption type {}", encryption))
+    })
+
+}
+
+fn determine_fpe_format(alphabet: &str, pattern: Option<&str>, tbl_name: &str, name: &str) -> Result<FpeFormat, String> {
+    let chars = alphabet.chars().collect::<Vec<char>>();
+    if chars.len() < 2 || chars.len() > 65536 || chars.iter().enumerate().any(|(i, c)| chars[..i].contains(c)) {
+        return Err(format!("Column: {}.{} fpe-alphabet must have between 2 and 65536 distinct characters", tbl_name, name))
+    }
+    let format = FpeFormat{alphabet: alphabet.to_string(), pattern: pattern.map(|p| p.to_string())};
+    if let Some(p) = pattern {
+        let placeholders = p.chars().filter(|c| *c == '#').count();
+        if placeholders < format.min_length() {
+            return Err(format!("Column: {}.{} fpe-pattern needs at least {} # placeholders for this alphabet", tbl_name, name, format.min_length()))
+        }
+    }
+    Ok(format)
+}
+
+fn determine_aad(policy: &str) -> Result<AadPolicy, String> {
+    match &policy.to_uppercase() as &str {
+        "COLUMN" => Ok(AadPolicy::Column),
+        "ROW" => Ok(AadPolicy::Row),
+        "NONE" => Ok(AadPolicy::NA),
+        _ => Err(format!("Unsupported aad policy {}", policy))
+    }
+}
+
+fn determine_tenant_key(table: &Node, name: &str) -> Result<Option<TenantKey>, ConfigError> {
+    match (table.get("tenant-column"), table.get("tenant-key-ref")) {
+        (Some(column), Some(key_ref)) => {
+            if !key_ref.contains("{}") {
+                return Err(table.error("tenant-key-ref", format!("Table {} tenant-key-ref {} must contain {{}} for the tenant", name, key_ref)))
+            }
+            let missing = match table.get("tenant-key-missing").map(|m| m.to_uppercase()) {
+                Some(ref m) if m == "NULL" => MissingKey::Null,
+                Some(ref m) if m == "ERROR" => MissingKey::Error,
+                None => MissingKey::Null,
+                Some(m) => return Err(table.error("tenant-key-missing", format!("Table {} tenant-key-missing must be null or error, found {}", name, m)))
+            };
+            Ok(Some(TenantKey{column: column.to_string(), key_ref: key_ref.to_string(), missing: missing}))
+        },
+        (None, None) => {
+            if table.get("tenant-key-missing").is_some() {
+                return Err(table.error("tenant-key-missing", format!("Table {} tenant-key-missing requires tenant-column and tenant-key-ref", name)))
+            }
+            Ok(None)
+        },
+        _ => Err(table.error("", format!("Table {} requires both tenant-column and tenant-key-ref", name)))
+    }
+}
+
+fn determine_key_derivation(derivation: &str) -> Result<KeyDerivation, String> {
+    match &derivation.to_uppercase() as &str {
+        "COLUMN" => Ok(KeyDerivation::Column),
+        "ROW" => Ok(KeyDerivation::Row),
+        _ => Err(format!("Unsupported key derivation {}", derivation))
     }
+}
+
+fn determine_key(key: &str) -> Result<SecretKey, String> {
+    check_hex(key, 32)?;
+    Ok(hex_key(key))
+}
 
+fn determine_key_version(version: &str, table: &str, column: &str) -> Result<u16, String> {
+    match version.parse::<u16>() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(format!("Column: {}.{} invalid key version {}", table, column, version))
+    }
 }
 
-fn determine_key(key: &str) -> [u8; 32] {
-    hex_key(key)
+fn iv_from_hex(hex: &str) -> Result<[u8; 12], String> {
+    check_hex(hex, 12)?;
+    Ok(hex_to_iv(hex))
 }
 
-fn iv_from_hex(hex: &str) -> [u8; 12] {
-    hex_to_iv(hex)
+// the value is never echoed, it may be a key
+fn check_hex(hex: &str, bytes: usize) -> Result<(), String> {
+    if !hex.chars().all(|c| c.is_digit(16)) {
+        Err(format!("must be hex encoded"))
+    } else if hex.len() != bytes * 2 {
+        Err(format!("must be {} hex digits, found {}", bytes * 2, hex.len()))
+    } else {
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct ColumnConfig {
     pub name: String,
     pub encryption: EncryptionType,
-    pub key: [u8; 32],
-    pub native_type: NativeType
+    pub key: KeyRing,
+    pub key_ref: Option<String>,
+    pub wrapped_key: Option<String>,
+    pub previous_keys: Vec<KeyVersionConfig>,
+    pub native_type: NativeType,
+    pub aad: AadPolicy,
+    pub key_derivation: KeyDerivation,
+    pub blind_index: Option<BlindIndex>,
+    pub like_index: Option<LikeIndex>,
+    pub padding: Padding,
+    pub normalization: Option<Normalization>,
+    pub pk_ordinal: Option<u32>
+}
+
+// a retired key version, inline keys are already in the column's KeyRing
+#[derive(Debug, PartialEq)]
+pub struct KeyVersionConfig {
+    pub version: u16,
+    pub key_ref: Option<String>,
+    pub wrapped_key: Option<String>
 }
 
 #[derive(Debug, PartialEq)]
 pub struct TableConfig {
     pub name: String,
-    pub column_map: HashMap<String, ColumnConfig>
+    pub column_map: HashMap<String, ColumnConfig>,
+    pub tenant: Option<TenantKey>
 }
 
 struct TableConfigBuilder {
     column_map: HashMap<String, ColumnConfig>,
-    name: Option<String>
+    name: Option<String>,
+    tenant: Option<TenantKey>
 }
 
 impl TableConfigBuilder {
     fn new() -> TableConfigBuilder {
-        TableConfigBuilder{column_map: HashMap::new(), name: None}
+        TableConfigBuilder{column_map: HashMap::new(), name: None, tenant: None}
     }
 
     fn set_name(&mut self, name: String) {
         self.name = Some(name);
     }
 
+    fn set_tenant(&mut self, tenant: Option<TenantKey>) {
+        self.tenant = tenant;
+    }
+
     fn add_column(&mut self, column: ColumnConfig) {
         let key = column.name.clone(); // TODO downcase
         self.column_map.insert(key, column);
     }
 
-    fn build(self) -> TableConfig {
-        TableConfig {name: self.name.unwrap(), column_map: self.column_map}
+    fn build(self) -> Result<TableConfig, String> {
+        let name = self.name.unwrap();
+
+        // Row bound columns authenticate the primary key value, so it must be a single column that is not row bound itself
+        if self.column_map.values().any(|c| c.aad == AadPolicy::Row) {
+            let pks = self.column_map.values().filter(|c| c.pk_ordinal.is_some()).collect::<Vec<&ColumnConfig>>();
+            if pks.len() != 1 {
+                return Err(format!("Table {} has row bound columns and requires a single pkOrdinal column, found {}", name, pks.len()))
+            }
+            if pks[0].aad == AadPolicy::Row {
+                return Err(format!("Table {} primary key {} cannot itself be row bound", name, pks[0].name))
+            }
+        }
+
+        // index and display companions are written by the gateway, so cannot be configured columns themselves
+        let companions = self.column_map.values()
+            .flat_map(|c| c.blind_index.iter().map(|bi| bi.column.to_lowercase())
+                .chain(c.like_index.iter().map(|li| li.column.to_lowercase()))
+                .chain(c.normalization.iter().flat_map(|n| n.display.iter()).map(|d| d.to_lowercase()))
+                .map(move |companion| (c.name.clone(), companion)))
+            .collect::<Vec<(String, String)>>();
+        for &(ref column, ref companion) in companions.iter() {
+            if self.column_map.keys().any(|k| k.to_lowercase() == *companion) ||
+                companions.iter().filter(|&&(_, ref other)| other == companion).count() > 1 {
+                return Err(format!("Table {} companion column {} of {} must not be used by another column", name, companion, column))
+            }
+        }
+
+        // the tenant column selects the key, so is read in the clear, and the tenants' keys have a single version
+        // and are unknown when queries are planned, so only suit randomized encryption without indexes
+        if let Some(ref tenant) = self.tenant {
+            match self.column_map.values().find(|c| c.name.to_lowercase() == tenant.column.to_lowercase()) {
+                Some(c) if c.encryption == EncryptionType::NA => {},
+                Some(_) => return Err(format!("Table {} tenant-column {} cannot be encrypted", name, tenant.column)),
+                None => return Err(format!("Table {} tenant-column {} is not a configured column", name, tenant.column))
+            }
+            for c in self.column_map.values().filter(|c| c.encryption != EncryptionType::NA) {
+                if c.encryption != EncryptionType::AesGcm {
+                    return Err(format!("Table {} is encrypted per tenant, column {} requires aes_gcm encryption", name, c.name))
+                }
+                if c.key_ref.is_some() || c.wrapped_key.is_some() || !c.previous_keys.is_empty() {
+                    return Err(format!("Table {} is encrypted per tenant, column {} cannot have its own keys", name, c.name))
+                }
+                if c.blind_index.is_some() || c.like_index.is_some() {
+                    return Err(format!("Table {} is encrypted per tenant, column {} cannot be indexed", name, c.name))
+                }
+            }
+        }
+
+        Ok(TableConfig {name: name, column_map: self.column_map, tenant: self.tenant})
     }
 }
 
@@ -435,35 +1174,127 @@ pub struct ParsingConfig {
     pub props: HashMap<String, String>
 }
 
+#[derive(Debug, Clone)]
+pub struct KeyProviderConfig {
+    pub props: HashMap<String, String>
+}
+
+#[derive(Debug)]
+pub struct MasterKeyConfig {
+    pub props: HashMap<String, String>
+}
+
 
 #[derive(Debug)]
 pub struct Config {
     schema_map: HashMap<String, SchemaConfig>,
     connection_config : ConnectionConfig,
     client_config: ClientConfig,
-    parsing_config: ParsingConfig
+    parsing_config: ParsingConfig,
+    key_provider_config: KeyProviderConfig,
+    master_key_config: MasterKeyConfig
 }
 
 struct ConfigBuilder {
-    schema_map : HashMap<String, SchemaConfig>,
+    schemas : HashMap<String, HashMap<String, TableConfigBuilder>>,
     conn_props : HashMap<String, String>,
     client_props : HashMap<String,String>,
-    parsing_props : HashMap<String, String>
+    parsing_props : HashMap<String, String>,
+    key_provider_props : HashMap<String, String>,
+    master_key_props : HashMap<String, String>,
+    // the files in the order read, and where each table and column was last defined
+    files: Vec<String>,
+    is_override: bool,
+    table_sources: HashMap<(String, String), (String, Pos)>,
+    column_sources: HashMap<(String, String, String), (String, Pos, bool)>,
+    // tables with a problem already reported, which are not checked as a whole
+    invalid: HashSet<(String, String)>,
+    errors: Vec<ConfigError>
 }
 
 impl ConfigBuilder {
     fn new() -> ConfigBuilder {
         ConfigBuilder{
-            schema_map: HashMap::new(),
+            schemas: HashMap::new(),
             conn_props: HashMap::new(),
             client_props: HashMap::new(),
-            parsing_props: HashMap::new()
+            parsing_props: HashMap::new(),
+            key_provider_props: HashMap::new(),
+            master_key_props: HashMap::new(),
+            files: Vec::new(),
+            is_override: false,
+            table_sources: HashMap::new(),
+            column_sources: HashMap::new(),
+            invalid: HashSet::new(),
+            errors: Vec::new()
+        }
+    }
+
+    fn set_file(&mut self, file: &str, is_override: bool) {
+        self.files.push(file.to_string());
+        self.is_override = is_override;
+    }
+
+    fn file(&self) -> String {
+        self.files.last().cloned().unwrap_or_default()
+    }
+
+    fn error(&mut self, mut error: ConfigError) {
+        if error.file.is_empty() {
+            error.file = self.file();
+        }
+        self.errors.push(error);
+    }
+
+    fn invalidate(&mut self, schema: &str, table: &str) {
+        self.invalid.insert((schema.to_string(), table.to_string()));
+    }
+
+    fn add_schema(&mut self, schema: &str) {
+        self.schemas.entry(schema.to_string()).or_insert_with(HashMap::new); // TODO downcase
+    }
+
+    // a table's tenant attributes are kept unless a later file sets them, returns whether the table is encrypted per tenant
+    fn add_table(&mut self, schema: &str, table: &str, tenant: Option<TenantKey>, pos: Pos) -> bool {
+        let file = self.file();
+        self.table_sources.insert((schema.to_string(), table.to_string()), (file, pos));
+        let tb = self.schemas.entry(schema.to_string()).or_insert_with(HashMap::new)
+            .entry(table.to_string()).or_insert_with(|| {
+                let mut tb = TableConfigBuilder::new();
+                tb.set_name(table.to_string());
+                tb
+            });
+        if tenant.is_some() {
+            tb.set_tenant(tenant);
         }
+        tb.tenant.is_some()
     }
 
-    fn add_schema(&mut self, schema: SchemaConfig) {
-        let key = schema.name.clone(); // TODO downcase
-        self.schema_map.insert(key, schema);
+    // a later file replaces a column, but overrides disagreeing on its encryption are most likely a mistake
+    fn add_column(&mut self, schema: &str, table: &str, column: ColumnConfig, e: &Node) {
+        let file = self.file();
+        let key = (schema.to_string(), table.to_string(), column.name.clone());
+        let conflict = {
+            let existing = self.schemas.get(schema).and_then(|s| s.get(table)).and_then(|t| t.column_map.get(&column.name));
+            match (self.column_sources.get(&key), existing) {
+                (Some(&(ref other, _, _)), _) if *other == file =>
+                    Some(e.error("name", format!("Column: {}.{} is defined more than once", table.to_uppercase(), column.name))),
+                (Some(&(ref other, pos, true)), Some(c)) if self.is_override && c.encryption != column.encryption =>
+                    Some(e.error("encryption", format!("Column: {}.{}.{} encryption differs from override {}:{}",
+                        schema, table, column.name, other, pos.line))),
+                _ => None
+            }
+        };
+        match conflict {
+            Some(err) => {
+                self.error(err);
+                self.invalidate(schema, table);
+            },
+            None => {
+                self.column_sources.insert(key, (file, e.pos, self.is_override));
+                self.schemas.get_mut(schema).unwrap().get_mut(table).unwrap().add_column(column);
+            }
+        }
     }
 
     fn add_client_prop(&mut self, key: String, value: String) {
@@ -478,14 +1309,169 @@ impl ConfigBuilder {
         self.parsing_props.insert(key, value);
     }
 
-    fn build(self) -> Config {
-        Config {
-            schema_map: self.schema_map,
+    fn add_key_provider_prop(&mut self, key: String, value: String) {
+        self.key_provider_props.insert(key, value);
+    }
+
+    fn add_master_key_prop(&mut self, key: String, value: String) {
+        self.master_key_props.insert(key, value);
+    }
+
+    fn build(mut self) -> Result<Config, Vec<ConfigError>> {
+        // problems found once every file is read, reported in file order
+        let mut errors = Vec::new();
+        let locate = |sources: &HashMap<(String, String), (String, Pos)>, schema: &String, table: &String, message: String| {
+            let (file, pos) = sources.get(&(schema.clone(), table.clone())).cloned().unwrap_or_default();
+            ConfigError{file: file, line: pos.line, column: pos.column, message: message}
+        };
+
+        let mut schema_map = HashMap::new();
+        for (schema, tables) in self.schemas.drain() {
+            let mut sb = SchemaConfigBuilder::new();
+            sb.set_name(schema.clone());
+            for (table, tb) in tables {
+                if self.invalid.contains(&(schema.clone(), table.clone())) {
+                    continue;
+                }
+                match tb.build() {
+                    Ok(t) => {
+                        if t.tenant.is_some() && self.key_provider_props.is_empty() {
+                            errors.push(locate(&self.table_sources, &schema, &table,
+                                format!("Table {} tenant-key-ref requires a key-provider", table)));
+                        }
+                        sb.add_table(t);
+                    },
+                    Err(message) => errors.push(locate(&self.table_sources, &schema, &table, message))
+                }
+            }
+            schema_map.insert(schema, sb.build());
+        }
+
+        let key_provider_config = KeyProviderConfig{props: self.key_provider_props};
+        let master_key_config = MasterKeyConfig{props: self.master_key_props};
+        for (key, message) in resolve_keys(&mut schema_map, &key_provider_config, &master_key_config) {
+            let (file, pos, _) = self.column_sources.get(&key).cloned().unwrap_or_default();
+            errors.push(ConfigError{file: file, line: pos.line, column: pos.column, message: message});
+        }
+
+        if !self.errors.is_empty() || !errors.is_empty() {
+            let files = self.files;
+            errors.sort_by_key(|e| (files.iter().position(|f| *f == e.file), e.line, e.column));
+            self.errors.extend(errors);
+            return Err(self.errors)
+        }
+
+        Ok(Config {
+            schema_map: schema_map,
             connection_config : ConnectionConfig {props: self.conn_props},
             client_config: ClientConfig {props: self.client_props},
-            parsing_config: ParsingConfig{props: self.parsing_props}
+            parsing_config: ParsingConfig{props: self.parsing_props},
+            key_provider_config: key_provider_config,
+            master_key_config: master_key_config
+        })
+    }
+}
+
+// fetches the key for every key-ref and unwraps every wrapped-key, current and previous versions alike,
+// returning the (schema, table, column) of any that failed
+fn resolve_keys(schema_map: &mut HashMap<String, SchemaConfig>, config: &KeyProviderConfig, master_config: &MasterKeyConfig)
+    -> Vec<((String, String, String), String)> {
+    let mut resolver = KeyResolver{provider_config: config, master_config: master_config, provider: None, master: None};
+    let mut failed = Vec::new();
+
+    for schema in schema_map.values_mut() {
+        for table in schema.table_map.values_mut() {
+            for column in table.column_map.values_mut() {
+                let key = (schema.name.clone(), table.name.clone(), column.name.clone());
+                let version = column.key.current().0;
+                match resolver.resolve(&column.key_ref, &column.wrapped_key) {
+                    Ok(Some(k)) => column.key.add(version, k),
+                    Ok(None) => {},
+                    Err(e) => failed.push((key.clone(), format!("Column: {}.{} failed to resolve key: {}", table.name, column.name, e)))
+                }
+
+                for p in column.previous_keys.iter() {
+                    match resolver.resolve(&p.key_ref, &p.wrapped_key) {
+                        Ok(Some(k)) => column.key.add(p.version, k),
+                        Ok(None) => {},
+                        Err(e) => failed.push((key.clone(), format!("Column: {}.{} failed to resolve key version {}: {}", table.name, column.name, p.version, e)))
+                    }
+                }
+            }
+        }
+    }
+    failed
+}
+
+// the provider and master key are only loaded once something refers to them, and a failure to load is kept
+// rather than retried for every column
+struct KeyResolver<'a> {
+    provider_config: &'a KeyProviderConfig,
+    master_config: &'a MasterKeyConfig,
+    provider: Option<Result<Box<KeyProvider>, Box<ZeroError>>>,
+    master: Option<Result<SecretKey, Box<ZeroError>>>
+}
+
+impl<'a> KeyResolver<'a> {
+    // None for keys given inline
+    fn resolve(&mut self, key_ref: &Option<String>, wrapped_key: &Option<String>) -> Result<Option<SecretKey>, Box<ZeroError>> {
+        if let Some(ref wrapped) = *wrapped_key {
+            if self.master.is_none() {
+                self.master = Some(load_master_key(self.master_config, self.provider_config));
+            }
+            return match self.master.as_ref().unwrap() {
+                &Ok(ref master) => unwrap_key(master, wrapped).map(Some),
+                &Err(ref e) => Err(e.clone())
+            }
+        }
+
+        if let Some(ref r) = *key_ref {
+            if self.provider.is_none() {
+                self.provider = Some(create_key_provider(self.provider_config));
+            }
+            return match self.provider.as_ref().unwrap() {
+                &Ok(ref provider) => provider.get_key(r).map(Some),
+                &Err(ref e) => Err(e.clone())
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Config {
+    // every configured column as (schema, table, column)
+    pub fn column_configs(&self) -> Vec<(&String, &String, &ColumnConfig)> {
+        let mut columns = Vec::new();
+        for schema in self.schema_map.values() {
+            for table in schema.table_map.values() {
+                for column in table.column_map.values() {
+                    columns.push((&schema.name, &table.name, column));
+                }
+            }
+        }
+        columns
+    }
+
+    /// Derives the Paillier keys of every version of each PAILLIER column's key, which takes
+    /// a second or more each, so no query waits for them on the event loop
+    pub fn derive_paillier_keys(&self) {
+        for key in self.paillier_seeds() {
+            paillier::key(key, PAILLIER_BITS);
         }
     }
+
+    /// Drops the Paillier keys derived for columns, or key versions, no longer configured
+    pub fn retain_paillier_keys(&self) {
+        paillier::retain(&self.paillier_seeds());
+    }
+
+    fn paillier_seeds(&self) -> Vec<&SecretKey> {
+        self.column_configs().into_iter()
+            .filter(|&(_, _, c)| c.encryption == EncryptionType::Paillier)
+            .flat_map(|(_, _, c)| c.key.versions().into_iter().map(move |v| c.key.get(v).unwrap()))
+            .collect()
+    }
 }
 
 pub trait TConfig {
@@ -495,6 +1481,8 @@ pub trait TConfig {
     fn get_parsing_config(&self) -> &ParsingConfig;
     fn get_connection_config(&self) -> &ConnectionConfig;
     fn get_client_config(&self) -> &ClientConfig;
+    fn get_key_provider_config(&self) -> &KeyProviderConfig;
+    fn get_master_key_config(&self) -> &MasterKeyConfig;
 }
 
 impl TConfig for Config {
@@ -529,6 +1517,14 @@ impl TConfig for Config {
         &self.parsing_config
     }
 
+    fn get_key_provider_config(&self) -> &KeyProviderConfig {
+        &self.key_provider_config
+    }
+
+    fn get_master_key_config(&self) -> &MasterKeyConfig {
+        &self.master_key_config
+    }
+
 }
 
 pub trait TSchemaConfig {
@@ -543,12 +1539,17 @@ impl TSchemaConfig for SchemaConfig {
 
 pub trait TTableConfig {
     fn get_column_config(&self, column: &String) -> Option<&ColumnConfig>;
+    fn get_primary_key(&self) -> Option<&ColumnConfig>;
 }
 
 impl TTableConfig for TableConfig {
     fn get_column_config(&self, column: &String) -> Option<&ColumnConfig> {
         self.column_map.get(column)
     }
+
+    fn get_primary_key(&self) -> Option<&ColumnConfig> {
+        self.column_map.values().filter(|c| c.pk_ordinal.is_some()).min_by_key(|c| c.pk_ordinal)
+    }
 }
 
 #[cfg(test)]
@@ -559,14 +1560,14 @@ mod tests {
 
     #[test]
     fn config_test() {
-        let config = super::parse_config("zero-config.xml");
+        let config = super::parse_config("zero-config.xml").unwrap();
         debug!("CONFIG {:#?}", config);
         debug!("HERE {:#?}", config.get_column_config(&String::from("zero"), &String::from("users"), &String::from("age")))
     }
 
     #[test]
     fn test_config_data_types() {
-        let s_config = super::parse_config("src/test/test-zero-config.xml");
+        let s_config = super::parse_config("src/test/test-zero-config.xml").unwrap();
         let test_schema = "zero".into();
         // Numerics
 
@@ -656,11 +1657,85 @@ mod tests {
         assert_eq!(config.column_map.get("i").unwrap().native_type,I64);
         assert_eq!(config.column_map.get("j").unwrap().native_type,U64);
 
+        config = s_config.get_table_config(&test_schema, &"characters_siv".into()).unwrap();
+        assert_eq!(config.column_map.get("a").unwrap().native_type,Varchar(50));
+        assert_eq!(config.column_map.get("a").unwrap().encryption,AesSiv);
+        assert_eq!(config.column_map.get("b").unwrap().native_type,U64);
+        assert_eq!(config.column_map.get("b").unwrap().encryption,AesSiv);
+
+        config = s_config.get_table_config(&test_schema, &"bound".into()).unwrap();
+        assert_eq!(config.column_map.get("id").unwrap().aad,AadPolicy::NA);
+        assert_eq!(config.column_map.get("a").unwrap().aad,AadPolicy::Column);
+        assert_eq!(config.column_map.get("b").unwrap().aad,AadPolicy::Row);
+        assert_eq!(config.column_map.get("b").unwrap().key_derivation,KeyDerivation::Column);
+        assert_eq!(config.column_map.get("c").unwrap().aad,AadPolicy::Row);
+        assert_eq!(config.column_map.get("c").unwrap().key_derivation,KeyDerivation::Row);
+        assert_eq!(config.get_primary_key().unwrap().name, "id");
+
+        config = s_config.get_table_config(&test_schema, &"rotated".into()).unwrap();
+        let keys = &config.column_map.get("a").unwrap().key;
+        assert_eq!(keys.versions(), vec![1, 2]);
+        assert_eq!(keys.current().0, 2);
+        assert_eq!(keys.get(1), Some(&hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985")));
+
+        config = s_config.get_table_config(&test_schema, &"ordered".into()).unwrap();
+        assert_eq!(config.column_map.get("amount").unwrap().encryption, EncryptionType::Ope);
+        assert_eq!(config.column_map.get("created").unwrap().native_type, NativeType::DATETIME(0));
+
+        config = s_config.get_table_config(&test_schema, &"ledger".into()).unwrap();
+        assert_eq!(config.column_map.get("amount").unwrap().encryption, EncryptionType::Paillier);
+        assert_eq!(config.column_map.get("quantity").unwrap().native_type, NativeType::I64);
+
+        config = s_config.get_table_config(&test_schema, &"documents".into()).unwrap();
+        assert_eq!(config.column_map.get("notes").unwrap().native_type, NativeType::Varchar(65536));
+        assert_eq!(config.column_map.get("body").unwrap().native_type, NativeType::LONGTEXT(2_u64.pow(32)));
+        assert_eq!(config.column_map.get("attachment").unwrap().native_type, NativeType::LONGBLOB(2_u64.pow(24)));
+        assert_eq!(config.column_map.get("digest").unwrap().native_type, NativeType::FIXEDBINARY(32));
+        assert_eq!(config.column_map.get("token").unwrap().native_type, NativeType::VARBINARY(64));
+        assert_eq!(config.column_map.get("signed").unwrap().encryption, EncryptionType::Ope);
+        assert_eq!(config.column_map.get("signed").unwrap().native_type, NativeType::TIMESTAMP(3));
+        assert_eq!(config.column_map.get("duration").unwrap().native_type, NativeType::TIME(0));
+        assert_eq!(config.column_map.get("filed").unwrap().native_type, NativeType::YEAR(4));
+
+        config = s_config.get_table_config(&test_schema, &"profiles".into()).unwrap();
+        assert_eq!(config.column_map.get("sex").unwrap().native_type, NativeType::ENUM(vec!["M".into(), "F".into()]));
+        assert_eq!(config.column_map.get("sports").unwrap().native_type, NativeType::SET(vec!["chess".into(), "golf".into(), "rowing".into()]));
+
+        config = s_config.get_table_config(&test_schema, &"formatted".into()).unwrap();
+        assert_eq!(config.column_map.get("ssn").unwrap().encryption,
+                   EncryptionType::Fpe(FpeFormat{alphabet: "0123456789".into(), pattern: Some("###-##-####".into())}));
+        assert_eq!(config.column_map.get("card").unwrap().encryption,
+                   EncryptionType::Fpe(FpeFormat{alphabet: "0123456789".into(), pattern: None}));
+
+        config = s_config.get_table_config(&test_schema, &"indexed".into()).unwrap();
+        assert_eq!(config.column_map.get("ssn").unwrap().blind_index, Some(BlindIndex{column: "ssn_bidx".into(), length: 16}));
+        assert_eq!(config.column_map.get("age").unwrap().blind_index, Some(BlindIndex{column: "age_bidx".into(), length: 8}));
+        assert_eq!(config.column_map.get("id").unwrap().blind_index, None);
+
+        config = s_config.get_table_config(&test_schema, &"searchable".into()).unwrap();
+        assert_eq!(config.column_map.get("last_name").unwrap().like_index,
+                   Some(LikeIndex{column: "last_name_like".into(), mode: LikeMode::NGram, length: 3}));
+        assert_eq!(config.column_map.get("email").unwrap().like_index,
+                   Some(LikeIndex{column: "email_like".into(), mode: LikeMode::Prefix, length: 6}));
+        assert!(config.column_map.get("email").unwrap().blind_index.is_some());
+
+        config = s_config.get_table_config(&test_schema, &"padded".into()).unwrap();
+        assert_eq!(config.column_map.get("first_name").unwrap().padding, Padding::Max(20));
+        assert_eq!(config.column_map.get("bio").unwrap().padding, Padding::Bucket(32));
+        assert_eq!(config.column_map.get("code").unwrap().padding, Padding::Bucket(DEFAULT_PADDING_BUCKET));
+
+        config = s_config.get_table_config(&test_schema, &"normalized".into()).unwrap();
+        assert_eq!(config.column_map.get("c_last").unwrap().normalization,
+                   Some(Normalization{case_fold: true, nfc: true, trim: true, display: Some("c_last_display".into())}));
+        assert_eq!(config.column_map.get("c_first").unwrap().normalization,
+                   Some(Normalization{case_fold: false, nfc: false, trim: true, display: None}));
+        assert_eq!(config.column_map.get("id").unwrap().normalization, None);
+
     }
 
     #[test]
     fn config_test_override() {
-        let config = super::parse_configs("src/test/test-zero-config.xml", "src/test/config_override");
+        let config = super::parse_configs("src/test/test-zero-config.xml", "src/test/config_override").unwrap();
 
         // token test sourced from the default config, i.e. not overridden anywhere
         let c = config.get_client_config();
@@ -673,17 +1748,245 @@ mod tests {
         assert_eq!("barpassword", c.props.get("password").unwrap());
         assert_eq!("localhost", c.props.get("host").unwrap());
 
+        // merged column by column, the rest of the table stays as the default config has it
         let c = config.get_table_config(&"zero".into(), &"users".into()).unwrap();
         assert_eq!(c.column_map.get("sex").unwrap().encryption, AesGcm);
+        assert!(match c.column_map.get("ssn").unwrap().encryption { Aes(_) => true, _ => false });
+        assert_eq!(c.column_map.get("id").unwrap().pk_ordinal, Some(0));
 
         let c = config.get_table_config(&"fooschema".into(), &"footable".into()).unwrap();
         assert_eq!(c.column_map.get("bar").unwrap().encryption, NA);
 
+        // toml overrides alongside the xml ones
+        let c = config.get_table_config(&"tomlschema".into(), &"tomltable".into()).unwrap();
+        assert_eq!(c.column_map.get("id").unwrap().pk_ordinal, Some(0));
+        let email = c.column_map.get("email").unwrap();
+        assert_eq!(email.encryption, AesGcm);
+        assert_eq!(email.native_type, Varchar(50));
+        assert_eq!(email.key.current().0, 2);
+        assert!(email.key.get(1).is_some());
+    }
+
+    #[test]
+    fn config_test_toml() {
+        use std::env;
+        use std::fs::File;
+        use std::io::Write;
+
+        let toml = super::xml_to_toml("src/test/test-zero-config.xml").unwrap();
+        let path = env::temp_dir().join("zero-config-test.toml").to_str().unwrap().to_string();
+        File::create(&path).unwrap().write_all(toml.as_bytes()).unwrap();
+
+        // a converted config reads back to the same model
+        let xml_config = super::parse_config("src/test/test-zero-config.xml").unwrap();
+        let toml_config = super::parse_config(&path).unwrap();
+        assert_eq!(xml_config.get_client_config().props, toml_config.get_client_config().props);
+        assert_eq!(xml_config.get_connection_config().props, toml_config.get_connection_config().props);
+        assert_eq!(xml_config.get_parsing_config().props, toml_config.get_parsing_config().props);
+        assert_eq!(xml_config.schema_map.len(), toml_config.schema_map.len());
+        for (name, schema) in xml_config.schema_map.iter() {
+            assert_eq!(schema.table_map, toml_config.get_schema_config(name).unwrap().table_map);
+        }
+
+        // and takes xml overrides
+        let config = super::parse_configs(&path, "src/test/config_override").unwrap();
+        let c = config.get_table_config(&"fooschema".into(), &"footable".into()).unwrap();
+        assert_eq!(c.column_map.get("bar").unwrap().encryption, NA);
+    }
+
+    #[test]
+    fn config_test_toml_errors() {
+        use std::env;
+        use std::io::Write;
+
+        let path = env::temp_dir().join("zero-config-invalid.toml").to_str().unwrap().to_string();
+        File::create(&path).unwrap().write_all(b"[connection\nhost = 1").unwrap();
+        let errors = super::parse_config(&path).unwrap_err();
+        assert_eq!(1, errors.len());
+        assert_eq!((path.clone(), 1), (errors[0].file.clone(), errors[0].line));
+        assert!(errors[0].message.starts_with("Invalid TOML"));
+
+        let toml = "[[schema]]\nname = \"zero\"\n\n[[schema.table]]\nname = \"users\"\n\n\
+            [[schema.table.column]]\nname = \"id\"\ntype = \"INTEGER\"\nencryption = \"NONE\"\n\n\
+            [[schema.table.column]]\nname = \"age\"\ntype = \"INTEGER\"\n  encryption = \"AES_GCM\"\nkey = \"44E6\"\n";
+        File::create(&path).unwrap().write_all(toml.as_bytes()).unwrap();
+        let errors = super::parse_config(&path).unwrap_err();
+        assert_eq!(vec![(16, 1, "Column: USERS.age key must be 64 hex digits, found 4".to_string())],
+            errors.iter().map(|e| (e.line, e.column, e.message.clone())).collect::<Vec<(usize, usize, String)>>());
+    }
+
+    #[test]
+    fn config_test_errors() {
+        use std::env;
+        use std::io::Write;
+
+        // every problem is reported, at the attribute it concerns
+        let xml = r#"<zero-config>
+    <schema name="zero">
+        <table name="users">
+            <column name="id" type="INTEGER" encryption="none" pkOrdinal="0"/>
+            <column name="ssn" type="VARCHAR(50)" encryption="AES" iv="03F72E7479F3E34752E4DD91" key="44E6884D78AA18FA"/>
+            <column name="age" type="VARCHAR(10)" encryption="PAILLIER" key="44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"/>
+            <column name="id" type="INTEGER" encryption="NONE"/>
+            <column name="email" typ="VARCHAR(50)" encryption="NONE"/>
+            <column name="notes" type="VARCHAR(50)" encryption="AES_GCM" header="false" key="44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"/>
+            <column name="balance" type="DECIMAL(20,2)" encryption="OPE" key="44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"/>
+        </table>
+    </schema>
+    <connection>
+        <property name="host"/>
+    </connection>
+</zero-config>"#;
+        let path = env::temp_dir().join("zero-config-test-errors.xml").to_str().unwrap().to_string();
+        File::create(&path).unwrap().write_all(xml.as_bytes()).unwrap();
+
+        let errors = super::parse_config(&path).unwrap_err();
+        assert_eq!(vec![
+            (5, 98, "Column: USERS.ssn key must be 64 hex digits, found 16".to_string()),
+            (6, 51, "Column: USERS.age Native Type \"VARCHAR(10)\" is not supported by encryption Paillier".to_string()),
+            (7, 21, "Column: USERS.id is defined more than once".to_string()),
+            (8, 13, "Missing attribute type on column".to_string()),
+            (9, 74, "Column: USERS.notes header=\"false\" requires encryption AES or AES_SIV".to_string()),
+            (10, 57, "Column: USERS.balance encryption OPE supports DECIMAL of at most 15 digits, found 20".to_string()),
+            (14, 9, "Missing attribute value on property".to_string()),
+        ], errors.iter().map(|e| (e.line, e.column, e.message.clone())).collect::<Vec<(usize, usize, String)>>());
+        assert_eq!(format!("{}:5:98: Column: USERS.ssn key must be 64 hex digits, found 16", path), errors[0].to_string());
+
+        let errors = super::parse_config("src/test/nonexistent.xml").unwrap_err();
+        assert_eq!(("src/test/nonexistent.xml".to_string(), 0), (errors[0].file.clone(), errors[0].line));
+
+        File::create(&path).unwrap().write_all(b"<zero-config>\n<schema name=zero>\n</schema>\n</zero-config>").unwrap();
+        let errors = super::parse_config(&path).unwrap_err();
+        assert_eq!(2, errors[0].line);
+    }
+
+    #[test]
+    fn config_test_override_duplicates() {
+        use std::env;
+        use std::fs;
+        use std::io::Write;
+
+        let dir = env::temp_dir().join("zero-config-test-duplicates");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let column = |encryption: &str, pk: &str| format!("<zero-config><schema name=\"zero\"><table name=\"users\">\n\
+            <column name=\"sex\" type=\"VARCHAR(50)\" encryption=\"{}\"{}/>\n</table></schema></zero-config>", encryption, pk);
+        File::create(dir.join("b.xml")).unwrap().write_all(column("NONE", "").as_bytes()).unwrap();
+        File::create(dir.join("a.xml")).unwrap().write_all(column("NONE", " pkOrdinal=\"1\"").as_bytes()).unwrap();
+        File::create(dir.join("c.xml.bak")).unwrap().write_all(b"not a config").unwrap();
+
+        // overrides agreeing on the encryption merge in file name order, other files are skipped
+        let config = super::parse_configs("src/test/test-zero-config.xml", dir.to_str().unwrap()).unwrap();
+        let c = config.get_table_config(&"zero".into(), &"users".into()).unwrap();
+        assert_eq!(c.column_map.get("sex").unwrap().encryption, NA);
+        assert_eq!(c.column_map.get("sex").unwrap().pk_ordinal, None);
+
+        // but not when they disagree
+        File::create(dir.join("c.xml")).unwrap().write_all(column("AES_GCM", " key=\"44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985\"").as_bytes()).unwrap();
+        let errors = super::parse_configs("src/test/test-zero-config.xml", dir.to_str().unwrap()).unwrap_err();
+        assert_eq!(1, errors.len());
+        assert!(errors[0].file.ends_with("c.xml"));
+        assert_eq!(2, errors[0].line);
+        assert!(errors[0].message.starts_with("Column: zero.users.sex encryption differs from override"));
+    }
+
+    #[test]
+    fn config_test_key_provider() {
+        use std::env;
+        use std::io::Write;
+        use key_provider::write_keystore;
+
+        let dir = env::temp_dir();
+        let ks_path = dir.join("zero-config-test.keystore").to_str().unwrap().to_string();
+        let mut keys = HashMap::new();
+        keys.insert(String::from("customer-pii"), SecretKey::new([3u8; 32]));
+        write_keystore(&ks_path, "config test", 10, &keys).unwrap();
+        env::set_var("ZERO_CONFIG_TEST_PASSPHRASE", "config test");
+
+        let xml = format!(r#"<zero-config>
+            <key-provider>
+                <property name="type" value="keystore"/>
+                <property name="path" value="{}"/>
+                <property name="passphrase-env" value="ZERO_CONFIG_TEST_PASSPHRASE"/>
+            </key-provider>
+            <schema name="zero">
+                <table name="customers">
+                    <column name="id" type="INTEGER" encryption="NONE"/>
+                    <column name="email" type="VARCHAR(50)" encryption="AES_GCM" key-ref="customer-pii"/>
+                </table>
+            </schema>
+        </zero-config>"#, ks_path);
+        let path = dir.join("zero-config-test-key-provider.xml");
+        File::create(&path).unwrap().write_all(xml.as_bytes()).unwrap();
+
+        let config = super::parse_config(path.to_str().unwrap()).unwrap();
+        let c = config.get_column_config(&"zero".into(), &"customers".into(), &"email".into()).unwrap();
+        assert_eq!(&SecretKey::new([3u8; 32]), c.key.current().1);
+        assert_eq!(c.key_ref, Some(String::from("customer-pii")));
+        assert_eq!("keystore", config.get_key_provider_config().props.get("type").unwrap());
+    }
+
+    #[test]
+    fn config_test_tenant() {
+        use std::env;
+        use std::io::Write;
+
+        // tenant keys are only fetched as rows are read and written
+        let xml = r#"<zero-config>
+            <key-provider>
+                <property name="type" value="keystore"/>
+                <property name="path" value="/nonexistent/zero-tenants.keystore"/>
+            </key-provider>
+            <schema name="zero">
+                <table name="tickets" tenant-column="tenant_id" tenant-key-ref="tenant-{}" tenant-key-missing="error">
+                    <column name="id" type="INTEGER" encryption="NONE" pkOrdinal="0"/>
+                    <column name="tenant_id" type="INTEGER" encryption="NONE"/>
+                    <column name="body" type="TEXT" encryption="AES_GCM"/>
+                </table>
+            </schema>
+        </zero-config>"#;
+        let path = env::temp_dir().join("zero-config-test-tenant.xml");
+        File::create(&path).unwrap().write_all(xml.as_bytes()).unwrap();
+
+        let config = super::parse_config(path.to_str().unwrap()).unwrap();
+        let t = config.get_table_config(&"zero".into(), &"tickets".into()).unwrap();
+        assert_eq!(Some(TenantKey{column: "tenant_id".into(), key_ref: "tenant-{}".into(), missing: MissingKey::Error}), t.tenant);
+        assert_eq!("tenant-42", t.tenant.as_ref().unwrap().key_ref("42"));
+    }
+
+    #[test]
+    fn config_test_master_key() {
+        use std::env;
+        use std::io::Write;
+        use key_provider::wrap_key;
+
+        let dir = env::temp_dir();
+        let mk_path = dir.join("zero-config-test.master").to_str().unwrap().to_string();
+        File::create(&mk_path).unwrap().write_all(&hex_encode(&[8u8; 32]).into_bytes()).unwrap();
+
+        let xml = format!(r#"<zero-config>
+            <master-key>
+                <property name="type" value="file"/>
+                <property name="path" value="{}"/>
+            </master-key>
+            <schema name="zero">
+                <table name="customers">
+                    <column name="email" type="VARCHAR(50)" encryption="AES_GCM" wrapped-key="{}"/>
+                </table>
+            </schema>
+        </zero-config>"#, mk_path, wrap_key(&SecretKey::new([8u8; 32]), &SecretKey::new([4u8; 32])).unwrap());
+        let path = dir.join("zero-config-test-master-key.xml");
+        File::create(&path).unwrap().write_all(xml.as_bytes()).unwrap();
+
+        let config = super::parse_config(path.to_str().unwrap()).unwrap();
+        let c = config.get_column_config(&"zero".into(), &"customers".into(), &"email".into()).unwrap();
+        assert_eq!(&SecretKey::new([4u8; 32]), c.key.current().1);
+        assert_eq!(1, config.column_configs().len());
     }
 
     #[test]
     fn config_test_override_dir_doesnt_exist() {
-        let config = super::parse_configs("src/test/test-zero-config.xml", "src/foo");
+        let config = super::parse_configs("src/test/test-zero-config.xml", "src/foo").unwrap();
         // No assertions, just should not blow up.
     }
 
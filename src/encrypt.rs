@@ -12,28 +12,642 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-extern crate crypto;
-use self::crypto::aes::KeySize;
-use self::crypto::aes_gcm::AesGcm;
-use self::crypto::aead::{AeadEncryptor, AeadDecryptor};
+use backend::{backend, BlockCipher, fixed_time_eq};
+use secret::SecretKey;
 use std::iter::repeat;
+use std::cmp;
+use std::mem;
+use rand::{Rng, OsRng};
 use error::ZeroError;
 use byteorder::{WriteBytesExt,ReadBytesExt,BigEndian};
 use std::io::Cursor;
 
-use chrono::{DateTime, TimeZone, Timelike};
+use chrono::{DateTime, TimeZone};
 use chrono::offset::utc::UTC;
 
 use decimal::d128;
 use std::str::from_utf8_unchecked;
+use std::str::FromStr;
+use std::sync::Arc;
+use paillier::{self, PaillierKey, Accumulator};
+use nat::Nat;
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum EncryptionType {
     Aes([u8;12]), // AES equality, with IV
     AesGcm, // Full AES gcm
+    AesSiv, // Deterministic AES-SIV (RFC 5297), safe for equality
+    Ope, // Order preserving prefix followed by AES-SIV, safe for equality, ranges and sorting
+    Paillier, // Randomized and additively homomorphic, ciphertexts can be summed without decrypting
+    Fpe(FpeFormat), // FF1 format preserving, deterministic and safe for equality
     NA, // None
 }
 
+impl EncryptionType {
+    /// Number of bytes the ciphertext adds to the plaintext
+    pub fn overhead(&self) -> u32 {
+        self.overhead_with(true)
+    }
+
+    /// ... for values written with or without a header, only deterministic AES and AES-SIV values can be written without
+    pub fn overhead_with(&self, header: bool) -> u32 {
+        match *self {
+            EncryptionType::Aes(_) | EncryptionType::AesSiv if !header => self.overhead_headed() - HEADER_LEN,
+            _ => self.overhead_headed()
+        }
+    }
+
+    fn overhead_headed(&self) -> u32 {
+        match *self {
+            EncryptionType::Aes(_) | EncryptionType::AesGcm => HEADER_LEN + 28, // header + nonce + tag
+            EncryptionType::AesSiv => HEADER_LEN + 16, // header + synthetic iv
+            EncryptionType::Ope => OPE_LEN + HEADER_LEN + 16, // order prefix + header + synthetic iv
+            EncryptionType::Paillier => HEADER_LEN + PAILLIER_BITS as u32 / 4, // header + value mod n^2, independent of the plaintext
+            EncryptionType::Fpe(_) => 0, // same characters, no header
+            EncryptionType::NA => 0
+        }
+    }
+
+    /// Whether the scheme can encrypt values of the native type
+    pub fn supports(&self, native_type: &NativeType) -> bool {
+        match *self {
+            EncryptionType::Ope => match *native_type {
+                NativeType::U64 | NativeType::I64 | NativeType::F64 | NativeType::D128 |
+                NativeType::DATE | NativeType::DATETIME(_) | NativeType::TIMESTAMP(_) |
+                NativeType::TIME(_) | NativeType::YEAR(_) => true,
+                _ => false
+            },
+            EncryptionType::Paillier => match *native_type {
+                NativeType::U64 | NativeType::I64 | NativeType::D128 => true,
+                _ => false
+            },
+            EncryptionType::Fpe(_) => match *native_type {
+                NativeType::Char(_) | NativeType::Varchar(_) => true,
+                _ => false
+            },
+            _ => true
+        }
+    }
+
+    /// Whether equal values encrypt to equal ciphertexts under the same key, AES with a configured IV and AES-SIV,
+    /// the schemes whose values can be written without a header
+    pub fn is_deterministic(&self) -> bool {
+        match *self {
+            EncryptionType::Aes(_) | EncryptionType::AesSiv => true,
+            _ => false
+        }
+    }
+
+    // bytes preceding the header
+    fn prefix_len(&self) -> usize {
+        match *self {
+            EncryptionType::Ope => OPE_LEN as usize,
+            _ => 0
+        }
+    }
+
+    // identifies the scheme in a ciphertext header
+    fn id(&self) -> u8 {
+        match *self {
+            EncryptionType::Aes(_) => 1,
+            EncryptionType::AesGcm => 2,
+            EncryptionType::AesSiv => 3,
+            EncryptionType::Ope => 4,
+            EncryptionType::Paillier => 5,
+            EncryptionType::Fpe(_) => 6, // never written, the format leaves no room for a header
+            EncryptionType::NA => 0
+        }
+    }
+}
+
+/// Ciphertext header: magic, format version, scheme id and big endian key version
+const HEADER_MAGIC: u8 = 0x5A;
+const HEADER_FORMAT: u8 = 1;
+pub const HEADER_LEN: u32 = 5;
+
+/// Header format of AES-GCM values longer than a segment, which are sealed as a STREAM of
+/// segments each with its own nonce and tag, so they can be decrypted as they are read
+const STREAM_FORMAT: u8 = 2;
+/// Plaintext bytes in each segment of a stream but the last
+pub const STREAM_SEGMENT_LEN: usize = 64 * 1024;
+const STREAM_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+
+/// Header format of AES-GCM strings padded to hide their length, the padding is stripped on decrypt
+const PADDED_FORMAT: u8 = 3;
+
+/// Size of the Paillier modulus n, ciphertexts are values mod n^2
+pub const PAILLIER_BITS: usize = 2048;
+/// Decimals are encrypted as integers scaled by 10^PAILLIER_SCALE, so sums are exact
+const PAILLIER_SCALE: usize = 10;
+
+/// The versions of a column key. New values are written with the newest version,
+/// older versions are kept so existing values can still be read while they are rotated.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyRing {
+    keys: Vec<(u16, SecretKey)>, // ascending by version
+    // whether deterministic values are written with a header, columns holding values written before
+    // headers keep writing without one so equality still matches them
+    header: bool
+}
+
+impl KeyRing {
+    pub fn new(version: u16, key: SecretKey) -> KeyRing {
+        KeyRing{keys: vec![(version, key)], header: true}
+    }
+
+    pub fn set_header(&mut self, header: bool) {
+        self.header = header;
+    }
+
+    pub fn header(&self) -> bool {
+        self.header
+    }
+
+    // adds or replaces a version
+    pub fn add(&mut self, version: u16, key: SecretKey) {
+        self.keys.retain(|&(v, _)| v != version);
+        self.keys.push((version, key));
+        self.keys.sort_by_key(|&(v, _)| v);
+    }
+
+    pub fn current(&self) -> (u16, &SecretKey) {
+        let &(v, ref k) = self.keys.last().unwrap();
+        (v, k)
+    }
+
+    pub fn get(&self, version: u16) -> Option<&SecretKey> {
+        self.keys.iter().find(|&&(v, _)| v == version).map(|&(_, ref k)| k)
+    }
+
+    pub fn versions(&self) -> Vec<u16> {
+        self.keys.iter().map(|&(v, _)| v).collect()
+    }
+
+    // the ring of a single version, which writes values as that version did
+    pub fn at(&self, version: u16) -> Option<KeyRing> {
+        self.get(version).map(|k| KeyRing{keys: vec![(version, k.clone())], header: self.header})
+    }
+
+    /// Each version's key derived with HKDF-SHA256, salt and info name what the keys are for
+    pub fn derive(&self, salt: &[u8], info: &[u8]) -> KeyRing {
+        KeyRing{keys: self.keys.iter().map(|&(v, ref k)| (v, SecretKey::new(backend().hkdf_sha256(salt, &**k, info)))).collect(), header: self.header}
+    }
+}
+
+/// How much of a value's location is authenticated along with its ciphertext
+#[derive(Debug, PartialEq, Clone)]
+pub enum AadPolicy {
+    Column, // schema.table.column
+    Row, // schema.table.column plus the primary key value
+    NA, // None
+}
+
+/// Whether a column's values are encrypted under its key or a key derived for each row
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyDerivation {
+    Column,
+    Row, // HKDF of the column key and the primary key value, so one row's key exposes no other row
+}
+
+/// Associated data binding a column's ciphertexts to where they are stored
+#[derive(Debug, PartialEq, Clone)]
+pub struct AadBinding {
+    pub column: String, // schema.table.column
+    pub row_key: Option<String>, // primary key column, if also bound to the row
+    pub row_key_type: NativeType, // ... and its type
+    pub row_keys: bool, // values encrypted under keys derived for their row
+    pub tenant: Option<TenantKey>, // values encrypted under their tenant's key
+}
+
+impl AadBinding {
+    pub fn to_bytes(&self, row: Option<&str>) -> Vec<u8> {
+        let mut aad = self.column.clone().into_bytes();
+        if let Some(r) = row {
+            aad.push(0);
+            aad.extend_from_slice(self.row_text(r).as_bytes());
+        }
+        aad
+    }
+
+    /// A primary key as MySQL returns it, so a value written with 07 or '7 ' is bound to the row read back as 7
+    pub fn row_text(&self, row: &str) -> String {
+        match self.row_key_type {
+            NativeType::U64 => u64::from_str(row.trim()).map(|v| v.to_string()).unwrap_or(row.to_string()),
+            NativeType::I64 => i64::from_str(row.trim()).map(|v| v.to_string()).unwrap_or(row.to_string()),
+            NativeType::Char(_) => row.trim_right_matches(' ').to_string(),
+            _ => row.to_string()
+        }
+    }
+
+    /// The keys a value is encrypted under, derived from the column keys for its row
+    /// when the column is keyed per row
+    pub fn keys(&self, keys: &KeyRing, row: Option<&str>) -> KeyRing {
+        match row {
+            Some(r) if self.row_keys => keys.derive(b"zero-row-key", &self.to_bytes(Some(r))),
+            _ => keys.clone()
+        }
+    }
+
+    /// The keys of the column's values of a tenant, derived from the tenant's key
+    pub fn tenant_keys(&self, tenant_key: &SecretKey) -> KeyRing {
+        KeyRing::new(0, tenant_key.clone()).derive(b"zero-tenant-key", self.column.as_bytes())
+    }
+}
+
+/// Tables holding the rows of several tenants can encrypt each row under a key of the tenant named
+/// by one of its columns, fetched from the key provider, so destroying the key destroys the tenant's data
+#[derive(Debug, PartialEq, Clone)]
+pub struct TenantKey {
+    pub column: String, // the tenant column
+    pub key_ref: String, // key provider reference, {} stands for the tenant
+    pub missing: MissingKey
+}
+
+impl TenantKey {
+    pub fn key_ref(&self, tenant: &str) -> String {
+        self.key_ref.replace("{}", tenant)
+    }
+}
+
+/// What reading a value whose tenant key no longer exists returns
+#[derive(Debug, PartialEq, Clone)]
+pub enum MissingKey {
+    Null,
+    Error
+}
+
+/// Companion column holding a truncated HMAC of a column's values, so equality can be
+/// tested on columns whose ciphertexts are randomized
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlindIndex {
+    pub column: String,
+    pub length: u32, // bytes of the HMAC kept
+}
+
+pub const DEFAULT_BLIND_INDEX_LENGTH: u32 = 16;
+
+/// Companion column holding keyed hashes of the n-grams or prefixes of a string column's
+/// values, so LIKE patterns can be looked up without decrypting
+#[derive(Debug, PartialEq, Clone)]
+pub struct LikeIndex {
+    pub column: String,
+    pub mode: LikeMode,
+    pub length: u32, // characters in each n-gram, or in the longest prefix
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LikeMode {
+    NGram,
+    Prefix
+}
+
+pub const DEFAULT_NGRAM_LENGTH: u32 = 3;
+pub const DEFAULT_PREFIX_LENGTH: u32 = 10;
+
+/// Padding added to a string column's values before encryption, so the length of a
+/// ciphertext does not give away the length of its value
+#[derive(Debug, PartialEq, Clone)]
+pub enum Padding {
+    Bucket(u32), // up to the next multiple of the bucket size
+    Max(u32), // up to the declared length of the column
+    NA
+}
+
+pub const DEFAULT_PADDING_BUCKET: u32 = 16;
+
+impl Padding {
+    /// Length of a value of len bytes once padded, padded values gain at least one byte
+    pub fn padded_len(&self, len: u32) -> u32 {
+        match *self {
+            Padding::Bucket(size) => (len / size + 1) * size,
+            Padding::Max(max) => ::std::cmp::max(len, max) + 1,
+            Padding::NA => len
+        }
+    }
+
+    // ISO/IEC 7816-4, a 0x80 byte followed by zeros
+    fn pad(&self, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.padded_len(buf.len() as u32) as usize);
+        out.extend_from_slice(buf);
+        out.push(0x80);
+        out.resize(self.padded_len(buf.len() as u32) as usize, 0);
+        out
+    }
+}
+
+fn unpad(mut buf: Vec<u8>) -> Result<Vec<u8>, Box<ZeroError>> {
+    match buf.iter().rposition(|b| *b != 0) {
+        Some(i) if buf[i] == 0x80 => {
+            buf.truncate(i);
+            Ok(buf)
+        },
+        _ => Err(ZeroError::DecryptionError{message: "Failed decrypting data, invalid padding".into(), code: "123".into()}.into())
+    }
+}
+
+/// Folds the differences a case insensitive PAD SPACE collation ignores out of a character column's
+/// values before deterministic encryption, so values the collation compares as equal encrypt alike
+#[derive(Debug, PartialEq, Clone)]
+pub struct Normalization {
+    pub case_fold: bool,
+    pub nfc: bool, // Unicode canonical composition
+    pub trim: bool, // trailing spaces
+    pub display: Option<String>, // companion column holding the original value under randomized encryption
+}
+
+impl Normalization {
+    pub fn apply(&self, value: &str) -> String {
+        let mut v = if self.case_fold { value.to_lowercase() } else { value.to_string() };
+        if self.nfc {
+            v = v.nfc().collect::<String>();
+        }
+        if self.trim {
+            let len = v.trim_right_matches(' ').len();
+            v.truncate(len);
+        }
+        v
+    }
+
+    /// Keys of the display column, derived from the column keys
+    pub fn display_keys(&self, keys: &KeyRing) -> KeyRing {
+        keys.derive(b"zero-display-key", self.display.as_ref().map_or(&[][..], |d| d.as_bytes()))
+    }
+}
+
+// bytes of each token's HMAC, hex encoded in the companion column
+const LIKE_TOKEN_LEN: usize = 8;
+// n-grams at the start and end of a value are anchored with these
+const LIKE_START: char = '\u{2}';
+const LIKE_END: char = '\u{3}';
+
+impl LikeIndex {
+    /// The space separated tokens stored in the companion column for a value
+    pub fn tokens(&self, keys: &KeyRing, value: &str) -> String {
+        let chars = value.chars().collect::<Vec<char>>();
+        let grams = match self.mode {
+            LikeMode::NGram => {
+                let mut anchored = vec![LIKE_START];
+                anchored.extend_from_slice(&chars);
+                anchored.push(LIKE_END);
+                self.ngrams(&anchored, true)
+            },
+            LikeMode::Prefix => (1..cmp::min(chars.len(), self.length as usize) + 1).map(|k| chars[..k].to_vec()).collect()
+        };
+        self.hash(keys, grams).join(" ")
+    }
+
+    /// The tokens every value matching a LIKE pattern carries, as a boolean mode full text
+    /// search. Values holding them all may still not match the pattern.
+    pub fn pattern_tokens(&self, keys: &KeyRing, pattern: &str) -> Result<String, Box<ZeroError>> {
+        // literal runs between wildcards, and whether each is preceded by a wildcard
+        let mut segments: Vec<(bool, Vec<char>)> = vec![(false, vec![])];
+        let mut it = pattern.chars();
+        while let Some(c) = it.next() {
+            match c {
+                '%' | '_' => segments.push((true, vec![])),
+                '\\' => match it.next() {
+                    Some(e) => segments.last_mut().unwrap().1.push(e),
+                    None => segments.last_mut().unwrap().1.push(c)
+                },
+                _ => segments.last_mut().unwrap().1.push(c)
+            }
+        }
+
+        let grams = match self.mode {
+            LikeMode::NGram => {
+                let n = segments.len();
+                let mut grams = Vec::new();
+                for (i, &(after_wildcard, ref literal)) in segments.iter().enumerate() {
+                    let mut s = Vec::new();
+                    if !after_wildcard {
+                        s.push(LIKE_START);
+                    }
+                    s.extend_from_slice(literal);
+                    if i == n - 1 {
+                        s.push(LIKE_END);
+                    }
+                    grams.extend(self.ngrams(&s, n == 1));
+                }
+                grams
+            },
+            LikeMode::Prefix => match segments.first() {
+                Some(&(_, ref prefix)) if !prefix.is_empty() => vec![prefix[..cmp::min(prefix.len(), self.length as usize)].to_vec()],
+                _ => vec![]
+            }
+        };
+        if grams.is_empty() {
+            return Err(ZeroError::EncryptionError {
+                message: format!("LIKE pattern '{}' is too short to search {}", pattern, self.column),
+                code: "1064".into()
+            }.into())
+        }
+        // values keep the tokens of the key version they were written with until they are rotated,
+        // so with several versions the tokens under each are an alternative
+        let versions = keys.versions();
+        let required = |key: &SecretKey| self.hash_under(key, &grams).iter().map(|t| format!("+{}", t)).collect::<Vec<String>>().join(" ");
+        if versions.len() == 1 {
+            return Ok(required(keys.current().1))
+        }
+        Ok(versions.iter().rev().map(|&v| format!("({})", required(keys.get(v).unwrap()))).collect::<Vec<String>>().join(" "))
+    }
+
+    // n-grams of the characters, or all of them if shorter and complete is set
+    fn ngrams(&self, chars: &[char], complete: bool) -> Vec<Vec<char>> {
+        let n = self.length as usize;
+        if chars.len() < n {
+            return if complete { vec![chars.to_vec()] } else { vec![] }
+        }
+        chars.windows(n).map(|w| w.to_vec()).collect()
+    }
+
+    fn hash(&self, keys: &KeyRing, grams: Vec<Vec<char>>) -> Vec<String> {
+        self.hash_under(keys.current().1, &grams)
+    }
+
+    fn hash_under(&self, key: &SecretKey, grams: &Vec<Vec<char>>) -> Vec<String> {
+        let subkey = SecretKey::new(backend().hmac_sha256(key, &[b"zero-like-index"]));
+
+        let mut tokens = grams.iter().map(|g| {
+            backend().hmac_sha256(&subkey, &[g.iter().cloned().collect::<String>().as_bytes()])[..LIKE_TOKEN_LEN].iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        }).collect::<Vec<String>>();
+        tokens.sort();
+        tokens.dedup();
+        tokens
+    }
+}
+
+/// Alphabet and optional pattern of a format preserving column. The characters of a value that
+/// are in the alphabet are enciphered with FF1 over the alphabet, others are kept. A pattern
+/// fixes the format, each `#` standing for a character of the alphabet and others for themselves.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FpeFormat {
+    pub alphabet: String,
+    pub pattern: Option<String>
+}
+
+impl FpeFormat {
+    /// Fewest enciphered characters for at least a million possible values, as FF1 requires
+    pub fn min_length(&self) -> usize {
+        let radix = self.alphabet.chars().count() as u64;
+        let mut domain = 1u64;
+        let mut n = 0;
+        while domain < 1000000 || n < 2 {
+            domain *= radix;
+            n += 1;
+        }
+        n
+    }
+
+    pub fn encrypt(&self, key: &[u8], tweak: &[u8], value: &str) -> Result<String, Box<ZeroError>> {
+        self.transform(key, tweak, value, true).map_err(|message| ZeroError::EncryptionError{message: message, code: "123".into()}.into())
+    }
+
+    pub fn decrypt(&self, key: &[u8], tweak: &[u8], value: &str) -> Result<String, Box<ZeroError>> {
+        self.transform(key, tweak, value, false).map_err(|message| ZeroError::DecryptionError{message: message, code: "123".into()}.into())
+    }
+
+    fn transform(&self, key: &[u8], tweak: &[u8], value: &str, encrypt: bool) -> Result<String, String> {
+        let alphabet = self.alphabet.chars().collect::<Vec<char>>();
+        let mut chars = value.chars().collect::<Vec<char>>();
+        let positions = match self.pattern {
+            Some(ref pattern) => {
+                let pattern = pattern.chars().collect::<Vec<char>>();
+                let matches = pattern.len() == chars.len() && pattern.iter().zip(chars.iter())
+                    .all(|(p, c)| if *p == '#' { alphabet.contains(c) } else { p == c });
+                if !matches {
+                    return Err(format!("Value does not match the format preserving pattern {}", self.pattern.as_ref().unwrap()))
+                }
+                (0..chars.len()).filter(|&i| pattern[i] == '#').collect::<Vec<usize>>()
+            },
+            None => (0..chars.len()).filter(|&i| alphabet.contains(&chars[i])).collect::<Vec<usize>>()
+        };
+        if positions.len() < self.min_length() {
+            return Err(format!("Format preserving encryption needs at least {} characters of the alphabet, found {}", self.min_length(), positions.len()))
+        }
+
+        let numerals = positions.iter().map(|&i| alphabet.iter().position(|a| *a == chars[i]).unwrap() as u32).collect::<Vec<u32>>();
+        let out = ff1(key, tweak, alphabet.len() as u32, &numerals, encrypt);
+        for (numeral, &i) in out.into_iter().zip(positions.iter()) {
+            chars[i] = alphabet[numeral as usize];
+        }
+        Ok(chars.into_iter().collect())
+    }
+}
+
+/// A value of one of the encryptable native types
+#[derive(Debug, PartialEq, Clone)]
+pub enum Plaintext {
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    D128(d128),
+    Bool(bool),
+    Text(String),
+    DateTime(DateTime<UTC>),
+    Time(i64), // signed microseconds
+    Bytes(Vec<u8>),
+}
+
+impl Plaintext {
+    pub fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+        match self {
+            Plaintext::U64(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::I64(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::F64(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::D128(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::Bool(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::Text(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::DateTime(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::Time(v) => v.encrypt(scheme, keys, aad),
+            Plaintext::Bytes(v) => v.encrypt(scheme, keys, aad),
+        }
+    }
+
+    /// As encrypt, with text padded to hide its length
+    pub fn encrypt_padded(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8], padding: &Padding) -> Result<Vec<u8>, Box<ZeroError>> {
+        match self {
+            Plaintext::Text(ref v) if *padding != Padding::NA => padded_encrypt(scheme, keys, v.as_bytes(), aad, padding),
+            p => p.encrypt(scheme, keys, aad)
+        }
+    }
+
+    pub fn decrypt(native_type: &NativeType, v: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Plaintext, Box<ZeroError>> {
+        Ok(match *native_type {
+            NativeType::U64 | NativeType::YEAR(_) => Plaintext::U64(u64::decrypt(v, scheme, keys, aad)?),
+            NativeType::I64 => Plaintext::I64(i64::decrypt(v, scheme, keys, aad)?),
+            NativeType::F64 => Plaintext::F64(f64::decrypt(v, scheme, keys, aad)?),
+            NativeType::D128 => Plaintext::D128(d128::decrypt(v, scheme, keys, aad)?),
+            NativeType::BOOL => Plaintext::Bool(bool::decrypt(v, scheme, keys, aad)?),
+            NativeType::Varchar(_) | NativeType::Char(_) | NativeType::LONGTEXT(_) |
+            NativeType::ENUM(_) | NativeType::SET(_) => Plaintext::Text(String::decrypt(v, scheme, keys, aad)?),
+            NativeType::DATE | NativeType::DATETIME(_) | NativeType::TIMESTAMP(_) => Plaintext::DateTime(DateTime::<UTC>::decrypt(v, scheme, keys, aad)?),
+            NativeType::TIME(_) => Plaintext::Time(i64::decrypt(v, scheme, keys, aad)?),
+            NativeType::FIXEDBINARY(_) | NativeType::VARBINARY(_) | NativeType::LONGBLOB(_) => Plaintext::Bytes(Vec::<u8>::decrypt(v, scheme, keys, aad)?),
+            ref t => return Err(ZeroError::DecryptionError{message: format!("Decryption not supported for {:?}", t), code: "123".into()}.into())
+        })
+    }
+
+    /// Every ciphertext an equal value of a deterministic column may be stored as until the column is
+    /// rotated: under each key version, newest first, and without a header too for columns still writing none
+    pub fn comparands(&self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<Vec<u8>>, Box<ZeroError>> {
+        let mut out: Vec<Vec<u8>> = Vec::new();
+        for v in keys.versions().into_iter().rev() {
+            let mut ring = keys.at(v).unwrap();
+            let mut forms = vec![true];
+            if !keys.header() {
+                forms.push(false);
+            }
+            for header in forms {
+                ring.set_header(header);
+                let encrypted = self.clone().encrypt(scheme, &ring, aad)?;
+                if !out.contains(&encrypted) {
+                    out.push(encrypted);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Truncated HMAC of the value under a subkey of the newest column key
+    pub fn blind_index(&self, keys: &KeyRing, length: u32) -> Vec<u8> {
+        self.blind_index_under(keys.current().1, length)
+    }
+
+    /// The value's index under each version of the column key, newest first, as values keep
+    /// the index of the version they were written with until they are rotated
+    pub fn blind_indexes(&self, keys: &KeyRing, length: u32) -> Vec<Vec<u8>> {
+        keys.versions().iter().rev().map(|&v| self.blind_index_under(keys.get(v).unwrap(), length)).collect()
+    }
+
+    fn blind_index_under(&self, key: &SecretKey, length: u32) -> Vec<u8> {
+        let subkey = SecretKey::new(backend().hmac_sha256(key, &[b"zero-blind-index"]));
+        backend().hmac_sha256(&subkey, &[&self.index_bytes()])[..length as usize].to_vec()
+    }
+
+    // values MySQL would compare as equal hash alike
+    fn index_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        match *self {
+            Plaintext::U64(v) => buf.write_u64::<BigEndian>(v).unwrap(),
+            Plaintext::I64(v) => buf.write_i64::<BigEndian>(v).unwrap(),
+            Plaintext::F64(v) => buf.write_f64::<BigEndian>(if v == 0.0 { 0.0 } else { v }).unwrap(),
+            Plaintext::D128(v) => buf.extend_from_slice(format!("{}", v.reduce()).as_bytes()),
+            Plaintext::Bool(v) => buf.push(v as u8),
+            // trailing spaces are ignored by the default PAD SPACE collations
+            Plaintext::Text(ref v) => buf.extend_from_slice(v.trim_right_matches(' ').as_bytes()),
+            Plaintext::DateTime(ref v) => {
+                buf.write_i64::<BigEndian>(v.timestamp()).unwrap();
+                buf.write_u32::<BigEndian>(v.timestamp_subsec_nanos()).unwrap();
+            },
+            Plaintext::Time(v) => buf.write_i64::<BigEndian>(v).unwrap(),
+            Plaintext::Bytes(ref v) => buf.extend_from_slice(v),
+        }
+        buf
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum NativeType {
     U64,
@@ -45,51 +659,143 @@ pub enum NativeType {
     BOOL,
     DATETIME(u32), // fsp
     DATE,
-
-    // These are representative native types, but not supported as encrypted columns
-    TIMESTAMP(u32),
-    TIME(u32),
-    YEAR(u32),
+    TIMESTAMP(u32), // fsp
+    TIME(u32), // fsp
+    YEAR(u32), // display
     FIXEDBINARY(u32),
     VARBINARY(u32), // Max of 65535
     LONGBLOB(u64),
     LONGTEXT(u64),
+    ENUM(Vec<String>), // members
+    SET(Vec<String>), // members
 
-    // Unknown
+    // Unknown, not supported as encrypted columns
     UNKNOWN
 }
 
 impl NativeType {
+    /// Most bytes a value of the type encrypts, None for blobs and text, whose columns are sized by MySQL's limits
+    pub fn max_len(&self) -> Option<u32> {
+        match *self {
+            NativeType::U64 | NativeType::I64 | NativeType::F64 | NativeType::TIME(_) | NativeType::YEAR(_) => Some(8),
+            NativeType::D128 => Some(16),
+            NativeType::BOOL => Some(1),
+            NativeType::DATE | NativeType::DATETIME(_) | NativeType::TIMESTAMP(_) => Some(12),
+            NativeType::Char(l) | NativeType::Varchar(l) | NativeType::FIXEDBINARY(l) | NativeType::VARBINARY(l) => Some(l),
+            NativeType::ENUM(ref members) => Some(members.iter().map(|m| m.len() as u32).max().unwrap_or(0)),
+            NativeType::SET(ref members) => Some(members.iter().fold(0, |l, m| l + m.len() as u32 + 1)),
+            NativeType::LONGBLOB(_) | NativeType::LONGTEXT(_) | NativeType::UNKNOWN => None
+        }
+    }
+
     pub fn is_supported(&self) -> bool {
         match *self {
-            NativeType::U64 | NativeType::I64 | NativeType::Char(_) |
-            NativeType::Varchar(_) | NativeType::F64 | NativeType::D128 |
-            NativeType::BOOL | NativeType::DATETIME(_) |
-            NativeType::DATE => true,
-            _ => false
+            NativeType::UNKNOWN => false,
+            _ => true
+        }
+    }
+
+    /// The declared spelling of an ENUM member, or of the members of a SET value in declared
+    /// order, matched without case as MySQL does. None when the value holds anything else.
+    pub fn member_value(&self, value: &str) -> Option<String> {
+        let is = |member: &String, v: &str| member.to_lowercase() == v.to_lowercase();
+        match *self {
+            NativeType::ENUM(ref members) => members.iter().find(|m| is(m, value)).cloned(),
+            NativeType::SET(ref members) => {
+                let values = if value.is_empty() { vec![] } else { value.split(',').collect::<Vec<&str>>() };
+                if values.iter().all(|v| members.iter().any(|m| is(m, v))) {
+                    Some(members.iter().filter(|m| values.iter().any(|v| is(m, v))).cloned().collect::<Vec<String>>().join(","))
+                } else {
+                    None
+                }
+            },
+            _ => None
+        }
+    }
+
+    /// The value of an ENUM member given by its index from 1, or of a SET given by a bitmask of members
+    pub fn member_number(&self, n: u64) -> Option<String> {
+        match *self {
+            NativeType::ENUM(ref members) if n >= 1 && n <= members.len() as u64 => Some(members[n as usize - 1].clone()),
+            NativeType::SET(ref members) if members.len() >= 64 || n >> members.len() == 0 => {
+                Some(members.iter().enumerate().filter(|&(i, _)| n & (1u64 << i) != 0).map(|(_, m)| m.clone()).collect::<Vec<String>>().join(","))
+            },
+            _ => None
         }
     }
 }
 
+/// Signed microseconds of a TIME value such as `-838:59:59`, `12:30` or `1 02:00:00.5`
+pub fn parse_time(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (negative, value) = if value.starts_with('-') { (true, &value[1..]) } else { (false, value) };
+    let (days, value) = match value.find(' ') {
+        Some(i) => match value[..i].parse::<i64>() {
+            Ok(d) if d <= 34 => (d, &value[i + 1..]),
+            _ => return None
+        },
+        None => (0, value)
+    };
+    let (clock, fraction) = match value.find('.') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, "")
+    };
+    if fraction.len() > 6 || !fraction.chars().all(|c| c.is_digit(10)) {
+        return None
+    }
+
+    let parts = clock.split(':').map(|p| {
+        if !p.is_empty() && p.len() <= 3 && p.chars().all(|c| c.is_digit(10)) { p.parse::<i64>().ok() } else { None }
+    }).collect::<Option<Vec<i64>>>();
+    let (h, m, sec) = match parts {
+        Some(ref p) if p.len() == 3 => (p[0], p[1], p[2]),
+        Some(ref p) if p.len() == 2 => (p[0], p[1], 0),
+        _ => return None
+    };
+    if m > 59 || sec > 59 {
+        return None
+    }
+
+    let seconds = ((days * 24 + h) * 60 + m) * 60 + sec;
+    let micros = seconds * 1000000 + format!("{:0<6}", fraction).parse::<i64>().unwrap();
+    // the range of TIME is -838:59:59 to 838:59:59
+    if micros > (838 * 3600 + 59 * 60 + 59) * 1000000 {
+        return None
+    }
+    Some(if negative { -micros } else { micros })
+}
+
+/// The text of a TIME value, with fsp fractional digits
+pub fn format_time(micros: i64, fsp: u32) -> String {
+    let seconds = micros.abs() / 1000000;
+    let mut out = format!("{}{:02}:{:02}:{:02}", if micros < 0 { "-" } else { "" }, seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if fsp > 0 {
+        let fraction = format!("{:06}", micros.abs() % 1000000);
+        out.push('.');
+        out.push_str(&fraction[..cmp::min(fsp, 6) as usize]);
+    }
+    out
+}
+
 pub trait Encrypt {
 
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>>;
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>>;
 
 }
 
 pub trait Decrypt {
     type DecType;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<Self::DecType, Box<ZeroError>>;
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Self::DecType, Box<ZeroError>>;
 }
 
 impl Decrypt for bool {
     type DecType = bool;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<bool, Box<ZeroError>> {
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<bool, Box<ZeroError>> {
         match scheme {
-            &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
-                let decrypted = decrypt(key, value)?;
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv | &EncryptionType::Ope => {
+                let decrypted = scheme_decrypt(scheme, keys, value, aad)?;
                 match Cursor::new(decrypted).read_u8().unwrap() {
                     0 => Ok(false),
                     1 => Ok(true),
@@ -103,20 +809,24 @@ impl Decrypt for bool {
 
 impl Encrypt for bool {
 
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
         let mut buf: Vec<u8> = Vec::new();
         buf.write_u8(self as u8).unwrap();
-        encrypt(key, &buf, make_nonce(scheme)?)
+        scheme_encrypt(scheme, keys, &buf, aad)
     }
 }
 
 impl Decrypt for u64 {
     type DecType = u64;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<u64, Box<ZeroError>> {
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<u64, Box<ZeroError>> {
         match scheme {
-            &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
-                let decrypted = decrypt(key, value)?;
+            &EncryptionType::Paillier => match paillier_decrypt(keys, value)? {
+                (false, m) => m.to_u64().ok_or(out_of_range()),
+                (true, _) => Err(out_of_range())
+            },
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv | &EncryptionType::Ope => {
+                let decrypted = scheme_decrypt(scheme, keys, value, aad)?;
                 Ok(Cursor::new(decrypted).read_u64::<BigEndian>().unwrap())
             },
             _ => Err(ZeroError::DecryptionError{message: format!("Decryption not supported {:?}", scheme), code: "123".into()}.into())
@@ -126,11 +836,14 @@ impl Decrypt for u64 {
 
 impl Encrypt for u64 {
 
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
 
+        if *scheme == EncryptionType::Paillier {
+            return paillier_encrypt(keys, false, Nat::from_u64(self))
+        }
         let mut buf: Vec<u8> = Vec::new();
         buf.write_u64::<BigEndian>(self).unwrap();
-        encrypt(key, &buf, make_nonce(scheme)?)
+        ordered_encrypt(scheme, keys, self, &buf, aad)
 
     }
 }
@@ -138,10 +851,18 @@ impl Encrypt for u64 {
 impl Decrypt for i64 {
     type DecType = i64;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<i64, Box<ZeroError>> {
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<i64, Box<ZeroError>> {
         match scheme {
-            &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
-                let decrypted = decrypt(key, value)?;
+            &EncryptionType::Paillier => {
+                let (negative, m) = paillier_decrypt(keys, value)?;
+                match (negative, m.to_u64()) {
+                    (false, Some(v)) if v <= i64::max_value() as u64 => Ok(v as i64),
+                    (true, Some(v)) if v <= 1 << 63 => Ok(0u64.wrapping_sub(v) as i64),
+                    _ => Err(out_of_range())
+                }
+            },
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv | &EncryptionType::Ope => {
+                let decrypted = scheme_decrypt(scheme, keys, value, aad)?;
                 Ok(Cursor::new(decrypted).read_i64::<BigEndian>().unwrap())
             },
             _ => Err(ZeroError::DecryptionError{message: format!("Decryption not supported {:?}", scheme), code: "123".into()}.into())
@@ -151,11 +872,14 @@ impl Decrypt for i64 {
 
 impl Encrypt for i64 {
 
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
 
+        if *scheme == EncryptionType::Paillier {
+            return paillier_encrypt(keys, self < 0, Nat::from_u64(if self < 0 { 0u64.wrapping_sub(self as u64) } else { self as u64 }))
+        }
         let mut buf: Vec<u8> = Vec::new();
         buf.write_i64::<BigEndian>(self).unwrap();
-        encrypt(key, &buf, make_nonce(scheme)?)
+        ordered_encrypt(scheme, keys, order_i64(self), &buf, aad)
 
     }
 }
@@ -163,10 +887,10 @@ impl Encrypt for i64 {
 impl Decrypt for f64 {
     type DecType = f64;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<f64, Box<ZeroError>> {
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<f64, Box<ZeroError>> {
         match scheme {
-            &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
-                let decrypted = decrypt(key, value)?;
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv | &EncryptionType::Ope => {
+                let decrypted = scheme_decrypt(scheme, keys, value, aad)?;
                 Ok(Cursor::new(decrypted).read_f64::<BigEndian>().unwrap())
             },
             _ => Err(ZeroError::DecryptionError{message: format!("Decryption not supported {:?}", scheme), code: "123".into()}.into())
@@ -176,11 +900,11 @@ impl Decrypt for f64 {
 
 impl Encrypt for f64 {
 
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
 
         let mut buf: Vec<u8> = Vec::new();
         buf.write_f64::<BigEndian>(self).unwrap();
-        encrypt(key, &buf, make_nonce(scheme)?)
+        ordered_encrypt(scheme, keys, order_f64(self), &buf, aad)
 
     }
 }
@@ -188,10 +912,14 @@ impl Encrypt for f64 {
 impl Decrypt for d128 {
     type DecType = d128;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<d128, Box<ZeroError>> {
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<d128, Box<ZeroError>> {
         match scheme {
-            &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
-                let decrypted = decrypt(key, value)?;
+            &EncryptionType::Paillier => {
+                let (negative, m) = paillier_decrypt(keys, value)?;
+                Ok(paillier_decimal(negative, &m, PAILLIER_SCALE).0)
+            },
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv | &EncryptionType::Ope => {
+                let decrypted = scheme_decrypt(scheme, keys, value, aad)?;
 
                 let hex_str = decrypted.iter().rev()
                     .map(|b| format!("{:02x}", b))
@@ -208,7 +936,11 @@ impl Decrypt for d128 {
 impl Encrypt for d128 {
 
 
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+        if *scheme == EncryptionType::Paillier {
+            let (negative, magnitude) = paillier_scaled(self)?;
+            return paillier_encrypt(keys, negative, magnitude)
+        }
         // decimal does not expose underlying bytes.
         // get hex string, convert to bytes and encrypt
         let hex = format!("{:x}", self);
@@ -227,7 +959,12 @@ impl Encrypt for d128 {
                 })
             }
 
-            encrypt(key, &bytes, make_nonce(scheme)?)
+            // ordered as a double, so only the leading 15 significant digits are ordered exactly
+            let order = match format!("{}", self).parse::<f64>() {
+                Ok(f) => order_f64(f),
+                Err(_) => return Err(ZeroError::EncryptionError{message: format!("Cannot order decimal {}", self), code: "123".into()}.into())
+            };
+            ordered_encrypt(scheme, keys, order, &bytes, aad)
         }
 
     }
@@ -236,10 +973,18 @@ impl Encrypt for d128 {
 impl Decrypt for String {
     type DecType = String;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<String, Box<ZeroError>>{
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<String, Box<ZeroError>>{
         match scheme {
-            &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
-                let decrypted = decrypt(key, value)?;
+            // the format has no room for a key version, so only the current key is used
+            &EncryptionType::Fpe(ref format) => match String::from_utf8(value.to_vec()) {
+                Ok(v) => format.decrypt(keys.current().1, aad, &v),
+                Err(_) => Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+            },
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv | &EncryptionType::Ope => {
+                let mut decrypted = scheme_decrypt(scheme, keys, value, aad)?;
+                if is_padded(scheme, value) {
+                    decrypted = unpad(decrypted)?;
+                }
                 Ok(String::from_utf8(decrypted).expect("Invalid UTF-8"))
 
             },
@@ -249,20 +994,40 @@ impl Decrypt for String {
 }
 
 impl Encrypt for String {
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+        if let &EncryptionType::Fpe(ref format) = scheme {
+            return Ok(format.encrypt(keys.current().1, aad, &self)?.into_bytes())
+        }
         let buf = self.as_bytes();
-        encrypt(key, &buf, make_nonce(scheme)?)
+        scheme_encrypt(scheme, keys, &buf, aad)
+    }
+}
+
+impl Decrypt for Vec<u8> {
+    type DecType = Vec<u8>;
+
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>>{
+        match scheme {
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv => scheme_decrypt(scheme, keys, value, aad),
+            _ => Err(ZeroError::DecryptionError{message: format!("Decryption not supported {:?}", scheme), code: "123".into()}.into())
+        }
+    }
+}
+
+impl Encrypt for Vec<u8> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+        scheme_encrypt(scheme, keys, &self, aad)
     }
 }
 
 impl Decrypt for DateTime<UTC> {
     type DecType = DateTime<UTC>;
 
-    fn decrypt(value: &[u8], scheme: &EncryptionType, key: &[u8; 32]) -> Result<DateTime<UTC>, Box<ZeroError>>{
+    fn decrypt(value: &[u8], scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<DateTime<UTC>, Box<ZeroError>>{
         match scheme {
-            &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
+            &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv | &EncryptionType::Ope => {
 
-                let decrypted = decrypt(key, value)?;
+                let decrypted = scheme_decrypt(scheme, keys, value, aad)?;
                 let mut curs = Cursor::new(decrypted);
 
                 let timestamp = curs.read_i64::<BigEndian>().unwrap();
@@ -280,18 +1045,19 @@ impl Decrypt for DateTime<UTC> {
 
 impl<Tz: TimeZone> Encrypt for DateTime<Tz> {
 
-    fn encrypt(self, scheme: &EncryptionType, key: &[u8; 32]) -> Result<Vec<u8>, Box<ZeroError>> {
+    fn encrypt(self, scheme: &EncryptionType, keys: &KeyRing, aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
 
         let mut buf: Vec<u8> = Vec::new();
         // store fractional seconds alongside timestamp
         buf.write_i64::<BigEndian>(self.timestamp()).unwrap();
         buf.write_u32::<BigEndian>(self.timestamp_subsec_nanos()).unwrap();
-        encrypt(key, &buf, make_nonce(scheme)?)
+        let micros = self.timestamp().saturating_mul(1000000).saturating_add((self.timestamp_subsec_nanos() / 1000) as i64);
+        ordered_encrypt(scheme, keys, order_i64(micros), &buf, aad)
 
     }
 }
 
-pub fn hex_key(hex: &str) -> [u8; 32] {
+pub fn hex_key(hex: &str) -> SecretKey {
     let mut k = [0_u8; 32];
     let mut m = 0;
     let mut b = 0;
@@ -311,7 +1077,23 @@ pub fn hex_key(hex: &str) -> [u8; 32] {
         }
     }
 
-    k
+    SecretKey::new(k)
+}
+
+pub fn hex_decode(hex: &str) -> Result<Vec<u8>, Box<ZeroError>> {
+    if hex.len() % 2 != 0 {
+        return Err(ZeroError::EncryptionError{message: format!("Invalid hex string of length {}", hex.len()), code: "123".into()}.into())
+    }
+    hex.as_bytes().chunks(2).map(|pair| {
+        match ::std::str::from_utf8(pair).ok().and_then(|p| u8::from_str_radix(p, 16).ok()) {
+            Some(b) => Ok(b),
+            None => Err(ZeroError::EncryptionError{message: "Invalid hex string".into(), code: "123".into()}.into())
+        }
+    }).collect()
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join("")
 }
 
 pub fn hex_to_iv(hex: &str) -> [u8; 12] {
@@ -340,80 +1122,848 @@ pub fn hex_to_iv(hex: &str) -> [u8; 12] {
 pub fn make_nonce(scheme: &EncryptionType) -> Result<[u8; 12], Box<ZeroError>> {
     match scheme {
         &EncryptionType::Aes(ref iv) => Ok(iv.clone()),
-        &EncryptionType::AesGcm => Ok(gcm_nonce()),
+        &EncryptionType::AesGcm => gcm_nonce(),
+        // AES-SIV derives its iv from the plaintext
         _ => return Err(ZeroError::EncryptionError{message: format!("Encryption not supported {:?}", scheme), code: "123".into()}.into())
     }
 }
 
-// Create a random, unique nonce
-pub fn gcm_nonce() -> [u8; 12] {
-    let now = UTC::now();
-    let mut ts = now.timestamp();
-    let mut tn = now.nanosecond();
+// Create a random nonce from the OS CSPRNG, 96 random bits keep the collision
+// probability negligible well past the number of values a column key will ever protect
+pub fn gcm_nonce() -> Result<[u8; 12], Box<ZeroError>> {
     let mut nonce = [0u8; 12];
+    os_rng()?.fill_bytes(&mut nonce);
 
-    for i in 0..8 { nonce[i] = (ts & 0xff) as u8; ts >>= 4; }
-    for i in 0..4 { nonce[i + 8] = (tn & 0xff) as u8; tn >>= 8; }
-
-    nonce
+    Ok(nonce)
 }
 
-pub fn encrypt(key: &[u8], buf: &[u8], nonce: [u8; 12]) -> Result<Vec<u8>, Box<ZeroError>> {
-    let mut cipher = AesGcm::new(KeySize::KeySize256, key, &nonce, &[]);
+fn os_rng() -> Result<OsRng, Box<ZeroError>> {
+    match OsRng::new() {
+        Ok(rng) => Ok(rng),
+        Err(e) => Err(ZeroError::EncryptionError{message: format!("Failed to open OS random number generator: {}", e), code: "123".into()}.into())
+    }
+}
 
-    let mut tag = [0u8; 16];
-    let mut out: Vec<u8> = repeat(0).take(buf.len()).collect();
-    cipher.encrypt(&buf, &mut out, &mut tag);
-    debug!("encrypt: inp={:?} out={:?} tag={:?}", buf, out, tag);
+pub fn encrypt(key: &[u8], buf: &[u8], nonce: [u8; 12], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    let mut out = backend().aead_seal(key, &nonce, aad, buf);
 
-    let mut bs = Vec::with_capacity(12 + out.len() + 16);
-    for b in nonce.iter() { bs.push(*b); }
+    let mut bs = Vec::with_capacity(12 + out.len());
+    bs.extend_from_slice(&nonce);
     bs.append(&mut out);
-    for b in tag.iter() { bs.push(*b); }
     Ok(bs)
 }
 
-pub fn decrypt(key: &[u8], buf: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
-    if buf.len() < 12 {
+pub fn decrypt(key: &[u8], buf: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    if buf.len() < 28 {
         error!("ERROR: Buffer Length too short, are you trying to decrypt non-encrypted data?");
         return Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
     }
-    let iv: &[u8] = &buf[0..12];
-    let mut decipher = AesGcm::new(KeySize::KeySize256, &key, &iv, &[]);
-    let inp = &buf[12..buf.len() - 16];
-    let tag = &buf[buf.len() - 16..];
-    let mut out: Vec<u8> = repeat(0).take(buf.len() - 28).collect();
-    if decipher.decrypt(&inp, &mut out, &tag){
-        debug!("decrypt: inp={:?} out={:?}", inp, out);
-        Ok(out)
-    } else{
-        Err(ZeroError::DecryptionError{ message: "Failed decrypting data".into(), code: "123".into()}.into())
+    let mut iv = [0u8; 12];
+    iv.copy_from_slice(&buf[0..12]);
+    match backend().aead_open(key, &iv, aad, &buf[12..]) {
+        Some(out) => Ok(out),
+        None => Err(ZeroError::DecryptionError{ message: "Failed decrypting data".into(), code: "123".into()}.into())
     }
 }
 
-#[cfg(test)]
-mod test {
-
-    use super::*;
-    use chrono::*;
-
+// Encrypt with the newest key, prefixed by a header naming the scheme and key version.
+// The header is authenticated along with aad.
+pub fn scheme_encrypt(scheme: &EncryptionType, keys: &KeyRing, buf: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    if *scheme == EncryptionType::Ope {
+        return Err(ZeroError::EncryptionError{message: "OPE encryption requires a numeric or date value".into(), code: "123".into()}.into())
+    }
+    if *scheme == EncryptionType::Paillier {
+        return Err(ZeroError::EncryptionError{message: "Paillier encryption requires an integer or decimal value".into(), code: "123".into()}.into())
+    }
+    if let &EncryptionType::Fpe(_) = scheme {
+        return Err(ZeroError::EncryptionError{message: "Format preserving encryption requires a character value".into(), code: "123".into()}.into())
+    }
+    if *scheme == EncryptionType::AesGcm && buf.len() > STREAM_SEGMENT_LEN {
+        let (mut encryptor, mut out) = StreamEncryptor::new(keys, aad)?;
+        out.append(&mut encryptor.update(buf));
+        out.append(&mut encryptor.finish());
+        return Ok(out)
+    }
+    let (version, key) = keys.current();
+    if !keys.header() && scheme.is_deterministic() {
+        return cipher_encrypt(scheme, key, buf, aad)
+    }
+    let header = header(scheme, version);
+    let mut out = header.to_vec();
+    out.append(&mut cipher_encrypt(scheme, key, buf, &header_aad(&header, aad))?);
+    Ok(out)
+}
+
+// Encrypt a string padded to hide its length, padded values are never streamed so the
+// header can mark them as padded
+pub fn padded_encrypt(scheme: &EncryptionType, keys: &KeyRing, buf: &[u8], aad: &[u8], padding: &Padding) -> Result<Vec<u8>, Box<ZeroError>> {
+    if *scheme != EncryptionType::AesGcm {
+        return Err(ZeroError::EncryptionError{message: format!("Padding is not supported by encryption {:?}", scheme), code: "123".into()}.into())
+    }
+    let (version, key) = keys.current();
+    let mut header = header(scheme, version);
+    header[1] = PADDED_FORMAT;
+    let mut out = header.to_vec();
+    out.append(&mut cipher_encrypt(scheme, key, &padding.pad(buf), &header_aad(&header, aad))?);
+    Ok(out)
+}
+
+// Encrypt a value whose order is given by `order`, OPE prefixes the ciphertext with
+// a code that compares byte-wise in the same order
+pub fn ordered_encrypt(scheme: &EncryptionType, keys: &KeyRing, order: u64, buf: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    match *scheme {
+        EncryptionType::Ope => {
+            let (version, key) = keys.current();
+            let header = header(scheme, version);
+            let mut out = ope_code(key, order).to_vec();
+            out.extend_from_slice(&header);
+            out.append(&mut cipher_encrypt(scheme, key, buf, &header_aad(&header, aad))?);
+            Ok(out)
+        },
+        _ => scheme_encrypt(scheme, keys, buf, aad)
+    }
+}
+
+// Decrypt with the key version named in the header, values written before headers were
+// introduced are tried against each key, newest first
+pub fn scheme_decrypt(scheme: &EncryptionType, keys: &KeyRing, buf: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    if buf.len() < scheme.prefix_len() {
+        return Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+    }
+    let version = read_header(scheme, buf);
+    let buf = &buf[scheme.prefix_len()..];
+    let mut missing = None;
+    if let Some(version) = version {
+        match keys.get(version) {
+            Some(_) if is_stream(scheme, buf) => if let Ok(out) = stream_decrypt(keys, buf, aad) {
+                return Ok(out)
+            },
+            Some(key) => if let Ok(out) = cipher_decrypt(scheme, key, &buf[HEADER_LEN as usize..], &header_aad(&buf[0..HEADER_LEN as usize], aad)) {
+                return Ok(out)
+            },
+            None => missing = Some(version)
+        }
+    }
+
+    for version in keys.versions().into_iter().rev() {
+        if let Ok(out) = cipher_decrypt(scheme, keys.get(version).unwrap(), buf, aad) {
+            return Ok(out)
+        }
+    }
+
+    match missing {
+        Some(v) => Err(ZeroError::DecryptionError{message: format!("Failed decrypting data, key version {} is not configured", v), code: "123".into()}.into()),
+        None => Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+    }
+}
+
+fn stream_decrypt(keys: &KeyRing, buf: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    let mut decryptor = StreamDecryptor::new(keys, aad, buf.len());
+    let out = decryptor.update(buf)?;
+    decryptor.finish()?;
+    Ok(out)
+}
+
+/// Whether buf starts with the header of a value sealed as a stream of segments
+pub fn is_stream(scheme: &EncryptionType, buf: &[u8]) -> bool {
+    *scheme == EncryptionType::AesGcm && buf.len() >= 3 && buf[0] == HEADER_MAGIC && buf[1] == STREAM_FORMAT && buf[2] == scheme.id()
+}
+
+// whether buf starts with the header of a padded value
+fn is_padded(scheme: &EncryptionType, buf: &[u8]) -> bool {
+    *scheme == EncryptionType::AesGcm && buf.len() >= 3 && buf[0] == HEADER_MAGIC && buf[1] == PADDED_FORMAT && buf[2] == scheme.id()
+}
+
+/// Length of the plaintext of a stream of len bytes, or None when no stream has that length.
+/// Every segment but the last is full, so the length is known before any segment is opened.
+pub fn stream_plaintext_len(len: usize) -> Option<usize> {
+    let start = HEADER_LEN as usize + STREAM_PREFIX_LEN;
+    if len < start + TAG_LEN {
+        return None
+    }
+    let body = len - start;
+    let segment = STREAM_SEGMENT_LEN + TAG_LEN;
+    let segments = (body + segment - 1) / segment;
+    if body - (segments - 1) * segment < TAG_LEN {
+        None
+    } else {
+        Some(body - segments * TAG_LEN)
+    }
+}
+
+// Segment nonces are the random prefix of the value, the segment counter and a flag marking
+// the last segment, so segments cannot be reordered, dropped or truncated without detection
+fn segment_nonce(prefix: &[u8], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[0..STREAM_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[7] = (counter >> 24) as u8;
+    nonce[8] = (counter >> 16) as u8;
+    nonce[9] = (counter >> 8) as u8;
+    nonce[10] = counter as u8;
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Seals a value as a stream of segments: a header, a random nonce prefix and segments of
+/// STREAM_SEGMENT_LEN bytes of ciphertext each followed by its tag. The header is
+/// authenticated along with aad by every segment.
+pub struct StreamEncryptor {
+    key: SecretKey,
+    prefix: [u8; STREAM_PREFIX_LEN],
+    aad: Vec<u8>,
+    counter: u32,
+    pending: Vec<u8>
+}
+
+impl StreamEncryptor {
+    /// The encryptor and the header to write before its segments
+    pub fn new(keys: &KeyRing, aad: &[u8]) -> Result<(StreamEncryptor, Vec<u8>), Box<ZeroError>> {
+        let (version, key) = keys.current();
+        let mut header = header(&EncryptionType::AesGcm, version).to_vec();
+        header[1] = STREAM_FORMAT;
+        let mut prefix = [0u8; STREAM_PREFIX_LEN];
+        os_rng()?.fill_bytes(&mut prefix);
+
+        let encryptor = StreamEncryptor {
+            key: key.clone(),
+            prefix: prefix,
+            aad: header_aad(&header, aad),
+            counter: 0,
+            pending: Vec::with_capacity(STREAM_SEGMENT_LEN)
+        };
+        header.extend_from_slice(&prefix);
+        Ok((encryptor, header))
+    }
+
+    /// The segments completed by buf. A full segment is held back until more follows,
+    /// since only the last segment is sealed as last.
+    pub fn update(&mut self, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            if self.pending.len() == STREAM_SEGMENT_LEN {
+                let segment = self.seal(false);
+                out.extend_from_slice(&segment);
+            }
+            let n = cmp::min(STREAM_SEGMENT_LEN - self.pending.len(), buf.len() - pos);
+            self.pending.extend_from_slice(&buf[pos..pos + n]);
+            pos += n;
+        }
+        out
+    }
+
+    /// The last segment
+    pub fn finish(mut self) -> Vec<u8> {
+        self.seal(true)
+    }
+
+    fn seal(&mut self, last: bool) -> Vec<u8> {
+        let nonce = segment_nonce(&self.prefix, self.counter, last);
+        let out = backend().aead_seal(&self.key, &nonce, &self.aad, &self.pending);
+        self.counter += 1;
+        self.pending.clear();
+        out
+    }
+}
+
+/// Opens a stream of a known length as its bytes arrive, holding at most one segment
+pub struct StreamDecryptor {
+    keys: KeyRing,
+    aad: Vec<u8>,
+    // ciphertext bytes not yet received
+    remaining: usize,
+    header: Vec<u8>,
+    key: Option<SecretKey>,
+    counter: u32,
+    pending: Vec<u8>,
+    done: bool
+}
+
+impl StreamDecryptor {
+    pub fn new(keys: &KeyRing, aad: &[u8], len: usize) -> StreamDecryptor {
+        StreamDecryptor {
+            keys: keys.clone(),
+            aad: aad.to_vec(),
+            remaining: len,
+            header: Vec::with_capacity(HEADER_LEN as usize + STREAM_PREFIX_LEN),
+            key: None,
+            counter: 0,
+            pending: Vec::new(),
+            done: false
+        }
+    }
+
+    /// The plaintext of the segments completed by buf
+    pub fn update(&mut self, buf: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+        if buf.len() > self.remaining {
+            return Err(ZeroError::DecryptionError{message: "Failed decrypting data, stream is longer than expected".into(), code: "123".into()}.into())
+        }
+        let start = HEADER_LEN as usize + STREAM_PREFIX_LEN;
+        let segment = STREAM_SEGMENT_LEN + TAG_LEN;
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let n = if self.key.is_none() {
+                let n = cmp::min(start - self.header.len(), buf.len() - pos);
+                self.header.extend_from_slice(&buf[pos..pos + n]);
+                n
+            } else {
+                let n = cmp::min(segment - self.pending.len(), buf.len() - pos);
+                self.pending.extend_from_slice(&buf[pos..pos + n]);
+                n
+            };
+            pos += n;
+            self.remaining -= n;
+
+            if self.key.is_none() && self.header.len() == start {
+                self.key = Some(self.stream_key()?);
+            }
+            if self.key.is_some() && (self.pending.len() == segment || (self.remaining == 0 && self.pending.len() >= TAG_LEN)) {
+                let last = self.remaining == 0;
+                out.append(&mut self.open(last)?);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Checks the whole stream was received
+    pub fn finish(&self) -> Result<(), Box<ZeroError>> {
+        if !self.done {
+            return Err(ZeroError::DecryptionError{message: "Failed decrypting data, stream is truncated".into(), code: "123".into()}.into())
+        }
+        Ok(())
+    }
+
+    fn stream_key(&self) -> Result<SecretKey, Box<ZeroError>> {
+        let header = &self.header[0..HEADER_LEN as usize];
+        if !is_stream(&EncryptionType::AesGcm, header) {
+            return Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+        }
+        let version = ((header[3] as u16) << 8) | header[4] as u16;
+        match self.keys.get(version) {
+            Some(key) => Ok(key.clone()),
+            None => Err(ZeroError::DecryptionError{message: format!("Failed decrypting data, key version {} is not configured", version), code: "123".into()}.into())
+        }
+    }
+
+    fn open(&mut self, last: bool) -> Result<Vec<u8>, Box<ZeroError>> {
+        let nonce = segment_nonce(&self.header[HEADER_LEN as usize..], self.counter, last);
+        let aad = header_aad(&self.header[0..HEADER_LEN as usize], &self.aad);
+        let out = match backend().aead_open(self.key.as_ref().unwrap(), &nonce, &aad, &self.pending) {
+            Some(out) => out,
+            None => return Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+        };
+        self.counter += 1;
+        self.pending.clear();
+        self.done = last;
+        Ok(out)
+    }
+}
+
+// Paillier ciphertexts are a header followed by the value under the key derived from the
+// column key it names. They are randomized and not bound to aad, which would stop them adding.
+fn paillier_encrypt(keys: &KeyRing, negative: bool, magnitude: Nat) -> Result<Vec<u8>, Box<ZeroError>> {
+    let (version, key) = keys.current();
+    let key = paillier::key(key, PAILLIER_BITS);
+    let c = key.encrypt(&key.encode(negative, magnitude), &mut os_rng()?);
+    let mut out = header(&EncryptionType::Paillier, version).to_vec();
+    out.append(&mut c.to_bytes(key.ciphertext_len()));
+    Ok(out)
+}
+
+fn paillier_ciphertext(keys: &KeyRing, buf: &[u8]) -> Result<(u16, Arc<PaillierKey>, Nat), Box<ZeroError>> {
+    let version = match read_header(&EncryptionType::Paillier, buf) {
+        Some(v) => v,
+        None => return Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+    };
+    let key = match keys.get(version) {
+        Some(key) => paillier::key(key, PAILLIER_BITS),
+        None => return Err(ZeroError::DecryptionError{message: format!("Failed decrypting data, key version {} is not configured", version), code: "123".into()}.into())
+    };
+    match key.ciphertext(&buf[HEADER_LEN as usize..]) {
+        Some(c) => Ok((version, key, c)),
+        None => Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+    }
+}
+
+// sign and magnitude
+fn paillier_decrypt(keys: &KeyRing, buf: &[u8]) -> Result<(bool, Nat), Box<ZeroError>> {
+    let (_, key, c) = paillier_ciphertext(keys, buf)?;
+    Ok(key.decode(key.decrypt(&c)))
+}
+
+// sign and magnitude of a decimal scaled to an integer, decimals with more places are refused
+// rather than rounded so a sum is always the sum of the stored values
+fn paillier_scaled(value: d128) -> Result<(bool, Nat), Box<ZeroError>> {
+    let scaled = value.scaleb(d128::from(PAILLIER_SCALE as i32));
+    let integer = scaled.quantize(d128::zero());
+    if !integer.is_finite() || integer != scaled {
+        return Err(ZeroError::EncryptionError{
+            message: format!("Paillier encryption supports decimals of at most {} places, got {}", PAILLIER_SCALE, value),
+            code: "123".into()
+        }.into())
+    }
+    let digits = format!("{}", integer.abs());
+    match Nat::from_decimal(&digits) {
+        Some(m) => Ok((integer.is_negative(), m)),
+        None => Err(ZeroError::EncryptionError{message: format!("Failed to encrypt {}", value), code: "123".into()}.into())
+    }
+}
+
+// the decimal m / 10^scale and its number of places, without trailing zeros
+fn paillier_decimal(negative: bool, m: &Nat, scale: usize) -> (d128, usize) {
+    let mut digits = m.to_decimal();
+    while digits.len() <= scale {
+        digits.insert(0, '0');
+    }
+    let (int, frac) = digits.split_at(digits.len() - scale);
+    let frac = frac.trim_right_matches('0');
+    let sign = if negative && !m.is_zero() { "-" } else { "" };
+    let s = if frac.is_empty() { format!("{}{}", sign, int) } else { format!("{}{}.{}", sign, int, frac) };
+    (d128::from_str(&s).unwrap(), frac.len())
+}
+
+fn out_of_range() -> Box<ZeroError> {
+    ZeroError::DecryptionError{message: "Decrypted value is out of range for its type".into(), code: "123".into()}.into()
+}
+
+/// Sum of the Paillier ciphertexts of a column, folded without decrypting them. Ciphertexts
+/// under each key version are multiplied together and each product is decrypted once.
+pub struct PaillierSum {
+    keys: KeyRing,
+    sums: Vec<(u16, Accumulator)>,
+    count: u64
+}
+
+impl PaillierSum {
+    pub fn new(keys: &KeyRing) -> PaillierSum {
+        PaillierSum{keys: keys.clone(), sums: Vec::new(), count: 0}
+    }
+
+    pub fn add(&mut self, value: &[u8]) -> Result<(), Box<ZeroError>> {
+        let (version, key, c) = paillier_ciphertext(&self.keys, value)?;
+        let pos = self.sums.iter().position(|s| s.0 == version);
+        let pos = match pos {
+            Some(pos) => pos,
+            None => {
+                self.sums.push((version, Accumulator::new(key)));
+                self.sums.len() - 1
+            }
+        };
+        self.sums[pos].1.add(&c);
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The sum, or None when no values were added, as SQL's SUM
+    pub fn total(&self, native_type: &NativeType) -> Option<d128> {
+        self.total_places(native_type).map(|t| t.0)
+    }
+
+    /// The mean to four more places than the sum has, as SQL's AVG
+    pub fn average(&self, native_type: &NativeType) -> Option<d128> {
+        self.total_places(native_type).map(|(total, places)| {
+            let count = d128::from_str(&self.count.to_string()).unwrap();
+            (total / count).quantize(d128::from_str(&format!("1E-{}", places + 4)).unwrap())
+        })
+    }
+
+    fn total_places(&self, native_type: &NativeType) -> Option<(d128, usize)> {
+        if self.count == 0 {
+            return None
+        }
+        let scale = match *native_type {
+            NativeType::D128 => PAILLIER_SCALE,
+            _ => 0
+        };
+        Some(self.sums.iter().fold((d128::zero(), 0), |(total, places), &(_, ref acc)| {
+            let key = acc.key();
+            let (negative, m) = key.decode(key.decrypt(&acc.sum()));
+            let (sum, p) = paillier_decimal(negative, &m, scale);
+            (total + sum, cmp::max(places, p))
+        }))
+    }
+}
+
+fn header(scheme: &EncryptionType, version: u16) -> [u8; 5] {
+    [HEADER_MAGIC, HEADER_FORMAT, scheme.id(), (version >> 8) as u8, version as u8]
+}
+
+// the key version, if buf starts with a header for this scheme
+pub fn read_header(scheme: &EncryptionType, buf: &[u8]) -> Option<u16> {
+    let buf = &buf[::std::cmp::min(scheme.prefix_len(), buf.len())..];
+    if buf.len() > HEADER_LEN as usize && buf[0] == HEADER_MAGIC && (buf[1] == HEADER_FORMAT || buf[1] == STREAM_FORMAT || buf[1] == PADDED_FORMAT) && buf[2] == scheme.id() {
+        Some(((buf[3] as u16) << 8) | buf[4] as u16)
+    } else {
+        None
+    }
+}
+
+fn header_aad(header: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut out = header.to_vec();
+    out.extend_from_slice(aad);
+    out
+}
+
+fn cipher_encrypt(scheme: &EncryptionType, key: &[u8], buf: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    match scheme {
+        &EncryptionType::AesSiv | &EncryptionType::Ope => siv_encrypt(key, &siv_ad(aad), buf),
+        _ => encrypt(key, buf, make_nonce(scheme)?, aad)
+    }
+}
+
+fn cipher_decrypt(scheme: &EncryptionType, key: &[u8], buf: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    match scheme {
+        &EncryptionType::AesSiv | &EncryptionType::Ope => siv_decrypt(key, &siv_ad(aad), buf),
+        _ => decrypt(key, buf, aad)
+    }
+}
+
+const OPE_LEN: u32 = 16;
+
+// maps signed values onto u64 keeping their order
+fn order_i64(x: i64) -> u64 {
+    (x as u64) ^ (1 << 63)
+}
+
+fn order_f64(x: f64) -> u64 {
+    let bits: u64 = unsafe { mem::transmute(x) };
+    if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) }
+}
+
+// Order preserving encryption of a u64 into a 128 bit code. The domain is halved at each
+// level while a split point for the range is chosen by a keyed PRF, leaving each half of
+// the range at least as large as its half of the domain, so distinct values never collide
+// and codes compare in the same order as values. Like all OPE it reveals order and
+// approximate distance, so it should only protect columns where that is acceptable.
+pub fn ope_code(key: &[u8], x: u64) -> [u8; 16] {
+    let cipher = backend().block_cipher(&SecretKey::new(backend().hmac_sha256(key, &[b"zero-ope"])));
+
+    let (mut dlo, mut dhi) = (0u64, !0u64);
+    let (mut rlo, mut rhi) = (U128(0, 0), U128(!0, !0));
+    while dlo < dhi {
+        let mid = dlo + (dhi - dlo) / 2;
+        // the left range must fit [dlo, mid] and the right range (mid, dhi]
+        let a = rlo.add(U128(0, mid - dlo));
+        let b = rhi.sub(U128(0, dhi - mid));
+        let w = b.sub(a);
+        let split = a.add(w.shr(2)).add(ope_prf(&*cipher, dlo, dhi).and(w.shr(1).mask()));
+        if x <= mid {
+            dhi = mid;
+            rhi = split;
+        } else {
+            dlo = mid + 1;
+            rlo = split.add(U128(0, 1));
+        }
+    }
+    rlo.add(ope_prf(&*cipher, x, x).and(rhi.sub(rlo).mask())).to_bytes()
+}
+
+fn ope_prf(cipher: &BlockCipher, lo: u64, hi: u64) -> U128 {
+    let mut block = [0u8; 16];
+    block[..8].copy_from_slice(&U128(0, lo).to_bytes()[8..]);
+    block[8..].copy_from_slice(&U128(0, hi).to_bytes()[8..]);
+    U128::from_bytes(&cipher.encrypt_block(&block))
+}
+
+// just enough unsigned 128 bit arithmetic for ope_code, (high, low)
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+struct U128(u64, u64);
+
+impl U128 {
+    fn add(self, o: U128) -> U128 {
+        let (lo, carry) = self.1.overflowing_add(o.1);
+        U128(self.0.wrapping_add(o.0).wrapping_add(carry as u64), lo)
+    }
+
+    fn sub(self, o: U128) -> U128 {
+        let (lo, borrow) = self.1.overflowing_sub(o.1);
+        U128(self.0.wrapping_sub(o.0).wrapping_sub(borrow as u64), lo)
+    }
+
+    fn shr(self, n: u32) -> U128 {
+        U128(self.0 >> n, (self.1 >> n) | (self.0 << (64 - n)))
+    }
+
+    fn and(self, o: U128) -> U128 {
+        U128(self.0 & o.0, self.1 & o.1)
+    }
+
+    // all bits below the highest set bit, so always less than self unless zero
+    fn mask(self) -> U128 {
+        let bits = if self.0 != 0 { 128 - self.0.leading_zeros() } else { 64 - self.1.leading_zeros() };
+        let ones = |n: u32| if n == 0 { 0 } else { !0u64 >> (64 - n) };
+        match bits {
+            0 => U128(0, 0),
+            b if b - 1 >= 64 => U128(ones(b - 1 - 64), !0),
+            b => U128(0, ones(b - 1))
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut out = [0u8; 16];
+        for i in 0..8 {
+            out[i] = (self.0 >> (56 - i * 8)) as u8;
+            out[i + 8] = (self.1 >> (56 - i * 8)) as u8;
+        }
+        out
+    }
+
+    fn from_bytes(b: &[u8]) -> U128 {
+        let (mut hi, mut lo) = (0u64, 0u64);
+        for i in 0..8 {
+            hi = (hi << 8) | b[i] as u64;
+            lo = (lo << 8) | b[i + 8] as u64;
+        }
+        U128(hi, lo)
+    }
+}
+
+// An empty aad is no associated data at all, so values written before binding still decrypt
+fn siv_ad(aad: &[u8]) -> Vec<&[u8]> {
+    if aad.is_empty() {
+        vec![]
+    } else {
+        vec![aad]
+    }
+}
+
+// AES-SIV (RFC 5297) with a 256 bit key, the left half keys S2V and the right half keys CTR.
+// The same plaintext and associated data always produce the same ciphertext, so columns
+// keep equality search without ever reusing a nonce.
+pub fn siv_encrypt(key: &[u8], ad: &[&[u8]], buf: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    if key.len() != 32 {
+        return Err(ZeroError::EncryptionError{message: format!("AES-SIV requires a 256 bit key, received {} bytes", key.len()), code: "123".into()}.into())
+    }
+    let v = s2v(&key[0..16], ad, buf);
+
+    let mut bs = Vec::with_capacity(16 + buf.len());
+    bs.extend_from_slice(&v);
+    bs.append(&mut siv_ctr(&key[16..32], &v, buf));
+    Ok(bs)
+}
+
+pub fn siv_decrypt(key: &[u8], ad: &[&[u8]], buf: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    if key.len() != 32 {
+        return Err(ZeroError::DecryptionError{message: format!("AES-SIV requires a 256 bit key, received {} bytes", key.len()), code: "123".into()}.into())
+    }
+    if buf.len() < 16 {
+        error!("ERROR: Buffer Length too short, are you trying to decrypt non-encrypted data?");
+        return Err(ZeroError::DecryptionError{message: "Failed decrypting data".into(), code: "123".into()}.into())
+    }
+    let v = &buf[0..16];
+    let out = siv_ctr(&key[16..32], v, &buf[16..]);
+
+    if fixed_time_eq(v, &s2v(&key[0..16], ad, &out)) {
+        Ok(out)
+    } else {
+        Err(ZeroError::DecryptionError{ message: "Failed decrypting data".into(), code: "123".into()}.into())
+    }
+}
+
+fn s2v(key: &[u8], ad: &[&[u8]], buf: &[u8]) -> [u8; 16] {
+    let cipher = backend().block_cipher(key);
+
+    let mut d = cmac(&*cipher, &[0u8; 16]);
+    for a in ad.iter() {
+        d = dbl(&d);
+        xor_in(&mut d, &cmac(&*cipher, a));
+    }
+
+    let t = if buf.len() >= 16 {
+        let mut t = buf.to_vec();
+        let start = buf.len() - 16;
+        xor_in(&mut t[start..], &d);
+        t
+    } else {
+        let mut t = dbl(&d).to_vec();
+        let mut padded = [0u8; 16];
+        padded[..buf.len()].copy_from_slice(buf);
+        padded[buf.len()] = 0x80;
+        xor_in(&mut t, &padded);
+        t
+    };
+
+    cmac(&*cipher, &t)
+}
+
+fn siv_ctr(key: &[u8], v: &[u8], buf: &[u8]) -> Vec<u8> {
+    let cipher = backend().block_cipher(key);
+
+    // clear the 31st and 63rd bits (from the right) so implementations can use 32 bit counters
+    let mut ctr = [0u8; 16];
+    ctr.copy_from_slice(v);
+    ctr[8] &= 0x7f;
+    ctr[12] &= 0x7f;
+
+    let mut out = Vec::with_capacity(buf.len());
+    for chunk in buf.chunks(16) {
+        let ks = cipher.encrypt_block(&ctr);
+        for (b, k) in chunk.iter().zip(ks.iter()) {
+            out.push(b ^ k);
+        }
+        for i in (0..16).rev() {
+            ctr[i] = ctr[i].wrapping_add(1);
+            if ctr[i] != 0 { break; }
+        }
+    }
+    out
+}
+
+fn cmac(cipher: &BlockCipher, msg: &[u8]) -> [u8; 16] {
+    let l = cipher.encrypt_block(&[0u8; 16]);
+    let k1 = dbl(&l);
+    let k2 = dbl(&k1);
+
+    let n = if msg.is_empty() { 1 } else { (msg.len() + 15) / 16 };
+    let mut x = [0u8; 16];
+    for i in 0..n {
+        let block = &msg[i * 16..::std::cmp::min(msg.len(), (i + 1) * 16)];
+        let mut m = [0u8; 16];
+        m[..block.len()].copy_from_slice(block);
+        if i == n - 1 {
+            if block.len() == 16 {
+                xor_in(&mut m, &k1);
+            } else {
+                m[block.len()] = 0x80;
+                xor_in(&mut m, &k2);
+            }
+        }
+        xor_in(&mut x, &m);
+        x = cipher.encrypt_block(&x);
+    }
+    x
+}
+
+// doubling in GF(2^128)
+fn dbl(b: &[u8; 16]) -> [u8; 16] {
+    let mut out = [0u8; 16];
+    for i in 0..15 {
+        out[i] = (b[i] << 1) | (b[i + 1] >> 7);
+    }
+    out[15] = b[15] << 1;
+    if b[0] & 0x80 != 0 {
+        out[15] ^= 0x87;
+    }
+    out
+}
+
+// FF1 from NIST SP 800-38G with AES-256, a Feistel network of ten rounds over the numerals
+// of a radix. Decryption runs the rounds backwards.
+fn ff1(key: &[u8], tweak: &[u8], radix: u32, x: &[u32], encrypt: bool) -> Vec<u32> {
+    let cipher = backend().block_cipher(key);
+    let n = x.len();
+    let u = n / 2;
+    let v = n - u;
+    let (mut a, mut b) = (x[..u].to_vec(), x[u..].to_vec());
+    let (radix_u, radix_v) = (ff1_pow(radix, u), ff1_pow(radix, v));
+    // bytes for a numeral string of length v, and bytes of the round function output
+    let b_len = (radix_v.sub(&Nat::from_u64(1)).bits() + 7) / 8;
+    let d = 4 * ((b_len + 3) / 4) + 4;
+
+    let mut p = vec![1, 2, 1, (radix >> 16) as u8, (radix >> 8) as u8, radix as u8, 10, u as u8];
+    p.write_u32::<BigEndian>(n as u32).unwrap();
+    p.write_u32::<BigEndian>(tweak.len() as u32).unwrap();
+
+    for round in 0..10 {
+        let i = if encrypt { round } else { 9 - round };
+        let mut q = tweak.to_vec();
+        q.extend(repeat(0).take((16 - (tweak.len() + b_len + 1) % 16) % 16));
+        q.push(i as u8);
+        q.append(&mut ff1_num(radix, if encrypt { &b } else { &a }).to_bytes(b_len));
+
+        // CBC-MAC of P || Q, extended by encrypting it xor a counter
+        let mut r = [0u8; 16];
+        for block in p.chunks(16).chain(q.chunks(16)) {
+            xor_in(&mut r, block);
+            r = cipher.encrypt_block(&r);
+        }
+        let mut s = r.to_vec();
+        let mut j = 1u64;
+        while s.len() < d {
+            let mut input = r;
+            xor_in(&mut input[8..], &Nat::from_u64(j).to_bytes(8));
+            s.extend_from_slice(&cipher.encrypt_block(&input));
+            j += 1;
+        }
+        let y = Nat::from_bytes(&s[..d]);
+
+        let (m, modulus) = if i % 2 == 0 { (u, &radix_u) } else { (v, &radix_v) };
+        if encrypt {
+            let c = ff1_num(radix, &a).add(&y).rem(modulus);
+            a = b;
+            b = ff1_str(radix, m, c);
+        } else {
+            let (y, num_b) = (y.rem(modulus), ff1_num(radix, &b));
+            let c = if num_b >= y { num_b.sub(&y) } else { num_b.add(modulus).sub(&y) };
+            b = a;
+            a = ff1_str(radix, m, c);
+        }
+    }
+    a.append(&mut b);
+    a
+}
+
+fn ff1_pow(radix: u32, e: usize) -> Nat {
+    (0..e).fold(Nat::from_u64(1), |acc, _| acc.mul_add_small(radix, 0))
+}
+
+// the number a numeral string represents, most significant numeral first
+fn ff1_num(radix: u32, x: &[u32]) -> Nat {
+    x.iter().fold(Nat::zero(), |acc, d| acc.mul_add_small(radix, *d))
+}
+
+// the numeral string of length m representing c
+fn ff1_str(radix: u32, m: usize, c: Nat) -> Vec<u32> {
+    let mut out = vec![0; m];
+    let mut c = c;
+    for i in (0..m).rev() {
+        let (q, r) = c.divrem_small(radix);
+        out[i] = r;
+        c = q;
+    }
+    out
+}
+
+fn xor_in(a: &mut [u8], b: &[u8]) {
+    for (x, y) in a.iter_mut().zip(b.iter()) {
+        *x ^= *y;
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use chrono::*;
+
     use decimal::*;
     use std::str::FromStr;
+    use std::collections::HashSet;
 
     #[test]
     fn test_encrypt_u64() {
         let value = 12345_u64;
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
-        let encrypted = value.encrypt(&enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
 
-        let decrypted = u64::decrypt(&encrypted, &enc, &key).unwrap();
+        let decrypted = u64::decrypt(&encrypted, &enc, &key, &[]).unwrap();
 
         assert_eq!(decrypted, value);
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = value.encrypt(&enc, &key).unwrap();
-        let decrypted = u64::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = u64::decrypt(&encrypted, &enc, &key, &[]).unwrap();
+        assert_eq!(decrypted, value);
+
+        let enc = EncryptionType::AesSiv;
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = u64::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, value);
 
     }
@@ -421,63 +1971,68 @@ mod test {
     #[test]
     fn test_encrypt_i64() {
         let value = -12345_i64;
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
-        let encrypted = value.encrypt(&enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
 
-        let decrypted = i64::decrypt(&encrypted, &enc, &key).unwrap();
+        let decrypted = i64::decrypt(&encrypted, &enc, &key, &[]).unwrap();
 
         assert_eq!(decrypted, value);
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = value.encrypt(&enc, &key).unwrap();
-        let decrypted = i64::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = i64::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, value);
     }
 
     #[test]
     fn test_encrypt_string() {
         let value = String::from("Ima a sensitive string...");
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
-        let encrypted = value.clone().encrypt(&enc, &key).unwrap();
+        let encrypted = value.clone().encrypt(&enc, &key, &[]).unwrap();
 
-        let decrypted = String::decrypt(&encrypted, &enc, &key).unwrap();
+        let decrypted = String::decrypt(&encrypted, &enc, &key, &[]).unwrap();
 
         assert_eq!(decrypted, value.clone());
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = value.clone().encrypt(&enc, &key).unwrap();
-        let decrypted = String::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = value.clone().encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = String::decrypt(&encrypted, &enc, &key, &[]).unwrap();
+        assert_eq!(decrypted, value.clone());
+
+        let enc = EncryptionType::AesSiv;
+        let encrypted = value.clone().encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = String::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, value.clone());
     }
 
     #[test]
     fn test_encrypt_f64() {
         let value = 12345.6789_f64;
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
-        let encrypted = value.encrypt(&enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
 
-        let decrypted = f64::decrypt(&encrypted, &enc, &key).unwrap();
+        let decrypted = f64::decrypt(&encrypted, &enc, &key, &[]).unwrap();
 
         assert_eq!(decrypted, value);
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = value.encrypt(&enc, &key).unwrap();
-        let decrypted = f64::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = f64::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, value);
     }
 
     #[test]
     fn test_encrypt_datetime() {
         let value = String::from("2015-01-24 15:22:06");
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
         let datetime = UTC.datetime_from_str(&value, "%Y-%m-%d %H:%M:%S").unwrap();
 
-        let encrypted = datetime.encrypt(&enc, &key).unwrap();
-        let decrypted = DateTime::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = datetime.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = DateTime::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, datetime);
 
         let rewritten = decrypted.format("%Y-%m-%d %H:%M:%S").to_string();
@@ -486,8 +2041,8 @@ mod test {
 
         let value = String::from("2015-01-24 15:22:06");
         let enc = EncryptionType::AesGcm;
-        let encrypted = datetime.encrypt(&enc, &key).unwrap();
-        let decrypted = DateTime::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = datetime.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = DateTime::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, datetime);
         let rewritten = decrypted.format("%Y-%m-%d %H:%M:%S").to_string();
         assert_eq!(rewritten, value);
@@ -496,12 +2051,12 @@ mod test {
     #[test]
     fn test_encrypt_datetime_fsp() {
         let value = String::from("2014-11-28 21:00:09.778");
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
         let datetime = UTC.datetime_from_str(&value, "%Y-%m-%d %H:%M:%S%.f").unwrap();
 
-        let encrypted = datetime.encrypt(&enc, &key).unwrap();
-        let decrypted = DateTime::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = datetime.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = DateTime::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, datetime);
 
         let rewritten = decrypted.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
@@ -511,8 +2066,8 @@ mod test {
         let value = String::from("2014-11-28 21:00:09.778");
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = datetime.encrypt(&enc, &key).unwrap();
-        let decrypted = DateTime::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = datetime.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = DateTime::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, datetime);
         let rewritten = decrypted.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
         assert_eq!(rewritten, value);
@@ -521,12 +2076,12 @@ mod test {
     #[test]
     fn test_encrypt_date() {
         let value = String::from("2016-09-15");
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
         let datetime = UTC.datetime_from_str(&format!("{} 00:00:00",&value), "%Y-%m-%d %H:%M:%S").unwrap();
 
-        let encrypted = datetime.encrypt(&enc, &key).unwrap();
-        let decrypted = DateTime::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = datetime.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = DateTime::decrypt(&encrypted, &enc, &key, &[]).unwrap();
 
         assert_eq!(decrypted, datetime);
 
@@ -537,27 +2092,116 @@ mod test {
         let value = String::from("2016-09-15");
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = datetime.encrypt(&enc, &key).unwrap();
-        let decrypted = DateTime::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = datetime.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = DateTime::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, datetime);
         let rewritten = decrypted.date().format("%Y-%m-%d").to_string();
         assert_eq!(rewritten, value);
     }
 
+    #[test]
+    fn test_encrypt_time() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        assert_eq!(Some(45000000000), parse_time("12:30"));
+        assert_eq!(Some(-3020399000000), parse_time("-838:59:59"));
+        assert_eq!(Some(93600500000), parse_time("1 02:00:00.5"));
+        assert_eq!(None, parse_time("839:00:00"));
+        assert_eq!(None, parse_time("12:60:00"));
+        assert_eq!(None, parse_time("noon"));
+        assert_eq!("-838:59:59", format_time(-3020399000000, 0));
+        assert_eq!("26:00:00.500", format_time(93600500000, 3));
+
+        for enc in vec![EncryptionType::AesGcm, EncryptionType::Ope] {
+            let plaintext = Plaintext::Time(parse_time("-01:02:03.25").unwrap());
+            let encrypted = plaintext.clone().encrypt(&enc, &key, &[]).unwrap();
+            assert_eq!(plaintext, Plaintext::decrypt(&NativeType::TIME(2), &encrypted, &enc, &key, &[]).unwrap());
+        }
+        let early = Plaintext::Time(parse_time("-01:00:00").unwrap()).encrypt(&EncryptionType::Ope, &key, &[]).unwrap();
+        let late = Plaintext::Time(parse_time("00:30:00").unwrap()).encrypt(&EncryptionType::Ope, &key, &[]).unwrap();
+        assert!(early < late);
+    }
+
+    #[test]
+    fn test_encrypt_bytes() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let value = vec![0u8, 0xff, 0xfe, 10, 0];
+        for enc in vec![EncryptionType::Aes([0u8;12]), EncryptionType::AesGcm, EncryptionType::AesSiv] {
+            let encrypted = value.clone().encrypt(&enc, &key, &[]).unwrap();
+            assert_eq!(value.len() + enc.overhead() as usize, encrypted.len());
+            assert_eq!(value, Vec::<u8>::decrypt(&encrypted, &enc, &key, &[]).unwrap());
+            assert_eq!(Plaintext::Bytes(value.clone()), Plaintext::decrypt(&NativeType::LONGBLOB(1 << 32), &encrypted, &enc, &key, &[]).unwrap());
+        }
+        assert!(value.clone().encrypt(&EncryptionType::Paillier, &key, &[]).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_stream() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let enc = EncryptionType::AesGcm;
+        for len in vec![STREAM_SEGMENT_LEN + 1, 2 * STREAM_SEGMENT_LEN, 3 * STREAM_SEGMENT_LEN + 100] {
+            let value: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encrypted = value.clone().encrypt(&enc, &key, b"aad").unwrap();
+            assert!(is_stream(&enc, &encrypted));
+            assert_eq!(Some(len), stream_plaintext_len(encrypted.len()));
+            assert_eq!(value, Vec::<u8>::decrypt(&encrypted, &enc, &key, b"aad").unwrap());
+            assert!(Vec::<u8>::decrypt(&encrypted, &enc, &key, b"other").is_err());
+
+            // fed in pieces that do not line up with segments
+            let mut decryptor = StreamDecryptor::new(&key, b"aad", encrypted.len());
+            let mut decrypted = Vec::new();
+            for chunk in encrypted.chunks(10007) {
+                decrypted.append(&mut decryptor.update(chunk).unwrap());
+            }
+            decryptor.finish().unwrap();
+            assert_eq!(value, decrypted);
+
+            // a stream cut at a segment boundary is refused
+            let segments = HEADER_LEN as usize + STREAM_PREFIX_LEN + STREAM_SEGMENT_LEN + TAG_LEN;
+            assert!(Vec::<u8>::decrypt(&encrypted[0..segments], &enc, &key, b"aad").is_err());
+        }
+
+        // short values keep the single message format
+        let encrypted = vec![1u8; STREAM_SEGMENT_LEN].encrypt(&enc, &key, &[]).unwrap();
+        assert!(!is_stream(&enc, &encrypted));
+        assert_eq!(None, stream_plaintext_len(HEADER_LEN as usize + STREAM_PREFIX_LEN + STREAM_SEGMENT_LEN + TAG_LEN + 3));
+    }
+
+    #[test]
+    fn test_members() {
+        let sex = NativeType::ENUM(vec![String::from("M"), String::from("F")]);
+        assert_eq!(Some(String::from("F")), sex.member_value("f"));
+        assert_eq!(None, sex.member_value("X"));
+        assert_eq!(None, sex.member_value(""));
+        assert_eq!(Some(String::from("M")), sex.member_number(1));
+        assert_eq!(None, sex.member_number(0));
+        assert_eq!(None, sex.member_number(3));
+
+        let flags = NativeType::SET(vec![String::from("a"), String::from("b"), String::from("c")]);
+        assert_eq!(Some(String::from("a,c")), flags.member_value("C,a,c"));
+        assert_eq!(Some(String::new()), flags.member_value(""));
+        assert_eq!(None, flags.member_value("a,d"));
+        assert_eq!(Some(String::from("b,c")), flags.member_number(6));
+        assert_eq!(None, flags.member_number(8));
+
+        let keys = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let encrypted = Plaintext::Text(String::from("a,c")).encrypt(&EncryptionType::AesSiv, &keys, &[]).unwrap();
+        assert_eq!(Plaintext::Text(String::from("a,c")), Plaintext::decrypt(&flags, &encrypted, &EncryptionType::AesSiv, &keys, &[]).unwrap());
+    }
+
     #[test]
     fn test_encrypt_bool() {
         let value = true;
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
 
-        let encrypted = value.encrypt(&enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
 
-        let decrypted = bool::decrypt(&encrypted, &enc, &key).unwrap();
+        let decrypted = bool::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, value);
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = value.encrypt(&enc, &key).unwrap();
-        let decrypted = bool::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = bool::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, value);
     }
 
@@ -565,21 +2209,450 @@ mod test {
     fn test_encrypt_decimal() {
         let src_string = String::from("10.2345");
         let value = d128::from_str(&src_string).unwrap();
-        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
         let enc = EncryptionType::Aes([0u8;12]);
 
-        let encrypted = value.encrypt(&enc, &key).unwrap();
-        let decrypted = d128::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = d128::decrypt(&encrypted, &enc, &key, &[]).unwrap();
 
         assert_eq!(decrypted, value);
         assert_eq!(decrypted.to_string(), src_string);
 
         let enc = EncryptionType::AesGcm;
-        let encrypted = value.encrypt(&enc, &key).unwrap();
-        let decrypted = d128::decrypt(&encrypted, &enc, &key).unwrap();
+        let encrypted = value.encrypt(&enc, &key, &[]).unwrap();
+        let decrypted = d128::decrypt(&encrypted, &enc, &key, &[]).unwrap();
         assert_eq!(decrypted, value);
         assert_eq!(decrypted.to_string(), src_string);
 
     }
 
+    #[test]
+    fn test_siv_rfc5297_vector() {
+        let key = hex_decode("fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").unwrap();
+        let ad = hex_decode("101112131415161718191a1b1c1d1e1f2021222324252627").unwrap();
+        let plaintext = hex_decode("112233445566778899aabbccddee").unwrap();
+        let expected = hex_decode("85632d07c6e8f37f950acd320a2ecc9340c02b9690c4dc04daef7f6afe5c").unwrap();
+
+        let encrypted = siv_encrypt(&key, &[&ad], &plaintext).unwrap();
+        assert_eq!(encrypted, expected);
+
+        let decrypted = siv_decrypt(&key, &[&ad], &encrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        let mut tampered = encrypted.clone();
+        tampered[20] ^= 1;
+        assert!(siv_decrypt(&key, &[&ad], &tampered).is_err());
+    }
+
+    // values written by earlier builds, before the crypto backend could be chosen
+    #[test]
+    fn test_stored_values() {
+        let keys = KeyRing::new(3, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let aad = b"zero.users.email";
+
+        let gcm = hex_decode("5A01020003E9829AA59E286BA2817128624F76ABA06767A1EF623C0F431C86732D71C4183C352AEB39358A045788EEE96370").unwrap();
+        assert_eq!("alice@example.com", String::decrypt(&gcm, &EncryptionType::AesGcm, &keys, aad).unwrap());
+
+        let siv = hex_decode("5A010300034A9D0BB52AD57F0312A3ABE3114CFFDF63F2F48D5D511ED3862B14949C1B73D8E5").unwrap();
+        assert_eq!(siv, String::from("alice@example.com").encrypt(&EncryptionType::AesSiv, &keys, aad).unwrap());
+
+        let ope = hex_decode("00000000000000004498D5AC2E31132F5A010400033962EB64A1F4A02F47ADFFA789AD1709B88B1A68B8C3EE43").unwrap();
+        assert_eq!(ope, Plaintext::U64(1234).encrypt(&EncryptionType::Ope, &keys, aad).unwrap());
+
+        assert_eq!("758DBEB78393F5978D2EB33AF8026DB2", hex_encode(&Plaintext::Text("alice@example.com".into()).blind_index(&keys, 16)));
+        assert_eq!("83CF6D36A4C66F9F7FB35EBA17DFF93FD0470ACC6AEEEE114ED2CAA231EA65F8", hex_encode(keys.derive(b"zero-row-key", b"42").current().1));
+    }
+
+    #[test]
+    fn test_siv_deterministic() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let enc = EncryptionType::AesSiv;
+
+        let a = String::from("alice@example.com").encrypt(&enc, &key, &[]).unwrap();
+        let b = String::from("alice@example.com").encrypt(&enc, &key, &[]).unwrap();
+        let c = String::from("bob@example.com").encrypt(&enc, &key, &[]).unwrap();
+
+        assert_eq!(a, b);
+        assert!(a != c);
+        assert_eq!(a.len(), "alice@example.com".len() + enc.overhead() as usize);
+    }
+
+    #[test]
+    fn test_gcm_nonce_unique() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let enc = EncryptionType::AesGcm;
+
+        let mut nonces = HashSet::new();
+        for i in 0..10000_u64 {
+            let encrypted = (i % 3).encrypt(&enc, &key, &[]).unwrap();
+            assert!(nonces.insert(encrypted[5..17].to_vec()), "nonce reused after {} encryptions", i);
+        }
+        assert_eq!(10000, nonces.len());
+    }
+
+    #[test]
+    fn test_aad_binding() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let ssn = AadBinding{column: "zero.users.ssn".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: false, tenant: None};
+        let other = AadBinding{column: "zero.users.credit_card".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: false, tenant: None};
+
+        for enc in vec![EncryptionType::AesGcm, EncryptionType::AesSiv] {
+            let encrypted = String::from("123-45-6789").encrypt(&enc, &key, &ssn.to_bytes(Some("1"))).unwrap();
+
+            let decrypted = String::decrypt(&encrypted, &enc, &key, &ssn.to_bytes(Some("1"))).unwrap();
+            assert_eq!(decrypted, "123-45-6789");
+
+            // moved to another row
+            assert!(String::decrypt(&encrypted, &enc, &key, &ssn.to_bytes(Some("2"))).is_err());
+            // moved to another column sharing the key
+            assert!(String::decrypt(&encrypted, &enc, &key, &other.to_bytes(Some("1"))).is_err());
+            // aad dropped
+            assert!(String::decrypt(&encrypted, &enc, &key, &[]).is_err());
+        }
+
+        // primary keys are bound as MySQL returns them
+        assert_eq!(ssn.to_bytes(Some("7")), ssn.to_bytes(Some("07")));
+        assert_eq!(ssn.to_bytes(Some("7")), ssn.to_bytes(Some("+7")));
+        assert!(ssn.to_bytes(Some("7")) != ssn.to_bytes(Some("70")));
+        let text = AadBinding{column: "zero.users.ssn".into(), row_key: Some("code".into()), row_key_type: NativeType::Char(4), row_keys: false, tenant: None};
+        assert_eq!(text.to_bytes(Some("ab")), text.to_bytes(Some("ab  ")));
+        assert!(text.to_bytes(Some("7")) != text.to_bytes(Some("07")));
+    }
+
+    #[test]
+    fn test_row_keys() {
+        let mut key = KeyRing::new(1, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        key.add(2, hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A"));
+        let notes = AadBinding{column: "zero.users.notes".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: true, tenant: None};
+
+        let row1 = notes.keys(&key, Some("1"));
+        assert_eq!(vec![1, 2], row1.versions());
+        assert_eq!(row1, notes.keys(&key, Some("1")));
+        assert!(row1.get(1).unwrap() != key.get(1).unwrap());
+        assert!(row1.get(1).unwrap() != row1.get(2).unwrap());
+        assert!(row1 != notes.keys(&key, Some("2")));
+        // keyed by the column too
+        let other = AadBinding{column: "zero.users.other".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: true, tenant: None};
+        assert!(row1 != other.keys(&key, Some("1")));
+        // columns keyed per column are unchanged
+        let bound = AadBinding{column: "zero.users.notes".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: false, tenant: None};
+        assert_eq!(key, bound.keys(&key, Some("1")));
+
+        let encrypted = String::from("private").encrypt(&EncryptionType::AesGcm, &row1, &notes.to_bytes(Some("1"))).unwrap();
+        assert_eq!("private", String::decrypt(&encrypted, &EncryptionType::AesGcm, &row1, &notes.to_bytes(Some("1"))).unwrap());
+        assert!(String::decrypt(&encrypted, &EncryptionType::AesGcm, &key, &notes.to_bytes(Some("1"))).is_err());
+
+        // tenant keys differ by column and by tenant
+        assert_eq!(vec![0], notes.tenant_keys(&SecretKey::new([1u8; 32])).versions());
+        assert!(notes.tenant_keys(&SecretKey::new([1u8; 32])) != other.tenant_keys(&SecretKey::new([1u8; 32])));
+        assert!(notes.tenant_keys(&SecretKey::new([1u8; 32])) != notes.tenant_keys(&SecretKey::new([2u8; 32])));
+    }
+
+    #[test]
+    fn test_blind_index() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let other = KeyRing::new(0, hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A"));
+
+        let index = Plaintext::Text("123-45-6789".into()).blind_index(&key, 16);
+        assert_eq!(16, index.len());
+        assert_eq!(index, Plaintext::Text("123-45-6789  ".into()).blind_index(&key, 16));
+        assert_eq!(index[..8].to_vec(), Plaintext::Text("123-45-6789".into()).blind_index(&key, 8));
+        assert!(index != Plaintext::Text("123-45-6780".into()).blind_index(&key, 16));
+        assert!(index != Plaintext::Text("123-45-6789".into()).blind_index(&other, 16));
+
+        // values written before a key was added keep the index of their version
+        let mut rotated = key.clone();
+        rotated.add(1, hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A"));
+        let indexes = Plaintext::Text("123-45-6789".into()).blind_indexes(&rotated, 16);
+        assert_eq!(vec![Plaintext::Text("123-45-6789".into()).blind_index(&other, 16), index.clone()], indexes);
+        assert_eq!(indexes[0], Plaintext::Text("123-45-6789".into()).blind_index(&rotated, 16));
+
+        assert_eq!(Plaintext::D128(d128::from_str("1.50").unwrap()).blind_index(&key, 16),
+                   Plaintext::D128(d128::from_str("1.5").unwrap()).blind_index(&key, 16));
+        assert_eq!(Plaintext::F64(-0.0).blind_index(&key, 16), Plaintext::F64(0.0).blind_index(&key, 16));
+
+        let gcm = EncryptionType::AesGcm;
+        let encrypted = Plaintext::U64(42).encrypt(&gcm, &key, &[]).unwrap();
+        assert_eq!(Plaintext::U64(42), Plaintext::decrypt(&NativeType::U64, &encrypted, &gcm, &key, &[]).unwrap());
+    }
+
+    #[test]
+    fn test_like_index() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let ngram = LikeIndex{column: "c_last_like".into(), mode: LikeMode::NGram, length: 3};
+        let stored = ngram.tokens(&key, "SMITH").split(' ').map(String::from).collect::<Vec<String>>();
+        // ^SM SMI MIT ITH TH$
+        assert_eq!(5, stored.len());
+
+        let matches = |pattern: &str| ngram.pattern_tokens(&key, pattern).unwrap().split(' ').all(|t| stored.contains(&t[1..].to_string()));
+        assert!(matches("SMI%"));
+        assert!(matches("%MIT%"));
+        assert!(matches("%ITH"));
+        assert!(matches("SMITH"));
+        assert!(matches("S_ITH"));
+        assert!(!matches("MIT%"));
+        assert!(!matches("%SMIX%"));
+        assert!(ngram.pattern_tokens(&key, "%MI%").is_err());
+        assert!(ngram.pattern_tokens(&key, "%").is_err());
+
+        // short values are a single token, found by an exact pattern
+        let short = ngram.tokens(&key, "A");
+        assert_eq!(ngram.pattern_tokens(&key, "A").unwrap(), format!("+{}", short));
+
+        let prefix = LikeIndex{column: "c_last_like".into(), mode: LikeMode::Prefix, length: 4};
+        let stored = prefix.tokens(&key, "SMITH");
+        assert_eq!(4 * 17 - 1, stored.len());
+        assert!(stored.contains(&prefix.pattern_tokens(&key, "SMI%").unwrap()[1..]));
+        assert!(stored.contains(&prefix.pattern_tokens(&key, "SMITHS%").unwrap()[1..]));
+        assert!(prefix.pattern_tokens(&key, "%MIT%").is_err());
+
+        let other = KeyRing::new(0, hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A"));
+        assert!(prefix.tokens(&key, "SMITH") != prefix.tokens(&other, "SMITH"));
+
+        // with several key versions, the tokens under each version are alternative groups
+        let mut rotated = key.clone();
+        rotated.add(1, hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A"));
+        assert_eq!(format!("({}) ({})", prefix.pattern_tokens(&other, "SMI%").unwrap(), prefix.pattern_tokens(&key, "SMI%").unwrap()),
+                   prefix.pattern_tokens(&rotated, "SMI%").unwrap());
+        assert_eq!(prefix.tokens(&other, "SMITH"), prefix.tokens(&rotated, "SMITH"));
+    }
+
+    #[test]
+    fn test_ope_order() {
+        let key = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let values = vec![0u64, 1, 2, 3, 100, 101, 65535, 65536, 1 << 40, (1 << 63) - 1, 1 << 63, !0 - 1, !0];
+        let codes = values.iter().map(|v| ope_code(&key, *v)).collect::<Vec<[u8; 16]>>();
+        for i in 1..codes.len() {
+            assert!(codes[i - 1] < codes[i], "{} and {} out of order", values[i - 1], values[i]);
+        }
+        assert_eq!(codes[4], ope_code(&key, 100));
+        assert!(codes[4] != ope_code(&hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A"), 100));
+    }
+
+    #[test]
+    fn test_ope_encrypt() {
+        let key = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let enc = EncryptionType::Ope;
+
+        let ints = vec![-1000_i64, -1, 0, 1, 99, 100, 1000000];
+        let encrypted = ints.iter().map(|i| i.encrypt(&enc, &key, &[]).unwrap()).collect::<Vec<Vec<u8>>>();
+        for i in 0..ints.len() {
+            assert_eq!(ints[i], i64::decrypt(&encrypted[i], &enc, &key, &[]).unwrap());
+            assert_eq!(8 + enc.overhead() as usize, encrypted[i].len());
+            if i > 0 {
+                assert!(encrypted[i - 1] < encrypted[i]);
+            }
+        }
+        // deterministic, so equality holds too
+        assert_eq!(encrypted[5], 100_i64.encrypt(&enc, &key, &[]).unwrap());
+
+        let floats = vec![-2.5_f64, -0.1, 0.0, 0.1, 3.14159, 1e10];
+        for w in floats.windows(2) {
+            assert!(w[0].encrypt(&enc, &key, &[]).unwrap() < w[1].encrypt(&enc, &key, &[]).unwrap());
+        }
+
+        let decimals = vec!["-10.5", "0.01", "10.2345", "10.2346"];
+        for w in decimals.windows(2) {
+            let a = d128::from_str(w[0]).unwrap().encrypt(&enc, &key, &[]).unwrap();
+            let b = d128::from_str(w[1]).unwrap().encrypt(&enc, &key, &[]).unwrap();
+            assert!(a < b);
+            assert_eq!(d128::from_str(w[1]).unwrap(), d128::decrypt(&b, &enc, &key, &[]).unwrap());
+        }
+
+        let a = UTC.datetime_from_str("2016-09-15 10:00:00.5", "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        let b = UTC.datetime_from_str("2016-09-15 10:00:00.75", "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        let c = UTC.datetime_from_str("1969-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let (ea, eb, ec) = (a.encrypt(&enc, &key, &[]).unwrap(), b.encrypt(&enc, &key, &[]).unwrap(), c.encrypt(&enc, &key, &[]).unwrap());
+        assert!(ec < ea && ea < eb);
+        assert_eq!(a, DateTime::decrypt(&ea, &enc, &key, &[]).unwrap());
+
+        assert_eq!(Some(0), read_header(&enc, &ea));
+        assert!(String::from("abc").encrypt(&enc, &key, &[]).is_err());
+    }
+
+    #[test]
+    fn test_padding() {
+        let keys = KeyRing::new(1, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let enc = EncryptionType::AesGcm;
+
+        // values of different lengths in the same bucket encrypt to the same length
+        let bucket = Padding::Bucket(16);
+        let short = Plaintext::Text("Al".into()).encrypt_padded(&enc, &keys, &[], &bucket).unwrap();
+        let long = Plaintext::Text("Bartholomew".into()).encrypt_padded(&enc, &keys, &[], &bucket).unwrap();
+        assert_eq!(16 + enc.overhead() as usize, short.len());
+        assert_eq!(short.len(), long.len());
+        assert_eq!("Al", String::decrypt(&short, &enc, &keys, &[]).unwrap());
+        assert_eq!("Bartholomew", String::decrypt(&long, &enc, &keys, &[]).unwrap());
+
+        // a full bucket still takes a byte of padding
+        let full = Plaintext::Text("x".repeat(16)).encrypt_padded(&enc, &keys, &[], &bucket).unwrap();
+        assert_eq!(32 + enc.overhead() as usize, full.len());
+        assert_eq!("x".repeat(16), String::decrypt(&full, &enc, &keys, &[]).unwrap());
+
+        let max = Padding::Max(20);
+        assert_eq!(21, max.padded_len(0));
+        assert_eq!(21, max.padded_len(20));
+        let empty = Plaintext::Text("".into()).encrypt_padded(&enc, &keys, &[], &max).unwrap();
+        assert_eq!(21 + enc.overhead() as usize, empty.len());
+        assert_eq!("", String::decrypt(&empty, &enc, &keys, &[]).unwrap());
+
+        // unpadded values and other types are unaffected
+        let plain = String::from("Al").encrypt(&enc, &keys, &[]).unwrap();
+        assert_eq!(2 + enc.overhead() as usize, plain.len());
+        assert_eq!("Al", String::decrypt(&plain, &enc, &keys, &[]).unwrap());
+        let n = Plaintext::U64(7).encrypt_padded(&enc, &keys, &[], &bucket).unwrap();
+        assert_eq!(7, u64::decrypt(&n, &enc, &keys, &[]).unwrap());
+
+        // the padded format is authenticated with the header
+        let mut tampered = short.clone();
+        tampered[1] = HEADER_FORMAT;
+        assert!(String::decrypt(&tampered, &enc, &keys, &[]).is_err());
+
+        assert!(unpad(vec![1, 2, 0]).is_err());
+        assert!(Plaintext::Text("Al".into()).encrypt_padded(&EncryptionType::AesSiv, &keys, &[], &bucket).is_err());
+    }
+
+    #[test]
+    fn test_normalization() {
+        let n = Normalization{case_fold: true, nfc: true, trim: true, display: Some("c_last_display".into())};
+        assert_eq!("smith", n.apply("SMITH  "));
+        assert_eq!("  smith", n.apply("  Smith"));
+        // a decomposed e and combining acute accent compose to a single character
+        assert_eq!("jos\u{e9}", n.apply("JOSE\u{301}"));
+        assert_eq!("jos\u{e9}", n.apply("Jos\u{e9}"));
+
+        let trim = Normalization{case_fold: false, nfc: false, trim: true, display: None};
+        // only spaces are trimmed, and case is kept
+        assert_eq!("Smith \t", trim.apply("Smith \t "));
+
+        // normalized values encrypt alike under deterministic encryption
+        let keys = KeyRing::new(1, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let enc = EncryptionType::AesSiv;
+        assert_eq!(n.apply("SMITH").encrypt(&enc, &keys, &[]).unwrap(), n.apply("smith ").encrypt(&enc, &keys, &[]).unwrap());
+
+        // the display column has keys of its own
+        let display = n.display_keys(&keys);
+        assert!(display.current().1 != keys.current().1);
+        assert_eq!(1, display.current().0);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let v1 = hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985");
+        let v2 = hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A");
+        let old = KeyRing::new(1, v1.clone());
+        let mut rotated = KeyRing::new(2, v2.clone());
+        rotated.add(1, v1.clone());
+        assert_eq!(2, rotated.current().0);
+
+        for enc in vec![EncryptionType::Aes([0u8;12]), EncryptionType::AesGcm, EncryptionType::AesSiv] {
+            let encrypted = 42_u64.encrypt(&enc, &old, &[]).unwrap();
+            assert_eq!(&[0x5A, 1, enc.id(), 0, 1], &encrypted[0..5]);
+
+            // values under the previous version stay readable, new ones use the newest
+            assert_eq!(42, u64::decrypt(&encrypted, &enc, &rotated, &[]).unwrap());
+            let reencrypted = 42_u64.encrypt(&enc, &rotated, &[]).unwrap();
+            assert_eq!(&[0x5A, 1, enc.id(), 0, 2], &reencrypted[0..5]);
+
+            // once retired, the version is reported
+            match u64::decrypt(&encrypted, &enc, &KeyRing::new(2, v2.clone()), &[]) {
+                Err(e) => assert_eq!("[123] Failed decrypting data, key version 1 is not configured", format!("{}", e)),
+                _ => panic!("TEST FAIL")
+            }
+
+            // the header is authenticated
+            let mut tampered = reencrypted.clone();
+            tampered[4] = 1;
+            assert!(u64::decrypt(&tampered, &enc, &rotated, &[]).is_err());
+        }
+
+        // header-less values written before versioning
+        let legacy = encrypt(&v1, b"legacy", make_nonce(&EncryptionType::AesGcm).unwrap(), &[]).unwrap();
+        assert_eq!("legacy", String::decrypt(&legacy, &EncryptionType::AesGcm, &rotated, &[]).unwrap());
+        let legacy = siv_encrypt(&v1, &[], b"legacy").unwrap();
+        assert_eq!("legacy", String::decrypt(&legacy, &EncryptionType::AesSiv, &rotated, &[]).unwrap());
+
+        // deterministic columns holding such values keep writing them, so equality still matches
+        let mut headerless = old.clone();
+        headerless.set_header(false);
+        assert_eq!(legacy, String::from("legacy").encrypt(&EncryptionType::AesSiv, &headerless, &[]).unwrap());
+        for enc in vec![EncryptionType::Aes([0u8;12]), EncryptionType::AesSiv] {
+            let encrypted = 42_u64.encrypt(&enc, &headerless, &[]).unwrap();
+            assert_eq!(None, read_header(&enc, &encrypted));
+            assert_eq!(8 + enc.overhead_with(false) as usize, encrypted.len());
+            assert_eq!(42, u64::decrypt(&encrypted, &enc, &rotated, &[]).unwrap());
+        }
+        // randomized values always carry one
+        let encrypted = 42_u64.encrypt(&EncryptionType::AesGcm, &headerless, &[]).unwrap();
+        assert_eq!(Some(1), read_header(&EncryptionType::AesGcm, &encrypted));
+    }
+
+    #[test]
+    fn test_paillier_sum() {
+        let enc = EncryptionType::Paillier;
+        let mut keys = KeyRing::new(1, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+
+        let encrypted = 42_u64.encrypt(&enc, &keys, &[]).unwrap();
+        assert_eq!(enc.overhead() as usize, encrypted.len());
+        assert!(encrypted != 42_u64.encrypt(&enc, &keys, &[]).unwrap());
+        assert_eq!(42, u64::decrypt(&encrypted, &enc, &keys, &[]).unwrap());
+        assert_eq!(-42, i64::decrypt(&(-42_i64).encrypt(&enc, &keys, &[]).unwrap(), &enc, &keys, &[]).unwrap());
+        assert!(u64::decrypt(&(-42_i64).encrypt(&enc, &keys, &[]).unwrap(), &enc, &keys, &[]).is_err());
+        let amount = d128::from_str("-1234.5678").unwrap();
+        assert_eq!(amount, d128::decrypt(&amount.encrypt(&enc, &keys, &[]).unwrap(), &enc, &keys, &[]).unwrap());
+        assert!(d128::from_str("0.00000000001").unwrap().encrypt(&enc, &keys, &[]).is_err());
+
+        // ciphertexts under either key version add up
+        let old = keys.clone();
+        keys.add(2, hex_key("E346673B1FDA598544E6884D78AA18FA690917F84145AA4415FC3CD560915C7A"));
+        let mut sum = PaillierSum::new(&keys);
+        assert_eq!(None, sum.total(&NativeType::D128));
+        sum.add(&d128::from_str("10.25").unwrap().encrypt(&enc, &old, &[]).unwrap()).unwrap();
+        sum.add(&d128::from_str("-0.5").unwrap().encrypt(&enc, &keys, &[]).unwrap()).unwrap();
+        sum.add(&d128::from_str("5").unwrap().encrypt(&enc, &keys, &[]).unwrap()).unwrap();
+        assert!(sum.add(&42_u64.encrypt(&EncryptionType::AesGcm, &keys, &[]).unwrap()).is_err());
+        assert_eq!(3, sum.count());
+        assert_eq!(d128::from_str("14.75").unwrap(), sum.total(&NativeType::D128).unwrap());
+        assert_eq!("4.916667", format!("{}", sum.average(&NativeType::D128).unwrap()));
+    }
+
+    #[test]
+    fn test_ff1() {
+        // NIST SP 800-38G samples 7 to 9, AES-256
+        let key = hex_key("2B7E151628AED2A6ABF7158809CF4F3CEF4359D8D580AA4F7F036D6F04FC6A94");
+        let digits = |s: &str| s.chars().map(|c| c.to_digit(36).unwrap()).collect::<Vec<u32>>();
+
+        assert_eq!(digits("6657667009"), ff1(&key, &[], 10, &digits("0123456789"), true));
+        let tweak = hex_decode("39383736353433323130").unwrap();
+        assert_eq!(digits("1001623463"), ff1(&key, &tweak, 10, &digits("0123456789"), true));
+        assert_eq!(digits("0123456789"), ff1(&key, &tweak, 10, &digits("1001623463"), false));
+        let tweak = hex_decode("3737373770717273373737").unwrap();
+        assert_eq!(digits("xs8a0azh2avyalyzuwd"), ff1(&key, &tweak, 36, &digits("0123456789abcdefghi"), true));
+        assert_eq!(digits("0123456789abcdefghi"), ff1(&key, &tweak, 36, &digits("xs8a0azh2avyalyzuwd"), false));
+    }
+
+    #[test]
+    fn test_encrypt_fpe() {
+        let keys = KeyRing::new(0, hex_key("44E6884D78AA18FA690917F84145AA4415FC3CD560915C7AE346673B1FDA5985"));
+        let ssn = EncryptionType::Fpe(FpeFormat{alphabet: "0123456789".into(), pattern: Some("###-##-####".into())});
+        let encrypted = String::from("123-45-6789").encrypt(&ssn, &keys, &[]).unwrap();
+        assert_eq!(11, encrypted.len());
+        assert_eq!(b'-', encrypted[3]);
+        assert!(encrypted.iter().all(|c| (*c as char).is_digit(10) || *c == b'-'));
+        assert_eq!(encrypted, String::from("123-45-6789").encrypt(&ssn, &keys, &[]).unwrap());
+        assert!(encrypted != String::from("123-45-6789").encrypt(&ssn, &keys, b"tweak").unwrap());
+        assert_eq!("123-45-6789", String::decrypt(&encrypted, &ssn, &keys, &[]).unwrap());
+        assert!(String::from("123-456-789").encrypt(&ssn, &keys, &[]).is_err());
+        assert!(String::from("12a-45-6789").encrypt(&ssn, &keys, &[]).is_err());
+
+        // without a pattern other characters pass through
+        let phone = EncryptionType::Fpe(FpeFormat{alphabet: "0123456789".into(), pattern: None});
+        let encrypted = String::from("+1 (555) 010-9999").encrypt(&phone, &keys, &[]).unwrap();
+        assert!(encrypted.iter().zip(b"+1 (555) 010-9999".iter()).all(|(e, p)| (*e as char).is_digit(10) == (*p as char).is_digit(10)));
+        assert!(encrypted.iter().zip(b"+1 (555) 010-9999".iter()).filter(|&(_, p)| !(*p as char).is_digit(10)).all(|(e, p)| e == p));
+        assert_eq!("+1 (555) 010-9999", String::decrypt(&encrypted, &phone, &keys, &[]).unwrap());
+        assert!(String::from("55-010").encrypt(&phone, &keys, &[]).is_err());
+        assert_eq!(6, FpeFormat{alphabet: "0123456789".into(), pattern: None}.min_length());
+    }
+
 }
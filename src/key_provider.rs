@@ -0,0 +1,855 @@
+// Copyright 2016 AgilData
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http:// www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use backend::backend;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::cmp;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, OsRng};
+
+use config::{KeyProviderConfig, MasterKeyConfig};
+use encrypt::{AadBinding, KeyRing, encrypt, decrypt, gcm_nonce, hex_decode, hex_encode};
+use error::ZeroError;
+use secret::{SecretKey, wipe};
+
+const KEYSTORE_HEADER: &'static str = "zero-keystore 1";
+const DEFAULT_PASSPHRASE_ENV: &'static str = "ZERO_KEYSTORE_PASSPHRASE";
+pub const DEFAULT_KEYSTORE_ITERATIONS: u32 = 100000;
+const WRAPPED_KEY_AAD: &'static [u8] = b"zero-data-key";
+const DEFAULT_TENANT_CACHE_MS: u64 = 60000;
+const DEFAULT_TENANT_CACHE_SIZE: usize = 10000;
+const DEFAULT_TENANT_FETCH_RATE: usize = 10;
+
+/// Resolves a key reference from the config, e.g. key-ref="customer-pii", to key material
+pub trait KeyProvider: Send + Sync {
+    fn get_key(&self, key_ref: &str) -> Result<SecretKey, Box<ZeroError>>;
+
+    /// As get_key, but a key that does not exist is None rather than an error
+    fn find_key(&self, key_ref: &str) -> Result<Option<SecretKey>, Box<ZeroError>>;
+}
+
+// creates the provider described by the key-provider config element
+pub fn create_key_provider(config: &KeyProviderConfig) -> Result<Box<KeyProvider>, Box<ZeroError>> {
+    match config.props.get("type").map(|t| t.to_lowercase()) {
+        Some(ref t) if t == "keystore" => {
+            let path = get_prop(config, "path")?;
+            let env_name = config.props.get("passphrase-env").map(|s| s as &str).unwrap_or(DEFAULT_PASSPHRASE_ENV);
+            let passphrase = match env::var(env_name) {
+                Ok(p) => p,
+                Err(_) => return Err(key_error(format!("Keystore passphrase variable {} is not set", env_name)))
+            };
+            Ok(Box::new(KeystoreKeyProvider::open(&path, &passphrase)?))
+        },
+        Some(ref t) if t == "http" => {
+            let url = get_prop(config, "url")?;
+            let token = match config.props.get("token-env") {
+                Some(env_name) => match env::var(env_name) {
+                    Ok(t) => Some(t),
+                    Err(_) => return Err(key_error(format!("KMS token variable {} is not set", env_name)))
+                },
+                None => None
+            };
+            let timeout = match config.props.get("timeout-ms") {
+                Some(t) => match t.parse::<u64>() {
+                    Ok(t) => t,
+                    Err(_) => return Err(key_error(format!("Invalid timeout-ms {}", t)))
+                },
+                None => 5000
+            };
+            Ok(Box::new(HttpKmsKeyProvider::new(&url, token, Duration::from_millis(timeout))?))
+        },
+        Some(t) => Err(key_error(format!("Unsupported key provider type {}", t))),
+        None => Err(key_error("Columns use key-ref but no key-provider is configured".into()))
+    }
+}
+
+/// Loads the master key used to unwrap column data keys, from the key provider, a file or a passphrase
+pub fn load_master_key(config: &MasterKeyConfig, provider_config: &KeyProviderConfig) -> Result<SecretKey, Box<ZeroError>> {
+    match config.props.get("type").map(|t| t.to_lowercase()) {
+        Some(ref t) if t == "key-provider" => {
+            let key_ref = match config.props.get("key-ref") {
+                Some(r) => r,
+                None => return Err(key_error("master-key property key-ref is required".into()))
+            };
+            create_key_provider(provider_config)?.get_key(key_ref)
+        },
+        Some(ref t) if t == "file" => {
+            let path = match config.props.get("path") {
+                Some(p) => p,
+                None => return Err(key_error("master-key property path is required".into()))
+            };
+            let mut hex = String::new();
+            match File::open(path).and_then(|mut f| f.read_to_string(&mut hex)) {
+                Ok(_) => to_key("master", &hex_decode(hex.trim())?),
+                Err(e) => Err(key_error(format!("Unable to read master key {}: {}", path, e)))
+            }
+        },
+        Some(ref t) if t == "passphrase" => {
+            let env_name = config.props.get("passphrase-env").map(|s| s as &str).unwrap_or("ZERO_MASTER_PASSPHRASE");
+            let passphrase = match env::var(env_name) {
+                Ok(p) => p,
+                Err(_) => return Err(key_error(format!("Master key passphrase variable {} is not set", env_name)))
+            };
+            let salt = match config.props.get("salt") {
+                Some(s) => hex_decode(s)?,
+                None => return Err(key_error("master-key property salt is required".into()))
+            };
+            let iterations = match config.props.get("iterations").map(|i| i.parse::<u32>()) {
+                Some(Ok(0)) => return Err(key_error("master-key property iterations must be at least 1".into())),
+                Some(Ok(i)) => i,
+                Some(Err(_)) => return Err(key_error("master-key property iterations must be a number".into())),
+                None => DEFAULT_KEYSTORE_ITERATIONS
+            };
+            Ok(derive_key(&passphrase, &salt, iterations))
+        },
+        Some(t) => Err(key_error(format!("Unsupported master key type {}", t))),
+        None => Err(key_error("Columns use wrapped-key but no master-key is configured".into()))
+    }
+}
+
+/// Encrypts a column data key under the master key, for use as a wrapped-key attribute
+pub fn wrap_key(master: &SecretKey, key: &SecretKey) -> Result<String, Box<ZeroError>> {
+    Ok(hex_encode(&encrypt(master, key, gcm_nonce()?, WRAPPED_KEY_AAD)?))
+}
+
+pub fn unwrap_key(master: &SecretKey, wrapped: &str) -> Result<SecretKey, Box<ZeroError>> {
+    match decrypt(master, &hex_decode(wrapped)?, WRAPPED_KEY_AAD) {
+        Ok(mut key) => {
+            let unwrapped = to_key("wrapped", &key);
+            wipe(&mut key);
+            unwrapped
+        },
+        Err(_) => Err(key_error("Unable to unwrap data key, wrong master key or corrupt wrapped-key".into()))
+    }
+}
+
+/// Keys held in a local file encrypted under a passphrase.
+///
+/// The file holds a header, the PBKDF2-HMAC-SHA256 parameters and an AES-GCM encrypted
+/// list of `name hexkey` lines, authenticated together with the header lines.
+pub struct KeystoreKeyProvider {
+    keys: HashMap<String, SecretKey>
+}
+
+impl KeystoreKeyProvider {
+
+    pub fn open(path: &str, passphrase: &str) -> Result<Self, Box<ZeroError>> {
+        let mut contents = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => {},
+            Err(e) => return Err(key_error(format!("Unable to read keystore {}: {}", path, e)))
+        }
+
+        let lines = contents.lines().collect::<Vec<&str>>();
+        if lines.len() != 3 || lines[0] != KEYSTORE_HEADER {
+            return Err(key_error(format!("{} is not a keystore", path)))
+        }
+
+        let kdf = lines[1].split(' ').collect::<Vec<&str>>();
+        let (iterations, salt) = match (kdf.get(0), kdf.get(1).and_then(|i| i.parse::<u32>().ok()), kdf.get(2)) {
+            (Some(&"pbkdf2-sha256"), Some(0), _) => return Err(key_error(format!("Keystore {} has an iteration count of zero", path))),
+            (Some(&"pbkdf2-sha256"), Some(i), Some(salt)) => (i, hex_decode(salt)?),
+            _ => return Err(key_error(format!("Unsupported keystore key derivation {}", lines[1])))
+        };
+
+        let aad = format!("{}\n{}", lines[0], lines[1]);
+        let plain = match decrypt(&derive_key(passphrase, &salt, iterations), &hex_decode(lines[2])?, aad.as_bytes()) {
+            Ok(p) => p,
+            Err(_) => return Err(key_error(format!("Unable to unlock keystore {}, wrong passphrase or corrupt file", path)))
+        };
+        let mut plain = match String::from_utf8(plain) {
+            Ok(p) => p,
+            Err(e) => {
+                wipe(&mut e.into_bytes());
+                return Err(key_error(format!("Corrupt keystore {}", path)))
+            }
+        };
+
+        let keys = plain.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(parse_key_line)
+            .collect::<Result<HashMap<String, SecretKey>, Box<ZeroError>>>();
+        unsafe { wipe(plain.as_bytes_mut()); }
+
+        Ok(KeystoreKeyProvider{keys: keys?})
+    }
+}
+
+impl KeyProvider for KeystoreKeyProvider {
+    fn get_key(&self, key_ref: &str) -> Result<SecretKey, Box<ZeroError>> {
+        match self.keys.get(key_ref) {
+            Some(k) => Ok(k.clone()),
+            None => Err(key_error(format!("Key {} not found in keystore", key_ref)))
+        }
+    }
+
+    fn find_key(&self, key_ref: &str) -> Result<Option<SecretKey>, Box<ZeroError>> {
+        Ok(self.keys.get(key_ref).cloned())
+    }
+}
+
+/// Writes a keystore readable by KeystoreKeyProvider
+pub fn write_keystore(path: &str, passphrase: &str, iterations: u32, keys: &HashMap<String, SecretKey>) -> Result<(), Box<ZeroError>> {
+    let mut rng = match OsRng::new() {
+        Ok(rng) => rng,
+        Err(e) => return Err(key_error(format!("Failed to open OS random number generator: {}", e)))
+    };
+    let mut salt = [0u8; 16];
+    rng.fill_bytes(&mut salt);
+
+    let kdf = format!("pbkdf2-sha256 {} {}", iterations, hex_encode(&salt));
+    let aad = format!("{}\n{}", KEYSTORE_HEADER, kdf);
+
+    let mut plain = String::new();
+    for (name, key) in keys.iter() {
+        plain.push_str(&format!("{} {}\n", name, hex_encode(key)));
+    }
+
+    let encrypted = encrypt(&derive_key(passphrase, &salt, iterations), plain.as_bytes(), gcm_nonce()?, aad.as_bytes());
+    unsafe { wipe(plain.as_bytes_mut()); }
+    let encrypted = encrypted?;
+
+    match create_private(path).and_then(|mut f| write!(f, "{}\n{}\n", aad, hex_encode(&encrypted))) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(key_error(format!("Unable to write keystore {}: {}", path, e)))
+    }
+}
+
+// keystores are only readable by their owner, including one written over an existing file
+#[cfg(unix)]
+fn create_private(path: &str) -> io::Result<File> {
+    use std::fs::{OpenOptions, Permissions};
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let f = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    f.set_permissions(Permissions::from_mode(0o600))?;
+    Ok(f)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &str) -> io::Result<File> {
+    File::create(path)
+}
+
+/// Parses a `name hexkey` line, as stored in a keystore
+pub fn parse_key_line(line: &str) -> Result<(String, SecretKey), Box<ZeroError>> {
+    let parts = line.split_whitespace().collect::<Vec<&str>>();
+    if parts.len() != 2 {
+        return Err(key_error(format!("Expected 'name hexkey', received {}", parts.get(0).unwrap_or(&""))))
+    }
+    let mut bytes = hex_decode(parts[1])?;
+    let key = to_key(parts[0], &bytes);
+    wipe(&mut bytes);
+    Ok((parts[0].to_string(), key?))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> SecretKey {
+    SecretKey::new(backend().pbkdf2_sha256(passphrase.as_bytes(), salt, iterations))
+}
+
+/// Fetches keys from an HTTP key management service.
+///
+/// A key is requested with `GET <url>/<key-ref>`, optionally with a bearer token, and
+/// the service answers 200 with the hex encoded key as the body. Only plain http is
+/// spoken, so the service should be reached over loopback or a TLS terminating sidecar.
+pub struct HttpKmsKeyProvider {
+    host: String,
+    path: String,
+    token: Option<String>,
+    timeout: Duration
+}
+
+impl HttpKmsKeyProvider {
+
+    pub fn new(url: &str, token: Option<String>, timeout: Duration) -> Result<Self, Box<ZeroError>> {
+        if !url.starts_with("http://") {
+            return Err(key_error(format!("Unsupported KMS url {}, expected http://host:port/path", url)))
+        }
+        let rest = &url["http://".len()..];
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_right_matches('/')),
+            None => (rest, "")
+        };
+        let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+
+        Ok(HttpKmsKeyProvider{host: host, path: path.to_string(), token: token, timeout: timeout})
+    }
+
+    fn request(&self, key_ref: &str) -> Result<(u32, String), Box<ZeroError>> {
+        let mut stream = match TcpStream::connect(&self.host as &str) {
+            Ok(s) => s,
+            Err(e) => return Err(key_error(format!("Unable to connect to KMS {}: {}", self.host, e)))
+        };
+        let _ = stream.set_read_timeout(Some(self.timeout));
+        let _ = stream.set_write_timeout(Some(self.timeout));
+
+        let mut req = format!("GET {}/{} HTTP/1.0\r\nHost: {}\r\nAccept: text/plain\r\n", self.path, key_ref, self.host);
+        if let Some(ref t) = self.token {
+            req.push_str(&format!("Authorization: Bearer {}\r\n", t));
+        }
+        req.push_str("Connection: close\r\n\r\n");
+
+        let mut response = String::new();
+        match stream.write_all(req.as_bytes()).and_then(|_| stream.read_to_string(&mut response)) {
+            Ok(_) => {},
+            Err(e) => return Err(key_error(format!("KMS request for {} failed: {}", key_ref, e)))
+        }
+
+        let (head, body) = match response.find("\r\n\r\n") {
+            Some(i) => (&response[..i], &response[i + 4..]),
+            None => return Err(key_error(format!("Malformed KMS response for {}", key_ref)))
+        };
+        let status = head.lines().next()
+            .and_then(|l| l.split(' ').nth(1))
+            .and_then(|s| s.parse::<u32>().ok());
+        match status {
+            Some(s) => Ok((s, body.trim().to_string())),
+            None => Err(key_error(format!("Malformed KMS response for {}", key_ref)))
+        }
+    }
+}
+
+impl KeyProvider for HttpKmsKeyProvider {
+    fn get_key(&self, key_ref: &str) -> Result<SecretKey, Box<ZeroError>> {
+        match self.find_key(key_ref)? {
+            Some(key) => Ok(key),
+            None => Err(key_error(format!("KMS returned status 404 for key {}", key_ref)))
+        }
+    }
+
+    fn find_key(&self, key_ref: &str) -> Result<Option<SecretKey>, Box<ZeroError>> {
+        // refs become a segment of the request path, so keep them to a safe alphabet and never . or ..
+        if key_ref.is_empty() || key_ref == "." || key_ref == ".." ||
+            !key_ref.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.') {
+            return Err(key_error(format!("Invalid key-ref {}", key_ref)))
+        }
+
+        match self.request(key_ref)? {
+            (200, body) => to_key(key_ref, &hex_decode(&body)?).map(Some),
+            (404, _) => Ok(None),
+            (status, _) => Err(key_error(format!("KMS returned status {} for key {}", status, key_ref)))
+        }
+    }
+}
+
+/// Keys of the tenants of tables encrypted per tenant, fetched through the key provider as rows
+/// are read and written. Keys, and their absence, are kept for tenant-cache-ms. A worker thread
+/// reopens the provider and fetches the keys in use again every half of that time, so a destroyed
+/// key stops decrypting its tenant's rows within twice that time. The event loop never opens the
+/// provider and looks up at most tenant-fetch-rate new keys a second, statements needing others
+/// fail while the worker fetches them.
+pub struct TenantKeys {
+    state: Arc<TenantKeyState>
+}
+
+struct TenantKeyState {
+    // replaced when the config is reloaded
+    settings: Mutex<TenantKeySettings>,
+    // opened off the event loop and replaced by the worker, since a keystore holds the keys it was opened with
+    provider: Mutex<Option<Arc<KeyProvider>>>,
+    // when each key was fetched and whether it was used since
+    cache: Mutex<HashMap<String, (Instant, bool, Option<SecretKey>)>>,
+    // keys for the worker to fetch, it is woken when one is added
+    requests: Mutex<Vec<String>>,
+    wake: Condvar,
+    // the start of the current second and the keys looked up on the event loop in it
+    lookups: Mutex<(Instant, usize)>
+}
+
+#[derive(Clone)]
+struct TenantKeySettings {
+    config: KeyProviderConfig,
+    ttl: Duration,
+    // the most keys cached, as tenants are named by the rows clients send
+    size: usize,
+    // the most new keys looked up on the event loop each second
+    rate: usize
+}
+
+impl TenantKeys {
+
+    /// Opens the provider at startup, before the event loop runs
+    pub fn new(config: &KeyProviderConfig) -> Result<Self, Box<ZeroError>> {
+        let state = Arc::new(TenantKeyState {
+            settings: Mutex::new(TenantKeys::settings(config)?),
+            provider: Mutex::new(TenantKeys::open(config).map(Arc::from)),
+            cache: Mutex::new(HashMap::new()),
+            requests: Mutex::new(Vec::new()),
+            wake: Condvar::new(),
+            lookups: Mutex::new((Instant::now(), 0))
+        });
+
+        let weak = Arc::downgrade(&state);
+        thread::spawn(move || {
+            let mut refreshed = Instant::now();
+            loop {
+                let state = match weak.upgrade() {
+                    Some(state) => state,
+                    None => return
+                };
+                let interval = cmp::max(state.settings.lock().unwrap().ttl / 2, Duration::from_secs(1));
+                let requested = {
+                    let mut requests = state.requests.lock().unwrap();
+                    if requests.is_empty() {
+                        if let Some(remaining) = interval.checked_sub(refreshed.elapsed()) {
+                            requests = state.wake.wait_timeout(requests, remaining).unwrap().0;
+                        }
+                    }
+                    requests.drain(..).collect::<Vec<String>>()
+                };
+                if refreshed.elapsed() >= interval {
+                    if let Err(e) = state.refresh() {
+                        warn!("Unable to refresh tenant keys: {}", e);
+                    }
+                    refreshed = Instant::now();
+                }
+                if let Err(e) = state.fetch(requested) {
+                    warn!("Unable to fetch tenant keys: {}", e);
+                }
+            }
+        });
+
+        Ok(TenantKeys{state: state})
+    }
+
+    /// The provider of a config naming one, opened by the caller's thread as opening a keystore
+    /// derives its key. A provider failing to open is opened again by the worker when keys are needed.
+    pub fn open(config: &KeyProviderConfig) -> Option<Box<KeyProvider>> {
+        if !config.props.contains_key("type") {
+            return None
+        }
+        match create_key_provider(config) {
+            Ok(provider) => Some(provider),
+            Err(e) => {
+                warn!("Unable to open key provider for tenant keys: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Uses a reloaded key provider config and the provider opened for it, dropping every cached key
+    pub fn reload(&self, config: &KeyProviderConfig, provider: Option<Box<KeyProvider>>) -> Result<(), Box<ZeroError>> {
+        *self.state.settings.lock().unwrap() = TenantKeys::settings(config)?;
+        *self.state.provider.lock().unwrap() = provider.map(Arc::from);
+        self.state.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn settings(config: &KeyProviderConfig) -> Result<TenantKeySettings, Box<ZeroError>> {
+        let ttl = match config.props.get("tenant-cache-ms") {
+            Some(t) => match t.parse::<u64>() {
+                Ok(t) => t,
+                Err(_) => return Err(key_error(format!("Invalid tenant-cache-ms {}", t)))
+            },
+            None => DEFAULT_TENANT_CACHE_MS
+        };
+        let size = match config.props.get("tenant-cache-size") {
+            Some(s) => match s.parse::<usize>() {
+                Ok(s) if s > 0 => s,
+                _ => return Err(key_error(format!("Invalid tenant-cache-size {}", s)))
+            },
+            None => DEFAULT_TENANT_CACHE_SIZE
+        };
+        let rate = match config.props.get("tenant-fetch-rate") {
+            Some(r) => match r.parse::<usize>() {
+                Ok(r) => r,
+                Err(_) => return Err(key_error(format!("Invalid tenant-fetch-rate {}", r)))
+            },
+            None => DEFAULT_TENANT_FETCH_RATE
+        };
+        Ok(TenantKeySettings{config: config.clone(), ttl: Duration::from_millis(ttl), size: size, rate: rate})
+    }
+
+    /// The key named key_ref, None once it has been destroyed
+    pub fn find(&self, key_ref: &str) -> Result<Option<SecretKey>, Box<ZeroError>> {
+        let settings = self.state.settings.lock().unwrap().clone();
+        if let Some(&mut (at, ref mut used, ref key)) = self.state.cache.lock().unwrap().get_mut(key_ref) {
+            if at.elapsed() < settings.ttl {
+                *used = true;
+                return Ok(key.clone())
+            }
+        }
+
+        // tenants are named by the rows clients send, so only a few lookups a second block the event loop
+        let provider = self.state.provider.lock().unwrap().clone();
+        let provider = match provider {
+            Some(p) => if self.state.lookup(&settings) { p } else { return Err(self.state.request(key_ref)) },
+            None => return Err(self.state.request(key_ref))
+        };
+        let key = provider.find_key(key_ref)?;
+        self.state.insert(key_ref, key.clone(), &settings);
+        Ok(key)
+    }
+
+    /// Reopens the key provider and fetches the keys used since the last refresh again, as the worker does
+    pub fn refresh(&self) -> Result<(), Box<ZeroError>> {
+        self.state.refresh()
+    }
+
+    /// The keys of a tenant's values of a column encrypted per tenant, None once the tenant's key has been destroyed
+    pub fn keys(&self, aad: &AadBinding, tenant: &str) -> Result<Option<KeyRing>, Box<ZeroError>> {
+        match aad.tenant {
+            Some(ref t) => Ok(self.find(&t.key_ref(tenant))?.map(|k| aad.tenant_keys(&k))),
+            None => Ok(None)
+        }
+    }
+}
+
+impl TenantKeyState {
+
+    // counts a lookup on the event loop, false once the second's are used up
+    fn lookup(&self, settings: &TenantKeySettings) -> bool {
+        let mut lookups = self.lookups.lock().unwrap();
+        if lookups.0.elapsed() >= Duration::from_secs(1) {
+            *lookups = (Instant::now(), 0);
+        }
+        if lookups.1 >= settings.rate {
+            return false
+        }
+        lookups.1 += 1;
+        true
+    }
+
+    // leaves a key to the worker, failing the statement needing it
+    fn request(&self, key_ref: &str) -> Box<ZeroError> {
+        let mut requests = self.requests.lock().unwrap();
+        if !requests.iter().any(|r| r == key_ref) {
+            requests.push(key_ref.to_string());
+        }
+        self.wake.notify_one();
+        key_error(format!("Key {} is being fetched from the key provider, retry the statement", key_ref))
+    }
+
+    // fetches requested keys on the worker, opening the provider if it is not open
+    fn fetch(&self, key_refs: Vec<String>) -> Result<(), Box<ZeroError>> {
+        if key_refs.is_empty() {
+            return Ok(())
+        }
+        let settings = self.settings.lock().unwrap().clone();
+        let open = self.provider.lock().unwrap().clone();
+        let provider = match open {
+            Some(p) => p,
+            None => {
+                let p: Arc<KeyProvider> = Arc::from(create_key_provider(&settings.config)?);
+                if self.settings.lock().unwrap().config.props != settings.config.props {
+                    return Ok(())
+                }
+                *self.provider.lock().unwrap() = Some(p.clone());
+                p
+            }
+        };
+        for key_ref in key_refs.iter() {
+            let key = provider.find_key(key_ref)?;
+            // a reload while the key was fetched wins
+            if self.settings.lock().unwrap().config.props != settings.config.props {
+                return Ok(())
+            }
+            self.insert(key_ref, key, &settings);
+        }
+        Ok(())
+    }
+
+    fn refresh(&self) -> Result<(), Box<ZeroError>> {
+        let settings = self.settings.lock().unwrap().clone();
+        let used = {
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, &mut (at, used, _)| used || at.elapsed() < settings.ttl);
+            cache.iter().filter(|&(_, &(_, used, _))| used).map(|(r, _)| r.clone()).collect::<Vec<String>>()
+        };
+        if used.is_empty() && self.provider.lock().unwrap().is_none() {
+            return Ok(())
+        }
+
+        let provider = create_key_provider(&settings.config)?;
+        let mut keys = Vec::with_capacity(used.len());
+        for key_ref in used.into_iter() {
+            let key = provider.find_key(&key_ref)?;
+            keys.push((key_ref, key));
+        }
+
+        // a reload while the keys were fetched wins
+        if self.settings.lock().unwrap().config.props != settings.config.props {
+            return Ok(())
+        }
+        *self.provider.lock().unwrap() = Some(Arc::from(provider));
+        for (key_ref, key) in keys.into_iter() {
+            self.insert(&key_ref, key, &settings);
+        }
+        Ok(())
+    }
+
+    // evicts the longest cached key when full
+    fn insert(&self, key_ref: &str, key: Option<SecretKey>, settings: &TenantKeySettings) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= settings.size && !cache.contains_key(key_ref) {
+            let oldest = cache.iter().min_by_key(|&(_, &(at, _, _))| at).map(|(r, _)| r.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(key_ref.to_string(), (Instant::now(), false, key));
+    }
+}
+
+fn to_key(key_ref: &str, bytes: &[u8]) -> Result<SecretKey, Box<ZeroError>> {
+    if bytes.len() != 32 {
+        return Err(key_error(format!("Key {} must be 256 bits, received {} bytes", key_ref, bytes.len())))
+    }
+    Ok(SecretKey::from_slice(bytes).unwrap())
+}
+
+fn get_prop(config: &KeyProviderConfig, name: &str) -> Result<String, Box<ZeroError>> {
+    match config.props.get(name) {
+        Some(v) => Ok(v.clone()),
+        None => Err(key_error(format!("key-provider property {} is required", name)))
+    }
+}
+
+fn key_error(message: String) -> Box<ZeroError> {
+    ZeroError::EncryptionError {
+        message: message,
+        code: "1064".into()
+    }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{KeyProviderConfig, MasterKeyConfig};
+    use secret::SecretKey;
+    use std::collections::HashMap;
+    use std::env;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use rand::{Rng, OsRng};
+
+    fn temp_path(name: &str) -> String {
+        let suffix = OsRng::new().unwrap().next_u32();
+        env::temp_dir().join(format!("{}-{}", name, suffix)).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_keystore() {
+        let path = temp_path("zero-keystore");
+        let mut keys = HashMap::new();
+        keys.insert(String::from("customer-pii"), SecretKey::new([7u8; 32]));
+        keys.insert(String::from("payments"), SecretKey::new([9u8; 32]));
+        write_keystore(&path, "correct horse", 10, &keys).unwrap();
+
+        let ks = KeystoreKeyProvider::open(&path, "correct horse").unwrap();
+        assert_eq!(SecretKey::new([7u8; 32]), ks.get_key("customer-pii").unwrap());
+        assert_eq!(SecretKey::new([9u8; 32]), ks.get_key("payments").unwrap());
+        assert!(ks.get_key("missing").is_err());
+
+        assert!(KeystoreKeyProvider::open(&path, "battery staple").is_err());
+
+        // a tampered iteration count is an error rather than a failed derivation
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        let tampered = temp_path("zero-keystore-tampered");
+        File::create(&tampered).unwrap().write_all(contents.replacen("pbkdf2-sha256 10 ", "pbkdf2-sha256 0 ", 1).as_bytes()).unwrap();
+        assert!(KeystoreKeyProvider::open(&tampered, "correct horse").is_err());
+
+        // resolved through the config, passphrase taken from the named variable
+        env::set_var("ZERO_TEST_KEYSTORE_PASSPHRASE", "correct horse");
+        let mut props = HashMap::new();
+        props.insert(String::from("type"), String::from("keystore"));
+        props.insert(String::from("path"), path.clone());
+        props.insert(String::from("passphrase-env"), String::from("ZERO_TEST_KEYSTORE_PASSPHRASE"));
+        let provider = create_key_provider(&KeyProviderConfig{props: props}).unwrap();
+        assert_eq!(SecretKey::new([9u8; 32]), provider.get_key("payments").unwrap());
+    }
+
+    #[test]
+    fn test_wrap_key() {
+        let master = SecretKey::new([1u8; 32]);
+        let other = SecretKey::new([2u8; 32]);
+        let wrapped = wrap_key(&master, &SecretKey::new([5u8; 32])).unwrap();
+        assert_eq!(120, wrapped.len());
+        assert_eq!(SecretKey::new([5u8; 32]), unwrap_key(&master, &wrapped).unwrap());
+        assert!(unwrap_key(&other, &wrapped).is_err());
+
+        // rotating the master key only rewraps, the data key is unchanged
+        let rotated = wrap_key(&other, &unwrap_key(&master, &wrapped).unwrap()).unwrap();
+        assert_eq!(SecretKey::new([5u8; 32]), unwrap_key(&other, &rotated).unwrap());
+
+        env::set_var("ZERO_TEST_MASTER_PASSPHRASE", "open sesame");
+        let mut props = HashMap::new();
+        props.insert(String::from("type"), String::from("passphrase"));
+        props.insert(String::from("passphrase-env"), String::from("ZERO_TEST_MASTER_PASSPHRASE"));
+        props.insert(String::from("salt"), String::from("00112233445566778899AABBCCDDEEFF"));
+        props.insert(String::from("iterations"), String::from("10"));
+        let mk = MasterKeyConfig{props: props};
+        let kp = KeyProviderConfig{props: HashMap::new()};
+        let a = load_master_key(&mk, &kp).unwrap();
+        assert_eq!(a, load_master_key(&mk, &kp).unwrap());
+        assert!(a != SecretKey::new([0u8; 32]));
+
+        let mut props = mk.props.clone();
+        props.insert(String::from("iterations"), String::from("0"));
+        assert!(load_master_key(&MasterKeyConfig{props: props}, &kp).is_err());
+    }
+
+    // answers each request on its own connection, requiring the bearer token
+    fn mock_kms(requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).unwrap();
+                let req = String::from_utf8_lossy(&buf[..n]).to_string();
+
+                let response = if !req.contains("Authorization: Bearer s3cret\r\n") {
+                    "HTTP/1.0 401 Unauthorized\r\n\r\n".to_string()
+                } else if req.starts_with("GET /v1/keys/customer-pii ") {
+                    format!("HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n{}\n", "2A".repeat(32))
+                } else {
+                    "HTTP/1.0 404 Not Found\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{}/v1/keys/", addr)
+    }
+
+    #[test]
+    fn test_http_kms() {
+        let url = mock_kms(5);
+        let timeout = Duration::from_millis(2000);
+
+        let kms = HttpKmsKeyProvider::new(&url, Some("s3cret".into()), timeout).unwrap();
+        assert_eq!(SecretKey::new([0x2a; 32]), kms.get_key("customer-pii").unwrap());
+
+        match kms.get_key("payments") {
+            Err(e) => assert_eq!("[1064] KMS returned status 404 for key payments", format!("{}", e)),
+            _ => panic!("TEST FAIL")
+        }
+        assert_eq!(None, kms.find_key("payments").unwrap());
+
+        // refs that would escape the key path are never sent
+        assert!(kms.get_key("../admin").is_err());
+        assert!(kms.get_key("..").is_err());
+        assert!(kms.find_key(".").is_err());
+
+        let kms = HttpKmsKeyProvider::new(&url, Some("wrong".into()), timeout).unwrap();
+        match kms.get_key("customer-pii") {
+            Err(e) => assert_eq!("[1064] KMS returned status 401 for key customer-pii", format!("{}", e)),
+            _ => panic!("TEST FAIL")
+        }
+
+        let kms = HttpKmsKeyProvider::new(&url, None, timeout).unwrap();
+        assert!(kms.get_key("customer-pii").is_err());
+
+        assert!(HttpKmsKeyProvider::new("https://kms.example.com/keys", None, timeout).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keystore_mode() {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_path("zero-keystore-mode");
+        File::create(&path).unwrap().set_permissions(Permissions::from_mode(0o644)).unwrap();
+        write_keystore(&path, "correct horse", 10, &HashMap::new()).unwrap();
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+    }
+
+    #[test]
+    fn test_tenant_keys() {
+        let path = temp_path("zero-tenant-keystore");
+        let mut keys = HashMap::new();
+        keys.insert(String::from("tenant-1"), SecretKey::new([1u8; 32]));
+        keys.insert(String::from("tenant-2"), SecretKey::new([2u8; 32]));
+        write_keystore(&path, "correct horse", 10, &keys).unwrap();
+
+        env::set_var("ZERO_TEST_TENANT_PASSPHRASE", "correct horse");
+        let mut props = HashMap::new();
+        props.insert(String::from("type"), String::from("keystore"));
+        props.insert(String::from("path"), path.clone());
+        props.insert(String::from("passphrase-env"), String::from("ZERO_TEST_TENANT_PASSPHRASE"));
+        props.insert(String::from("tenant-cache-ms"), String::from("0"));
+        props.insert(String::from("tenant-fetch-rate"), String::from("100"));
+        let tenants = TenantKeys::new(&KeyProviderConfig{props: props.clone()}).unwrap();
+        let reload = |props: &HashMap<String, String>| {
+            let config = KeyProviderConfig{props: props.clone()};
+            tenants.reload(&config, TenantKeys::open(&config))
+        };
+        assert_eq!(Some(SecretKey::new([1u8; 32])), tenants.find("tenant-1").unwrap());
+        assert_eq!(None, tenants.find("tenant-3").unwrap());
+
+        // destroying a key takes effect once the provider is refreshed
+        keys.remove("tenant-1");
+        write_keystore(&path, "correct horse", 10, &keys).unwrap();
+        tenants.refresh().unwrap();
+        assert_eq!(None, tenants.find("tenant-1").unwrap());
+        assert_eq!(Some(SecretKey::new([2u8; 32])), tenants.find("tenant-2").unwrap());
+
+        // a reload drops keys cached for longer
+        props.insert(String::from("tenant-cache-ms"), String::from("60000"));
+        reload(&props).unwrap();
+        assert_eq!(Some(SecretKey::new([2u8; 32])), tenants.find("tenant-2").unwrap());
+        keys.insert(String::from("tenant-2"), SecretKey::new([4u8; 32]));
+        write_keystore(&path, "correct horse", 10, &keys).unwrap();
+        assert_eq!(Some(SecretKey::new([2u8; 32])), tenants.find("tenant-2").unwrap());
+        reload(&props).unwrap();
+        assert_eq!(Some(SecretKey::new([4u8; 32])), tenants.find("tenant-2").unwrap());
+
+        // keys in use are fetched again by a refresh
+        keys.insert(String::from("tenant-2"), SecretKey::new([5u8; 32]));
+        write_keystore(&path, "correct horse", 10, &keys).unwrap();
+        assert_eq!(Some(SecretKey::new([4u8; 32])), tenants.find("tenant-2").unwrap());
+        tenants.refresh().unwrap();
+        assert_eq!(Some(SecretKey::new([5u8; 32])), tenants.find("tenant-2").unwrap());
+
+        // the cache holds at most tenant-cache-size keys
+        props.insert(String::from("tenant-cache-size"), String::from("1"));
+        reload(&props).unwrap();
+        assert_eq!(None, tenants.find("tenant-1").unwrap());
+        assert_eq!(Some(SecretKey::new([5u8; 32])), tenants.find("tenant-2").unwrap());
+        assert_eq!(1, tenants.state.cache.lock().unwrap().len());
+
+        props.insert(String::from("tenant-cache-size"), String::from("0"));
+        assert!(reload(&props).is_err());
+        props.remove("tenant-cache-size");
+
+        // new keys beyond tenant-fetch-rate a second are left to the worker
+        props.insert(String::from("tenant-fetch-rate"), String::from("0"));
+        reload(&props).unwrap();
+        assert!(tenants.find("tenant-2").is_err());
+        let mut fetched = None;
+        for _ in 0..50 {
+            if let Ok(key) = tenants.find("tenant-2") {
+                fetched = Some(key);
+                break
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(Some(Some(SecretKey::new([5u8; 32]))), fetched);
+
+        props.insert(String::from("tenant-cache-ms"), String::from("soon"));
+        assert!(reload(&props).is_err());
+        assert!(TenantKeys::new(&KeyProviderConfig{props: props}).is_err());
+    }
+}
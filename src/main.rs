@@ -40,15 +40,27 @@ extern crate chrono;
 #[macro_use]
 extern crate decimal;
 
+extern crate rand;
+
+extern crate unicode_normalization;
+
 use std::str;
 use std::rc::Rc;
 use std::process;
+use std::env;
+use std::time;
 
+mod backend;
+mod secret;
 mod encrypt;
+mod nat;
+mod paillier;
+mod key_provider;
 mod config;
 mod proxy;
 mod error;
 mod query;
+mod rotate;
 
 pub const APP_NAME: &'static str = "AgilData Zero Gateway";
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -57,16 +69,40 @@ pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 pub struct Opts {
     pub ver: bool,
     pub cfg: String,
+    pub cfg_dir: String,
     pub log_cfg: String,
+    pub keystore_create: String,
+    pub wrap_keys: bool,
+    pub rewrap_keys: String,
+    pub rotate: String,
+    pub batch_size: u32,
+    pub throttle_ms: u64,
+    pub checkpoint: String,
+    pub convert_config: String,
+    pub check_config: bool,
+    pub watch_config: bool,
 }
 
 fn main() {
+    use config::TConfig;
+
     // env::set_var("RUST_BACKTRACE", "1");
 
     let mut opt = Opts {
         ver: false,
         cfg: String::from("zero-config.xml"),
+        cfg_dir: String::from("/etc/zero.d/"),
         log_cfg: String::from("log.toml"),
+        keystore_create: String::new(),
+        wrap_keys: false,
+        rewrap_keys: String::new(),
+        rotate: String::new(),
+        batch_size: 1000,
+        throttle_ms: 0,
+        checkpoint: String::new(),
+        convert_config: String::new(),
+        check_config: false,
+        watch_config: false,
     };
 
     let dsc = format!("{} version {}", APP_NAME, VERSION);
@@ -78,10 +114,44 @@ fn main() {
             "show version number and exit");
         ap.refer(&mut opt.cfg)
             .add_option(&["-C", "--config"], Store,
-            "path to configuration file defaults to ./zero-config.xml");
+            "path to configuration file, xml or .toml, defaults to ./zero-config.xml");
+        ap.refer(&mut opt.cfg_dir)
+            .add_option(&["-D", "--config-dir"], Store,
+            "directory of .xml and .toml override configs, merged in file name order, defaults to /etc/zero.d/");
         ap.refer(&mut opt.log_cfg)
             .add_option(&["-L", "--logconfig"], Store,
             "path to logging configuration file defaults to ./log.toml");
+        ap.refer(&mut opt.keystore_create)
+            .add_option(&["--keystore-create"], Store,
+            "write 'name hexkey' lines read from stdin to an encrypted keystore at this path and exit, \
+            the passphrase is read from ZERO_KEYSTORE_PASSPHRASE");
+        ap.refer(&mut opt.wrap_keys)
+            .add_option(&["--wrap-keys"], StoreTrue,
+            "wrap hex data keys read from stdin with the configured master key and exit");
+        ap.refer(&mut opt.rewrap_keys)
+            .add_option(&["--rewrap-keys"], Store,
+            "print every wrapped-key in the configuration rewrapped with the hex master key in this file and exit");
+        ap.refer(&mut opt.rotate)
+            .add_option(&["--rotate"], Store,
+            "re-encrypt schema.table under the newest column key versions and exit");
+        ap.refer(&mut opt.batch_size)
+            .add_option(&["--batch-size"], Store,
+            "rows per --rotate batch, defaults to 1000");
+        ap.refer(&mut opt.throttle_ms)
+            .add_option(&["--throttle-ms"], Store,
+            "pause between --rotate batches in milliseconds, defaults to 0");
+        ap.refer(&mut opt.checkpoint)
+            .add_option(&["--checkpoint"], Store,
+            "--rotate progress file, defaults to ./zero-rotate-<schema>.<table>.checkpoint");
+        ap.refer(&mut opt.convert_config)
+            .add_option(&["--convert-config"], Store,
+            "print the xml configuration file at this path as toml and exit");
+        ap.refer(&mut opt.check_config)
+            .add_option(&["--check-config"], StoreTrue,
+            "validate the configuration file and its overrides, print every problem found and exit");
+        ap.refer(&mut opt.watch_config)
+            .add_option(&["--watch-config"], StoreTrue,
+            "reload the configuration when it or its overrides change, as well as on SIGHUP");
         ap.parse_args_or_exit();
     }
 
@@ -90,18 +160,220 @@ fn main() {
         process::exit(0);
     }
 
+    if !opt.keystore_create.is_empty() {
+        create_keystore(&opt.keystore_create);
+        process::exit(0);
+    }
+
+    if !opt.convert_config.is_empty() {
+        match config::xml_to_toml(&opt.convert_config) {
+            Ok(toml) => print!("{}", toml),
+            Err(e) => {
+                println!("{}", e);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
+    let parsed = config::parse_configs(&opt.cfg, &opt.cfg_dir);
+    if opt.check_config {
+        match parsed {
+            Ok(_) => println!("Configuration OK"),
+            Err(errors) => {
+                for e in errors.iter() {
+                    println!("{}", e);
+                }
+                println!("{} problem{} found", errors.len(), if errors.len() == 1 { "" } else { "s" });
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
     if log4rs::init_file(&opt.log_cfg, Default::default()).is_err() {
         println!("Unable to open logging configuration file: {}", opt.log_cfg);
         process::exit(1);
     }
 
     info!("{}", dsc);
+    info!("Crypto backend: {}", backend::backend().name());
+
+    let config = match parsed {
+        Ok(c) => c,
+        Err(errors) => {
+            for e in errors.iter() {
+                error!("{}", e);
+                println!("{}", e);
+            }
+            process::exit(1);
+        }
+    };
 
-    // TODO decide on a dirname for override configs
-    let config = config::parse_configs(&opt.cfg, "/etc/zero.d/");
+    if opt.wrap_keys {
+        wrap_keys(&config);
+        process::exit(0);
+    }
+    if !opt.rewrap_keys.is_empty() {
+        rewrap_keys(&config, &opt.rewrap_keys);
+        process::exit(0);
+    }
+    if !opt.rotate.is_empty() {
+        rotate_table(&config, &opt);
+        process::exit(0);
+    }
+
+    config.derive_paillier_keys();
     let config = Rc::new(config);
     let provider = proxy::schema_provider::MySQLBackedSchemaProvider::new(config.clone());
     let stmt_cache = proxy::statement_cache::StatementCache::new();
-    proxy::server::Proxy::run(config, Rc::new(provider), Rc::new(stmt_cache));
+    let tenant_keys = match key_provider::TenantKeys::new(config.get_key_provider_config()) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    let provider = Rc::new(provider);
+    let stmt_cache = Rc::new(stmt_cache);
+    let tenant_keys = Rc::new(tenant_keys);
+    let reloader = proxy::reload::ConfigReloader::new(&opt.cfg, &opt.cfg_dir, opt.watch_config,
+        provider.clone(), stmt_cache.clone(), tenant_keys.clone());
+    proxy::server::Proxy::run(config, provider, stmt_cache, tenant_keys, Rc::new(reloader));
+
+}
+
+fn create_keystore(path: &str) {
+    use std::io::{self, BufRead};
+    use std::collections::HashMap;
+
+    let passphrase = match env::var("ZERO_KEYSTORE_PASSPHRASE") {
+        Ok(p) => p,
+        Err(_) => {
+            println!("ZERO_KEYSTORE_PASSPHRASE must be set to create a keystore");
+            process::exit(1);
+        }
+    };
+
+    let mut keys = HashMap::new();
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap();
+        if line.trim().is_empty() {
+            continue;
+        }
+        match key_provider::parse_key_line(&line) {
+            Ok((name, key)) => { keys.insert(name, key); },
+            Err(e) => {
+                println!("Invalid key line: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Err(e) = key_provider::write_keystore(path, &passphrase, key_provider::DEFAULT_KEYSTORE_ITERATIONS, &keys) {
+        println!("{}", e);
+        process::exit(1);
+    }
+    println!("Wrote {} keys to {}", keys.len(), path);
+}
+
+fn wrap_keys(config: &config::Config) {
+    use std::io::{self, BufRead};
+    use config::TConfig;
+
+    let master = match key_provider::load_master_key(config.get_master_key_config(), config.get_key_provider_config()) {
+        Ok(k) => k,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let mut line = line.unwrap();
+        if line.trim().is_empty() {
+            continue;
+        }
+        // the hex keys read are wiped along with the keys parsed from them
+        let mut key_line = format!("key {}", line.trim());
+        let wrapped = key_provider::parse_key_line(&key_line).and_then(|(_, key)| key_provider::wrap_key(&master, &key));
+        unsafe {
+            secret::wipe(line.as_bytes_mut());
+            secret::wipe(key_line.as_bytes_mut());
+        }
+        match wrapped {
+            Ok(wrapped) => println!("{}", wrapped),
+            Err(e) => {
+                println!("Invalid key: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
 
+// master key rotation, the data keys and so the table data are unchanged
+fn rewrap_keys(config: &config::Config, master_path: &str) {
+    use std::collections::HashMap;
+
+    let mut props = HashMap::new();
+    props.insert(String::from("type"), String::from("file"));
+    props.insert(String::from("path"), String::from(master_path));
+    let master = match key_provider::load_master_key(&config::MasterKeyConfig{props: props}, &config::KeyProviderConfig{props: HashMap::new()}) {
+        Ok(k) => k,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    for (schema, table, column) in config.column_configs() {
+        let (current, _) = column.key.current();
+        let mut versions = vec![];
+        if column.wrapped_key.is_some() {
+            versions.push(current);
+        }
+        for p in column.previous_keys.iter().filter(|p| p.wrapped_key.is_some()) {
+            versions.push(p.version);
+        }
+
+        for v in versions {
+            match key_provider::wrap_key(&master, column.key.get(v).unwrap()) {
+                Ok(wrapped) => println!("{}.{}.{} version {} {}", schema, table, column.name, v, wrapped),
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+fn rotate_table(config: &config::Config, opt: &Opts) {
+    let parts = opt.rotate.split('.').collect::<Vec<&str>>();
+    if parts.len() != 2 || opt.batch_size == 0 {
+        println!("--rotate expects schema.table and a non zero --batch-size");
+        process::exit(1);
+    }
+
+    let opts = rotate::RotateOpts {
+        batch_size: opt.batch_size,
+        throttle: time::Duration::from_millis(opt.throttle_ms),
+        checkpoint: if opt.checkpoint.is_empty() {
+            format!("zero-rotate-{}.checkpoint", opt.rotate)
+        } else {
+            opt.checkpoint.clone()
+        }
+    };
+
+    let pool = proxy::schema_provider::create_pool(config);
+    match rotate::plan(config, parts[0], parts[1]).and_then(|plan| rotate::rotate_table(&pool, &plan, &opts)) {
+        Ok(stats) => println!("Rotated {}: {} rows, {} values rewritten, {} changed concurrently",
+            opt.rotate, stats.rows, stats.rewritten, stats.conflicts),
+        Err(e) => {
+            println!("Rotation of {} stopped, rerun to resume: {}", opt.rotate, e);
+            process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,286 @@
+// Copyright 2016 AgilData
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http:// www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unsigned integers of any size, for the arithmetic of Paillier and FF1
+
+use std::cmp::Ordering;
+use std::ptr;
+use std::sync::atomic;
+
+/// Unsigned integer of any size, little endian 32 bit limbs without high zero limbs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nat {
+    limbs: Vec<u32>
+}
+
+impl Nat {
+    pub fn zero() -> Nat {
+        Nat{limbs: vec![]}
+    }
+
+    pub fn from_u64(v: u64) -> Nat {
+        Nat::from_limbs(vec![v as u32, (v >> 32) as u32])
+    }
+
+    pub fn from_limbs(mut limbs: Vec<u32>) -> Nat {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        Nat{limbs: limbs}
+    }
+
+    /// Big endian bytes
+    pub fn from_bytes(b: &[u8]) -> Nat {
+        let mut limbs = vec![0u32; (b.len() + 3) / 4];
+        for (i, byte) in b.iter().rev().enumerate() {
+            limbs[i / 4] |= (*byte as u32) << (8 * (i % 4));
+        }
+        Nat::from_limbs(limbs)
+    }
+
+    /// Big endian bytes, left padded to len
+    pub fn to_bytes(&self, len: usize) -> Vec<u8> {
+        let mut out = vec![0u8; len];
+        for i in 0..len {
+            if let Some(limb) = self.limbs.get(i / 4) {
+                out[len - 1 - i] = (limb >> (8 * (i % 4))) as u8;
+            }
+        }
+        out
+    }
+
+    pub fn from_decimal(s: &str) -> Option<Nat> {
+        if s.is_empty() {
+            return None
+        }
+        let mut n = Nat::zero();
+        for c in s.chars() {
+            match c.to_digit(10) {
+                Some(d) => n = n.mul_add_small(10, d),
+                None => return None
+            }
+        }
+        Some(n)
+    }
+
+    pub fn to_decimal(&self) -> String {
+        let mut chunks = Vec::new();
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (q, r) = n.divrem_small(1000000000);
+            chunks.push(r);
+            n = q;
+        }
+        match chunks.pop() {
+            Some(top) => chunks.iter().rev().fold(format!("{}", top), |s, c| format!("{}{:09}", s, c)),
+            None => "0".into()
+        }
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs.len() {
+            0 => Some(0),
+            1 => Some(self.limbs[0] as u64),
+            2 => Some(((self.limbs[1] as u64) << 32) | self.limbs[0] as u64),
+            _ => None
+        }
+    }
+
+    pub fn limbs(&self) -> &[u32] {
+        &self.limbs
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * 32 - top.leading_zeros() as usize,
+            None => 0
+        }
+    }
+
+    pub fn bit(&self, i: usize) -> bool {
+        self.limbs.get(i / 32).map_or(false, |l| (l >> (i % 32)) & 1 == 1)
+    }
+
+    pub fn add(&self, o: &Nat) -> Nat {
+        let (long, short) = if self.limbs.len() >= o.limbs.len() { (self, o) } else { (o, self) };
+        let mut out = Vec::with_capacity(long.limbs.len() + 1);
+        let mut carry = 0u64;
+        for (i, l) in long.limbs.iter().enumerate() {
+            let t = *l as u64 + *short.limbs.get(i).unwrap_or(&0) as u64 + carry;
+            out.push(t as u32);
+            carry = t >> 32;
+        }
+        out.push(carry as u32);
+        Nat::from_limbs(out)
+    }
+
+    /// self - o, which must not be negative
+    pub fn sub(&self, o: &Nat) -> Nat {
+        let mut out = self.clone();
+        out.sub_assign(o);
+        out
+    }
+
+    pub fn sub_assign(&mut self, o: &Nat) {
+        assert!(*self >= *o, "Nat subtraction underflow");
+        let mut borrow = 0i64;
+        for i in 0..self.limbs.len() {
+            let t = self.limbs[i] as i64 - *o.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            self.limbs[i] = t as u32;
+            borrow = if t < 0 { 1 } else { 0 };
+        }
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    pub fn mul(&self, o: &Nat) -> Nat {
+        let mut out = vec![0u32; self.limbs.len() + o.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b) in o.limbs.iter().enumerate() {
+                let t = out[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                out[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            out[i + o.limbs.len()] = carry as u32;
+        }
+        Nat::from_limbs(out)
+    }
+
+    pub fn mul_add_small(&self, m: u32, a: u32) -> Nat {
+        let mut out = Vec::with_capacity(self.limbs.len() + 1);
+        let mut carry = a as u64;
+        for l in self.limbs.iter() {
+            let t = *l as u64 * m as u64 + carry;
+            out.push(t as u32);
+            carry = t >> 32;
+        }
+        out.push(carry as u32);
+        Nat::from_limbs(out)
+    }
+
+    pub fn divrem_small(&self, d: u32) -> (Nat, u32) {
+        let mut q = vec![0u32; self.limbs.len()];
+        let mut r = 0u64;
+        for i in (0..self.limbs.len()).rev() {
+            let cur = (r << 32) | self.limbs[i] as u64;
+            q[i] = (cur / d as u64) as u32;
+            r = cur % d as u64;
+        }
+        (Nat::from_limbs(q), r as u32)
+    }
+
+    /// Quotient and remainder, by shifting and subtracting a bit at a time
+    pub fn divrem(&self, d: &Nat) -> (Nat, Nat) {
+        assert!(!d.is_zero(), "Nat division by zero");
+        if self < d {
+            return (Nat::zero(), self.clone())
+        }
+        let mut q = vec![0u32; self.limbs.len()];
+        let mut r = Nat::zero();
+        for i in (0..self.bits()).rev() {
+            r.shl1(self.bit(i));
+            if r >= *d {
+                r.sub_assign(d);
+                q[i / 32] |= 1 << (i % 32);
+            }
+        }
+        (Nat::from_limbs(q), r)
+    }
+
+    pub fn rem(&self, d: &Nat) -> Nat {
+        self.divrem(d).1
+    }
+
+    // shifts left one bit, shifting in low
+    fn shl1(&mut self, low: bool) {
+        let mut carry = low as u32;
+        for l in self.limbs.iter_mut() {
+            let next = *l >> 31;
+            *l = (*l << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            self.limbs.push(carry);
+        }
+    }
+
+    pub fn shr1(&self) -> Nat {
+        let mut out = self.limbs.clone();
+        for i in 0..out.len() {
+            let high = out.get(i + 1).map_or(0, |h| h << 31);
+            out[i] = (out[i] >> 1) | high;
+        }
+        Nat::from_limbs(out)
+    }
+}
+
+impl Ord for Nat {
+    fn cmp(&self, o: &Nat) -> Ordering {
+        if self.limbs.len() != o.limbs.len() {
+            return self.limbs.len().cmp(&o.limbs.len())
+        }
+        for (a, b) in self.limbs.iter().rev().zip(o.limbs.iter().rev()) {
+            if a != b {
+                return a.cmp(b)
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Nat {
+    fn partial_cmp(&self, o: &Nat) -> Option<Ordering> {
+        Some(self.cmp(o))
+    }
+}
+
+// Paillier primes and plaintexts are held as Nats, so their limbs are wiped once dropped
+impl Drop for Nat {
+    fn drop(&mut self) {
+        for l in self.limbs.iter_mut() {
+            unsafe { ptr::write_volatile(l, 0); }
+        }
+        atomic::compiler_fence(atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_nat() {
+        let a = Nat::from_decimal("123456789012345678901234567890").unwrap();
+        let b = Nat::from_decimal("987654321987654321").unwrap();
+        assert_eq!("123456789012345678901234567890", a.to_decimal());
+        assert_eq!("0", Nat::zero().to_decimal());
+        assert_eq!(None, Nat::from_decimal("12a"));
+
+        let c = Nat::from_u64(12345);
+        let (q, r) = a.mul(&b).add(&c).divrem(&b);
+        assert_eq!(a, q);
+        assert_eq!(c, r);
+        assert_eq!(a, a.add(&b).sub(&b));
+        assert_eq!(a, Nat::from_bytes(&a.to_bytes(16)));
+        assert_eq!(Some(987654321987654321), b.to_u64());
+        assert_eq!(None, a.to_u64());
+    }
+}
@@ -0,0 +1,525 @@
+// Copyright 2016 AgilData
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http:// www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Paillier encryption, whose ciphertexts multiply to the encryption of the sum of their
+//! plaintexts. Keys are derived from a column key, so they need no configuration of their
+//! own, but deriving one means searching for two large primes so they are cached per thread.
+
+use backend::backend;
+use secret::{SecretKey, wipe};
+use std::cmp;
+use std::sync::{Arc, Mutex, MutexGuard};
+use rand::Rng;
+use nat::Nat;
+
+// Miller-Rabin rounds for each prime
+const PRIME_ROUNDS: usize = 20;
+// candidates are first checked for factors below this
+const SIEVE_LIMIT: u32 = 2000;
+
+// Montgomery multiplication modulo an odd modulus m, with R = 2^(32 * limbs of m)
+struct Montgomery {
+    m: Nat,
+    // -m^-1 mod 2^32
+    m_inv: u32,
+    // R mod m and R^2 mod m
+    r1: Nat,
+    r2: Nat
+}
+
+impl Montgomery {
+    fn new(m: &Nat) -> Montgomery {
+        // Newton's iteration doubles the correct low bits of the inverse each step
+        let mut inv = 1u32;
+        for _ in 0..5 {
+            inv = inv.wrapping_mul(2u32.wrapping_sub(m.limbs()[0].wrapping_mul(inv)));
+        }
+        let mut r = vec![0u32; m.limbs().len()];
+        r.push(1);
+        let r1 = Nat::from_limbs(r).rem(m);
+        Montgomery{m: m.clone(), m_inv: 0u32.wrapping_sub(inv), r2: r1.mul(&r1).rem(m), r1: r1}
+    }
+
+    // a * b / R mod m, for a and b less than m
+    fn mul(&self, a: &Nat, b: &Nat) -> Nat {
+        let s = self.m.limbs().len();
+        let m = self.m.limbs();
+        let mut a = a.limbs().to_vec();
+        a.resize(s, 0);
+        let mut t = vec![0u32; s + 2];
+        for i in 0..s {
+            let bi = *b.limbs().get(i).unwrap_or(&0) as u64;
+            let mut c = 0u64;
+            for j in 0..s {
+                let x = t[j] as u64 + a[j] as u64 * bi + c;
+                t[j] = x as u32;
+                c = x >> 32;
+            }
+            let x = t[s] as u64 + c;
+            t[s] = x as u32;
+            t[s + 1] = (x >> 32) as u32;
+
+            // add a multiple of m clearing the low limb, then shift it out
+            let q = t[0].wrapping_mul(self.m_inv) as u64;
+            let mut c = (t[0] as u64 + q * m[0] as u64) >> 32;
+            for j in 1..s {
+                let x = t[j] as u64 + q * m[j] as u64 + c;
+                t[j - 1] = x as u32;
+                c = x >> 32;
+            }
+            let x = t[s] as u64 + c;
+            t[s - 1] = x as u32;
+            t[s] = t[s + 1] + (x >> 32) as u32;
+            t[s + 1] = 0;
+        }
+
+        // t < 2m, subtract m unless that borrows, without branching on the result
+        let mut d = vec![0u32; s + 1];
+        let mut borrow = 0u64;
+        for j in 0..s + 1 {
+            let x = (t[j] as u64).wrapping_sub(*m.get(j).unwrap_or(&0) as u64).wrapping_sub(borrow);
+            d[j] = x as u32;
+            borrow = (x >> 63) & 1;
+        }
+        let keep = 0u32.wrapping_sub(borrow as u32);
+        for j in 0..s + 1 {
+            d[j] = (t[j] & keep) | (d[j] & !keep);
+        }
+        Nat::from_limbs(d)
+    }
+
+    // a * b mod m
+    fn mul_mod(&self, a: &Nat, b: &Nat) -> Nat {
+        self.mul(&self.mul(a, b), &self.r2)
+    }
+
+    // base^exp mod m, with a fixed window of four bits. Exponents are secret in decryption, so
+    // every window of the modulus's size is multiplied in and table entries are read by masking
+    // all of them, leaving the time and memory accesses independent of exponents below m.
+    fn pow(&self, base: &Nat, exp: &Nat) -> Nat {
+        let mut table = vec![self.r1.clone(), self.mul(&base.rem(&self.m), &self.r2)];
+        for i in 2..16 {
+            let next = self.mul(&table[i - 1], &table[1]);
+            table.push(next);
+        }
+        let mut acc = self.r1.clone();
+        for k in (0..(cmp::max(exp.bits(), self.m.bits()) + 3) / 4).rev() {
+            for _ in 0..4 {
+                acc = self.mul(&acc, &acc);
+            }
+            let w = (0..4).fold(0, |w, b| w | ((exp.bit(4 * k + b) as usize) << b));
+            acc = self.mul(&acc, &self.select(&table, w));
+        }
+        self.mul(&acc, &Nat::from_u64(1))
+    }
+
+    // table[w], reading every entry
+    fn select(&self, table: &[Nat], w: usize) -> Nat {
+        let mut out = vec![0u32; self.m.limbs().len()];
+        for (i, entry) in table.iter().enumerate() {
+            let d = (i ^ w) as u32;
+            let mask = ((d | d.wrapping_neg()) >> 31).wrapping_sub(1);
+            for (j, o) in out.iter_mut().enumerate() {
+                *o |= *entry.limbs().get(j).unwrap_or(&0) & mask;
+            }
+        }
+        Nat::from_limbs(out)
+    }
+}
+
+/// A Paillier key pair with g = n + 1. Encryption is (1 + mn) r^n mod n^2 for a random r,
+/// decryption and r^n are computed modulo the squares of the primes and recombined.
+pub struct PaillierKey {
+    bits: usize,
+    n: Nat,
+    half_n: Nat,
+    n2: Montgomery,
+    p: Nat,
+    q: Nat,
+    // modulo p^2 and q^2
+    pp: Montgomery,
+    qq: Montgomery,
+    // n reduced modulo the orders of the groups mod p^2 and q^2
+    n_p: Nat,
+    n_q: Nat,
+    // (q^2)^-1 mod p^2 and q^-1 mod p
+    qq_inv: Nat,
+    q_inv: Nat,
+    // L(g^(p-1) mod p^2)^-1 mod p, and likewise for q
+    h_p: Nat,
+    h_q: Nat
+}
+
+impl PaillierKey {
+    /// The key derived from a seed, always the same for the same seed and size
+    pub fn derive(seed: &[u8], bits: usize) -> PaillierKey {
+        assert!(bits >= 128 && bits % 64 == 0, "Paillier modulus must be a multiple of 64 bits");
+        let mut rng = Drbg::new(seed, bits);
+        let p = prime(&mut rng, bits / 2);
+        let mut q = prime(&mut rng, bits / 2);
+        while q == p {
+            q = prime(&mut rng, bits / 2);
+        }
+        PaillierKey::from_primes(bits, p, q)
+    }
+
+    fn from_primes(bits: usize, p: Nat, q: Nat) -> PaillierKey {
+        let one = Nat::from_u64(1);
+        let n = p.mul(&q);
+        let pp = Montgomery::new(&p.mul(&p));
+        let qq = Montgomery::new(&q.mul(&q));
+        let (phi_p, phi_q) = (p.mul(&p.sub(&one)), q.mul(&q.sub(&one)));
+        let g = n.add(&one);
+        let h_p = inverse(&l(&pp.pow(&g, &p.sub(&one)), &p), &p);
+        let h_q = inverse(&l(&qq.pow(&g, &q.sub(&one)), &q), &q);
+        PaillierKey {
+            bits: bits,
+            half_n: n.shr1(),
+            n2: Montgomery::new(&n.mul(&n)),
+            n_p: n.rem(&phi_p),
+            n_q: n.rem(&phi_q),
+            // Euler's theorem, q^2 is a unit mod p^2
+            qq_inv: pp.pow(&qq.m, &phi_p.sub(&one)),
+            q_inv: inverse(&q, &p),
+            h_p: h_p,
+            h_q: h_q,
+            n: n,
+            p: p,
+            q: q,
+            pp: pp,
+            qq: qq
+        }
+    }
+
+    pub fn n(&self) -> &Nat {
+        &self.n
+    }
+
+    /// Bytes of an encoded ciphertext
+    pub fn ciphertext_len(&self) -> usize {
+        self.bits / 4
+    }
+
+    /// A ciphertext from its encoding, if it is one
+    pub fn ciphertext(&self, b: &[u8]) -> Option<Nat> {
+        let c = Nat::from_bytes(b);
+        if b.len() == self.ciphertext_len() && !c.is_zero() && c < self.n2.m { Some(c) } else { None }
+    }
+
+    pub fn encrypt<R: Rng>(&self, m: &Nat, rng: &mut R) -> Nat {
+        assert!(*m < self.n, "Paillier plaintext must be less than n");
+        let mut r = Nat::zero();
+        while r.is_zero() {
+            r = random_below(rng, &self.n);
+        }
+        let r_n = self.crt_squares(&self.pp.pow(&r, &self.n_p), &self.qq.pow(&r, &self.n_q));
+        let g_m = m.mul(&self.n).add(&Nat::from_u64(1));
+        self.n2.mul_mod(&g_m, &r_n)
+    }
+
+    pub fn decrypt(&self, c: &Nat) -> Nat {
+        let one = Nat::from_u64(1);
+        let m_p = l(&self.pp.pow(c, &self.p.sub(&one)), &self.p).mul(&self.h_p).rem(&self.p);
+        let m_q = l(&self.qq.pow(c, &self.q.sub(&one)), &self.q).mul(&self.h_q).rem(&self.q);
+        m_q.add(&crt_step(&m_p, &m_q, &self.p, &self.q_inv).mul(&self.q))
+    }
+
+    /// The ciphertext of the sum of two plaintexts
+    pub fn add(&self, a: &Nat, b: &Nat) -> Nat {
+        self.n2.mul_mod(a, b)
+    }
+
+    /// Plaintexts are integers mod n, negative values are stored as n - |v|
+    pub fn encode(&self, negative: bool, magnitude: Nat) -> Nat {
+        if negative && !magnitude.is_zero() { self.n.sub(&magnitude) } else { magnitude }
+    }
+
+    /// The sign and magnitude of a plaintext, those above n / 2 are negative
+    pub fn decode(&self, m: Nat) -> (bool, Nat) {
+        if m > self.half_n { (true, self.n.sub(&m)) } else { (false, m) }
+    }
+
+    // x mod n^2 from x mod p^2 and x mod q^2
+    fn crt_squares(&self, x_p: &Nat, x_q: &Nat) -> Nat {
+        x_q.add(&crt_step(x_p, x_q, &self.pp.m, &self.qq_inv).mul(&self.qq.m))
+    }
+}
+
+// Garner's step, ((x_p - x_q) * inv) mod m
+fn crt_step(x_p: &Nat, x_q: &Nat, m: &Nat, inv: &Nat) -> Nat {
+    let x_q = x_q.rem(m);
+    let diff = if *x_p >= x_q { x_p.sub(&x_q) } else { x_p.add(m).sub(&x_q) };
+    diff.mul(inv).rem(m)
+}
+
+// L(x) = (x - 1) / p
+fn l(x: &Nat, p: &Nat) -> Nat {
+    if x.is_zero() { Nat::zero() } else { x.sub(&Nat::from_u64(1)).divrem(p).0 }
+}
+
+// a^-1 mod a prime p, by Fermat's little theorem
+fn inverse(a: &Nat, p: &Nat) -> Nat {
+    Montgomery::new(p).pow(a, &p.sub(&Nat::from_u64(2)))
+}
+
+/// Running homomorphic sum of ciphertexts under one key. The product is kept with a
+/// factor of R^-1 per ciphertext, so each costs a single Montgomery multiplication.
+pub struct Accumulator {
+    key: Arc<PaillierKey>,
+    product: Nat,
+    count: u64
+}
+
+impl Accumulator {
+    pub fn new(key: Arc<PaillierKey>) -> Accumulator {
+        Accumulator{key: key, product: Nat::from_u64(1), count: 0}
+    }
+
+    pub fn key(&self) -> &PaillierKey {
+        &self.key
+    }
+
+    /// Adds a ciphertext checked by PaillierKey::ciphertext
+    pub fn add(&mut self, c: &Nat) {
+        self.product = self.key.n2.mul(&self.product, c);
+        self.count += 1;
+    }
+
+    /// The ciphertext of the sum
+    pub fn sum(&self) -> Nat {
+        let n2 = &self.key.n2;
+        n2.mul(&self.product, &n2.pow(&n2.r1, &Nat::from_u64(self.count + 1)))
+    }
+}
+
+// keyed by a hash of the column key rather than the key itself, derived by the thread loading
+// a config and shared with the event loop using it
+static KEYS: Mutex<Vec<([u8; 32], usize, Arc<PaillierKey>)>> = Mutex::new(Vec::new());
+
+// a panic while the keys are held cannot leave them inconsistent, so ignore poisoning
+fn lock_keys() -> MutexGuard<'static, Vec<([u8; 32], usize, Arc<PaillierKey>)>> {
+    match KEYS.lock() {
+        Ok(keys) => keys,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+fn find(keys: &Vec<([u8; 32], usize, Arc<PaillierKey>)>, id: &[u8; 32], bits: usize) -> Option<Arc<PaillierKey>> {
+    keys.iter().find(|&&(ref i, b, _)| i == id && b == bits).map(|&(_, _, ref k)| k.clone())
+}
+
+/// The key derived from a column key, derived once and shared by every thread
+pub fn key(seed: &[u8], bits: usize) -> Arc<PaillierKey> {
+    let id = backend().hmac_sha256(seed, &[b"zero-paillier-id"]);
+    if let Some(key) = find(&lock_keys(), &id, bits) {
+        return key
+    }
+    // derived without holding the lock, so keys already derived stay available meanwhile
+    let key = Arc::new(PaillierKey::derive(seed, bits));
+    let mut keys = lock_keys();
+    match find(&keys, &id, bits) {
+        Some(existing) => existing,
+        None => {
+            keys.push((id, bits, key.clone()));
+            key
+        }
+    }
+}
+
+/// Drops the keys derived from any other column keys, their primes are wiped once the sums
+/// still using them finish
+pub fn retain(seeds: &[&SecretKey]) {
+    let ids = seeds.iter().map(|s| backend().hmac_sha256(s, &[b"zero-paillier-id"])).collect::<Vec<[u8; 32]>>();
+    lock_keys().retain(|&(ref id, _, _)| ids.contains(id));
+}
+
+// A random number below bound, from 64 more bits than it has so the bias is negligible
+fn random_below<R: Rng>(rng: &mut R, bound: &Nat) -> Nat {
+    let mut bytes = vec![0u8; (bound.bits() + 7) / 8 + 8];
+    rng.fill_bytes(&mut bytes);
+    Nat::from_bytes(&bytes).rem(bound)
+}
+
+// The first probable prime of the given size at or after a random odd number with its two
+// high bits set, so the product of two always has twice the bits
+fn prime<R: Rng>(rng: &mut R, bits: usize) -> Nat {
+    let small = small_primes();
+    let two = Nat::from_u64(2);
+    loop {
+        let mut bytes = vec![0u8; bits / 8];
+        rng.fill_bytes(&mut bytes);
+        bytes[0] |= 0xc0;
+        bytes[bits / 8 - 1] |= 1;
+        let mut candidate = Nat::from_bytes(&bytes);
+        wipe(&mut bytes);
+        while candidate.bits() == bits {
+            if small.iter().all(|s| candidate.divrem_small(*s).1 != 0) && probably_prime(&candidate, rng, PRIME_ROUNDS) {
+                return candidate
+            }
+            candidate = candidate.add(&two);
+        }
+    }
+}
+
+fn small_primes() -> Vec<u32> {
+    let mut sieve = vec![true; SIEVE_LIMIT as usize];
+    let mut primes = Vec::new();
+    for i in 3..SIEVE_LIMIT as usize {
+        if sieve[i] {
+            primes.push(i as u32);
+            let mut j = i * i;
+            while j < SIEVE_LIMIT as usize {
+                sieve[j] = false;
+                j += i;
+            }
+        }
+    }
+    primes
+}
+
+// Miller-Rabin, for odd n greater than 3
+fn probably_prime<R: Rng>(n: &Nat, rng: &mut R, rounds: usize) -> bool {
+    let one = Nat::from_u64(1);
+    let n_1 = n.sub(&one);
+    let mut d = n_1.clone();
+    let mut s = 0;
+    while !d.bit(0) {
+        d = d.shr1();
+        s += 1;
+    }
+    let mont = Montgomery::new(n);
+    'witness: for _ in 0..rounds {
+        let a = random_below(rng, &n.sub(&Nat::from_u64(3))).add(&Nat::from_u64(2));
+        let mut x = mont.pow(&a, &d);
+        if x == one || x == n_1 {
+            continue
+        }
+        for _ in 1..s {
+            x = mont.mul_mod(&x, &x);
+            if x == n_1 {
+                continue 'witness
+            }
+            if x == one {
+                return false
+            }
+        }
+        return false
+    }
+    true
+}
+
+// HMAC-SHA256 in counter mode, keyed by the seed
+struct Drbg {
+    key: SecretKey,
+    counter: u64
+}
+
+impl Drbg {
+    fn new(seed: &[u8], bits: usize) -> Drbg {
+        Drbg{key: SecretKey::new(backend().hmac_sha256(seed, &[b"zero-paillier", &[(bits >> 8) as u8, bits as u8]])), counter: 0}
+    }
+}
+
+impl Rng for Drbg {
+    fn next_u32(&mut self) -> u32 {
+        let mut b = [0u8; 4];
+        self.fill_bytes(&mut b);
+        b.iter().fold(0, |v, x| (v << 8) | *x as u32)
+    }
+
+    fn fill_bytes(&mut self, out: &mut [u8]) {
+        for chunk in out.chunks_mut(32) {
+            let mut block = backend().hmac_sha256(&self.key, &[&Nat::from_u64(self.counter).to_bytes(8)]);
+            self.counter += 1;
+            let len = chunk.len();
+            chunk.copy_from_slice(&block[..len]);
+            wipe(&mut block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use nat::Nat;
+    use rand::{Rng, OsRng};
+
+    #[test]
+    fn test_montgomery() {
+        let a = Nat::from_decimal("123456789012345678901234567890").unwrap();
+        let b = Nat::from_decimal("987654321987654321").unwrap();
+        let m = Nat::from_decimal("170141183460469231731687303715884105727").unwrap(); // 2^127 - 1
+        let mont = Montgomery::new(&m);
+        assert_eq!(a.mul(&b).rem(&m), mont.pow(&a.mul(&b), &Nat::from_u64(1)));
+        assert_eq!(Nat::from_u64(1), mont.pow(&a, &m.sub(&Nat::from_u64(1))));
+        // exponents of every size, and windows of zeros, take the same path
+        assert_eq!(Nat::from_u64(1), mont.pow(&a, &Nat::zero()));
+        assert_eq!(a.mul(&a).rem(&m), mont.pow(&a, &Nat::from_u64(2)));
+        assert_eq!(a.mul(&a).mul(&a).rem(&m), mont.pow(&a, &Nat::from_u64(3)));
+        let mut table = vec![Nat::zero()];
+        for i in 1..16 {
+            table.push(Nat::from_u64(i));
+        }
+        assert_eq!(Nat::from_u64(9), mont.select(&table, 9));
+        assert_eq!(Nat::zero(), mont.select(&table, 0));
+        assert!(probably_prime(&m, &mut OsRng::new().unwrap(), 10));
+        assert!(!probably_prime(&m.mul(&Nat::from_u64(8191)), &mut OsRng::new().unwrap(), 10));
+    }
+
+    #[test]
+    fn test_paillier() {
+        let mut rng = OsRng::new().unwrap();
+        let key = PaillierKey::derive(b"0123456789abcdef0123456789abcdef", 512);
+        assert_eq!(512, key.n().bits());
+        assert_eq!(key.n(), PaillierKey::derive(b"0123456789abcdef0123456789abcdef", 512).n());
+        assert!(key.n() != PaillierKey::derive(b"fedcba9876543210fedcba9876543210", 512).n());
+
+        let m = Nat::from_u64(1234567);
+        let c = key.encrypt(&m, &mut rng);
+        assert_eq!(m, key.decrypt(&c));
+        assert!(c != key.encrypt(&m, &mut rng));
+        assert_eq!(Some(c.clone()), key.ciphertext(&c.to_bytes(key.ciphertext_len())));
+
+        // negative values wrap around n
+        let minus = key.encrypt(&key.encode(true, Nat::from_u64(1234000)), &mut rng);
+        assert_eq!((false, Nat::from_u64(567)), key.decode(key.decrypt(&key.add(&c, &minus))));
+        assert_eq!((true, Nat::from_u64(1234000)), key.decode(key.decrypt(&minus)));
+
+        let key = Arc::new(key);
+        let mut acc = Accumulator::new(key.clone());
+        let mut total = 0u64;
+        for _ in 0..50 {
+            let v = rng.next_u32() as u64;
+            total += v;
+            acc.add(&key.encrypt(&Nat::from_u64(v), &mut rng));
+        }
+        assert_eq!(Nat::from_u64(total), key.decrypt(&acc.sum()));
+        assert_eq!(Nat::zero(), key.decrypt(&Accumulator::new(key.clone()).sum()));
+    }
+
+    #[test]
+    fn test_key_cache() {
+        let (kept, dropped) = (SecretKey::new([5u8; 32]), SecretKey::new([6u8; 32]));
+        let (k, d) = (key(&kept, 512), key(&dropped, 512));
+        assert!(Arc::ptr_eq(&k, &key(&kept, 512)));
+        assert!(Arc::ptr_eq(&d, &key(&dropped, 512)));
+
+        // keys of columns no longer configured are dropped, and wiped once their last use ends
+        retain(&[&kept]);
+        assert!(Arc::ptr_eq(&k, &key(&kept, 512)));
+        assert!(!Arc::ptr_eq(&d, &key(&dropped, 512)));
+        assert_eq!(d.n(), key(&dropped, 512).n());
+    }
+}
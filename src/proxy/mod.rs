@@ -16,4 +16,5 @@ pub mod server;
 mod writers;
 pub mod schema_provider;
 pub mod statement_cache;
+pub mod reload;
 pub mod physical_planner;
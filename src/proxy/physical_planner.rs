@@ -12,8 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use query::planner::{Rel, Rex};
-use encrypt::{NativeType, EncryptionType};
+use query::planner::{Rel, Rex, Element, TupleType, HasTupleType, homomorphic_argument};
+use encrypt::{NativeType, EncryptionType, AadBinding, KeyRing, BlindIndex, LikeIndex, Padding, Normalization};
 use query::{ASTNode, LiteralToken, Operator};
 use error::ZeroError;
 
@@ -23,7 +23,63 @@ use std::collections::HashMap;
 pub struct EncryptionPlan {
     pub data_type: NativeType,
     pub encryption: EncryptionType,
-    pub key: Option<[u8; 32]>
+    pub key: Option<KeyRing>,
+    pub aad: Option<AadBinding>,
+    // index of the literal, or projected column, holding the primary key of a row bound value
+    pub aad_row: Option<usize>,
+    // ... and the tenant of a value encrypted under its tenant's key
+    pub tenant_row: Option<usize>,
+    pub blind_index: Option<BlindIndexUse>,
+    pub like_index: Option<LikeIndexUse>,
+    pub padding: Padding,
+    pub normalization: Option<NormalizationUse>,
+    // whether the value is compared with the column rather than written to it
+    pub compare: bool
+}
+
+/// How a value relates to its column's blind index
+#[derive(Debug, PartialEq, Clone)]
+pub enum BlindIndexUse {
+    // the value is stored and its index written to the companion column
+    Store(BlindIndex),
+    // the value's index is compared against the companion column
+    Compare(BlindIndex)
+}
+
+/// How a value relates to its column's LIKE index
+#[derive(Debug, PartialEq, Clone)]
+pub enum LikeIndexUse {
+    // the value is stored and its tokens written to the companion column
+    Store(LikeIndex),
+    // the value is a pattern looked up in the companion column
+    Match(LikeIndex)
+}
+
+/// How a value relates to its column's normalization
+#[derive(Debug, PartialEq, Clone)]
+pub enum NormalizationUse {
+    // the value is stored normalized and its original written to the display column
+    Store(Normalization),
+    // the value is normalized before it is compared
+    Compare(Normalization),
+    // the value is read from the display column, or from the column with this plan where the display column is NULL
+    Read(Normalization, Box<EncryptionPlan>)
+}
+
+impl NormalizationUse {
+    pub fn normalization(&self) -> &Normalization {
+        match *self {
+            NormalizationUse::Store(ref n) | NormalizationUse::Compare(ref n) | NormalizationUse::Read(ref n, _) => n
+        }
+    }
+}
+
+/// A SUM or AVG of a Paillier column, the backend returns the column's ciphertexts
+/// and the gateway folds them into a single row
+#[derive(Debug, PartialEq, Clone)]
+pub enum Aggregate {
+    Sum,
+    Avg
 }
 
 #[derive(Debug, PartialEq)]
@@ -31,6 +87,12 @@ pub struct PPlan {
     pub literals: HashMap<usize, EncryptionPlan>,
     pub params: HashMap<usize, EncryptionPlan>,
     pub projection: Vec<EncryptionPlan>,
+    // one per projected column when the query is folded, otherwise empty
+    pub aggregates: Vec<Aggregate>,
+    // leading projected columns added by the planner, removed from the results by the gateway
+    pub hidden: usize,
+    // whether normalized columns are projected from their display columns
+    pub displayed: bool,
     pub ast: ASTNode
 }
 
@@ -44,7 +106,16 @@ pub enum PhysicalPlan {
 pub struct PhysicalPlanBuilder {
     literals: HashMap<usize, EncryptionPlan>,
     params: HashMap<usize, EncryptionPlan>,
-    projection: Vec<EncryptionPlan>
+    projection: Vec<EncryptionPlan>,
+    aggregates: Vec<Aggregate>,
+    // whether primary keys of row bound columns may be projected when the query does not select them
+    hide_row_keys: bool,
+    // relation and name of the primary keys projected ahead of the selected columns
+    hidden: Vec<(String, String)>,
+    // relation and name of each selected column, and the display column and plan it is read from instead
+    display: Vec<(String, String, Option<(String, EncryptionPlan)>)>,
+    // whether any selected column is read from its display column
+    displayed: bool
 }
 
 impl PhysicalPlanBuilder {
@@ -53,26 +124,157 @@ impl PhysicalPlanBuilder {
         PhysicalPlanBuilder {
             literals: HashMap::new(),
             params: HashMap::new(),
-            projection: Vec::new()
+            projection: Vec::new(),
+            aggregates: Vec::new(),
+            hide_row_keys: false,
+            hidden: Vec::new(),
+            display: Vec::new(),
+            displayed: false
         }
     }
 
     // build consumes self
-    fn build(self, ast: ASTNode) -> PhysicalPlan {
+    fn build(mut self, ast: ASTNode, literals: &Vec<LiteralToken>) -> PhysicalPlan {
+        let display = self.display.iter().any(|&(_, _, ref d)| d.is_some());
+        let ast = match ast {
+            ASTNode::SQLSelect{expr_list: box ASTNode::SQLExprList(list), relation, selection, order, limit, for_update}
+                if display || !self.hidden.is_empty() => {
+                let mut list = self.read_display_columns(list);
+                let order = match order {
+                    Some(box ASTNode::SQLExprList(items)) if !self.hidden.is_empty() => {
+                        match items.into_iter().map(|o| Self::order_by_column(o, &list, literals)).collect() {
+                            Ok(items) => Some(Box::new(ASTNode::SQLExprList(items))),
+                            Err(e) => return PhysicalPlan::Error(e)
+                        }
+                    },
+                    order => order
+                };
+                for (i, (relation, name)) in self.hidden.iter().cloned().enumerate() {
+                    list.insert(i, ASTNode::SQLIdentifier{id: format!("{}.{}", relation, name), parts: vec![relation, name]});
+                }
+                ASTNode::SQLSelect{expr_list: Box::new(ASTNode::SQLExprList(list)), relation: relation, selection: selection,
+                                   order: order, limit: limit, for_update: for_update}
+            },
+            _ if !self.hidden.is_empty() => return PhysicalPlan::Error(ZeroError::EncryptionError {
+                message: "Primary keys of row bound columns can only be added to a SELECT".into(),
+                code: "1064".into()
+            }.into()),
+            ast => ast
+        };
         PhysicalPlan::Plan(
             PPlan {
                 literals: self.literals,
                 params: self.params,
                 projection: self.projection,
+                aggregates: self.aggregates,
+                hidden: self.hidden.len(),
+                displayed: self.displayed,
                 ast: ast
             }
         )
     }
 
+    // The hidden columns shift the positions of the selected ones, so positional
+    // ORDER BY references are replaced by the column they refer to
+    fn order_by_column(o: ASTNode, list: &Vec<ASTNode>, literals: &Vec<LiteralToken>) -> Result<ASTNode, Box<ZeroError>> {
+        let (n, is_asc) = match o {
+            ASTNode::SQLOrderBy{expr: box ASTNode::SQLLiteral(i), is_asc} => match literals.get(i) {
+                Some(&LiteralToken::LiteralLong(_, ref v)) => (v.parse::<usize>().unwrap_or(0), is_asc),
+                _ => return Ok(ASTNode::SQLOrderBy{expr: Box::new(ASTNode::SQLLiteral(i)), is_asc: is_asc})
+            },
+            o => return Ok(o)
+        };
+        let star = list.iter().take(n).any(|e| match *e { ASTNode::SQLIdentifier{ref id, ..} => id == "*", _ => false });
+        let column = match list.get(n.wrapping_sub(1)) {
+            Some(&ASTNode::SQLIdentifier{ref id, ref parts}) |
+            Some(&ASTNode::SQLAlias{alias: box ASTNode::SQLIdentifier{ref id, ref parts}, ..}) if !star =>
+                ASTNode::SQLIdentifier{id: id.clone(), parts: parts.clone()},
+            _ => return Err(ZeroError::EncryptionError {
+                message: format!("ORDER BY {} must refer to a column or alias when primary keys of row bound columns are added to the projection", n).into(),
+                code: "1064".into()
+            }.into())
+        };
+        Ok(ASTNode::SQLOrderBy{expr: Box::new(column), is_asc: is_asc})
+    }
+
+    // Selected normalized columns are read from their display columns instead, under the name of the column,
+    // falling back to the normalized value for rows written before the display column.
+    // A wildcard stands for the columns the other expressions do not select, so only one can be expanded
+    fn read_display_columns(&mut self, list: Vec<ASTNode>) -> Vec<ASTNode> {
+        let stars = list.iter().filter(|e| match *e { &ASTNode::SQLIdentifier{ref id, ..} => id == "*", _ => false }).count();
+        if stars > 1 || list.len() - stars > self.display.len() {
+            return list
+        }
+        let width = self.display.len() + stars - list.len();
+
+        let mut columns = Vec::new();
+        let mut pos = 0;
+        for e in list {
+            let star = match e {
+                ASTNode::SQLIdentifier{ref id, ..} => id == "*",
+                _ => false
+            };
+            if !star {
+                columns.push(self.display_column(pos, e));
+                pos += 1;
+            } else if self.display[pos..pos + width].iter().any(|&(_, _, ref d)| d.is_some()) {
+                for j in pos..pos + width {
+                    let (relation, name) = (self.display[j].0.clone(), self.display[j].1.clone());
+                    let column = ASTNode::SQLIdentifier{id: format!("{}.{}", relation, name), parts: vec![relation, name]};
+                    columns.push(self.display_column(j, column));
+                }
+                pos += width;
+            } else {
+                columns.push(e);
+                pos += width;
+            }
+        }
+        columns
+    }
+
+    fn display_column(&mut self, j: usize, e: ASTNode) -> ASTNode {
+        let (column, plan) = match self.display[j].2 {
+            Some((ref column, ref plan)) => (column.clone(), plan.clone()),
+            None => return e
+        };
+        let display = |parts: &Vec<String>| {
+            let mut display_parts = parts[..parts.len() - 1].to_vec();
+            display_parts.push(column.clone());
+            let args = vec![ASTNode::SQLIdentifier{id: display_parts.join("."), parts: display_parts},
+                            ASTNode::SQLIdentifier{id: parts.join("."), parts: parts.clone()}];
+            let coalesce = String::from("COALESCE");
+            ASTNode::SQLFunctionCall{identifier: Box::new(ASTNode::SQLIdentifier{id: coalesce.clone(), parts: vec![coalesce]}), args: args}
+        };
+        let e = match e {
+            ASTNode::SQLIdentifier{parts, ..} if parts.len() > 0 => {
+                let name = parts[parts.len() - 1].clone();
+                ASTNode::SQLAlias{expr: Box::new(display(&parts)), alias: Box::new(ASTNode::SQLIdentifier{id: name.clone(), parts: vec![name]})}
+            },
+            ASTNode::SQLAlias{expr: box ASTNode::SQLIdentifier{parts, ..}, alias} if parts.len() > 0 =>
+                ASTNode::SQLAlias{expr: Box::new(display(&parts)), alias: alias},
+            e => return e
+        };
+        let hidden = self.hidden.len();
+        self.projection[hidden + j] = plan;
+        self.displayed = true;
+        e
+    }
+
     fn push_literal(&mut self, index: usize, e: EncryptionPlan) {
         self.literals.insert(index, e);
     }
 
+    fn assigned(&mut self, value: &Rex) {
+        let plan = match *value {
+            Rex::Literal(i) => self.literals.get_mut(&i),
+            Rex::BoundParam(i) => self.params.get_mut(&i),
+            _ => None
+        };
+        if let Some(p) = plan {
+            p.compare = false;
+        }
+    }
+
     fn push_param(&mut self, index: usize, e: EncryptionPlan) {
         self.params.insert(index, e);
     }
@@ -87,9 +289,11 @@ pub struct PhysicalPlanner {}
 impl PhysicalPlanner {
     pub fn plan(&self, logical: Rel, ast: ASTNode, literals: &Vec<LiteralToken>) -> PhysicalPlan {
         let mut builder = PhysicalPlanBuilder::new();
+        // only the results of the outermost SELECT reach the gateway
+        builder.hide_row_keys = true;
 
         match self.plan_rel(&logical, &mut builder, literals) {
-            Ok(()) => builder.build(ast),
+            Ok(()) => builder.build(ast, literals),
             Err(e) => PhysicalPlan::Error(e)
         }
 
@@ -100,17 +304,68 @@ impl PhysicalPlanner {
             Rel::Projection { box ref project, box ref input, ref tt } => {
                 // push projection encryption types into builder
                 // if this is the highest level projection
-                if builder.projection.len() == 0 {
-                    for el in tt.elements.iter() {
+                let top = builder.projection.len() == 0;
+                if top {
+                    // row bound values need their primary key, which is projected ahead of
+                    // the selected columns when the query does not select it
+                    let hidden = if builder.hide_row_keys { Self::unprojected_row_columns(&tt.elements, input.tt()) } else { vec![] };
+                    for el in hidden.iter() {
+                        builder.push_projection(EncryptionPlan {
+                            data_type: el.data_type.clone(),
+                            encryption: el.encryption.clone(),
+                            key: Some(el.key.clone()),
+                            aad: el.aad.clone(),
+                            aad_row: None,
+                            tenant_row: None,
+                            blind_index: None,
+                            like_index: None,
+                            padding: el.padding.clone(),
+                            normalization: None,
+                            compare: false
+                        });
+                        builder.hidden.push((el.relation.clone(), el.name.clone()));
+                    }
+
+                    let project_list = match project {
+                        &Rex::RexExprList(ref list) => list.iter().collect::<Vec<&Rex>>(),
+                        _ => vec![]
+                    };
+                    for (j, el) in tt.elements.iter().enumerate() {
 
                         let enc_plan = EncryptionPlan {
                             data_type: el.data_type.clone(),
                             encryption: el.encryption.clone(),
-                            key: Some(el.key.clone())
+                            key: Some(el.key.clone()),
+                            aad: el.aad.clone(),
+                            aad_row: self.projected_row_column(el, Self::row_key(el), &tt.elements, &hidden)?,
+                            tenant_row: self.projected_row_column(el, Self::tenant_key(el), &tt.elements, &hidden)?,
+                            blind_index: None,
+                            like_index: None,
+                            padding: el.padding.clone(),
+                            normalization: None,
+                            compare: false
                         };
 
                         builder.push_projection(enc_plan);
+                        builder.display.push((el.relation.clone(), el.name.clone(), Self::display_plan(el, project_list.get(j))));
+                    }
+                }
+
+                // Paillier sums are folded by the gateway, so they must make up the whole result
+                let aggregates = match project {
+                    &Rex::RexExprList(ref list) => list.iter().map(Self::aggregate).collect::<Vec<Option<Aggregate>>>(),
+                    _ => vec![]
+                };
+                if aggregates.iter().any(|a| a.is_some()) {
+                    if !top {
+                        return Err(self.zero_error("1064",
+                            "SUM and AVG of Paillier encrypted columns are only supported in the outermost SELECT, without ORDER BY".into()))
                     }
+                    if aggregates.iter().any(|a| a.is_none()) {
+                        return Err(self.zero_error("1064",
+                            "SUM and AVG of Paillier encrypted columns cannot be selected with other expressions".into()))
+                    }
+                    builder.aggregates = aggregates.into_iter().map(|a| a.unwrap()).collect();
                 }
 
                 self.plan_rex(project, builder, literals)?;
@@ -128,6 +383,12 @@ impl PhysicalPlanner {
                 if let &Rex::RexExprList(ref list) = sort_expr {
                     for e in list {
                         match self.get_encryption_scheme(e, builder, &mut None, literals)? {
+                            // order codes are keyed by the key version, so values under different versions do not sort together
+                            EncScheme::Encrypted(EncryptionType::Ope, _, ref k, _) if k.versions().len() > 1 =>
+                                return Err(self.zero_error("1064",
+                                       format!("Cannot perform ORDER BY on OPE column with several key versions until it is rotated"))),
+                            // order preserving ciphertexts sort like their plaintext
+                            EncScheme::Encrypted(EncryptionType::Ope, ..) => {},
                             EncScheme::Encrypted(..) =>
                                 return Err(self.zero_error("1064",
                                        format!("Cannot perform ORDER BY on encrypted column"))),
@@ -137,6 +398,9 @@ impl PhysicalPlanner {
                 }
             },
             Rel::Limit {box ref input, box ref limit_expr} => {
+                if !builder.aggregates.is_empty() {
+                    return Err(self.zero_error("1064", "SUM and AVG of Paillier encrypted columns do not support LIMIT".into()))
+                }
                 self.plan_rex(limit_expr, builder, literals)?;
                 self.plan_rel(input, builder, literals)?;
             },
@@ -151,11 +415,44 @@ impl PhysicalPlanner {
             },
             Rel::AliasedRel { box ref input, .. } => self.plan_rel(input, builder, literals)?,
             Rel::Dual { .. } => {},
-            Rel::Update { box ref set_stmts, ref selection, .. } => {
+            Rel::Update { box ref set_stmts, ref selection, ref tt, .. } => {
                 match set_stmts {
                     &Rex::RexExprList(ref list) => {
                         for e in list.iter() {
-                            self.plan_rex(e, builder, literals)?;
+                            if let &Rex::BinaryExpr{left: box Rex::Identifier{ref el, ..}, ..} = e {
+                                self.updated_row_key(el, tt)?;
+                            }
+                            match e {
+                                // row bound values take their primary key from the WHERE clause,
+                                // indexed values also set their companion columns
+                                &Rex::BinaryExpr{left: box Rex::Identifier{ref el, ..}, op: Operator::EQ, right: box Rex::Literal(i)}
+                                    if Self::row_bound(&el.aad) || Self::tenant_key(el).is_some() || Self::indexed(el) => {
+
+                                    let enc_plan = EncryptionPlan {
+                                        data_type: el.data_type.clone(),
+                                        encryption: el.encryption.clone(),
+                                        key: Some(el.key.clone()),
+                                        aad: el.aad.clone(),
+                                        aad_row: self.updated_row_column(el, Self::row_key(el), selection)?,
+                                        tenant_row: self.updated_row_column(el, Self::tenant_key(el), selection)?,
+                                        blind_index: el.blind_index.clone().map(BlindIndexUse::Store),
+                                        like_index: el.like_index.clone().map(LikeIndexUse::Store),
+                                        padding: el.padding.clone(),
+                                        normalization: el.normalization.clone().map(NormalizationUse::Store),
+                                        compare: false
+                                    };
+                                    builder.push_literal(i, enc_plan);
+                                },
+                                &Rex::BinaryExpr{left: box Rex::Identifier{ref el, ..}, ..} if Self::indexed(el) => {
+                                    return Err(self.index_error(el))
+                                },
+                                // planned as an equality, but the value is written
+                                &Rex::BinaryExpr{left: box Rex::Identifier{..}, op: Operator::EQ, right: box ref right} => {
+                                    self.plan_rex(e, builder, literals)?;
+                                    builder.assigned(right);
+                                },
+                                _ => self.plan_rex(e, builder, literals)?
+                            }
                         }
                     },
                     _ => {}
@@ -184,11 +481,20 @@ impl PhysicalPlanner {
                                         let enc_plan = EncryptionPlan {
                                             data_type: el.data_type.clone(),
                                             encryption: el.encryption.clone(),
-                                            key: Some(el.key.clone())
+                                            key: Some(el.key.clone()),
+                                            aad: el.aad.clone(),
+                                            aad_row: self.inserted_row_column(el, Self::row_key(el), c_list, v_list)?,
+                                            tenant_row: self.inserted_row_column(el, Self::tenant_key(el), c_list, v_list)?,
+                                            blind_index: el.blind_index.clone().map(BlindIndexUse::Store),
+                                            like_index: el.like_index.clone().map(LikeIndexUse::Store),
+                                            padding: el.padding.clone(),
+                                            normalization: el.normalization.clone().map(NormalizationUse::Store),
+                                            compare: false
                                         };
 
                                         match *value_expr {
                                             &Rex::Literal(i) => builder.push_literal(i.clone(), enc_plan),
+                                            _ if Self::indexed(el) => return Err(self.index_error(el)),
                                             &Rex::BoundParam(i) => builder.push_param(i.clone(), enc_plan),
                                             _ => self.plan_rex(value_expr, builder, literals)?
                                         }
@@ -209,6 +515,16 @@ impl PhysicalPlanner {
         Ok(())
     }
 
+    fn aggregate(rex: &Rex) -> Option<Aggregate> {
+        match rex {
+            &Rex::Alias{box ref expr, ..} => Self::aggregate(expr),
+            &Rex::RexFunctionCall{ref name, ref args} if homomorphic_argument(name, args).is_some() => {
+                if name == "SUM" { Some(Aggregate::Sum) } else { Some(Aggregate::Avg) }
+            },
+            _ => None
+        }
+    }
+
     fn zero_error(&self, code: &'static str, msg: String) -> Box<ZeroError> {
         ZeroError::EncryptionError {
             message: msg,
@@ -216,6 +532,227 @@ impl PhysicalPlanner {
         }.into()
     }
 
+    /// The primary key a row bound element's values are authenticated against
+    fn row_key(el: &Element) -> Option<(&String, &'static str, &'static str)> {
+        el.aad.as_ref().and_then(|a| a.row_key.as_ref()).map(|pk| (pk, "is bound to its row", "primary key"))
+    }
+
+    /// The column naming the tenant whose key an element's values are encrypted under
+    fn tenant_key(el: &Element) -> Option<(&String, &'static str, &'static str)> {
+        el.aad.as_ref().and_then(|a| a.tenant.as_ref()).map(|t| (&t.column, "is encrypted under its tenant's key", "tenant column"))
+    }
+
+    /// Locates a column of an element's row among the projected elements, which follow the hidden ones
+    fn projected_row_column(&self, el: &Element, column: Option<(&String, &str, &str)>, elements: &Vec<Element>, hidden: &Vec<Element>) -> Result<Option<usize>, Box<ZeroError>> {
+        match column {
+            Some((name, reason, kind)) => {
+                match elements.iter().position(|e| e.relation == el.relation && e.p_name.as_ref().unwrap_or(&e.name) == name) {
+                    Some(i) => Ok(Some(hidden.len() + i)),
+                    None => match hidden.iter().position(|e| e.relation == el.relation && &e.name == name) {
+                        Some(i) => Ok(Some(i)),
+                        None => Err(self.zero_error("1064",
+                            format!("Column {}.{} {}, {} {} must be projected", el.relation, el.name, reason, kind, name)))
+                    }
+                }
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Primary keys and tenant columns the projected elements need that are not projected themselves
+    /// but can be selected from the input
+    fn unprojected_row_columns(elements: &Vec<Element>, input: &TupleType) -> Vec<Element> {
+        let mut columns: Vec<Element> = Vec::new();
+        for el in elements.iter() {
+            for &(name, _, _) in Self::row_key(el).iter().chain(Self::tenant_key(el).iter()) {
+                let projected = elements.iter().any(|e| e.relation == el.relation && e.p_name.as_ref().unwrap_or(&e.name) == name);
+                let hidden = columns.iter().any(|e| e.relation == el.relation && &e.name == name);
+                if !projected && !hidden {
+                    if let Some(e) = input.elements.iter().find(|e| e.relation == el.relation && &e.name == name) {
+                        columns.push(e.clone());
+                    }
+                }
+            }
+        }
+        columns
+    }
+
+    /// Locates the literal holding a column of an element's row in the same VALUES list
+    fn inserted_row_column(&self, el: &Element, column: Option<(&String, &str, &str)>, columns: &Vec<Rex>, values: &Vec<Rex>) -> Result<Option<usize>, Box<ZeroError>> {
+        match column {
+            Some((name, reason, kind)) => {
+                let position = columns.iter().position(|c| match c {
+                    &Rex::Identifier{ref el, ..} => &el.name == name,
+                    _ => false
+                });
+                match position.and_then(|i| values.get(i)) {
+                    Some(&Rex::Literal(i)) => Ok(Some(i)),
+                    _ => Err(self.zero_error("1064",
+                        format!("Column {}.{} {}, {} {} must be inserted as a literal", el.relation, el.name, reason, kind, name)))
+                }
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Locates the literal of a `column = literal` conjunct in the selection for a column of an element's row
+    fn updated_row_column(&self, el: &Element, column: Option<(&String, &str, &str)>, selection: &Option<Box<Rex>>) -> Result<Option<usize>, Box<ZeroError>> {
+        fn find(rex: &Rex, name: &String) -> Option<usize> {
+            match rex {
+                &Rex::BinaryExpr{box ref left, op: Operator::AND, box ref right} => find(left, name).or(find(right, name)),
+                &Rex::BinaryExpr{left: box Rex::Identifier{ref el, ..}, op: Operator::EQ, right: box Rex::Literal(i)} |
+                &Rex::BinaryExpr{left: box Rex::Literal(i), op: Operator::EQ, right: box Rex::Identifier{ref el, ..}} if &el.name == name => Some(i),
+                &Rex::RexNested(box ref expr) => find(expr, name),
+                _ => None
+            }
+        }
+
+        match column {
+            Some((name, reason, _)) => match selection.as_ref().and_then(|s| find(s, name)) {
+                Some(i) => Ok(Some(i)),
+                None => Err(self.zero_error("1064",
+                    format!("Column {}.{} {}, UPDATE requires WHERE {} = <value>", el.relation, el.name, reason, name)))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Fails for a column other columns of the row are bound to, as their values would no longer decrypt
+    fn updated_row_key(&self, el: &Element, tt: &TupleType) -> Result<(), Box<ZeroError>> {
+        for e in tt.elements.iter() {
+            for &(name, reason, kind) in Self::row_key(e).iter().chain(Self::tenant_key(e).iter()) {
+                if name == &el.name {
+                    return Err(self.zero_error("1064",
+                        format!("Column {}.{} {}, its {} {} cannot be updated", e.relation, e.name, reason, kind, name)))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Encrypt the literals and params collected from one side of a comparison with the column's plan
+    fn push_potentials(&self, potentials: PotentialsBuilder, builder: &mut PhysicalPlanBuilder, e: &EncryptionType,
+                       dt: &NativeType, k: &KeyRing, a: &Option<AadBinding>, index: &Option<BlindIndexUse>, like: &Option<LikeIndexUse>,
+                       normalization: &Option<NormalizationUse>) {
+        let ps = potentials.build();
+        for p in ps.params {
+            let enc_plan = EncryptionPlan {
+                data_type: dt.clone(),
+                encryption: e.clone(),
+                key: Some(k.clone()),
+                aad: a.clone(),
+                aad_row: None,
+                tenant_row: None,
+                blind_index: index.clone(),
+                like_index: like.clone(),
+                padding: Padding::NA,
+                normalization: normalization.clone(),
+                compare: true
+            };
+
+            builder.push_param(p, enc_plan);
+        }
+
+        for p in ps.literals {
+            let enc_plan = EncryptionPlan {
+                data_type: dt.clone(),
+                encryption: e.clone(),
+                key: Some(k.clone()),
+                aad: a.clone(),
+                aad_row: None,
+                tenant_row: None,
+                blind_index: index.clone(),
+                like_index: like.clone(),
+                padding: Padding::NA,
+                normalization: normalization.clone(),
+                compare: true
+            };
+
+            builder.push_literal(p, enc_plan);
+        }
+    }
+
+    // a blind indexed column compared with a literal or param, as the writer can only rewrite that form
+    fn blind_indexed<'b>(left: &'b Rex, right: &'b Rex) -> Option<&'b Element> {
+        match (left, right) {
+            (&Rex::Identifier{ref el, ..}, &Rex::Literal(_)) | (&Rex::Identifier{ref el, ..}, &Rex::BoundParam(_)) |
+            (&Rex::Literal(_), &Rex::Identifier{ref el, ..}) | (&Rex::BoundParam(_), &Rex::Identifier{ref el, ..}) if el.blind_index.is_some() => Some(el),
+            _ => None
+        }
+    }
+
+    // a LIKE indexed column matched against a literal or param pattern
+    fn like_indexed<'b>(left: &'b Rex, right: &'b Rex) -> Option<&'b Element> {
+        match (left, right) {
+            (&Rex::Identifier{ref el, ..}, &Rex::Literal(_)) | (&Rex::Identifier{ref el, ..}, &Rex::BoundParam(_)) if el.like_index.is_some() => Some(el),
+            _ => None
+        }
+    }
+
+    // the display column a selected column can be read from, and the plan of its values
+    fn display_plan(el: &Element, rex: Option<&&Rex>) -> Option<(String, EncryptionPlan)> {
+        match (rex, el.normalization.as_ref().and_then(|n| n.display.as_ref().map(|d| (n, d)))) {
+            (Some(&&Rex::Identifier{..}), Some((n, column))) | (Some(&&Rex::Alias{expr: box Rex::Identifier{..}, ..}), Some((n, column))) => {
+                let stored = EncryptionPlan {
+                    data_type: el.data_type.clone(),
+                    encryption: el.encryption.clone(),
+                    key: Some(el.key.clone()),
+                    aad: None,
+                    aad_row: None,
+                    tenant_row: None,
+                    blind_index: None,
+                    like_index: None,
+                    padding: el.padding.clone(),
+                    normalization: None,
+                    compare: false
+                };
+                Some((column.clone(), EncryptionPlan {
+                    data_type: el.data_type.clone(),
+                    encryption: EncryptionType::AesGcm,
+                    key: Some(n.display_keys(&el.key)),
+                    aad: None,
+                    aad_row: None,
+                    tenant_row: None,
+                    blind_index: None,
+                    like_index: None,
+                    padding: Padding::NA,
+                    normalization: Some(NormalizationUse::Read(n.clone(), Box::new(stored))),
+                    compare: false
+                }))
+            },
+            _ => None
+        }
+    }
+
+    // a normalized column compared with a literal or param
+    fn normalized<'b>(left: &'b Rex, right: &'b Rex) -> Option<&'b Element> {
+        match (left, right) {
+            (&Rex::Identifier{ref el, ..}, &Rex::Literal(_)) | (&Rex::Identifier{ref el, ..}, &Rex::BoundParam(_)) |
+            (&Rex::Literal(_), &Rex::Identifier{ref el, ..}) | (&Rex::BoundParam(_), &Rex::Identifier{ref el, ..}) if el.normalization.is_some() => Some(el),
+            _ => None
+        }
+    }
+
+    // columns whose stored values also write a companion column
+    fn indexed(el: &Element) -> bool {
+        el.blind_index.is_some() || el.like_index.is_some() || el.normalization.as_ref().map_or(false, |n| n.display.is_some())
+    }
+
+    fn index_error(&self, el: &Element) -> Box<ZeroError> {
+        let index = if el.blind_index.is_some() {
+            "a blind index"
+        } else if el.like_index.is_some() {
+            "a LIKE index"
+        } else {
+            "a display column"
+        };
+        self.zero_error("1064", format!("Column {}.{} has {} and can only be set to a literal value", el.relation, el.name, index))
+    }
+
+    fn row_bound(aad: &Option<AadBinding>) -> bool {
+        aad.as_ref().map_or(false, |a| a.row_key.is_some())
+    }
+
     fn plan_rex(&self, rex: &Rex, builder: &mut PhysicalPlanBuilder, literals: &Vec<LiteralToken>) -> Result<(), Box<ZeroError>>  {
         match self.get_encryption_scheme(rex, builder, &mut None, literals) {
             Ok(_) => Ok(()),
@@ -232,7 +769,8 @@ impl PhysicalPlanner {
                 _ => Ok(EncScheme::Encrypted(
                     el.encryption.clone(),
                     el.data_type.clone(),
-                    el.key.clone()
+                    el.key.clone(),
+                    el.aad.clone()
                 ))
             },
             Rex::Alias { box ref expr, .. } => self.get_encryption_scheme(expr, builder, potentials, literals),
@@ -248,7 +786,15 @@ impl PhysicalPlanner {
                 let enc_plan = EncryptionPlan {
                     data_type: NativeType::UNKNOWN,
                     encryption: EncryptionType::NA,
-                    key: None
+                    key: None,
+                    aad: None,
+                    aad_row: None,
+                    tenant_row: None,
+                    blind_index: None,
+                    like_index: None,
+                    padding: Padding::NA,
+                    normalization: None,
+                    compare: false
                 };
                 builder.push_literal(i.clone(), enc_plan);
 
@@ -265,7 +811,15 @@ impl PhysicalPlanner {
                 let enc_plan = EncryptionPlan {
                     data_type: NativeType::UNKNOWN,
                     encryption: EncryptionType::NA,
-                    key: None
+                    key: None,
+                    aad: None,
+                    aad_row: None,
+                    tenant_row: None,
+                    blind_index: None,
+                    like_index: None,
+                    padding: Padding::NA,
+                    normalization: None,
+                    compare: false
                 };
                 builder.push_param(i.clone(), enc_plan);
 
@@ -304,22 +858,53 @@ impl PhysicalPlanner {
                 match *op {
                     // If AND||OR, the resolved uncryption scheme is unimportant
                     Operator::AND | Operator::OR => Ok(EncScheme::Inconsequential),
+                    // Equality on a blind indexed column compares the companion column instead
+                    Operator::EQ | Operator::NEQ if Self::blind_indexed(left, right).is_some() => {
+                        let el = Self::blind_indexed(left, right).unwrap();
+                        let index = el.blind_index.clone().map(BlindIndexUse::Compare);
+                        self.push_potentials(potentials_builder.unwrap(), builder, &el.encryption, &el.data_type, &el.key, &el.aad, &index, &None, &None);
+                        Ok(EncScheme::Inconsequential)
+                    },
+                    // LIKE on an indexed column looks its pattern's tokens up in the companion column
+                    Operator::LIKE if Self::like_indexed(left, right).is_some() => {
+                        let el = Self::like_indexed(left, right).unwrap();
+                        let like = el.like_index.clone().map(LikeIndexUse::Match);
+                        self.push_potentials(potentials_builder.unwrap(), builder, &el.encryption, &el.data_type, &el.key, &el.aad, &None, &like, &None);
+                        Ok(EncScheme::Inconsequential)
+                    },
+                    // Equality on a normalized column compares the normalized value
+                    Operator::EQ | Operator::NEQ if Self::normalized(left, right).is_some() => {
+                        let el = Self::normalized(left, right).unwrap();
+                        let normalization = el.normalization.clone().map(NormalizationUse::Compare);
+                        self.push_potentials(potentials_builder.unwrap(), builder, &el.encryption, &el.data_type, &el.key, &el.aad, &None, &None, &normalization);
+                        Ok(EncScheme::Inconsequential)
+                    },
                     // Equality comparisons
                     Operator::EQ | Operator::NEQ => {
                         match (l, r) {
                             // An eq between two encrypted columns...
-                            (EncScheme::Encrypted (ref le, ref ldt, ref lk ), EncScheme::Encrypted ( ref re, ref rdt, ref rk )) => {
+                            (EncScheme::Encrypted (ref le, ref ldt, ref lk, ref la), EncScheme::Encrypted ( ref re, ref rdt, ref rk, ref ra )) => {
                                 // If both do not share the same encryption, data type, and key, fail
                                 if !(le == re && ldt == rdt && lk == rk) {
                                     Err(self.zero_error(
                                         "1064",
                                         format!("Unsupported operation between columns of differing encryption and type, expr: {}", rex.to_readable(literals))
                                     ))
+                                } else if la != ra || la.as_ref().map_or(false, |a| a.row_key.is_some()) {
+                                    Err(self.zero_error(
+                                        "1064",
+                                        format!("Unsupported operation between columns bound to differing associated data, expr: {}", rex.to_readable(literals))
+                                    ))
                                 } else if *le == EncryptionType::AesGcm || *re == EncryptionType::AesGcm {
                                     Err(self.zero_error(
                                         "1064",
                                         format!("Unsupported operation between columns of AesGcm encryption, expr: {}", rex.to_readable(literals))
                                     ))
+                                } else if *le == EncryptionType::Paillier {
+                                    Err(self.zero_error(
+                                        "1064",
+                                        format!("Unsupported operation between columns of Paillier encryption, expr: {}", rex.to_readable(literals))
+                                    ))
                                 } else {
                                     // The operation is legal
                                     Ok(EncScheme::Inconsequential)
@@ -337,31 +922,18 @@ impl PhysicalPlanner {
                             // Catch all eq between an unencrypted column and any other expression, legal, allow to delegate to dbms
                             (EncScheme::Unencrypted, _) | (_, EncScheme::Unencrypted) => Ok(EncScheme::Inconsequential), // OK
                             // EQ between an encrypted column and potentially encryptable expressions, e.g a = 1, a = (1), etc
-                            (EncScheme::Encrypted(ref e, ref dt, ref k), EncScheme::Potential) | (EncScheme::Potential, EncScheme::Encrypted(ref e, ref dt, ref k)) => {
-
-                                match e {
-                                    &EncryptionType::Aes(_) => {
-                                        let ps = potentials_builder.unwrap().build();
-                                        for p in ps.params {
-                                            let enc_plan = EncryptionPlan {
-                                                data_type: dt.clone(),
-                                                encryption: e.clone(),
-                                                key: Some(k.clone())
-                                            };
-
-                                            builder.push_param(p, enc_plan);
-                                        }
+                            (EncScheme::Encrypted(ref e, ref dt, ref k, ref a), EncScheme::Potential) | (EncScheme::Potential, EncScheme::Encrypted(ref e, ref dt, ref k, ref a)) => {
 
-                                        for p in ps.literals {
-                                            let enc_plan = EncryptionPlan {
-                                                data_type: dt.clone(),
-                                                encryption: e.clone(),
-                                                key: Some(k.clone())
-                                            };
-
-                                            builder.push_literal(p, enc_plan);
-                                        }
+                                if a.as_ref().map_or(false, |a| a.row_key.is_some()) {
+                                    return Err(self.zero_error(
+                                        "1064",
+                                        format!("Equality on row bound column is unsupported: {}", rex.to_readable(literals))
+                                    ))
+                                }
 
+                                match e {
+                                    &EncryptionType::Aes(_) | &EncryptionType::AesSiv | &EncryptionType::Ope | &EncryptionType::Fpe(_) => {
+                                        self.push_potentials(potentials_builder.unwrap(), builder, e, dt, k, a, &None, &None, &None);
                                         Ok(EncScheme::Inconsequential)
                                     },
                                     &EncryptionType::AesGcm => {
@@ -388,6 +960,36 @@ impl PhysicalPlanner {
                             }
                         }
                     },
+                    // Range comparisons, only order preserving columns may be compared
+                    Operator::GT | Operator::LT | Operator::GTEQ | Operator::LTEQ => {
+                        match (l, r) {
+                            // order codes are keyed by the key version, so values under different versions do not compare
+                            (EncScheme::Encrypted(EncryptionType::Ope, _, ref k, _), _) |
+                            (_, EncScheme::Encrypted(EncryptionType::Ope, _, ref k, _)) if k.versions().len() > 1 => {
+                                Err(self.zero_error(
+                                    "1064",
+                                    format!("Range comparison on OPE column with several key versions is unsupported until it is rotated: {}", rex.to_readable(literals))
+                                ))
+                            },
+                            (EncScheme::Encrypted(ref le, ref ldt, ref lk, ref la), EncScheme::Encrypted(ref re, ref rdt, ref rk, ref ra))
+                                if *le == EncryptionType::Ope && le == re && ldt == rdt && lk == rk && la == ra && !Self::row_bound(la) => {
+                                Ok(EncScheme::Inconsequential)
+                            },
+                            (EncScheme::Encrypted(ref e, ref dt, ref k, ref a), EncScheme::Potential) |
+                            (EncScheme::Potential, EncScheme::Encrypted(ref e, ref dt, ref k, ref a))
+                                if *e == EncryptionType::Ope && !Self::row_bound(a) => {
+                                self.push_potentials(potentials_builder.unwrap(), builder, e, dt, k, a, &None, &None, &None);
+                                Ok(EncScheme::Inconsequential)
+                            },
+                            (EncScheme::Encrypted(..), _) | (_, EncScheme::Encrypted(..)) => {
+                                Err(self.zero_error(
+                                    "1064",
+                                    format!("Unsupported operation on encrypted column: {}", rex.to_readable(literals))
+                                ))
+                            },
+                            _ => Ok(EncScheme::UnencryptedOperation)
+                        }
+                    },
                     // Non eq comparisons and arithmetic
                     _ => {
                         match (l, r) {
@@ -422,11 +1024,15 @@ impl PhysicalPlanner {
                 let mut sub_builder = PhysicalPlanBuilder::new();
                 self.plan_rel(rel, &mut sub_builder, literals)?;
 
-                let sub_plan = match sub_builder.build(ASTNode::SQLLiteral(0)) {
+                let sub_plan = match sub_builder.build(ASTNode::SQLLiteral(0), literals) {
                     PhysicalPlan::Plan(p) => p,
                     _ => panic!("")
                 };
 
+                if !sub_plan.aggregates.is_empty() {
+                    return Err(self.zero_error("1064", "SUM and AVG of Paillier encrypted columns are unsupported in subqueries".into()))
+                }
+
                 for (i, lp) in sub_plan.literals {
                     builder.push_literal(i, lp);
                 }
@@ -442,7 +1048,8 @@ impl PhysicalPlanner {
                         _ => Ok(EncScheme::Encrypted(
                             e.encryption.clone(),
                             e.data_type.clone(),
-                            e.key.unwrap().clone()
+                            e.key.clone().unwrap(),
+                            e.aad.clone()
                         ))
                     }
                 } else {
@@ -459,7 +1066,7 @@ impl PhysicalPlanner {
 }
 
 enum EncScheme {
-    Encrypted(EncryptionType, NativeType, [u8; 32]),
+    Encrypted(EncryptionType, NativeType, KeyRing, Option<AadBinding>),
     Unencrypted,
     Potential,
     Inconsequential,
@@ -511,7 +1118,8 @@ mod tests {
     use query::dialects::mysqlsql::*;
     use query::{Tokenizer, Parser, ASTNode, LiteralToken};
     use query::planner::{Planner, Rel, SchemaProvider, TableMeta, ColumnMeta};
-    use encrypt::{EncryptionType, NativeType};
+    use encrypt::{EncryptionType, NativeType, AadBinding, KeyRing, BlindIndex, LikeIndex, LikeMode, FpeFormat, Padding, Normalization, TenantKey, MissingKey};
+    use secret::SecretKey;
     use std::rc::Rc;
 
     #[test]
@@ -592,6 +1200,408 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_physical_plan_siv_equality() {
+        let sql = String::from("SELECT id FROM users WHERE email = 'alice@example.com'");
+        let res = parse_and_plan(sql).unwrap();
+        let literals = res.0;
+        let parsed = res.1;
+        let plan = res.2;
+
+        let planner = PhysicalPlanner{};
+        let pplan = planner.plan(plan, parsed, &literals);
+
+        match pplan {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(1, p.literals.len());
+
+                let lit = p.literals.get(&(0 as usize)).unwrap();
+                assert_eq!(NativeType::Varchar(50), lit.data_type);
+                assert_eq!(EncryptionType::AesSiv, lit.encryption);
+                assert_eq!(true, lit.key.is_some());
+                assert_eq!(true, lit.compare);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // assigned values are written rather than compared
+        let (literals, parsed, plan) = parse_and_plan(String::from("UPDATE users SET email = 'bob@example.com' WHERE email = 'alice@example.com'")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(false, p.literals.get(&(0 as usize)).unwrap().compare);
+                assert_eq!(true, p.literals.get(&(1 as usize)).unwrap().compare);
+            },
+            _ => panic!("TEST FAIL")
+        }
+    }
+
+    #[test]
+    fn test_physical_plan_aad() {
+        let planner = PhysicalPlanner{};
+
+        // row bound values locate the primary key among the projection
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT a, b, id FROM bound WHERE a = 'x'")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(None, p.projection[0].aad_row);
+                assert_eq!(Some(2), p.projection[1].aad_row);
+                assert_eq!("zero.bound.b", p.projection[1].aad.as_ref().unwrap().column);
+
+                let lit = p.literals.get(&(0 as usize)).unwrap();
+                assert_eq!(Some(AadBinding{column: "zero.bound.a".into(), row_key: None, row_key_type: NativeType::UNKNOWN, row_keys: false, tenant: None}), lit.aad);
+                assert_eq!(None, lit.aad_row);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // ... and among the inserted values
+        let (literals, parsed, plan) = parse_and_plan(String::from("INSERT INTO bound (b, a, id) VALUES ('y', 'x', 7)")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(Some(2), p.literals.get(&(0 as usize)).unwrap().aad_row);
+                assert_eq!(None, p.literals.get(&(1 as usize)).unwrap().aad_row);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // ... and in the WHERE clause of an UPDATE
+        let (literals, parsed, plan) = parse_and_plan(String::from("UPDATE bound SET b = 'y' WHERE id = 7")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(Some(1), p.literals.get(&(0 as usize)).unwrap().aad_row);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // ... or project it ahead of the selected columns
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT b, a FROM bound AS x")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(1, p.hidden);
+                assert_eq!(3, p.projection.len());
+                assert_eq!(Some(0), p.projection[1].aad_row);
+                match p.ast {
+                    ASTNode::SQLSelect{expr_list: box ASTNode::SQLExprList(ref list), ..} => {
+                        assert_eq!(3, list.len());
+                        match list[0] {
+                            ASTNode::SQLIdentifier{ref parts, ..} => assert_eq!(vec![String::from("x"), String::from("id")], *parts),
+                            _ => panic!("TEST FAIL")
+                        }
+                    },
+                    _ => panic!("TEST FAIL")
+                }
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // positional ORDER BY references are not shifted onto the hidden primary key
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT b, a FROM bound ORDER BY 2 DESC")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => match p.ast {
+                ASTNode::SQLSelect{order: Some(box ASTNode::SQLExprList(ref items)), ..} => match items[0] {
+                    ASTNode::SQLOrderBy{expr: box ASTNode::SQLIdentifier{ref parts, ..}, is_asc} => {
+                        assert_eq!(vec![String::from("a")], *parts);
+                        assert!(!is_asc);
+                    },
+                    _ => panic!("TEST FAIL")
+                },
+                _ => panic!("TEST FAIL")
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let errors = vec![
+            ("SELECT b, a FROM bound ORDER BY 3", "ORDER BY 3 must refer to a column or alias when primary keys of row bound columns are added to the projection"),
+            ("UPDATE keyed SET id = 8 WHERE id = 7", "Column keyed.notes is bound to its row, its primary key id cannot be updated"),
+            ("UPDATE tickets SET tenant_id = 8 WHERE tenant_id = 7", "Column tickets.body is encrypted under its tenant's key, its tenant column tenant_id cannot be updated"),
+            ("SELECT id FROM bound WHERE id = (SELECT b FROM bound)", "Column bound.b is bound to its row, primary key id must be projected"),
+            ("INSERT INTO bound (b) VALUES ('y')", "Column bound.b is bound to its row, primary key id must be inserted as a literal"),
+            ("UPDATE bound SET b = 'y' WHERE a = 'x'", "Column bound.b is bound to its row, UPDATE requires WHERE id = <value>"),
+            ("UPDATE bound SET id = 8 WHERE id = 7", "Column bound.b is bound to its row, its primary key id cannot be updated"),
+            ("SELECT id FROM bound WHERE b = 'y'", "Equality on row bound column is unsupported: b = 'y'"),
+        ];
+        for (sql, expected) in errors {
+            let (literals, parsed, plan) = parse_and_plan(String::from(sql)).unwrap();
+            match planner.plan(plan, parsed, &literals) {
+                PhysicalPlan::Error(box ZeroError::EncryptionError{message, ..}) => assert_eq!(String::from(expected), message),
+                _ => panic!("TEST FAIL")
+            }
+        }
+    }
+
+    #[test]
+    fn test_physical_plan_ope() {
+        let planner = PhysicalPlanner{};
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id FROM ordered WHERE amount > 10 AND 100 >= amount ORDER BY created")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(2, p.literals.len());
+                for i in 0..2 {
+                    let lit = p.literals.get(&(i as usize)).unwrap();
+                    assert_eq!(NativeType::I64, lit.data_type);
+                    assert_eq!(EncryptionType::Ope, lit.encryption);
+                    assert_eq!(true, lit.key.is_some());
+                }
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id FROM ordered WHERE amount < ? AND amount = 5")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(EncryptionType::Ope, p.params.get(&(0 as usize)).unwrap().encryption);
+                assert_eq!(EncryptionType::Ope, p.literals.get(&(0 as usize)).unwrap().encryption);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT l.id FROM ordered AS l JOIN ordered AS r ON l.id = r.id WHERE l.amount <= r.amount")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(_) => {},
+            _ => panic!("TEST FAIL")
+        }
+
+        let errors = vec![
+            ("SELECT id FROM ordered WHERE amount > created", "Unsupported operation on encrypted column: amount > created"),
+            ("SELECT id FROM ordered WHERE amount + 1 = 5", "Unsupported operation on encrypted column: amount + 1"),
+            ("SELECT id FROM ordered WHERE settled > 10", "Range comparison on OPE column with several key versions is unsupported until it is rotated: settled > 10"),
+            ("SELECT id FROM ordered ORDER BY settled", "Cannot perform ORDER BY on OPE column with several key versions until it is rotated"),
+        ];
+        for (sql, expected) in errors {
+            let (literals, parsed, plan) = parse_and_plan(String::from(sql)).unwrap();
+            match planner.plan(plan, parsed, &literals) {
+                PhysicalPlan::Error(box ZeroError::EncryptionError{message, ..}) => assert_eq!(String::from(expected), message),
+                _ => panic!("TEST FAIL")
+            }
+        }
+    }
+
+    #[test]
+    fn test_physical_plan_paillier() {
+        let planner = PhysicalPlanner{};
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT SUM(amount), AVG(quantity) AS q FROM ledger WHERE id > 10")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(vec![Aggregate::Sum, Aggregate::Avg], p.aggregates);
+                assert_eq!(EncryptionType::Paillier, p.projection[0].encryption);
+                assert_eq!(NativeType::I64, p.projection[1].data_type);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // values are decrypted one by one when not summed
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id, amount FROM ledger")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => assert!(p.aggregates.is_empty()),
+            _ => panic!("TEST FAIL")
+        }
+
+        let errors = vec![
+            ("SELECT id, SUM(amount) FROM ledger", "SUM and AVG of Paillier encrypted columns cannot be selected with other expressions"),
+            ("SELECT SUM(amount) FROM ledger LIMIT 1", "SUM and AVG of Paillier encrypted columns do not support LIMIT"),
+            ("SELECT SUM(amount) FROM ledger ORDER BY 1", "SUM and AVG of Paillier encrypted columns are only supported in the outermost SELECT, without ORDER BY"),
+            ("SELECT id FROM users WHERE id = (SELECT SUM(quantity) FROM ledger)", "SUM and AVG of Paillier encrypted columns are unsupported in subqueries"),
+            ("SELECT l.id FROM ledger AS l JOIN ledger AS r ON l.amount = r.amount", "Unsupported operation between columns of Paillier encryption, expr: l.amount = r.amount"),
+            ("SELECT id FROM ledger WHERE amount = 5", "Unsupported expr: amount = 5"),
+        ];
+        for (sql, expected) in errors {
+            let (literals, parsed, plan) = parse_and_plan(String::from(sql)).unwrap();
+            match planner.plan(plan, parsed, &literals) {
+                PhysicalPlan::Error(box ZeroError::EncryptionError{message, ..}) => assert_eq!(String::from(expected), message),
+                _ => panic!("TEST FAIL {}", sql)
+            }
+        }
+    }
+
+    #[test]
+    fn test_physical_plan_fpe() {
+        let planner = PhysicalPlanner{};
+        let ssn = EncryptionType::Fpe(FpeFormat{alphabet: String::from("0123456789"), pattern: Some(String::from("###-##-####"))});
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id, ssn FROM formatted WHERE ssn = '123-45-6789' OR ? <> ssn")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(ssn, p.literals.get(&0).unwrap().encryption);
+                assert_eq!(ssn, p.params.get(&0).unwrap().encryption);
+                assert_eq!(ssn, p.projection[1].encryption);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let errors = vec![
+            ("SELECT id FROM formatted WHERE ssn > '123-45-6789'", "Unsupported operation on encrypted column: ssn > '123-45-6789'"),
+            ("SELECT id FROM formatted WHERE ssn LIKE '123%'", "Unsupported operation on encrypted column: ssn LIKE '123%'"),
+        ];
+        for (sql, expected) in errors {
+            let (literals, parsed, plan) = parse_and_plan(String::from(sql)).unwrap();
+            match planner.plan(plan, parsed, &literals) {
+                PhysicalPlan::Error(box ZeroError::EncryptionError{message, ..}) => assert_eq!(String::from(expected), message),
+                _ => panic!("TEST FAIL {}", sql)
+            }
+        }
+    }
+
+    #[test]
+    fn test_physical_plan_blind_index() {
+        let planner = PhysicalPlanner{};
+        let index = BlindIndex{column: String::from("ssn_bidx"), length: 16};
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id FROM indexed WHERE ssn = '123-45-6789' OR ? <> ssn")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(Some(BlindIndexUse::Compare(index.clone())), p.literals.get(&0).unwrap().blind_index);
+                assert_eq!(Some(BlindIndexUse::Compare(index.clone())), p.params.get(&0).unwrap().blind_index);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("INSERT INTO indexed (id, ssn) VALUES (1, '123-45-6789')")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(None, p.literals.get(&0).unwrap().blind_index);
+                assert_eq!(Some(BlindIndexUse::Store(index.clone())), p.literals.get(&1).unwrap().blind_index);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("UPDATE indexed SET ssn = '123-45-6789' WHERE id = 1")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(Some(BlindIndexUse::Store(index.clone())), p.literals.get(&0).unwrap().blind_index);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("UPDATE indexed SET ssn = ? WHERE id = 1")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Error(box ZeroError::EncryptionError{message, ..}) =>
+                assert_eq!("Column indexed.ssn has a blind index and can only be set to a literal value", message),
+            _ => panic!("TEST FAIL")
+        }
+    }
+
+    #[test]
+    fn test_physical_plan_like_index() {
+        let planner = PhysicalPlanner{};
+        let index = LikeIndex{column: String::from("last_name_like"), mode: LikeMode::NGram, length: 3};
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id FROM indexed WHERE last_name LIKE 'SMI%' OR last_name LIKE ?")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(Some(LikeIndexUse::Match(index.clone())), p.literals.get(&0).unwrap().like_index);
+                assert_eq!(Some(LikeIndexUse::Match(index.clone())), p.params.get(&0).unwrap().like_index);
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("INSERT INTO indexed (id, last_name) VALUES (1, 'SMITH')")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => assert_eq!(Some(LikeIndexUse::Store(index.clone())), p.literals.get(&1).unwrap().like_index),
+            _ => panic!("TEST FAIL")
+        }
+
+        // unencrypted columns are left to the database
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id FROM indexed WHERE id LIKE '1%'")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(_) => {},
+            _ => panic!("TEST FAIL")
+        }
+
+        let errors = vec![
+            ("SELECT id FROM users WHERE first_name LIKE 'A%'", "Unsupported operation on encrypted column: first_name LIKE 'A%'"),
+            ("SELECT id FROM indexed WHERE 'SMITH' LIKE last_name", "Unsupported operation on encrypted column: 'SMITH' LIKE last_name"),
+            ("UPDATE indexed SET last_name = ? WHERE id = 1", "Column indexed.last_name has a LIKE index and can only be set to a literal value"),
+        ];
+        for (sql, expected) in errors {
+            let (literals, parsed, plan) = parse_and_plan(String::from(sql)).unwrap();
+            match planner.plan(plan, parsed, &literals) {
+                PhysicalPlan::Error(box ZeroError::EncryptionError{message, ..}) => assert_eq!(String::from(expected), message),
+                _ => panic!("TEST FAIL")
+            }
+        }
+    }
+
+    #[test]
+    fn test_physical_plan_normalization() {
+        let planner = PhysicalPlanner{};
+        let normalization = Normalization{case_fold: true, nfc: true, trim: true, display: Some(String::from("c_last_display"))};
+        let display_keys = normalization.display_keys(&KeyRing::new(0, SecretKey::new([5u8; 32])));
+
+        // compared values are normalized, and the column is read from its display column under its own name
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT id, n.c_last FROM normalized AS n WHERE c_last = 'SMITH' OR ? = c_last")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(Some(NormalizationUse::Compare(normalization.clone())), p.literals.get(&0).unwrap().normalization);
+                assert_eq!(Some(NormalizationUse::Compare(normalization.clone())), p.params.get(&0).unwrap().normalization);
+                assert!(p.displayed);
+                assert_eq!(EncryptionType::NA, p.projection[0].encryption);
+                assert_eq!(EncryptionType::AesGcm, p.projection[1].encryption);
+                assert_eq!(Some(display_keys.clone()), p.projection[1].key);
+                match p.ast {
+                    ASTNode::SQLSelect{expr_list: box ASTNode::SQLExprList(ref list), ..} => match list[1] {
+                        ASTNode::SQLAlias{expr: box ASTNode::SQLFunctionCall{ref args, ..}, alias: box ASTNode::SQLIdentifier{id: ref alias, ..}} => {
+                            match (&args[0], &args[1]) {
+                                (&ASTNode::SQLIdentifier{id: ref display, ..}, &ASTNode::SQLIdentifier{ref id, ..}) => {
+                                    assert_eq!("n.c_last_display", display);
+                                    assert_eq!("n.c_last", id);
+                                },
+                                _ => panic!("TEST FAIL")
+                            }
+                            assert_eq!("c_last", alias);
+                        },
+                        _ => panic!("TEST FAIL")
+                    },
+                    _ => panic!("TEST FAIL")
+                }
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // a wildcard is expanded to name the display column
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT * FROM normalized")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => {
+                assert_eq!(EncryptionType::AesGcm, p.projection[1].encryption);
+                match p.ast {
+                    ASTNode::SQLSelect{expr_list: box ASTNode::SQLExprList(ref list), ..} => {
+                        assert_eq!(2, list.len());
+                        match list[1] {
+                            ASTNode::SQLAlias{expr: box ASTNode::SQLFunctionCall{ref args, ..}, ..} => match args[0] {
+                                ASTNode::SQLIdentifier{ref id, ..} => assert_eq!("normalized.c_last_display", id),
+                                _ => panic!("TEST FAIL")
+                            },
+                            _ => panic!("TEST FAIL")
+                        }
+                    },
+                    _ => panic!("TEST FAIL")
+                }
+            },
+            _ => panic!("TEST FAIL")
+        }
+
+        // other expressions read the normalized value
+        let (literals, parsed, plan) = parse_and_plan(String::from("SELECT COUNT(id) FROM normalized WHERE c_last <> 'smith'")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => assert!(!p.displayed),
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("INSERT INTO normalized (id, c_last) VALUES (1, 'Smith')")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Plan(p) => assert_eq!(Some(NormalizationUse::Store(normalization.clone())), p.literals.get(&1).unwrap().normalization),
+            _ => panic!("TEST FAIL")
+        }
+
+        let (literals, parsed, plan) = parse_and_plan(String::from("UPDATE normalized SET c_last = ? WHERE id = 1")).unwrap();
+        match planner.plan(plan, parsed, &literals) {
+            PhysicalPlan::Error(box ZeroError::EncryptionError{message, ..}) =>
+                assert_eq!("Column normalized.c_last has a display column and can only be set to a literal value", message),
+            _ => panic!("TEST FAIL")
+        }
+    }
+
     #[test]
     fn test_physical_plan_illegal_operations() {
         // Eq between encrypted = unencrypted
@@ -760,25 +1770,28 @@ mod tests {
                         columns: vec![
                             ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
                                         encryption: EncryptionType::NA,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("first_name"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("last_name"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("ssn"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::AesGcm,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                              ColumnMeta {name: String::from("credit_card"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::AesGcm,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("age"), native_type: NativeType::U64,
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("sex"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("email"), native_type: NativeType::Varchar(50),
+                                        encryption: EncryptionType::AesSiv,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                         ]
                     }))
                 },
@@ -787,16 +1800,132 @@ mod tests {
                         columns: vec![
                             ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
                                         encryption: EncryptionType::NA,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("user_id"), native_type: NativeType::U64,
                                         encryption: EncryptionType::NA,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("item_code"), native_type: NativeType::U64,
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("amount"), native_type: NativeType::F64,
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "bound") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("a"), native_type: NativeType::Varchar(50),
+                                        encryption: EncryptionType::AesSiv,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: Some(AadBinding{column: "zero.bound.a".into(), row_key: None, row_key_type: NativeType::UNKNOWN, row_keys: false, tenant: None}), blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("b"), native_type: NativeType::Varchar(50),
+                                        encryption: EncryptionType::AesGcm,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: Some(AadBinding{column: "zero.bound.b".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: false, tenant: None}), blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "keyed") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("notes"), native_type: NativeType::LONGTEXT(1 << 32),
+                                        encryption: EncryptionType::AesGcm,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: Some(AadBinding{column: "zero.keyed.notes".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: true, tenant: None}), blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "tickets") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("tenant_id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("body"), native_type: NativeType::Varchar(50),
+                                        encryption: EncryptionType::AesGcm,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: Some(AadBinding{column: "zero.tickets.body".into(), row_key: None, row_key_type: NativeType::UNKNOWN, row_keys: false,
+                                            tenant: Some(TenantKey{column: "tenant_id".into(), key_ref: "tenant-{}".into(), missing: MissingKey::Null})}), blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "ledger") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("amount"), native_type: NativeType::D128,
+                                        encryption: EncryptionType::Paillier,
+                                        key: KeyRing::new(0, SecretKey::new([1u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("quantity"), native_type: NativeType::I64,
+                                        encryption: EncryptionType::Paillier,
+                                        key: KeyRing::new(0, SecretKey::new([2u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "formatted") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("ssn"), native_type: NativeType::Varchar(11),
+                                        encryption: EncryptionType::Fpe(FpeFormat{alphabet: String::from("0123456789"), pattern: Some(String::from("###-##-####"))}),
+                                        key: KeyRing::new(0, SecretKey::new([1u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "ordered") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("amount"), native_type: NativeType::I64,
+                                        encryption: EncryptionType::Ope,
+                                        key: KeyRing::new(0, SecretKey::new([1u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("created"), native_type: NativeType::DATETIME(0),
+                                        encryption: EncryptionType::Ope,
+                                        key: KeyRing::new(0, SecretKey::new([2u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("settled"), native_type: NativeType::I64,
+                                        encryption: EncryptionType::Ope,
+                                        key: { let mut k = KeyRing::new(1, SecretKey::new([3u8; 32])); k.add(2, SecretKey::new([4u8; 32])); k },
+                                        aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "indexed") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("ssn"), native_type: NativeType::Varchar(50),
+                                        encryption: EncryptionType::AesGcm,
+                                        key: KeyRing::new(0, SecretKey::new([3u8; 32])), aad: None,
+                                        blind_index: Some(BlindIndex{column: String::from("ssn_bidx"), length: 16}), like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("last_name"), native_type: NativeType::Varchar(50),
+                                        encryption: EncryptionType::AesGcm,
+                                        key: KeyRing::new(0, SecretKey::new([4u8; 32])), aad: None, blind_index: None,
+                                        like_index: Some(LikeIndex{column: String::from("last_name_like"), mode: LikeMode::NGram, length: 3}), padding: Padding::NA, normalization: None},
+                        ]
+                    }))
+                },
+                ("zero", "normalized") => {
+                    Some(Rc::new(TableMeta {
+                        columns: vec![
+                            ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
+                                        encryption: EncryptionType::NA,
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
+                            ColumnMeta {name: String::from("c_last"), native_type: NativeType::Varchar(16),
+                                        encryption: EncryptionType::Aes([0u8;12]),
+                                        key: KeyRing::new(0, SecretKey::new([5u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA,
+                                        normalization: Some(Normalization{case_fold: true, nfc: true, trim: true, display: Some(String::from("c_last_display"))})},
                         ]
                     }))
                 },
@@ -0,0 +1,214 @@
+// Copyright 2016 AgilData
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http:// www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs::{metadata, read_dir};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+use futures::Future;
+use tokio_core::reactor::{Handle, Timeout};
+
+use config::{self, Config, ConfigError, TConfig};
+use key_provider::{KeyProvider, TenantKeys};
+use super::schema_provider::MySQLBackedSchemaProvider;
+use super::statement_cache::StatementCache;
+
+// how often a pending SIGHUP and, when watching, the config files are checked
+const CHECK_INTERVAL_MS: u64 = 1000;
+
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+// reads the config again on SIGHUP, or once one of its files changes when watching, new statements
+// are planned with it while result sets already streaming keep decrypting with the plans they started with
+pub struct ConfigReloader {
+    path: String,
+    dir: String,
+    watch: bool,
+    modified: RefCell<Vec<(PathBuf, SystemTime)>>,
+    // the config being parsed, its keys resolved, Paillier keys derived and tenant key provider opened, on a worker thread
+    pending: RefCell<Option<Receiver<Result<(Config, Option<Box<KeyProvider>>), Vec<ConfigError>>>>>,
+    provider: Rc<MySQLBackedSchemaProvider>,
+    stmt_cache: Rc<StatementCache>,
+    tenant_keys: Rc<TenantKeys>
+}
+
+impl ConfigReloader {
+
+    pub fn new(path: &str, dir: &str, watch: bool, provider: Rc<MySQLBackedSchemaProvider>,
+               stmt_cache: Rc<StatementCache>, tenant_keys: Rc<TenantKeys>) -> Self {
+        handle_hangup();
+        ConfigReloader {
+            path: path.to_string(),
+            dir: dir.to_string(),
+            watch: watch,
+            modified: RefCell::new(last_modified(path, dir)),
+            pending: RefCell::new(None),
+            provider: provider,
+            stmt_cache: stmt_cache,
+            tenant_keys: tenant_keys
+        }
+    }
+
+    // checks every CHECK_INTERVAL_MS on the event loop, so a reload never runs part way through a packet
+    pub fn schedule(reloader: Rc<ConfigReloader>, handle: Handle) {
+        let timeout = match Timeout::new(Duration::from_millis(CHECK_INTERVAL_MS), &handle) {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Unable to schedule config reload checks: {}", e);
+                return
+            }
+        };
+        let next = handle.clone();
+        handle.spawn(timeout.map(move |_| {
+            reloader.check();
+            ConfigReloader::schedule(reloader, next);
+        }).map_err(|e| error!("Config reload check failed: {}", e)));
+    }
+
+    pub fn check(&self) {
+        // a SIGHUP or change while a config is parsed is handled once it is applied
+        let parsed = match *self.pending.borrow() {
+            Some(ref rx) => match rx.try_recv() {
+                Ok(parsed) => Some(parsed),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => None
+            },
+            None => None
+        };
+        if self.pending.borrow_mut().take().is_some() {
+            match parsed {
+                Some(parsed) => self.apply(parsed),
+                None => error!("Configuration not reloaded, parsing it failed")
+            }
+        }
+
+        let hangup = HANGUP.swap(false, Ordering::SeqCst);
+        let changed = self.watch && {
+            let modified = last_modified(&self.path, &self.dir);
+            let changed = modified != *self.modified.borrow();
+            *self.modified.borrow_mut() = modified;
+            changed
+        };
+        if hangup || changed {
+            self.reload();
+        }
+    }
+
+    // key providers may be slow to answer, so the config is parsed off the event loop
+    fn reload(&self) {
+        info!("Reloading configuration from {} and {}", self.path, self.dir);
+        let (tx, rx) = channel();
+        let (path, dir) = (self.path.clone(), self.dir.clone());
+        thread::spawn(move || {
+            let parsed = config::parse_configs(&path, &dir).map(|c| {
+                c.derive_paillier_keys();
+                let provider = TenantKeys::open(c.get_key_provider_config());
+                (c, provider)
+            });
+            let _ = tx.send(parsed);
+        });
+        *self.pending.borrow_mut() = Some(rx);
+    }
+
+    // a config with problems is reported and the running one kept
+    fn apply(&self, parsed: Result<(Config, Option<Box<KeyProvider>>), Vec<ConfigError>>) {
+        let (config, provider) = match parsed {
+            Ok(c) => c,
+            Err(errors) => {
+                for e in errors.iter() {
+                    error!("{}", e);
+                }
+                error!("Configuration not reloaded, {} problems found", errors.len());
+                return
+            }
+        };
+        if let Err(e) = self.tenant_keys.reload(config.get_key_provider_config(), provider) {
+            error!("Configuration not reloaded: {}", e);
+            return
+        }
+
+        let current = self.provider.config();
+        if current.get_client_config().props != config.get_client_config().props ||
+            current.get_connection_config().props != config.get_connection_config().props {
+            warn!("Changes to client and connection settings take effect on restart");
+        }
+
+        config.retain_paillier_keys();
+        self.provider.reload(Rc::new(config));
+        self.stmt_cache.clear();
+        info!("Configuration reloaded");
+    }
+}
+
+// the modification times of the config, the override directory and its files, so a file
+// removed, or replaced by one with an older time, is a change as well
+fn last_modified(path: &str, dir: &str) -> Vec<(PathBuf, SystemTime)> {
+    let mut paths = vec![Path::new(path).to_path_buf(), Path::new(dir).to_path_buf()];
+    if let Ok(entries) = read_dir(dir) {
+        paths.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+    }
+    paths[2..].sort();
+    paths.into_iter().filter_map(|p| metadata(&p).and_then(|m| m.modified()).ok().map(|t| (p, t))).collect()
+}
+
+#[cfg(unix)]
+fn handle_hangup() {
+    const SIGHUP: i32 = 1;
+    extern "C" fn on_hangup(_: i32) {
+        HANGUP.store(true, Ordering::SeqCst);
+    }
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    unsafe { signal(SIGHUP, on_hangup); }
+}
+
+#[cfg(not(unix))]
+fn handle_hangup() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    #[test]
+    fn test_last_modified() {
+        let dir = env::temp_dir().join("zero-reload-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+
+        let before = last_modified("src/test/test-zero-config.xml", dir);
+        assert_eq!(2, before.len());
+        assert_eq!(before, last_modified("src/test/test-zero-config.xml", dir));
+        assert_eq!(1, last_modified("src/test/test-zero-config.xml", "src/nonexistent").len());
+
+        // adding an override is a change, and so is removing it
+        let file = Path::new(dir).join("1.zero-config.xml");
+        File::create(&file).unwrap().write_all(b"<zero-config/>").unwrap();
+        let added = last_modified("src/test/test-zero-config.xml", dir);
+        assert_eq!(3, added.len());
+        assert!(added != before);
+        fs::remove_file(&file).unwrap();
+        assert_eq!(2, last_modified("src/test/test-zero-config.xml", dir).len());
+        assert!(last_modified("src/nonexistent.xml", "src/nonexistent").is_empty());
+    }
+}
@@ -17,19 +17,81 @@ use config::*;
 use std::str::FromStr;
 use std::collections::HashMap;
 use std::sync::Mutex;
-use query::{Parser, Tokenizer, ASTNode, MySQLDataType};
+use query::{Parser, Tokenizer, ASTNode, MySQLDataType, LiteralToken};
 use query::dialects::ansisql::*;
 use query::dialects::mysqlsql::*;
-use encrypt::{NativeType, EncryptionType};
+use encrypt::{NativeType, EncryptionType, AadPolicy, AadBinding, KeyDerivation, KeyRing, Padding};
+use secret::SecretKey;
 use std::rc::Rc;
 use error::ZeroError;
 use mysql;
 
 // Mysql and config backed provider
 // locks on mutex to prevent multiple threads querying the database for uncached meta
+// connects to the backend described by the connection config
+pub fn create_pool(config: &Config) -> mysql::Pool {
+    let conn = config.get_connection_config();
+    let conn_host = conn.props.get("host").unwrap().clone();
+    let default_port = &String::from("3306");
+    let conn_port = u16::from_str(conn.props.get("port").unwrap_or(default_port)).unwrap();
+    let user = conn.props.get("user").unwrap().clone();
+    let pw = conn.props.get("password").unwrap().clone();
+    //
+    let mut builder = mysql::conn::OptsBuilder::default();
+
+    builder.user(Some(user))
+           .pass(Some(pw))
+           .ip_or_hostname(Some(conn_host))
+           .tcp_port(conn_port);
+    let opts: mysql::conn::Opts = builder.into();
+    mysql::Pool::new(opts).unwrap()
+}
+
+pub fn build_aad(config: &Config, schema: &String, table: &String, column_config: &ColumnConfig) -> Option<AadBinding> {
+    let column = format!("{}.{}.{}", schema.to_lowercase(), table.to_lowercase(), column_config.name.to_lowercase());
+    let tenant = match column_config.encryption {
+        EncryptionType::NA => None,
+        _ => config.get_table_config(schema, table).and_then(|t| t.tenant.clone())
+    };
+    match column_config.aad {
+        AadPolicy::Column => Some(AadBinding{column: column, row_key: None, row_key_type: NativeType::UNKNOWN, row_keys: false, tenant: tenant}),
+        AadPolicy::Row => {
+            // presence of the primary key is enforced when the config is parsed
+            let pk = config.get_table_config(schema, table).and_then(|t| t.get_primary_key());
+            Some(AadBinding{column: column, row_key: pk.map(|c| c.name.clone()), row_key_type: pk.map_or(NativeType::UNKNOWN, |c| c.native_type.clone()),
+                            row_keys: column_config.key_derivation == KeyDerivation::Row, tenant: tenant})
+        },
+        // values encrypted under their tenant's key are bound to their column
+        AadPolicy::NA if tenant.is_some() => Some(AadBinding{column: column, row_key: None, row_key_type: NativeType::UNKNOWN, row_keys: false, tenant: tenant}),
+        AadPolicy::NA => None
+    }
+}
+
+// warns of columns too narrow for their ciphertexts, such as those created before the ciphertext header
+// was introduced, as MySQL truncates or rejects the values written to them
+fn check_width(schema: &String, table: &String, column_config: &ColumnConfig, data_type: &ASTNode) {
+    let needed = match column_config.encryption {
+        EncryptionType::NA | EncryptionType::Fpe(_) => None,
+        EncryptionType::Paillier => Some(column_config.encryption.overhead()),
+        ref e => column_config.native_type.max_len()
+            .map(|l| column_config.padding.padded_len(l) + e.overhead_with(column_config.key.header()))
+    };
+    let width = match *data_type {
+        ASTNode::MySQLDataType(MySQLDataType::Binary{length: Some(l)}) |
+        ASTNode::MySQLDataType(MySQLDataType::VarBinary{length: Some(l)}) => Some(l),
+        _ => None
+    };
+    if let (Some(needed), Some(width)) = (needed, width) {
+        if width < needed {
+            warn!("Column {}.{}.{} is {} bytes wide but its encrypted values take up to {}, widen it before writing to it",
+                schema, table, column_config.name, width, needed);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MySQLBackedSchemaProvider {
-    config: Rc<Config>,
+    config: Mutex<Rc<Config>>,
     pool: mysql::Pool,
     cache: Mutex<HashMap<String, Rc<TableMeta>>>
 }
@@ -37,29 +99,27 @@ pub struct MySQLBackedSchemaProvider {
 impl MySQLBackedSchemaProvider {
 
     pub fn new(config: Rc<Config>) -> Self {
-        let conn = config.get_connection_config();
-        let conn_host = conn.props.get("host").unwrap().clone();
-        let default_port = &String::from("3306");
-        let conn_port = u16::from_str(conn.props.get("port").unwrap_or(default_port)).unwrap();
-        let user = conn.props.get("user").unwrap().clone();
-        let pw = conn.props.get("password").unwrap().clone();
-        //
-        let mut builder = mysql::conn::OptsBuilder::default();
-
-        builder.user(Some(user))
-               .pass(Some(pw))
-               .ip_or_hostname(Some(conn_host))
-               .tcp_port(conn_port);
-        let opts: mysql::conn::Opts = builder.into();
-        let pool = mysql::Pool::new(opts).unwrap();
+        let pool = create_pool(&config);
 
         MySQLBackedSchemaProvider {
-            config: config.clone(),
+            config: Mutex::new(config.clone()),
             pool: pool,
             cache: Mutex::new(HashMap::new())
         }
     }
 
+    // the config new statements are planned with
+    pub fn config(&self) -> Rc<Config> {
+        self.config.lock().unwrap().clone()
+    }
+
+    // swaps in a reloaded config, table meta holds the columns' keys so is read again as tables are next used,
+    // the connection pool is kept
+    pub fn reload(&self, config: Rc<Config>) {
+        *self.config.lock().unwrap() = config;
+        self.cache.lock().unwrap().clear();
+    }
+
     fn _get_meta(&self, schema: &String, table: &String) -> Result<Option<TableMeta>, Box<ZeroError>> {
         match self.pool.prep_exec(format!("SHOW CREATE TABLE {}.{}", schema, table),()) {
             Ok(mut result) => match result.next() {
@@ -68,8 +128,9 @@ impl MySQLBackedSchemaProvider {
                     let ansi = AnsiSQLDialect::new();
                     let dialect = MySQLDialect::new(&ansi);
 
-                    let parsed = sql.tokenize(&dialect)?.parse()?;
-                    self._build_meta(schema, parsed)
+                    let tokens = sql.tokenize(&dialect)?;
+                    let parsed = tokens.parse()?;
+                    self._build_meta(schema, parsed, &tokens.literals)
 
                 },
                 Some(Err(e)) =>  Err(ZeroError::SchemaError{
@@ -85,18 +146,25 @@ impl MySQLBackedSchemaProvider {
         }
     }
 
-    fn _build_meta(&self, schema: &String, parsed: ASTNode) -> Result<Option<TableMeta>, Box<ZeroError>> {
+    fn _build_meta(&self, schema: &String, parsed: ASTNode, literals: &Vec<LiteralToken>) -> Result<Option<TableMeta>, Box<ZeroError>> {
+        let config = self.config();
         match parsed {
             ASTNode::MySQLCreateTable{table: box ASTNode::SQLIdentifier{id: ref table, ..}, ref column_list, ..} => {
                 let columns = column_list.iter().map(|c| {
                     match c {
                         &ASTNode::MySQLColumnDef{column: box ASTNode::SQLIdentifier{ref id, ..}, data_type: box ref dt, ref qualifiers} => {
-                            if let Some(column_config) = self.config.get_column_config(schema, table, id) {
+                            if let Some(column_config) = config.get_column_config(schema, table, id) {
+                                check_width(schema, table, column_config, dt);
                                 Ok(ColumnMeta {
                                     name: id.clone(),
                                     native_type: column_config.native_type.clone(),
                                     encryption: column_config.encryption.clone(),
                                     key: column_config.key.clone(),
+                                    aad: self._build_aad(schema, table, column_config),
+                                    blind_index: column_config.blind_index.clone(),
+                                    like_index: column_config.like_index.clone(),
+                                    padding: column_config.padding.clone(),
+                                    normalization: column_config.normalization.clone(),
                                 })
                             } else {
                                 let default = vec![];
@@ -104,9 +172,14 @@ impl MySQLBackedSchemaProvider {
                                 //qualifiers: Option<Vec<ASTNode>>
                                 Ok(ColumnMeta {
                                     name: id.clone(),
-                                    native_type: reconcile_native_type(dt, &reconcile_column_qualifiers(&qs, false)?)?,
+                                    native_type: reconcile_native_type(dt, &reconcile_column_qualifiers(&qs, false)?, literals)?,
                                     encryption: EncryptionType::NA,
-                                    key: [0u8; 32],
+                                    key: KeyRing::new(0, SecretKey::new([0u8; 32])),
+                                    aad: None,
+                                    blind_index: None,
+                                    like_index: None,
+                                    padding: Padding::NA,
+                                    normalization: None,
                                 })
                             }
                         },
@@ -127,6 +200,10 @@ impl MySQLBackedSchemaProvider {
         }
     }
 
+    fn _build_aad(&self, schema: &String, table: &String, column_config: &ColumnConfig) -> Option<AadBinding> {
+        build_aad(&self.config(), schema, table, column_config)
+    }
+
     fn _reconcile_native_type(&self, data_type: &MySQLDataType) -> Result<NativeType, Box<ZeroError>> {
         match data_type {
             &MySQLDataType::Int{..} => Ok(NativeType::U64), // TODO use display
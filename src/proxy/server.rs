@@ -24,12 +24,17 @@ use std::net::{SocketAddr};
 use std::io::Cursor;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::cmp;
+use std::mem;
 
 use config::{Config, TConfig};
 use error::ZeroError;
-use encrypt::{Decrypt, NativeType, EncryptionType};
+use encrypt::{Decrypt, NativeType, EncryptionType, KeyRing, MissingKey, Plaintext, PaillierSum, format_time};
+use encrypt::{StreamDecryptor, is_stream, stream_plaintext_len, STREAM_SEGMENT_LEN};
 
 use super::schema_provider::MySQLBackedSchemaProvider;
+use super::reload::ConfigReloader;
+use key_provider::TenantKeys;
 use super::writers::*;
 
 use super::statement_cache::*;
@@ -41,7 +46,8 @@ use query::dialects::ansisql::*;
 use query::planner::{Planner};
 
 use decimal::*;
-use chrono::{DateTime};
+use chrono::{DateTime, TimeZone};
+use chrono::offset::utc::UTC;
 
 use std::sync::atomic::{AtomicU32, Ordering};
 
@@ -53,7 +59,8 @@ pub struct Proxy {
 
 impl Proxy {
 
-    pub fn run(config: Rc<Config>, provider: Rc<MySQLBackedSchemaProvider>, stmt_cache: Rc<StatementCache>) {
+    pub fn run(config: Rc<Config>, provider: Rc<MySQLBackedSchemaProvider>, stmt_cache: Rc<StatementCache>, tenant_keys: Rc<TenantKeys>,
+               reloader: Rc<ConfigReloader>) {
 
         //env_logger::init().unwrap();
 
@@ -83,6 +90,7 @@ impl Proxy {
 
         // Get a reference to the reactor event loop
         let handle = l.handle();
+        ConfigReloader::schedule(reloader, handle.clone());
 
         // Create a TCP listener which will listen for incoming connections
         let socket = TcpListener::bind(&bind_addr, &l.handle()).unwrap();
@@ -91,15 +99,17 @@ impl Proxy {
         // for each incoming connection
         let done = socket.incoming().for_each(move |(socket, _)| {
 
-            let c = config.clone();
+            // connections take the parsing mode of the config current when they open
+            let c = provider.config();
             let p = provider.clone();
             let s = stmt_cache.clone();
+            let t = tenant_keys.clone();
 
             // create a future to serve requests
             let future = TcpStream::connect(&mysql_addr, &handle).and_then(move |mysql| {
                 Ok((socket, mysql))
             }).and_then(move |(client, server)| {
-                Pipe::new(Rc::new(client), Rc::new(server), ZeroHandler::new(c,p, s))
+                Pipe::new(Rc::new(client), Rc::new(server), ZeroHandler::new(c,p, s, t))
             });
 
             // tell the tokio reactor to run the future
@@ -178,6 +188,8 @@ struct PStmt {
     plan: Rc<PhysicalPlan>,
     /// does the result set need to be decrypted?
     decrypt_result_set: bool,
+    /// parameter types and flags sent by the client with the last execute
+    bound_types: Vec<(u8, u8)>,
 }
 
 #[derive(Debug,Clone,Copy,PartialEq)]
@@ -215,15 +227,461 @@ pub enum ProtocolBinary {
 }
 
 struct ZeroHandler {
-    config: Rc<Config>,
     provider: Rc<MySQLBackedSchemaProvider>,
     state: HandlerState,
     schema: Option<String>, // the current schema
     parsing_mode: ParsingMode,
     tt: Option<Vec<EncryptionPlan>>,
+    // leading columns of tt added by the planner, which the client does not receive
+    hidden: usize,
+    fold: Option<Fold>,
     stmt_map: HashMap<u16, Box<PStmt>>,
     stmt_cache: Rc<StatementCache>,
-    server_version: MySQLVersion
+    tenant_keys: Rc<TenantKeys>,
+    server_version: MySQLVersion,
+    // result row spanning several packets being decrypted
+    row: Option<RowStream>,
+    // added to the sequence ids of result packets following a row written in fewer packets than it arrived in
+    sequence_shift: u8,
+    // whether the last response packet was continued in the next
+    continued: bool,
+    // a request of 0xffffff bytes or more, joined from its packets until the last arrives
+    request: Option<Vec<u8>>
+}
+
+/// SUM and AVG of Paillier columns being folded from the rows of a COM_QUERY response
+struct Fold {
+    sums: Vec<(Aggregate, NativeType, PaillierSum)>,
+    // sequence id of the first dropped row, taken by the folded row
+    sequence_id: Option<u8>
+}
+
+impl Fold {
+    fn new(aggregates: &Vec<Aggregate>, projection: &Vec<EncryptionPlan>) -> Fold {
+        Fold {
+            sums: aggregates.iter().zip(projection.iter())
+                .map(|(a, e)| (a.clone(), e.data_type.clone(), PaillierSum::new(e.key.as_ref().unwrap())))
+                .collect(),
+            sequence_id: None
+        }
+    }
+
+    // NULLs are skipped, as SQL's SUM and AVG do
+    fn add(&mut self, p: &Packet) -> Result<(), Box<ZeroError>> {
+        if self.sequence_id.is_none() {
+            self.sequence_id = Some(p.bytes[3]);
+        }
+        let mut r = MySQLPacketParser::new(&p.bytes);
+        for &mut (_, _, ref mut sum) in self.sums.iter_mut() {
+            if let Some(v) = r.read_lenenc_bytes() {
+                sum.add(&v)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the folded row followed by the terminator, or an error in place of the dropped rows
+    fn finish(&self, terminator: &Packet) -> Packet {
+        let sequence_id = self.sequence_id.unwrap_or(terminator.bytes[3]);
+        let mut t = terminator.bytes.clone();
+        if t[4] == 0xff {
+            t[3] = sequence_id;
+            return Packet { bytes: t }
+        }
+
+        let mut w = MySQLPacketWriter::new(sequence_id);
+        for &(ref aggregate, ref native_type, ref sum) in self.sums.iter() {
+            let value = match *aggregate {
+                Aggregate::Sum => sum.total(native_type),
+                Aggregate::Avg => sum.average(native_type)
+            };
+            match value {
+                Some(v) => w.write_lenenc_bytes(format!("{}", v).as_bytes()),
+                None => w.write_byte(0xfb)
+            }
+        }
+        w.build();
+
+        t[3] = sequence_id.wrapping_add(1);
+        w.payload.extend_from_slice(&t);
+        Packet { bytes: w.payload }
+    }
+}
+
+/// A result row longer than 0xffffff bytes, which arrives as packets of 0xffffff bytes followed
+/// by a shorter one. Values are decrypted as they arrive, values sealed as a stream one segment
+/// at a time, and the row is written out in packets of its own, so the row is never held whole.
+struct RowStream {
+    plans: Vec<EncryptionPlan>,
+    binary: bool,
+    fields: Vec<RowField>,
+    field: usize,
+    // received bytes not yet consumed
+    input: Vec<u8>,
+    // column, handling and remaining bytes of a value passed on as it arrives
+    value: Option<(usize, ValueStream, usize)>,
+    // values of primary keys and tenants that values are decrypted with
+    keys: HashMap<usize, Vec<u8>>,
+    // leading columns added by the planner, which are not written out
+    hidden: usize,
+    tenant_keys: Rc<TenantKeys>,
+    out: MySQLPacketWriter
+}
+
+enum ValueStream {
+    Decrypt(StreamDecryptor),
+    Copy,
+    // written as NULL, as its tenant's key was destroyed
+    Skip
+}
+
+#[derive(Debug)]
+enum RowField {
+    // bytes copied as they are
+    Fixed(usize),
+    // a length byte followed by that many bytes, copied
+    Prefixed,
+    // the length encoded value of a projected column
+    Value(usize)
+}
+
+impl RowStream {
+    fn text(plans: &Vec<EncryptionPlan>, hidden: usize, tenant_keys: &Rc<TenantKeys>, sequence_id: u8) -> RowStream {
+        let mut row = RowStream::new(plans, false, (0..plans.len()).map(|i| RowField::Value(i)).collect(), tenant_keys, sequence_id);
+        row.hidden = hidden;
+        row
+    }
+
+    // the header and null bitmap are in the first packet
+    fn binary(plans: &Vec<EncryptionPlan>, types: &Vec<ProtocolBinary>, p: &Packet, tenant_keys: &Rc<TenantKeys>, sequence_id: u8) -> Result<RowStream, Box<ZeroError>> {
+        let null_bitmap_len = (types.len() + 7 + 2) / 8;
+        let mut fields = vec![RowField::Fixed(null_bitmap_len + 1)];
+        for (i, t) in types.iter().enumerate() {
+            if (p.bytes[5 + (i + 2) / 8] >> ((i + 2) % 8)) & 1 == 1 {
+                continue;
+            }
+            fields.push(match *t {
+                ProtocolBinary::Tiny => RowField::Fixed(1),
+                ProtocolBinary::Short | ProtocolBinary::Year => RowField::Fixed(2),
+                ProtocolBinary::Long | ProtocolBinary::Float => RowField::Fixed(4),
+                ProtocolBinary::LongLong | ProtocolBinary::Double => RowField::Fixed(8),
+                ProtocolBinary::DateTime | ProtocolBinary::Timestamp | ProtocolBinary::Date | ProtocolBinary::Time => RowField::Prefixed,
+                ProtocolBinary::Varchar | ProtocolBinary::Enum | ProtocolBinary::Set |
+                ProtocolBinary::Geometry | ProtocolBinary::Bit | ProtocolBinary::Decimal |
+                ProtocolBinary::NewDecimal | ProtocolBinary::String | ProtocolBinary::VarString |
+                ProtocolBinary::LongBlob |
+                ProtocolBinary::MediumBlob | ProtocolBinary::Blob | ProtocolBinary::TinyBlob => RowField::Value(i),
+                _ => return Err(ZeroError::DecryptionError {
+                    message: format!("no support for {:?} in pstmt result set", t).into(),
+                    code: "1064".into()
+                }.into())
+            });
+        }
+        Ok(RowStream::new(plans, true, fields, tenant_keys, sequence_id))
+    }
+
+    fn new(plans: &Vec<EncryptionPlan>, binary: bool, fields: Vec<RowField>, tenant_keys: &Rc<TenantKeys>, sequence_id: u8) -> RowStream {
+        RowStream {
+            plans: plans.clone(),
+            binary: binary,
+            fields: fields,
+            field: 0,
+            input: Vec::new(),
+            value: None,
+            keys: HashMap::new(),
+            hidden: 0,
+            tenant_keys: tenant_keys.clone(),
+            out: MySQLPacketWriter::new(sequence_id)
+        }
+    }
+
+    /// sequence id of the packet following the row
+    fn sequence_id(&self) -> u8 {
+        self.out.payload[3]
+    }
+
+    /// consumes the next packet of the row, returning the packets ready to be sent
+    fn feed(&mut self, p: &Packet) -> Result<Vec<u8>, Box<ZeroError>> {
+        let last = payload_len(&p.bytes) < 0xffffff;
+        let mut input = mem::replace(&mut self.input, Vec::new());
+        input.extend_from_slice(&p.bytes[4..]);
+
+        let mut pos = 0;
+        while let Some(n) = self.step(&input[pos..])? {
+            pos += n;
+        }
+        input.drain(..pos);
+        self.input = input;
+
+        if last && (self.field < self.fields.len() || !self.input.is_empty()) {
+            return Err(ZeroError::DecryptionError {
+                message: "Malformed result row".into(),
+                code: "1064".into()
+            }.into())
+        }
+        Ok(self.out.take_packets(last))
+    }
+
+    // consumes the next part of the row from buf, None when more input is needed
+    fn step(&mut self, buf: &[u8]) -> Result<Option<usize>, Box<ZeroError>> {
+        if self.value.is_some() {
+            let (n, done) = {
+                let plans = &self.plans;
+                let &mut (i, ref mut stream, ref mut remaining) = self.value.as_mut().unwrap();
+                let n = cmp::min(*remaining, buf.len());
+                if n == 0 {
+                    return Ok(None)
+                }
+                match *stream {
+                    ValueStream::Decrypt(ref mut d) => {
+                        let plaintext = d.update(&buf[..n]).map_err(|e| authentication_error(&plans[i], e))?;
+                        self.out.write_bytes(&plaintext);
+                    },
+                    ValueStream::Copy => self.out.write_bytes(&buf[..n]),
+                    ValueStream::Skip => {}
+                }
+                *remaining -= n;
+                if *remaining == 0 {
+                    if let ValueStream::Decrypt(ref d) = *stream {
+                        d.finish().map_err(|e| authentication_error(&plans[i], e))?;
+                    }
+                }
+                (n, *remaining == 0)
+            };
+            if done {
+                self.value = None;
+                self.field += 1;
+            }
+            return Ok(Some(n))
+        }
+
+        if self.field == self.fields.len() {
+            return Ok(None)
+        }
+        let n = match self.fields[self.field] {
+            RowField::Fixed(n) => n,
+            RowField::Prefixed => match buf.first() {
+                Some(len) => 1 + *len as usize,
+                None => return Ok(None)
+            },
+            RowField::Value(i) => return self.step_value(i, buf)
+        };
+        if buf.len() < n {
+            return Ok(None)
+        }
+        self.out.write_bytes(&buf[..n]);
+        self.field += 1;
+        Ok(Some(n))
+    }
+
+    // Values of stream ciphertexts, and long unencrypted values, are passed on as they arrive.
+    // Others are decrypted once they have arrived in full.
+    fn step_value(&mut self, i: usize, buf: &[u8]) -> Result<Option<usize>, Box<ZeroError>> {
+        let (header, len) = match read_lenenc(buf) {
+            Some((header, Some(len))) => (header, len),
+            Some((header, None)) => {
+                if i >= self.hidden {
+                    self.out.write_byte(0xfb);
+                }
+                self.field += 1;
+                return Ok(Some(header))
+            },
+            None => return Ok(None)
+        };
+        let v = &buf[header..];
+        let e = &self.plans[i];
+
+        // primary keys added by the planner are only kept for the values bound to them
+        if i < self.hidden {
+            if v.len() < len {
+                return Ok(None)
+            }
+            let v = &v[..len];
+            let key = if e.encryption == EncryptionType::NA {
+                Some(v.to_vec())
+            } else {
+                match self.binding(i)? {
+                    Some((aad, keys)) => Some(decrypt_text(e, &e.encryption, &keys, v, &aad).map_err(|err| authentication_error(e, err))?),
+                    None => None
+                }
+            };
+            if let Some(key) = key {
+                self.keys.insert(i, key);
+            }
+            self.field += 1;
+            return Ok(Some(header + len))
+        }
+
+        let streamed = match e.data_type {
+            NativeType::Varchar(_) | NativeType::Char(_) | NativeType::LONGTEXT(_) |
+            NativeType::FIXEDBINARY(_) | NativeType::VARBINARY(_) | NativeType::LONGBLOB(_) => e.encryption == EncryptionType::AesGcm,
+            _ => false
+        };
+        if streamed && v.len() < cmp::min(len, 3) {
+            return Ok(None)
+        }
+        if streamed && is_stream(&e.encryption, v) {
+            let plaintext_len = match stream_plaintext_len(len) {
+                Some(l) => l,
+                None => return Err(authentication_error(e, ZeroError::DecryptionError {
+                    message: "Failed decrypting data".into(),
+                    code: "123".into()
+                }.into()))
+            };
+            match self.binding(i)? {
+                Some((aad, keys)) => {
+                    self.out.write_lenenc_int(plaintext_len);
+                    self.value = Some((i, ValueStream::Decrypt(StreamDecryptor::new(&keys, &aad, len)), len));
+                },
+                None => {
+                    self.out.write_byte(0xfb);
+                    self.value = Some((i, ValueStream::Skip, len));
+                }
+            }
+            return Ok(Some(header))
+        }
+        if e.encryption == EncryptionType::NA && len > STREAM_SEGMENT_LEN {
+            self.out.write_lenenc_int(len);
+            self.value = Some((i, ValueStream::Copy, len));
+            return Ok(Some(header))
+        }
+
+        if v.len() < len {
+            return Ok(None)
+        }
+        let v = &v[..len];
+        if e.encryption == EncryptionType::NA {
+            self.out.write_lenenc_bytes(v);
+            if !self.binary {
+                self.keys.insert(i, v.to_vec());
+            }
+        } else if self.binary {
+            write_decrypted(e, v.to_vec(), &mut self.out)?;
+        } else {
+            match self.binding(i)? {
+                Some((aad, keys)) => {
+                    let plaintext = decrypt_text(e, &e.encryption, &keys, v, &aad).map_err(|err| authentication_error(e, err))?;
+                    self.out.write_lenenc_bytes(&plaintext);
+                    self.keys.insert(i, plaintext);
+                },
+                None => self.out.write_byte(0xfb)
+            }
+        }
+        self.field += 1;
+        Ok(Some(header + len))
+    }
+
+    // the aad and keys of a value, None when it reads as NULL
+    fn binding(&self, i: usize) -> Result<Option<(Vec<u8>, KeyRing)>, Box<ZeroError>> {
+        let e = &self.plans[i];
+        match e.aad {
+            Some(ref a) if self.binary && (e.aad_row.is_some() || e.tenant_row.is_some()) => Err(ZeroError::DecryptionError {
+                message: format!("Row bound column {} is not supported in prepared statement results", a.column).into(),
+                code: "1064".into()
+            }.into()),
+            _ => {
+                let pk = self.row_value(e, e.aad_row, "is bound to its row but the primary key")?;
+                let tenant = self.row_value(e, e.tenant_row, "is encrypted under its tenant's key but the tenant")?;
+                value_binding(e, &self.tenant_keys, pk.as_ref().map(|v| v as &str), tenant.as_ref().map(|v| v as &str))
+            }
+        }
+    }
+
+    // the primary key or tenant of a value's row, which must precede it, since the row is not held
+    fn row_value(&self, e: &EncryptionPlan, row: Option<usize>, what: &str) -> Result<Option<String>, Box<ZeroError>> {
+        match (&e.aad, row) {
+            (&Some(ref a), Some(i)) => match self.keys.get(&i) {
+                Some(v) => Ok(Some(String::from_utf8_lossy(v).into_owned())),
+                None => Err(ZeroError::DecryptionError {
+                    message: format!("Column {} {} is NULL or follows it in a row longer than 16MB", a.column, what).into(),
+                    code: "1064".into()
+                }.into())
+            },
+            _ => Ok(None)
+        }
+    }
+}
+
+// the aad and keys of a value given the primary key and tenant of its row, None when its tenant's key
+// was destroyed and the table reads such values as NULL
+fn value_binding(e: &EncryptionPlan, tenant_keys: &TenantKeys, pk: Option<&str>, tenant: Option<&str>) -> Result<Option<(Vec<u8>, KeyRing)>, Box<ZeroError>> {
+    let key = e.key.as_ref().unwrap();
+    let a = match e.aad {
+        Some(ref a) => a,
+        None => return Ok(Some((vec![], key.clone())))
+    };
+    let keys = match (&a.tenant, tenant) {
+        (&Some(ref t), Some(v)) => match tenant_keys.keys(a, v)? {
+            Some(keys) => keys,
+            None if t.missing == MissingKey::Null => return Ok(None),
+            None => return Err(ZeroError::DecryptionError {
+                message: format!("Column {} has no key for tenant {}", a.column, v).into(),
+                code: "1064".into()
+            }.into())
+        },
+        _ => key.clone()
+    };
+    Ok(Some((a.to_bytes(pk), a.keys(&keys, pk))))
+}
+
+// payload length from a packet header
+fn payload_len(bytes: &[u8]) -> usize {
+    bytes[0] as usize | (bytes[1] as usize) << 8 | (bytes[2] as usize) << 16
+}
+
+// the size of the length encoded integer at the start of buf and its value, None for NULL,
+// or None when buf does not hold all of it
+fn read_lenenc(buf: &[u8]) -> Option<(usize, Option<usize>)> {
+    let size = match buf.first() {
+        Some(&0xfb) => return Some((1, None)),
+        Some(&0xfc) => 3,
+        Some(&0xfd) => 4,
+        Some(&0xfe) => 9,
+        Some(n) => return Some((1, Some(*n as usize))),
+        None => return None
+    };
+    if buf.len() < size {
+        return None
+    }
+    Some((size, Some(Cursor::new(&buf[1..size]).read_uint::<LittleEndian>(size - 1).unwrap() as usize)))
+}
+
+// the column count of a result set
+fn column_count(p: &Packet) -> usize {
+    match read_lenenc(&p.bytes[4..]) {
+        Some((_, Some(n))) => n,
+        _ => 0
+    }
+}
+
+// the column count of a result set, less the columns added by the planner
+fn strip_column_count(p: &Packet, hidden: usize) -> Action {
+    if hidden == 0 {
+        return Action::Forward
+    }
+    let mut w = MySQLPacketWriter::new(p.bytes[3]);
+    w.write_lenenc_int(column_count(p) - hidden);
+    w.build();
+    Action::Mutate(Packet { bytes: w.payload })
+}
+
+// shifts the sequence ids of the packets an action sends, once a row was written back in
+// fewer packets than it arrived in, or packets before it were dropped
+fn renumber(action: Action, p: &Packet, shift: u8) -> Action {
+    if shift == 0 {
+        return action
+    }
+    let mut bytes = match action {
+        Action::Forward => p.bytes.clone(),
+        Action::Mutate(packet) => packet.bytes,
+        a @ _ => return a
+    };
+    let mut pos = 0;
+    while pos + 4 <= bytes.len() {
+        bytes[pos + 3] = bytes[pos + 3].wrapping_add(shift);
+        pos += 4 + payload_len(&bytes[pos..]);
+    }
+    Action::Mutate(Packet { bytes: bytes })
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -236,20 +694,26 @@ enum MySQLVersion {
 
 impl ZeroHandler {
 
-    fn new(config: Rc<Config>, provider: Rc<MySQLBackedSchemaProvider>, stmt_cache: Rc<StatementCache>) -> Self {
+    fn new(config: Rc<Config>, provider: Rc<MySQLBackedSchemaProvider>, stmt_cache: Rc<StatementCache>, tenant_keys: Rc<TenantKeys>) -> Self {
 
         let parsing_mode = determine_parsing_mode(&config.get_parsing_config().props.get("mode").unwrap());
 
         ZeroHandler {
-            config: config.clone(),
             provider: provider.clone(),
             state: HandlerState::Handshake,
             schema: None,
             parsing_mode: parsing_mode,
             tt: None,
+            hidden: 0,
+            fold: None,
             stmt_map: HashMap::new(),
             stmt_cache: stmt_cache,
-            server_version: MySQLVersion::Unknown
+            tenant_keys: tenant_keys,
+            server_version: MySQLVersion::Unknown,
+            row: None,
+            sequence_shift: 0,
+            continued: false,
+            request: None
         }
     }
 }
@@ -280,6 +744,27 @@ impl PacketHandler for ZeroHandler {
 
         print_packet_chars("handle_request", &p.bytes);
 
+        // requests of 0xffffff bytes or more arrive as several packets, the payloads of the continuation
+        // packets are appended to the first and the whole request handled once the last arrives
+        if self.request.is_some() || payload_len(&p.bytes) == 0xffffff {
+            let mut request = self.request.take().unwrap_or_else(|| p.bytes[0..4].to_vec());
+            request.extend_from_slice(&p.bytes[4..]);
+            if payload_len(&p.bytes) == 0xffffff {
+                self.request = Some(request);
+                return Action::Drop
+            }
+            let joined = Packet { bytes: request };
+            return match self.handle_request(&joined) {
+                // the packets were held back, so are sent on as they arrived
+                Action::Forward => Action::Mutate(split_request(&joined.bytes[4..])),
+                action => action
+            }
+        }
+
+        self.row = None;
+        self.sequence_shift = 0;
+        self.continued = false;
+
         let action = if let HandlerState::Handshake = self.state {
             self.state = HandlerState::ComQueryResponse;
 
@@ -354,6 +839,13 @@ impl PacketHandler for ZeroHandler {
 
         print_packet_chars("handle_response", &p.bytes);
 
+        // a packet continuing one of 0xffffff bytes is never a terminator, whatever its first byte
+        let continuation = self.continued;
+        self.continued = payload_len(&p.bytes) == 0xffffff;
+        if let Some(action) = self.stream_result_row(p, continuation) {
+            return action
+        }
+
         let (state, action) = match self.state {
             HandlerState::Handshake => {
                 let mut r = MySQLPacketParser::new(&p.bytes);
@@ -390,7 +882,7 @@ impl PacketHandler for ZeroHandler {
                                 // expect 0 or more result rows
                                 // expect result set terminator
 
-                                (Some(HandlerState::ComQueryFieldPacket(AtomicU32::new(tt.len() as u32))), Action::Forward)
+                                (Some(HandlerState::ComQueryFieldPacket(AtomicU32::new(tt.len() as u32))), strip_column_count(p, self.hidden))
                             },
                             None => {
                                 (Some(HandlerState::ForwardAll), Action::Forward)
@@ -399,11 +891,11 @@ impl PacketHandler for ZeroHandler {
                     },
                     _ => {
                         // expect a field_count packet
-                        let field_count = p.bytes[4] as usize;
+                        let field_count = column_count(p);
                         // expect field_count x field_meta packet
                         // expect 0 or more result rows
                         // expect result set terminator
-                        (Some(HandlerState::ComQueryFieldPacket(AtomicU32::new(field_count as u32))), Action::Forward)
+                        (Some(HandlerState::ComQueryFieldPacket(AtomicU32::new(field_count as u32))), strip_column_count(p, self.hidden))
                     }
                 }
             },
@@ -415,12 +907,23 @@ impl PacketHandler for ZeroHandler {
                         _ => (Some(HandlerState::ExpectResultRow), Action::Forward)
                     }
                 } else {
-                    n.fetch_sub(1, Ordering::SeqCst);
-                    //TODO: need to rewrite field packet to change data type for encrypted columns
-                    (None, Action::Forward)
+                    let total = self.tt.as_ref().map(|tt| tt.len()).unwrap_or(0);
+                    let i = total - n.fetch_sub(1, Ordering::SeqCst) as usize;
+                    if i < self.hidden {
+                        self.sequence_shift = self.sequence_shift.wrapping_sub(1);
+                        (None, Action::Drop)
+                    } else {
+                        //TODO: need to rewrite field packet to change data type for encrypted columns
+                        (None, Action::Forward)
+                    }
                 }
             },
             HandlerState::ExpectResultRow => match p.bytes[4] {
+                _ if continuation => (None, Action::Forward),
+                0x00 | 0xfe | 0xff if self.fold.is_some() => {
+                    let packet = self.fold.take().unwrap().finish(p);
+                    (Some(HandlerState::ExpectClientRequest), Action::Mutate(packet))
+                },
                 0x00 | 0xfe | 0xff => (Some(HandlerState::ExpectClientRequest), Action::Forward),
                 _ => {
                     match self.process_result_row(p) {
@@ -430,10 +933,12 @@ impl PacketHandler for ZeroHandler {
                 }
             },
             HandlerState::IgnoreFurtherResults => match p.bytes[4] {
+                _ if continuation => (None, Action::Drop),
                 0x00 | 0xfe | 0xff => (Some(HandlerState::ExpectClientRequest), Action::Drop),
                 _ => (None, Action::Drop)
             },
             HandlerState::ForwardAll => match p.bytes[4] {
+                _ if continuation => (None, Action::Forward),
                 0x00 | 0xfe | 0xff => (Some(HandlerState::ExpectClientRequest), Action::Forward),
                 _ => (None, Action::Forward)
             },
@@ -454,9 +959,8 @@ impl PacketHandler for ZeroHandler {
                 let decrypt_result_set = match plan.as_ref() {
                     &PhysicalPlan::Plan(ref p) => {
                         p.projection.iter().filter(|e| match e.encryption {
-                            EncryptionType::Aes(_) => true,
-                            EncryptionType::AesGcm => true,
                             EncryptionType::NA => false,
+                            _ => true,
                         }).count() > 0
                     },
                     _ => false
@@ -466,7 +970,8 @@ impl PacketHandler for ZeroHandler {
                     param_types: Vec::with_capacity(num_params as usize),
                     column_types: Vec::with_capacity(num_columns as usize),
                     plan: plan.clone(),
-                    decrypt_result_set: decrypt_result_set
+                    decrypt_result_set: decrypt_result_set,
+                    bound_types: Vec::new()
                 };
 
                 debug!("StmtPrepareResponse: stmt_id={}, num_columns={}, num_params={} decrypt_result_set={}",
@@ -578,6 +1083,7 @@ impl PacketHandler for ZeroHandler {
             HandlerState::StmtExecuteResultRow(ref pstmt) => {
                 print_packet_chars("StmtExecuteResultRow", &p.bytes);
                 match p.bytes[4] {
+                    _ if continuation => (None, Action::Forward),
                     0xfe | 0xff => (Some(HandlerState::ExpectClientRequest), Action::Forward),
                     0x00 => {
                         if pstmt.decrypt_result_set {
@@ -623,7 +1129,7 @@ impl PacketHandler for ZeroHandler {
 
                                                 // unencrypted integral types
                                                 ProtocolBinary::Tiny     => copy(&mut r, &mut w, 1),
-                                                ProtocolBinary::Short    => copy(&mut r, &mut w, 2),
+                                                ProtocolBinary::Short | ProtocolBinary::Year => copy(&mut r, &mut w, 2),
                                                 ProtocolBinary::Long     => copy(&mut r, &mut w, 4),
                                                 ProtocolBinary::LongLong => copy(&mut r, &mut w, 8),
 
@@ -631,7 +1137,7 @@ impl PacketHandler for ZeroHandler {
                                                 ProtocolBinary::Float    => copy(&mut r, &mut w, 4),
                                                 ProtocolBinary::Double   => copy(&mut r, &mut w, 8),
 
-                                                ProtocolBinary::DateTime | ProtocolBinary::Timestamp | ProtocolBinary::Date => {
+                                                ProtocolBinary::DateTime | ProtocolBinary::Timestamp | ProtocolBinary::Date | ProtocolBinary::Time => {
                                                     let len = r.read_byte().unwrap();
                                                     w.write_byte(len as u8);
                                                     copy(&mut r, &mut w, len as usize)
@@ -647,7 +1153,8 @@ impl PacketHandler for ZeroHandler {
                                                     let v = r.read_lenenc_bytes().unwrap();
 
                                                     match encryption {
-                                                        &EncryptionType::Aes(_) | &EncryptionType::AesGcm => {
+                                                        &EncryptionType::Aes(_) | &EncryptionType::AesGcm | &EncryptionType::AesSiv |
+                                                        &EncryptionType::Ope | &EncryptionType::Paillier | &EncryptionType::Fpe(_) => {
                                                             match write_decrypted(&pp.projection[i], v, &mut w) {
                                                                 Ok(()) => {},
                                                                 Err(e) => return create_error(format!("Failed to decrypt result row: {}", e))
@@ -712,6 +1219,8 @@ impl PacketHandler for ZeroHandler {
 
         debug!("State from {:?} to {:?}", self.state, state);
 
+        let action = renumber(action, p, self.sequence_shift);
+
         match state {
             Some(s) => {self.state = s},
             None => {}
@@ -723,49 +1232,88 @@ impl PacketHandler for ZeroHandler {
 }
 
 
+// the primary key or tenant of a decoded row a value is decrypted with
+fn row_value(values: &Vec<Option<Vec<u8>>>, e: &EncryptionPlan, row: Option<usize>, what: &str) -> Result<Option<String>, Box<ZeroError>> {
+    match (&e.aad, row) {
+        (&Some(ref a), Some(i)) => match values[i] {
+            Some(ref v) => Ok(Some(String::from_utf8_lossy(v).into_owned())),
+            None => Err(ZeroError::DecryptionError {
+                message: format!("Column {} {} is NULL", a.column, what).into(),
+                code: "1064".into()
+            }.into())
+        },
+        _ => Ok(None)
+    }
+}
+
 fn write_decrypted(e: &EncryptionPlan, v: Vec<u8>, w: &mut MySQLPacketWriter) -> Result<(), Box<ZeroError>> {
 
     debug!("write_decrypted()");
 
+    let aad = match (&e.aad, e.aad_row.or(e.tenant_row)) {
+        (&Some(ref a), None) => a.to_bytes(None),
+        (&Some(ref a), Some(_)) => return Err(ZeroError::DecryptionError {
+            message: format!("Row bound column {} is not supported in prepared statement results", a.column).into(),
+            code: "1064".into()
+        }.into()),
+        (&None, _) => vec![]
+    };
+
     match &e.data_type {
         &NativeType::U64 => {
-            let n = try!(u64::decrypt(&v, &e.encryption, &e.key.unwrap()));
+            let n = try!(u64::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
             n.encode(w);
             Ok(())
         },
+        &NativeType::YEAR(_) => {
+            let n = try!(u64::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
+            format!("{}", n).encode(w);
+            Ok(())
+        },
+        &NativeType::TIME(ref fsp) => {
+            let n = try!(i64::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
+            format_time(n, *fsp).encode(w);
+            Ok(())
+        },
+        &NativeType::FIXEDBINARY(_) | &NativeType::VARBINARY(_) | &NativeType::LONGBLOB(_) => {
+            let b = try!(Vec::<u8>::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
+            b.encode(w);
+            Ok(())
+        },
         &NativeType::I64 => {
-            let n = try!(i64::decrypt(&v, &e.encryption, &e.key.unwrap()));
+            let n = try!(i64::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
             n.encode(w);
             Ok(())
         },
-        &NativeType::Varchar(_) | &NativeType::Char(_) => { // TODO enforce length
-            let s = try!(String::decrypt(&v, &e.encryption, &e.key.unwrap()));
+        &NativeType::Varchar(_) | &NativeType::Char(_) | &NativeType::LONGTEXT(_) |
+        &NativeType::ENUM(_) | &NativeType::SET(_) => { // TODO enforce length
+            let s = try!(decrypt_string(e, &e.encryption, e.key.as_ref().unwrap(), &v, &aad).map_err(|err| authentication_error(e, err)));
             s.encode(w);
             Ok(())
         },
         &NativeType::BOOL => {
-            let s = try!(bool::decrypt(&v, &e.encryption, &e.key.unwrap()));
+            let s = try!(bool::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
             s.encode(w);
             Ok(())
         },
         &NativeType::D128 => {
-            let s = try!(d128::decrypt(&v, &e.encryption, &e.key.unwrap()));
+            let s = try!(d128::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
             s.encode(w);
             Ok(())
         },
         &NativeType::F64 => {
-            let s = try!(f64::decrypt(&v, &e.encryption, &e.key.unwrap()));
+            let s = try!(f64::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
             s.encode(w);
             Ok(())
         },
         &NativeType::DATE => {
-            let s = try!(DateTime::decrypt(&v, &e.encryption, &e.key.unwrap()));
+            let s = try!(DateTime::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
             let s = s.date().format("%Y-%m-%d").to_string();
             s.encode(w);
             Ok(())
         },
-        &NativeType::DATETIME(ref fsp) => {
-            let s = try!(DateTime::decrypt(&v, &e.encryption, &e.key.unwrap()));
+        &NativeType::DATETIME(ref fsp) | &NativeType::TIMESTAMP(ref fsp) => {
+            let s = try!(DateTime::decrypt(&v, &e.encryption, e.key.as_ref().unwrap(), &aad).map_err(|err| authentication_error(e, err)));
             let fmt = match fsp {
                 &0 => "%Y-%m-%d %H:%M:%S",
                 &1 => "%Y-%m-%d %H:%M:%S%.1f",
@@ -788,6 +1336,306 @@ fn write_decrypted(e: &EncryptionPlan, v: Vec<u8>, w: &mut MySQLPacketWriter) ->
 }
 
 
+// statements on indexed or normalized columns are rewritten, as are those selecting display columns
+fn needs_rewrite(plan: &PPlan) -> bool {
+    plan.displayed || plan.literals.values().chain(plan.params.values())
+        .any(|e| e.blind_index.is_some() || e.like_index.is_some() || e.normalization.is_some())
+}
+
+/// Replaces bound values compared against a blind index with their index, LIKE patterns
+/// with their tokens and values of normalized columns with their normalized ciphertext,
+/// see https://dev.mysql.com/doc/internals/en/com-stmt-execute.html
+fn rewrite_stmt_params(pstmt: &mut PStmt, p: &Packet) -> Result<Option<Packet>, Box<ZeroError>> {
+    let plans = match pstmt.plan.as_ref() {
+        &PhysicalPlan::Plan(ref pp) if pp.params.values()
+            .any(|e| e.blind_index.is_some() || e.like_index.is_some() || e.normalization.is_some()) => pp.params.clone(),
+        _ => return Ok(None)
+    };
+
+    let bytes = &p.bytes;
+    let n = pstmt.param_types.len();
+    let bitmap = 14; // header, command, statement id, flags and iteration count
+    let mut pos = bitmap + (n + 7) / 8;
+    if bytes.len() <= pos {
+        return Err(param_error("Malformed COM_STMT_EXECUTE packet".into()))
+    }
+    if bytes[pos] == 1 {
+        if bytes.len() < pos + 1 + 2 * n {
+            return Err(param_error("Malformed COM_STMT_EXECUTE packet".into()))
+        }
+        pstmt.bound_types = bytes[pos + 1..pos + 1 + 2 * n].chunks(2).map(|t| (t[0], t[1])).collect();
+        pos += 2 * n;
+    }
+    pos += 1;
+    if pstmt.bound_types.len() != n {
+        return Err(param_error("Statement executed without parameter types".into()))
+    }
+
+    let mut types = Vec::with_capacity(n);
+    let mut values = MySQLPacketWriter::new(0);
+    for i in 0..n {
+        let (t, flags) = pstmt.bound_types[i];
+        if (bytes[bitmap + i / 8] >> (i % 8)) & 1 == 1 {
+            types.push((t, flags));
+            continue;
+        }
+
+        let (header, len) = param_len(t, &bytes[pos..])?;
+        let raw = &bytes[pos + header..pos + header + len];
+        match plans.get(&i) {
+            Some(&EncryptionPlan{blind_index: Some(BlindIndexUse::Compare(ref bi)), ..}) |
+            Some(&EncryptionPlan{blind_index: Some(BlindIndexUse::Store(ref bi)), ..}) => {
+                let plan = plans.get(&i).unwrap();
+                let index = param_plaintext(plan, t, flags & 0x80 != 0, raw)?.blind_index(plan.key.as_ref().unwrap(), bi.length);
+                types.push((ProtocolBinary::Blob as u8, 0));
+                values.write_lenenc_bytes(&index);
+            },
+            Some(&EncryptionPlan{like_index: Some(LikeIndexUse::Match(ref li)), ..}) => {
+                let plan = plans.get(&i).unwrap();
+                let tokens = match param_plaintext(plan, t, flags & 0x80 != 0, raw)? {
+                    Plaintext::Text(pattern) => li.pattern_tokens(plan.key.as_ref().unwrap(), &pattern)?,
+                    v => return Err(param_error(format!("Expected a string LIKE pattern, found {:?}", v)))
+                };
+                types.push((ProtocolBinary::VarString as u8, 0));
+                values.write_lenenc_bytes(tokens.as_bytes());
+            },
+            Some(&EncryptionPlan{normalization: Some(_), ..}) => {
+                let plan = plans.get(&i).unwrap();
+                let value = normalized(param_plaintext(plan, t, flags & 0x80 != 0, raw)?, plan);
+                let encrypted = value.encrypt(&plan.encryption, plan.key.as_ref().unwrap(), &[])?;
+                types.push((ProtocolBinary::Blob as u8, 0));
+                values.write_lenenc_bytes(&encrypted);
+            },
+            _ => {
+                types.push((t, flags));
+                values.write_bytes(&bytes[pos..pos + header + len]);
+            }
+        }
+        pos += header + len;
+    }
+
+    let mut w = MySQLPacketWriter::new(bytes[3]);
+    w.write_bytes(&bytes[4..bitmap + (n + 7) / 8]);
+    w.write_byte(1); // new-params-bound, as the types have changed
+    for &(t, flags) in types.iter() {
+        w.write_byte(t);
+        w.write_byte(flags);
+    }
+    w.write_bytes(&values.payload[4..]);
+    Ok(Some(Packet { bytes: w.take_packets(true) }))
+}
+
+// a request payload as the packets of at most 0xffffff bytes it is sent in
+fn split_request(payload: &[u8]) -> Packet {
+    let mut w = MySQLPacketWriter::new(0x00);
+    w.write_bytes(payload);
+    Packet { bytes: w.take_packets(true) }
+}
+
+// length prefix and length of a binary protocol value
+fn param_len(t: u8, bytes: &[u8]) -> Result<(usize, usize), Box<ZeroError>> {
+    let (header, len) = match t {
+        0x06 => (0, 0), // Null
+        0x01 => (0, 1), // Tiny
+        0x02 | 0x0d => (0, 2), // Short, Year
+        0x03 | 0x09 | 0x04 => (0, 4), // Long, Int24, Float
+        0x08 | 0x05 => (0, 8), // LongLong, Double
+        0x07 | 0x0a | 0x0b | 0x0c => match bytes.first() { // Timestamp, Date, Time, DateTime
+            Some(&l) => (1, l as usize),
+            None => return Err(param_error("Truncated parameter".into()))
+        },
+        _ => match bytes.first() {
+            Some(&l) if l < 0xfb => (1, l as usize),
+            Some(&0xfc) if bytes.len() >= 3 => (3, Cursor::new(&bytes[1..3]).read_u16::<LittleEndian>().unwrap() as usize),
+            Some(&0xfd) if bytes.len() >= 4 => (4, Cursor::new(&bytes[1..4]).read_uint::<LittleEndian>(3).unwrap() as usize),
+            Some(&0xfe) if bytes.len() >= 9 => (9, Cursor::new(&bytes[1..9]).read_u64::<LittleEndian>().unwrap() as usize),
+            _ => return Err(param_error("Malformed length encoded parameter".into()))
+        }
+    };
+    if bytes.len() < header + len {
+        return Err(param_error("Truncated parameter".into()))
+    }
+    Ok((header, len))
+}
+
+// the typed value of a binary protocol parameter for a column of the plan's type
+fn param_plaintext(plan: &EncryptionPlan, t: u8, unsigned: bool, raw: &[u8]) -> Result<Plaintext, Box<ZeroError>> {
+    let mut r = Cursor::new(raw);
+    let int = match (t, unsigned) {
+        (0x01, true) => Some((r.read_u8().unwrap() as u64, true)),
+        (0x01, false) => Some((r.read_i8().unwrap() as i64 as u64, false)),
+        (0x02, true) | (0x0d, _) => Some((r.read_u16::<LittleEndian>().unwrap() as u64, true)),
+        (0x02, false) => Some((r.read_i16::<LittleEndian>().unwrap() as i64 as u64, false)),
+        (0x03, true) | (0x09, true) => Some((r.read_u32::<LittleEndian>().unwrap() as u64, true)),
+        (0x03, false) | (0x09, false) => Some((r.read_i32::<LittleEndian>().unwrap() as i64 as u64, false)),
+        (0x08, true) => Some((r.read_u64::<LittleEndian>().unwrap(), true)),
+        (0x08, false) => Some((r.read_i64::<LittleEndian>().unwrap() as u64, false)),
+        _ => None
+    };
+    let float = match t {
+        0x04 => Some(r.read_f32::<LittleEndian>().unwrap() as f64),
+        0x05 => Some(r.read_f64::<LittleEndian>().unwrap()),
+        _ => None
+    };
+
+    match (&plan.data_type, int, float) {
+        (&NativeType::U64, Some((v, unsigned)), _) if unsigned || (v as i64) >= 0 => Ok(Plaintext::U64(v)),
+        (&NativeType::I64, Some((v, unsigned)), _) if !unsigned || (v as i64) >= 0 => Ok(Plaintext::I64(v as i64)),
+        (&NativeType::BOOL, Some((v, _)), _) => Ok(Plaintext::Bool(v != 0)),
+        (&NativeType::F64, Some((v, unsigned)), _) => Ok(Plaintext::F64(if unsigned { v as f64 } else { v as i64 as f64 })),
+        (&NativeType::F64, _, Some(f)) => Ok(Plaintext::F64(f)),
+        (&NativeType::D128, _, Some(f)) => literal_plaintext(&LiteralToken::LiteralDouble(0, format!("{}", f)), plan),
+        (&NativeType::ENUM(_), Some((v, _)), _) | (&NativeType::SET(_), Some((v, _)), _) |
+        (&NativeType::YEAR(_), Some((v, _)), _) => literal_plaintext(&LiteralToken::LiteralLong(0, format!("{}", v)), plan),
+        (&NativeType::TIME(_), _, _) if t == 0x0b => {
+            let mut r = Cursor::new(raw);
+            let micros = if raw.len() >= 8 {
+                let negative = r.read_u8().unwrap() == 1;
+                let days = r.read_u32::<LittleEndian>().unwrap() as i64;
+                let (h, m, s) = (r.read_u8().unwrap() as i64, r.read_u8().unwrap() as i64, r.read_u8().unwrap() as i64);
+                let fraction = if raw.len() >= 12 { r.read_u32::<LittleEndian>().unwrap() as i64 } else { 0 };
+                let v = (((days * 24 + h) * 60 + m) * 60 + s) * 1000000 + fraction;
+                if negative { -v } else { v }
+            } else {
+                0
+            };
+            Ok(Plaintext::Time(micros))
+        },
+        // binary values are sent as they are, and need not be UTF-8
+        (&NativeType::FIXEDBINARY(_), None, None) | (&NativeType::VARBINARY(_), None, None) |
+        (&NativeType::LONGBLOB(_), None, None) => Ok(Plaintext::Bytes(raw.to_vec())),
+        (&NativeType::DATE, _, _) | (&NativeType::DATETIME(_), _, _) | (&NativeType::TIMESTAMP(_), _, _) if t == 0x07 || t == 0x0a || t == 0x0c => {
+            let mut r = Cursor::new(raw);
+            let mut parts = [0u32; 7];
+            for (i, p) in parts.iter_mut().enumerate() {
+                *p = match (i, raw.len()) {
+                    (0, l) if l >= 4 => r.read_u16::<LittleEndian>().unwrap() as u32,
+                    (1, l) | (2, l) if l >= 4 => r.read_u8().unwrap() as u32,
+                    (3, l) | (4, l) | (5, l) if l >= 7 => r.read_u8().unwrap() as u32,
+                    (6, 11) => r.read_u32::<LittleEndian>().unwrap(),
+                    _ => 0
+                };
+            }
+            match UTC.ymd_opt(parts[0] as i32, parts[1], parts[2]).single().and_then(|d| d.and_hms_micro_opt(parts[3], parts[4], parts[5], parts[6])) {
+                Some(v) => Ok(Plaintext::DateTime(v)),
+                None => Err(param_error(format!("Invalid date parameter {:?}", parts)))
+            }
+        },
+        // everything else arrives as text, and is read like a literal would be
+        (data_type, None, None) => {
+            let text = match String::from_utf8(raw.to_vec()) {
+                Ok(s) => s,
+                Err(_) => return Err(param_error("Parameter is not valid UTF-8".into()))
+            };
+            let lit = match *data_type {
+                NativeType::U64 | NativeType::I64 | NativeType::YEAR(_) => LiteralToken::LiteralLong(0, text),
+                NativeType::F64 | NativeType::D128 => LiteralToken::LiteralDouble(0, text),
+                NativeType::BOOL => LiteralToken::LiteralBool(0, text),
+                _ => LiteralToken::LiteralString(0, text)
+            };
+            literal_plaintext(&lit, plan)
+        },
+        (data_type, _, _) => Err(param_error(format!("Unsupported parameter type {:#x} for {:?}", t, data_type)))
+    }
+}
+
+fn param_error(message: String) -> Box<ZeroError> {
+    ZeroError::EncryptionError {
+        message: message,
+        code: "1064".into()
+    }.into()
+}
+
+// A column read from its display column is selected as COALESCE(display, column), so rows written
+// before the display column was added return the normalized value, under the column's own plan
+fn decrypt_string(e: &EncryptionPlan, encryption: &EncryptionType, keys: &KeyRing, v: &[u8], aad: &[u8]) -> Result<String, Box<ZeroError>> {
+    match e.normalization {
+        Some(NormalizationUse::Read(_, box ref stored)) => String::decrypt(v, encryption, keys, aad)
+            .or_else(|_| String::decrypt(v, &stored.encryption, stored.key.as_ref().unwrap(), &[])),
+        _ => String::decrypt(v, encryption, keys, aad)
+    }
+}
+
+// decrypts a value for the text protocol
+fn decrypt_text(e: &EncryptionPlan, encryption: &EncryptionType, keys: &KeyRing, v: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<ZeroError>> {
+    match &e.data_type {
+        &NativeType::U64 => {
+            let res = u64::decrypt(v, encryption, keys, aad)?;
+            Ok(format!("{}", res).into_bytes())
+        },
+        &NativeType::I64 => {
+            let res = i64::decrypt(v, encryption, keys, aad)?;
+            Ok(format!("{}", res).into_bytes())
+        },
+        &NativeType::YEAR(_) => {
+            let res = u64::decrypt(v, encryption, keys, aad)?;
+            Ok(format!("{}", res).into_bytes())
+        },
+        &NativeType::TIME(ref fsp) => {
+            let res = i64::decrypt(v, encryption, keys, aad)?;
+            Ok(format_time(res, *fsp).into_bytes())
+        },
+        &NativeType::FIXEDBINARY(_) | &NativeType::VARBINARY(_) | &NativeType::LONGBLOB(_) => {
+            Vec::<u8>::decrypt(v, encryption, keys, aad)
+        },
+        &NativeType::Varchar(_) | &NativeType::Char(_) | &NativeType::LONGTEXT(_) |
+        &NativeType::ENUM(_) | &NativeType::SET(_) => { // TODO enforce length
+            let res = decrypt_string(e, encryption, keys, v, aad)?;
+            Ok(res.into_bytes())
+        },
+        &NativeType::BOOL => {
+            debug!("try decrypt bool");
+            let res = bool::decrypt(v, encryption, keys, aad)?;
+            debug!("FINISH decrypt bool");
+            Ok(format!("{}", res).into_bytes())
+        },
+        &NativeType::D128 => {
+            let res = d128::decrypt(v, encryption, keys, aad)?;
+            Ok(format!("{}", res).into_bytes())
+        },
+        &NativeType::F64 => {
+            let res = f64::decrypt(v, encryption, keys, aad)?;
+            Ok(format!("{}", res).into_bytes())
+        },
+        &NativeType::DATE => {
+
+            let res = DateTime::decrypt(v, encryption, keys, aad)?;
+            Ok(res.date().format("%Y-%m-%d").to_string().into_bytes())
+        },
+        &NativeType::DATETIME(ref fsp) | &NativeType::TIMESTAMP(ref fsp) => {
+            let res = DateTime::decrypt(v, encryption, keys, aad)?;
+            let fmt = match fsp {
+                &0 => "%Y-%m-%d %H:%M:%S",
+                &1 => "%Y-%m-%d %H:%M:%S%.1f",
+                &2 => "%Y-%m-%d %H:%M:%S%.2f",
+                &3 => "%Y-%m-%d %H:%M:%S%.3f",
+                &4 => "%Y-%m-%d %H:%M:%S%.4f",
+                &5 => "%Y-%m-%d %H:%M:%S%.5f",
+                &6 => "%Y-%m-%d %H:%M:%S%.6f",
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid fractional second precision {}", fsp).into(),
+                    code: "1064".into()
+                }.into())
+            };
+            Ok(res.format(fmt).to_string().into_bytes())
+
+        }
+        native_type @ _ => panic!("Native type {:?} not implemented", native_type)
+    }
+}
+
+// a failure to decrypt a bound value means it failed authentication against its location
+fn authentication_error(e: &EncryptionPlan, err: Box<ZeroError>) -> Box<ZeroError> {
+    match e.aad {
+        Some(ref a) => ZeroError::DecryptionError {
+            message: format!("Failed to authenticate value of {}, it may have been tampered with or moved from another {}",
+                             a.column, if a.row_key.is_some() { "row or column" } else { "column" }).into(),
+            code: "1064".into()
+        }.into(),
+        None => err
+    }
+}
+
 #[allow(dead_code)]
 pub fn print_packet_chars(msg: &'static str, buf: &[u8]) {
     debug!("{} {:?}", msg, &buf);
@@ -819,6 +1667,8 @@ impl ZeroHandler {
         self.state = HandlerState::ComQueryResponse;
 
         self.tt = None;
+        self.hidden = 0;
+        self.fold = None;
 
         let physical_plan = self.get_physical_plan(parse_string(&p.bytes[5..]));
 
@@ -830,12 +1680,16 @@ impl ZeroHandler {
                 let action = match rewritten {
                     Ok(Some(sql)) => {
                         self.tt = Some(p.projection.clone());
+                        self.hidden = p.hidden;
+                        if !p.aggregates.is_empty() {
+                            self.fold = Some(Fold::new(&p.aggregates, &p.projection));
+                        }
                         // write packet with new query
                         let mut w = MySQLPacketWriter::new(0x00); // sequence_id 0x00
                         w.payload.push(0x03); // COM_QUERY request packet type
                         w.write_bytes(sql.as_bytes());
-                        w.build();
-                        let new_packet = Packet { bytes: w.payload };
+                        // rewritten values may take the query past 0xffffff bytes
+                        let new_packet = Packet { bytes: w.take_packets(true) };
                         Action::Mutate(new_packet)
                     },
                     Ok(None) => Action::Forward,
@@ -855,14 +1709,36 @@ impl ZeroHandler {
         debug!("COM_STMT_PREPARE : {}", sql);
         let plan = self.get_physical_plan(sql);
 
-        if let &PhysicalPlan::Error(ref e) = plan.physical_plan.as_ref() {
-            return create_error_from_err(e.clone())
+        match plan.physical_plan.as_ref() {
+            &PhysicalPlan::Error(ref e) => return create_error_from_err(e.clone()),
+            // rows are only folded for COM_QUERY
+            &PhysicalPlan::Plan(ref pp) if !pp.aggregates.is_empty() =>
+                return create_error(String::from("SUM and AVG of Paillier encrypted columns are not supported in prepared statements")),
+            // columns are only removed from text protocol results
+            &PhysicalPlan::Plan(ref pp) if pp.hidden > 0 =>
+                return create_error(String::from("Primary keys of row bound columns must be selected in prepared statements")),
+            _ => {}
         }
 
+        // statements on indexed and normalized columns are prepared against the companion columns
         //TODO: rewrite query if it contains literals for encrypted columns (or maybe reject as unsupported)
         // INSERT ... (id, ssn) VALUES (?, lit)
+        let action = match plan.physical_plan.as_ref() {
+            &PhysicalPlan::Plan(ref pp) if needs_rewrite(pp) => match self.rewrite_query(pp, &plan.literals) {
+                Ok(Some(sql)) => {
+                    let mut w = MySQLPacketWriter::new(0x00);
+                    w.payload.push(0x16); // COM_STMT_PREPARE request packet type
+                    w.write_bytes(sql.as_bytes());
+                    Action::Mutate(Packet { bytes: w.take_packets(true) })
+                },
+                Ok(None) => Action::Forward,
+                Err(e) => return create_error_from_err(e)
+            },
+            _ => Action::Forward
+        };
+
         self.state = HandlerState::StmtPrepareResponse(plan.physical_plan);
-        Action::Forward
+        action
     }
 
     fn process_com_stmt_execute(&mut self, p:&Packet) -> Action {
@@ -871,11 +1747,16 @@ impl ZeroHandler {
 
         debug!("stmt_id = {}", stmt_id);
 
-        match self.stmt_map.get(&stmt_id) {
-            Some(ref pstmt) => {
+        match self.stmt_map.get_mut(&stmt_id) {
+            Some(pstmt) => {
                 debug!("Executing with {:?}", pstmt);
-                self.state = HandlerState::StmtExecuteResponse((*pstmt).clone());
-                Action::Forward
+                let action = match rewrite_stmt_params(pstmt, p) {
+                    Ok(Some(packet)) => Action::Mutate(packet),
+                    Ok(None) => Action::Forward,
+                    Err(e) => return create_error_from_err(e)
+                };
+                self.state = HandlerState::StmtExecuteResponse(pstmt.clone());
+                action
             },
             None => {
                 debug!("No statement in map for id {}", stmt_id);
@@ -1000,17 +1881,26 @@ impl ZeroHandler {
 
     fn rewrite_query(&mut self, physical_plan: &PPlan, literals: &Vec<LiteralToken>) -> Result<Option<String>, Box<ZeroError>> {
 
+        let index_writer = IndexWriter {
+            literals: literals,
+            literal_plans: &physical_plan.literals,
+            param_plans: &physical_plan.params
+        };
+
         let lit_writer = LiteralEncryptionWriter {
             literals: literals,
-            literal_plans: &physical_plan.literals
+            literal_plans: &physical_plan.literals,
+            tenant_keys: &self.tenant_keys
         };
 
         let s = match self.schema {
             Some(ref s) => s.clone(),
             None => String::from("") // TODO
         };
+        // statements are planned and written with the config current when they arrive
+        let config = self.provider.config();
         let translator = CreateTranslatingWriter {
-            config: &self.config,
+            config: &config,
             schema: &s
         };
         let mysql_writer = MySQLWriter {};
@@ -1019,6 +1909,7 @@ impl ZeroHandler {
         };
 
         let writer = SQLWriter::new(vec![
+                                &index_writer,
                                 &lit_writer,
                                 &translator,
                                 &mysql_writer,
@@ -1032,19 +1923,84 @@ impl ZeroHandler {
 
     }
 
+    // Rows longer than 0xffffff bytes are sent as several packets, those of result sets being
+    // decrypted are streamed through a RowStream
+    fn stream_result_row(&mut self, p: &Packet, continuation: bool) -> Option<Action> {
+        if self.row.is_none() {
+            if continuation || payload_len(&p.bytes) < 0xffffff {
+                return None
+            }
+            let sequence_id = p.bytes[3].wrapping_add(self.sequence_shift);
+            let row = match self.state {
+                HandlerState::ExpectResultRow => match self.tt {
+                    Some(ref tt) if tt.iter().any(|e| e.encryption != EncryptionType::NA) => match self.fold {
+                        Some(_) => Err(ZeroError::DecryptionError {
+                            message: "Rows longer than 16MB cannot be summed".into(),
+                            code: "1064".into()
+                        }.into()),
+                        None => Ok(RowStream::text(tt, self.hidden, &self.tenant_keys, sequence_id))
+                    },
+                    _ => return None
+                },
+                HandlerState::StmtExecuteResultRow(ref pstmt) if pstmt.decrypt_result_set => match pstmt.plan.as_ref() {
+                    &PhysicalPlan::Plan(ref pp) => RowStream::binary(&pp.projection, &pstmt.column_types, p, &self.tenant_keys, sequence_id),
+                    _ => return None
+                },
+                _ => return None
+            };
+            match row {
+                Ok(row) => self.row = Some(row),
+                Err(e) => {
+                    self.fold = None;
+                    self.state = HandlerState::IgnoreFurtherResults;
+                    return Some(create_error_from_err(e))
+                }
+            }
+        }
+
+        let packets = self.row.as_mut().unwrap().feed(p);
+        match packets {
+            Ok(bytes) => {
+                if payload_len(&p.bytes) < 0xffffff {
+                    let next = self.row.take().unwrap().sequence_id();
+                    self.sequence_shift = next.wrapping_sub(p.bytes[3].wrapping_add(1));
+                }
+                Some(if bytes.is_empty() { Action::Drop } else { Action::Mutate(Packet { bytes: bytes }) })
+            },
+            Err(e) => {
+                // the client may already have part of the row, the error ends the result set
+                self.row = None;
+                self.state = HandlerState::IgnoreFurtherResults;
+                Some(create_error_from_err(e))
+            }
+        }
+    }
+
     fn process_result_row(&mut self,
                           p: &Packet,
                           ) -> Result<Action, Box<ZeroError>> {
 
+        // folded rows are replaced by a single row at the end of the result set
+        let folded = match self.fold {
+            Some(ref mut fold) => Some(fold.add(p)),
+            None => None
+        };
+        match folded {
+            Some(Ok(())) => return Ok(Action::Drop),
+            Some(Err(e)) => {
+                self.fold = None;
+                return Err(e)
+            },
+            None => {}
+        }
 
         match self.tt {
             Some(ref tt) => {
 
                 // do we need to decrypt anything?
                 let decrypt_result_set = tt.iter().filter(|e| match e.encryption {
-                    EncryptionType::Aes(_) => true,
-                    EncryptionType::AesGcm => true,
                     EncryptionType::NA => false,
+                    _ => true,
                 }).count() > 0;
 
                 if decrypt_result_set {
@@ -1053,78 +2009,35 @@ impl ZeroHandler {
                     let mut r = MySQLPacketParser::new(&p.bytes);
                     let mut w = MySQLPacketWriter::new(p.bytes[3]);
 
-                    for i in 0..tt.len() {
-                        debug!("decrypt element {} : {:?}", i, &tt[i]);
+                    let raw = (0..tt.len()).map(|_| r.read_lenenc_bytes()).collect::<Vec<Option<Vec<u8>>>>();
+                    let mut values: Vec<Option<Vec<u8>>> = vec![None; tt.len()];
 
-                        let value = match &tt[i].encryption {
-                            &EncryptionType::NA => r.read_lenenc_string(),
-                            encryption @ _ => {
-                                match r.read_lenenc_bytes() {
-                                    Some(ref v) => {
-                                        match &tt[i].data_type {
-                                            &NativeType::U64 => {
-                                                let res = try!(u64::decrypt(&v, &encryption, &tt[i].key.unwrap()));
-                                                Some(format!("{}", res))
-                                            },
-                                            &NativeType::I64 => {
-                                                let res = try!(i64::decrypt(&v, &encryption, &tt[i].key.unwrap()));
-                                                Some(format!("{}", res))
-                                            },
-                                            &NativeType::Varchar(_) | &NativeType::Char(_) => { // TODO enforce length
-                                                let res = try!(String::decrypt(&v, &encryption, &tt[i].key.unwrap()));
-                                                Some(res)
-                                            },
-                                            &NativeType::BOOL => {
-                                                debug!("try decrypt bool");
-                                                let res = bool::decrypt(&v, &encryption, &tt[i].key.unwrap())?;
-                                                debug!("FINISH decrypt bool");
-                                                Some(format!("{}", res))
-                                            },
-                                            &NativeType::D128 => {
-                                                let res = d128::decrypt(&v, &encryption, &tt[i].key.unwrap())?;
-                                                Some(format!("{}", res))
-                                            },
-                                            &NativeType::F64 => {
-                                                let res = f64::decrypt(&v, &encryption, &tt[i].key.unwrap())?;
-                                                Some(format!("{}", res))
-                                            },
-                                            &NativeType::DATE => {
-
-                                                let res = DateTime::decrypt(&v, &encryption, &tt[i].key.unwrap())?;
-                                                Some(res.date().format("%Y-%m-%d").to_string())
-                                            },
-                                            &NativeType::DATETIME(ref fsp) => {
-                                                let res = DateTime::decrypt(&v, &encryption, &tt[i].key.unwrap())?;
-                                                let fmt = match fsp {
-                                                    &0 => "%Y-%m-%d %H:%M:%S",
-                                                    &1 => "%Y-%m-%d %H:%M:%S%.1f",
-                                                    &2 => "%Y-%m-%d %H:%M:%S%.2f",
-                                                    &3 => "%Y-%m-%d %H:%M:%S%.3f",
-                                                    &4 => "%Y-%m-%d %H:%M:%S%.4f",
-                                                    &5 => "%Y-%m-%d %H:%M:%S%.5f",
-                                                    &6 => "%Y-%m-%d %H:%M:%S%.6f",
-                                                    _ => return Err(ZeroError::EncryptionError {
-                                                        message: format!("Invalid fractional second precision {}", fsp).into(),
-                                                        code: "1064".into()
-                                                    }.into())
-                                                };
-                                                Some(res.format(fmt).to_string())
+                    // row bound and tenant values need their primary key and tenant, so decrypt those last
+                    let (bound, unbound): (Vec<usize>, Vec<usize>) = (0..tt.len()).partition(|&i| tt[i].aad_row.is_some() || tt[i].tenant_row.is_some());
 
-                                            }
-                                            native_type @ _ => panic!("Native type {:?} not implemented", native_type)
-                                        }
-                                    },
+                    for i in unbound.into_iter().chain(bound.into_iter()) {
+                        debug!("decrypt element {} : {:?}", i, &tt[i]);
+
+                        let value = match (&tt[i].encryption, &raw[i]) {
+                            (_, &None) => None,
+                            (&EncryptionType::NA, &Some(ref v)) => Some(v.clone()),
+                            (encryption @ _, &Some(ref v)) => {
+                                let pk = row_value(&values, &tt[i], tt[i].aad_row, "is bound to its row but the primary key")?;
+                                let tenant = row_value(&values, &tt[i], tt[i].tenant_row, "is encrypted under its tenant's key but the tenant")?;
+                                match value_binding(&tt[i], &self.tenant_keys, pk.as_ref().map(|v| v as &str), tenant.as_ref().map(|v| v as &str))? {
+                                    Some((aad, keys)) => Some(decrypt_text(&tt[i], encryption, &keys, v, &aad).map_err(|e| authentication_error(&tt[i], e))?),
                                     None => None
                                 }
-
                             }
                         };
+                        values[i] = value;
+                    }
 
+                    for value in values.into_iter().skip(self.hidden) {
                         match value {
-                            Some(s) => w.write_lenenc_bytes(s.as_bytes()),
+                            Some(s) => w.write_lenenc_bytes(&s),
                             None => w.write_byte(0xfb)
                         }
-
                     }
 
                     // write the new packet
@@ -1189,11 +2102,15 @@ impl<'a> MySQLPacketParser<'a> {
                 len
             },
             0xfd => {
-                let len = Cursor::new(&self.payload[self.pos..]).read_u32::<LittleEndian>().unwrap() as usize;
-                self.pos += 4;
+                let len = Cursor::new(&self.payload[self.pos..]).read_uint::<LittleEndian>(3).unwrap() as usize;
+                self.pos += 3;
+                len
+            },
+            0xfe => {
+                let len = Cursor::new(&self.payload[self.pos..]).read_u64::<LittleEndian>().unwrap() as usize;
+                self.pos += 8;
                 len
             },
-            0xfe => panic!("no support yet for length >= 2^24"),
             _ => {
                 //debug!("read_len() returning {}", n);
                 n
@@ -1266,26 +2183,30 @@ impl MySQLPacketWriter {
     }
 
     fn write_lenenc_bytes(&mut self, b: &[u8]) {
-        let l = b.len();
+        self.write_lenenc_int(b.len());
+
+        // now write the actual data
+        self.payload.extend_from_slice(b);
+    }
 
-        // write the length of the data using variable-length encoding
-        if l < 0xfc {
-            // single byte to represent length
+    /// writes a length using variable-length encoding
+    fn write_lenenc_int(&mut self, l: usize) {
+        if l < 0xfb {
+            // single byte to represent length, 0xfb is NULL
             self.payload.push(l as u8);
         } else if l < 2_usize.pow(16) {
             // two bytes to represent length
             self.payload.push(0xfc);
             self.payload.write_u16::<LittleEndian>(l as u16).unwrap();
         } else if l < 2_usize.pow(24) {
-            // write four byte length
+            // three bytes to represent length
             self.payload.push(0xfd);
-            self.payload.write_u32::<LittleEndian>(l as u32).unwrap();
+            self.payload.write_uint::<LittleEndian>(l as u64, 3).unwrap();
         } else {
-            panic!("no support yet for length >= 2^24");
+            // eight bytes to represent length
+            self.payload.push(0xfe);
+            self.payload.write_u64::<LittleEndian>(l as u64).unwrap();
         }
-
-        // now write the actual data
-        self.payload.extend_from_slice(b);
     }
 
     fn write_byte(&mut self, b: u8) {
@@ -1310,6 +2231,29 @@ impl MySQLPacketWriter {
         self.payload[2] = header[2];
     }
 
+    /// Takes the packets written so far, leaving the rest of the payload to follow. Payloads of
+    /// 0xffffff bytes are continued in the next packet, so the last packet is always shorter
+    /// and may be empty.
+    fn take_packets(&mut self, last: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let l = self.payload.len() - 4;
+            if l < 0xffffff && !last {
+                break
+            }
+            let rest = if l > 0xffffff { self.payload.split_off(4 + 0xffffff) } else { vec![] };
+            let sequence_id = self.payload[3];
+            self.build();
+            out.append(&mut self.payload);
+            self.payload = vec![0x00, 0x00, 0x00, sequence_id.wrapping_add(1)];
+            self.payload.extend_from_slice(&rest);
+            if l < 0xffffff {
+                break
+            }
+        }
+        out
+    }
+
 }
 
 trait MySQLEncoder {
@@ -1367,3 +2311,202 @@ fn copy(r: &mut MySQLPacketParser, w: &mut MySQLPacketWriter, n: usize) {
     w.write_bytes(&r.payload[r.pos..r.pos+n]);
     r.skip(n);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encrypt::{Encrypt, KeyRing, AadBinding, Padding, TenantKey, Normalization};
+    use key_provider::write_keystore;
+    use secret::SecretKey;
+    use config::KeyProviderConfig;
+    use std::env;
+
+    fn no_tenant_keys() -> Rc<TenantKeys> {
+        Rc::new(TenantKeys::new(&KeyProviderConfig{props: HashMap::new()}).unwrap())
+    }
+
+    fn plan(data_type: NativeType, encryption: EncryptionType, key: Option<KeyRing>) -> EncryptionPlan {
+        EncryptionPlan {
+            data_type: data_type,
+            encryption: encryption,
+            key: key,
+            aad: None,
+            aad_row: None,
+            tenant_row: None,
+            blind_index: None,
+            like_index: None,
+            padding: Padding::NA,
+            normalization: None,
+            compare: false
+        }
+    }
+
+    // payload and sequence ids of consecutive packets
+    fn split(bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let (mut payload, mut ids, mut pos) = (Vec::new(), Vec::new(), 0);
+        while pos < bytes.len() {
+            let l = payload_len(&bytes[pos..]);
+            ids.push(bytes[pos + 3]);
+            payload.extend_from_slice(&bytes[pos + 4..pos + 4 + l]);
+            pos += 4 + l;
+        }
+        (payload, ids)
+    }
+
+    #[test]
+    fn stream_long_row() {
+        let keys = KeyRing::new(1, SecretKey::new([7u8; 32]));
+        let plans = vec![
+            plan(NativeType::U64, EncryptionType::NA, None),
+            plan(NativeType::LONGTEXT(1 << 32), EncryptionType::AesGcm, Some(keys.clone())),
+            plan(NativeType::Varchar(50), EncryptionType::AesGcm, Some(keys.clone()))
+        ];
+        let body: Vec<u8> = (0..0x1000010).map(|i| b'a' + (i % 26) as u8).collect();
+        let body_encrypted = body.clone().encrypt(&EncryptionType::AesGcm, &keys, &[]).unwrap();
+        let name_encrypted = String::from("short").encrypt(&EncryptionType::AesGcm, &keys, &[]).unwrap();
+
+        let mut w = MySQLPacketWriter::new(3);
+        w.write_lenenc_bytes(b"42");
+        w.write_lenenc_bytes(&body_encrypted);
+        w.write_lenenc_bytes(&name_encrypted);
+        let packets = w.take_packets(true);
+        let (_, ids) = split(&packets);
+        assert_eq!(vec![3, 4], ids);
+
+        let mut row = RowStream::text(&plans, 0, &no_tenant_keys(), 3);
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < packets.len() {
+            let l = payload_len(&packets[pos..]);
+            out.append(&mut row.feed(&Packet { bytes: packets[pos..pos + 4 + l].to_vec() }).unwrap());
+            pos += 4 + l;
+        }
+        let (payload, ids) = split(&out);
+        assert_eq!(vec![3, 4], ids);
+        assert_eq!(5, row.sequence_id());
+
+        let mut bytes = vec![0u8; 4];
+        bytes.extend_from_slice(&payload);
+        let mut r = MySQLPacketParser::new(&bytes);
+        assert_eq!(Some(b"42".to_vec()), r.read_lenenc_bytes());
+        assert_eq!(Some(body), r.read_lenenc_bytes());
+        assert_eq!(Some(b"short".to_vec()), r.read_lenenc_bytes());
+        assert_eq!(None, r.read_byte());
+    }
+
+    #[test]
+    fn stream_hidden_row_key() {
+        let keys = KeyRing::new(1, SecretKey::new([7u8; 32]));
+        let aad = AadBinding{column: "zero.bound.c".into(), row_key: Some("id".into()), row_key_type: NativeType::U64, row_keys: true, tenant: None};
+        let mut body = plan(NativeType::LONGTEXT(1 << 32), EncryptionType::AesGcm, Some(keys.clone()));
+        body.aad = Some(aad.clone());
+        body.aad_row = Some(0);
+        let plans = vec![plan(NativeType::U64, EncryptionType::NA, None), body];
+
+        let encrypted = String::from("private").encrypt(&EncryptionType::AesGcm, &aad.keys(&keys, Some("42")), &aad.to_bytes(Some("42"))).unwrap();
+        let mut w = MySQLPacketWriter::new(3);
+        w.write_lenenc_bytes(b"42");
+        w.write_lenenc_bytes(&encrypted);
+
+        // the primary key the planner added is dropped from the row
+        let mut row = RowStream::text(&plans, 1, &no_tenant_keys(), 3);
+        let (payload, ids) = split(&row.feed(&Packet { bytes: w.take_packets(true) }).unwrap());
+        assert_eq!(vec![3], ids);
+        assert_eq!(b"\x07private".to_vec(), payload);
+    }
+
+    #[test]
+    fn stream_destroyed_tenant_key() {
+        let path = env::temp_dir().join("zero-server-tenants.keystore").to_str().unwrap().to_string();
+        let mut tenants = HashMap::new();
+        tenants.insert(String::from("tenant-7"), SecretKey::new([7u8; 32]));
+        write_keystore(&path, "tenants", 10, &tenants).unwrap();
+        env::set_var("ZERO_SERVER_TENANT_PASSPHRASE", "tenants");
+        let mut props = HashMap::new();
+        props.insert(String::from("type"), String::from("keystore"));
+        props.insert(String::from("path"), path);
+        props.insert(String::from("passphrase-env"), String::from("ZERO_SERVER_TENANT_PASSPHRASE"));
+        let tenant_keys = Rc::new(TenantKeys::new(&KeyProviderConfig{props: props}).unwrap());
+
+        let aad = AadBinding{column: "zero.tickets.body".into(), row_key: None, row_key_type: NativeType::UNKNOWN, row_keys: false,
+                             tenant: Some(TenantKey{column: "tenant_id".into(), key_ref: "tenant-{}".into(), missing: MissingKey::Null})};
+        let mut body = plan(NativeType::LONGTEXT(1 << 32), EncryptionType::AesGcm, Some(KeyRing::new(0, SecretKey::new([0u8; 32]))));
+        body.aad = Some(aad.clone());
+        body.tenant_row = Some(0);
+        let plans = vec![plan(NativeType::U64, EncryptionType::NA, None), body];
+
+        let keys = aad.tenant_keys(&SecretKey::new([7u8; 32]));
+        let encrypted = String::from("private").encrypt(&EncryptionType::AesGcm, &keys, &aad.to_bytes(None)).unwrap();
+        let row = |tenant: &[u8]| {
+            let mut w = MySQLPacketWriter::new(3);
+            w.write_lenenc_bytes(tenant);
+            w.write_lenenc_bytes(&encrypted);
+            let mut row = RowStream::text(&plans, 0, &tenant_keys, 3);
+            let (payload, _) = split(&row.feed(&Packet { bytes: w.take_packets(true) }).unwrap());
+            payload
+        };
+        assert_eq!(b"\x017\x07private".to_vec(), row(b"7"));
+
+        // a tenant without a key reads as NULL
+        assert_eq!(b"\x018\xfb".to_vec(), row(b"8"));
+    }
+
+    #[test]
+    fn decrypt_display_fallback() {
+        let keys = KeyRing::new(1, SecretKey::new([7u8; 32]));
+        let n = Normalization{case_fold: true, nfc: true, trim: true, display: Some(String::from("c_last_display"))};
+        let mut display = plan(NativeType::Varchar(16), EncryptionType::AesGcm, Some(n.display_keys(&keys)));
+        let stored = plan(NativeType::Varchar(16), EncryptionType::AesSiv, Some(keys.clone()));
+        display.normalization = Some(NormalizationUse::Read(n.clone(), Box::new(stored)));
+        let decrypt = |v: &[u8]| decrypt_text(&display, &display.encryption, display.key.as_ref().unwrap(), v, &[]);
+
+        let original = String::from("Smith ").encrypt(&EncryptionType::AesGcm, &n.display_keys(&keys), &[]).unwrap();
+        assert_eq!(b"Smith ".to_vec(), decrypt(&original).unwrap());
+
+        // rows without a display value read their normalized value
+        let normalized = String::from("smith").encrypt(&EncryptionType::AesSiv, &keys, &[]).unwrap();
+        assert_eq!(b"smith".to_vec(), decrypt(&normalized).unwrap());
+
+        let other = String::from("smith").encrypt(&EncryptionType::AesSiv, &KeyRing::new(1, SecretKey::new([8u8; 32])), &[]).unwrap();
+        assert!(decrypt(&other).is_err());
+    }
+
+    #[test]
+    fn strip_long_column_count() {
+        let mut w = MySQLPacketWriter::new(1);
+        w.write_lenenc_int(0x101);
+        w.build();
+        let p = Packet { bytes: w.payload };
+        assert_eq!(0x101, column_count(&p));
+        // 251 columns need three bytes, as 0xfb is NULL
+        match strip_column_count(&p, 6) {
+            Action::Mutate(p) => assert_eq!(vec![0x03, 0x00, 0x00, 0x01, 0xfc, 0xfb, 0x00], p.bytes),
+            _ => panic!("TEST FAIL")
+        }
+    }
+
+    #[test]
+    fn take_packets() {
+        let mut w = MySQLPacketWriter::new(0);
+        w.write_bytes(&vec![1u8; 0xffffff]);
+        assert_eq!(4 + 0xffffff, w.take_packets(false).len());
+        assert_eq!(vec![0x00, 0x00, 0x00, 0x01], w.take_packets(true));
+
+        let mut w = MySQLPacketWriter::new(0);
+        w.write_lenenc_int(0xfb);
+        w.write_lenenc_int(0x10000);
+        assert_eq!(vec![0x07, 0x00, 0x00, 0x00, 0xfc, 0xfb, 0x00, 0xfd, 0x00, 0x00, 0x01], w.take_packets(true));
+    }
+
+    #[test]
+    fn split_long_request() {
+        let mut query = vec![0x03];
+        query.extend_from_slice(&vec![b'x'; 0xffffff + 2]);
+        let bytes = split_request(&query).bytes;
+        assert_eq!(4 + 0xffffff + 4 + 3, bytes.len());
+        assert_eq!(&[0xff, 0xff, 0xff, 0x00, 0x03], &bytes[0..5]);
+        assert_eq!(&[0x03, 0x00, 0x00, 0x01, b'x', b'x', b'x'], &bytes[4 + 0xffffff..]);
+
+        assert_eq!(vec![0x01, 0x00, 0x00, 0x00, 0x0e], split_request(&[0x0e]).bytes);
+    }
+}
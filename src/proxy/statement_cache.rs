@@ -46,4 +46,9 @@ impl StatementCache {
         data.insert(key, value);
         reference
     }
+
+    // plans hold the keys and encryption of the config they were made with, statements already running keep theirs
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
 }
\ No newline at end of file
@@ -12,14 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use query::{Writer, ExprWriter, ASTNode, MySQLColumnQualifier, LiteralToken};
+use query::{Writer, ExprWriter, ASTNode, MySQLColumnQualifier, LiteralToken, Operator, InsertMode};
 use query::MySQLDataType::*;
 use std::collections::HashMap;
 use std::fmt::Write;
 use config::*;
 use encrypt::*;
 use error::ZeroError;
-use super::physical_planner::{EncryptionPlan};
+use key_provider::TenantKeys;
+use super::physical_planner::{EncryptionPlan, BlindIndexUse, LikeIndexUse, NormalizationUse, Aggregate};
 use decimal::*;
 use std::fmt::Debug;
 use std::str::FromStr;
@@ -44,7 +45,8 @@ pub fn map_err_to_zero<E: Debug>(err: E) -> Box<ZeroError> {
 
 pub struct LiteralEncryptionWriter<'a> {
     pub literals: &'a Vec<LiteralToken>,
-    pub literal_plans: &'a HashMap<usize, EncryptionPlan>
+    pub literal_plans: &'a HashMap<usize, EncryptionPlan>,
+    pub tenant_keys: &'a TenantKeys
 }
 
 impl<'a> ExprWriter for LiteralEncryptionWriter<'a> {
@@ -65,147 +67,27 @@ impl<'a> ExprWriter for LiteralEncryptionWriter<'a> {
                     match plan.encryption {
                         EncryptionType::NA => Ok(false),
                         _ => {
-                            let key = plan.key.unwrap();
-                            let encrypted = match plan.data_type {
-                                NativeType::U64 => {
-                                    match lit {
-                                        &LiteralToken::LiteralLong(_, ref v) => {
-
-                                            let val = u64::from_str(v).map_err(map_err_to_zero)?;
-                                            val.encrypt(&plan.encryption, &key)?
-
+                            let key = plan.key.as_ref().unwrap();
+                            let value = normalized(literal_plaintext(lit, plan)?, plan);
+                            let encrypted = match plan.blind_index {
+                                // compared against the companion column, so only the index is sent
+                                Some(BlindIndexUse::Compare(ref bi)) => value.blind_index(key, bi.length),
+                                _ => {
+                                    let row = self.get_row_literal(plan, plan.aad_row, "is bound to its row and requires a non-null primary key")?;
+                                    let tenant = self.get_row_literal(plan, plan.tenant_row, "is encrypted under its tenant's key and requires a non-null tenant")?;
+                                    let keys = match (&plan.aad, tenant) {
+                                        (&Some(ref aad), Some(t)) => match self.tenant_keys.keys(aad, t)? {
+                                            Some(keys) => aad.keys(&keys, row),
+                                            None => return Err(ZeroError::EncryptionError {
+                                                message: format!("Column {} has no key for tenant {}", aad.column, t).into(),
+                                                code: "1064".into()
+                                            }.into())
                                         },
-                                        _ => return Err(ZeroError::EncryptionError {
-                                            message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
-                                            code: "1064".into()
-                                        }.into())
-                                    }
-                                },
-                                NativeType::I64 => {
-                                    return Err(ZeroError::EncryptionError {
-                                        message: format!("Signed numerics currently unsupported").into(),
-                                        code: "1064".into()
-                                    }.into())
-                                    // TODO reimplement
-                                    //                                match lit {
-                                    //                                    &LiteralToken::LiteralLong(ref i, ref val) => {
-                                    //                                        let v = match i64::from_str(val) {
-                                    //                                            Ok(v) => v,
-                                    //                                            Err(e) => return Err(ZeroError::EncryptionError {
-                                    //                                                message: format!("Failed to coerce {} to signed due to : {}", val, e).into(),
-                                    //                                                code: "1064".into()
-                                    //                                            }.into())
-                                    //                                        };
-                                    //
-                                    //                                        let encrypted = match sign {
-                                    //                                            Some(&Operator::SUB) => (-v).encrypt(&plan.encryption, &plan.key)?,
-                                    //                                            _ => v.encrypt(&plan.encryption, &plan.key)?
-                                    //                                        };
-                                    //                                         encrypted
-                                    //                                    },
-                                    //                                    _ => return Err(ZeroError::EncryptionError {
-                                    //                                        message: format!("Invalid value {:?} for column {}.{}", lit, plan.relation, plan.name).into(),
-                                    //                                        code: "1064".into()
-                                    //                                    }.into())
-                                    //                                }
-                                },
-                                NativeType::F64 => {
-                                    match lit {
-                                        &LiteralToken::LiteralDouble(_, ref v) => {
-                                            let val = f64::from_str(v).map_err(map_err_to_zero)?;
-                                            val.encrypt(&plan.encryption, &key)?
-                                        },
-                                        _ => return Err(ZeroError::EncryptionError {
-                                            message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
-                                            code: "1064".into()
-                                        }.into())
-                                    }
-                                },
-                                NativeType::D128 => {
-                                    match lit {
-                                        &LiteralToken::LiteralDouble(_, ref val) => {
-                                            let v = match d128::from_str(val) {
-                                                Ok(d) => d,
-                                                // Note: d128::from_str e is a ()
-                                                Err(_) => return Err(ZeroError::EncryptionError {
-                                                    message: format!("Failed to coerce {} to d128", val).into(),
-                                                    code: "1064".into()
-                                                }.into())
-                                            };
-
-                                            v.encrypt(&plan.encryption, &key)?
-                                        },
-                                        _ => return Err(ZeroError::EncryptionError {
-                                            message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
-                                            code: "1064".into()
-                                        }.into())
-                                    }
-                                },
-                                NativeType::BOOL => {
-                                    match lit {
-                                        &LiteralToken::LiteralBool(_, ref v) => {
-                                            let val = bool::from_str(v).map_err(map_err_to_zero)?;
-                                            val.encrypt(&plan.encryption, &key)?
-                                        },
-                                        _ => return Err(ZeroError::EncryptionError {
-                                            message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
-                                            code: "1064".into()
-                                        }.into())
-                                    }
-                                },
-                                NativeType::Varchar(..) | NativeType::Char(..) => {
-                                    match lit {
-                                        &LiteralToken::LiteralString(_, ref val) => {
-                                            val.clone().encrypt(&plan.encryption, &key)?
-                                        },
-                                        _ => return Err(ZeroError::EncryptionError {
-                                            message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
-                                            code: "1064".into()
-                                        }.into())
-                                    }
-                                },
-                                NativeType::DATE => {
-                                    match lit {
-                                        &LiteralToken::LiteralString(_, ref val) => {
-                                            let v = match UTC.datetime_from_str(&format!("{} 00:00:00",val), "%Y-%m-%d %H:%M:%S") {
-                                                Ok(v) => v,
-                                                Err(e) => return Err(ZeroError::EncryptionError {
-                                                    message: format!("Failed to coerce {} to date due to {}", val, e).into(),
-                                                    code: "1064".into()
-                                                }.into())
-                                            };
-
-                                            v.encrypt(&plan.encryption, &key)?
-                                        },
-                                        _ => return Err(ZeroError::EncryptionError {
-                                            message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
-                                            code: "1064".into()
-                                        }.into())
-                                    }
-                                },
-                                NativeType::DATETIME(..) => {
-                                    match lit {
-                                        &LiteralToken::LiteralString(_, ref val) => {
-                                            let v = match UTC.datetime_from_str(val, "%Y-%m-%d %H:%M:%S%.f") {
-                                                Ok(v) => v,
-                                                Err(e) => return Err(ZeroError::EncryptionError {
-                                                    message: format!("Failed to coerce {} to DATETIME due to {}", val, e).into(),
-                                                    code: "1064".into()
-                                                }.into())
-                                            };
-
-                                            v.clone().encrypt(&plan.encryption, &key)?
-                                        },
-                                        _ => return Err(ZeroError::EncryptionError {
-                                            message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
-                                            code: "1064".into()
-                                        }.into())
-                                    }
-                                },
-                                _ => return Err(ZeroError::EncryptionError {
-                                    message: format!("Unsupported encryption {:?} for data type {:?}", plan.encryption, plan.data_type).into(),
-                                    code: "1064".into()
-                                }.into())
+                                        (&Some(ref aad), None) => aad.keys(key, row),
+                                        (&None, _) => key.clone()
+                                    };
+                                    value.encrypt_padded(&plan.encryption, &keys, &self.get_aad(plan, row), &plan.padding)?
+                                }
                             };
 
                             write!(builder, "X'{}'", to_hex_string(&encrypted)).unwrap();
@@ -223,6 +105,262 @@ impl<'a> ExprWriter for LiteralEncryptionWriter<'a> {
     }
 }
 
+impl<'a> LiteralEncryptionWriter<'a> {
+    // the literal of a column of the value's row, the primary key or tenant
+    fn get_row_literal(&self, plan: &EncryptionPlan, row: Option<usize>, requirement: &str) -> Result<Option<&str>, Box<ZeroError>> {
+        match (&plan.aad, row) {
+            (&Some(ref aad), Some(i)) => match self.literals.get(i) {
+                Some(&LiteralToken::LiteralString(_, ref v)) | Some(&LiteralToken::LiteralLong(_, ref v)) |
+                Some(&LiteralToken::LiteralDouble(_, ref v)) | Some(&LiteralToken::LiteralBool(_, ref v)) => Ok(Some(v)),
+                _ => Err(ZeroError::EncryptionError {
+                    message: format!("Column {} {}", aad.column, requirement).into(),
+                    code: "1064".into()
+                }.into())
+            },
+            _ => Ok(None)
+        }
+    }
+
+    fn get_aad(&self, plan: &EncryptionPlan, row: Option<&str>) -> Vec<u8> {
+        match plan.aad {
+            Some(ref aad) => aad.to_bytes(row),
+            None => vec![]
+        }
+    }
+}
+
+// the typed value of a literal written to, or compared with, a column of the plan's type
+pub fn literal_plaintext(lit: &LiteralToken, plan: &EncryptionPlan) -> Result<Plaintext, Box<ZeroError>> {
+    Ok(match plan.data_type {
+        NativeType::U64 => {
+            match lit {
+                &LiteralToken::LiteralLong(_, ref v) => {
+                    Plaintext::U64(u64::from_str(v).map_err(map_err_to_zero)?)
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::I64 => {
+            return Err(ZeroError::EncryptionError {
+                message: format!("Signed numerics currently unsupported").into(),
+                code: "1064".into()
+            }.into())
+            // TODO reimplement
+            //                                match lit {
+            //                                    &LiteralToken::LiteralLong(ref i, ref val) => {
+            //                                        let v = match i64::from_str(val) {
+            //                                            Ok(v) => v,
+            //                                            Err(e) => return Err(ZeroError::EncryptionError {
+            //                                                message: format!("Failed to coerce {} to signed due to : {}", val, e).into(),
+            //                                                code: "1064".into()
+            //                                            }.into())
+            //                                        };
+            //
+            //                                        let encrypted = match sign {
+            //                                            Some(&Operator::SUB) => (-v).encrypt(&plan.encryption, &plan.key)?,
+            //                                            _ => v.encrypt(&plan.encryption, &plan.key)?
+            //                                        };
+            //                                         encrypted
+            //                                    },
+            //                                    _ => return Err(ZeroError::EncryptionError {
+            //                                        message: format!("Invalid value {:?} for column {}.{}", lit, plan.relation, plan.name).into(),
+            //                                        code: "1064".into()
+            //                                    }.into())
+            //                                }
+        },
+        NativeType::F64 => {
+            match lit {
+                &LiteralToken::LiteralDouble(_, ref v) => {
+                    Plaintext::F64(f64::from_str(v).map_err(map_err_to_zero)?)
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::D128 => {
+            match lit {
+                &LiteralToken::LiteralDouble(_, ref val) => {
+                    let v = match d128::from_str(val) {
+                        Ok(d) => d,
+                        // Note: d128::from_str e is a ()
+                        Err(_) => return Err(ZeroError::EncryptionError {
+                            message: format!("Failed to coerce {} to d128", val).into(),
+                            code: "1064".into()
+                        }.into())
+                    };
+
+                    Plaintext::D128(v)
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::BOOL => {
+            match lit {
+                &LiteralToken::LiteralBool(_, ref v) => {
+                    Plaintext::Bool(bool::from_str(v).map_err(map_err_to_zero)?)
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::Varchar(..) | NativeType::Char(..) | NativeType::LONGTEXT(..) => {
+            match lit {
+                &LiteralToken::LiteralString(_, ref val) => {
+                    Plaintext::Text(val.clone())
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::DATE => {
+            match lit {
+                &LiteralToken::LiteralString(_, ref val) => {
+                    let v = match UTC.datetime_from_str(&format!("{} 00:00:00",val), "%Y-%m-%d %H:%M:%S") {
+                        Ok(v) => v,
+                        Err(e) => return Err(ZeroError::EncryptionError {
+                            message: format!("Failed to coerce {} to date due to {}", val, e).into(),
+                            code: "1064".into()
+                        }.into())
+                    };
+
+                    Plaintext::DateTime(v)
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::DATETIME(..) | NativeType::TIMESTAMP(..) => {
+            match lit {
+                &LiteralToken::LiteralString(_, ref val) => {
+                    let v = match UTC.datetime_from_str(val, "%Y-%m-%d %H:%M:%S%.f") {
+                        Ok(v) => v,
+                        Err(e) => return Err(ZeroError::EncryptionError {
+                            message: format!("Failed to coerce {} to DATETIME due to {}", val, e).into(),
+                            code: "1064".into()
+                        }.into())
+                    };
+
+                    Plaintext::DateTime(v)
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::TIME(..) => {
+            match lit {
+                &LiteralToken::LiteralString(_, ref val) => match parse_time(val) {
+                    Some(v) => Plaintext::Time(v),
+                    None => return Err(ZeroError::EncryptionError {
+                        message: format!("Failed to coerce {} to TIME", val).into(),
+                        code: "1064".into()
+                    }.into())
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::YEAR(..) => {
+            match lit {
+                &LiteralToken::LiteralLong(_, ref val) | &LiteralToken::LiteralString(_, ref val) => match u64::from_str(val) {
+                    Ok(v) if v == 0 || (v >= 1901 && v <= 2155) => Plaintext::U64(v),
+                    _ => return Err(ZeroError::EncryptionError {
+                        message: format!("Failed to coerce {} to YEAR", val).into(),
+                        code: "1064".into()
+                    }.into())
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::ENUM(..) | NativeType::SET(..) => {
+            let member = match lit {
+                &LiteralToken::LiteralString(_, ref val) => plan.data_type.member_value(val),
+                &LiteralToken::LiteralLong(_, ref val) => u64::from_str(val).ok().and_then(|n| plan.data_type.member_number(n)),
+                _ => None
+            };
+            match member {
+                Some(m) => Plaintext::Text(m),
+                None => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        NativeType::FIXEDBINARY(..) | NativeType::VARBINARY(..) | NativeType::LONGBLOB(..) => {
+            match lit {
+                &LiteralToken::LiteralString(_, ref val) => {
+                    Plaintext::Bytes(val.clone().into_bytes())
+                },
+                _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Invalid value {:?} expected type {:?}", lit, plan.data_type).into(),
+                    code: "1064".into()
+                }.into())
+            }
+        },
+        _ => return Err(ZeroError::EncryptionError {
+            message: format!("Unsupported encryption {:?} for data type {:?}", plan.encryption, plan.data_type).into(),
+            code: "1064".into()
+        }.into())
+    })
+}
+
+/// Rewrites SUM and AVG of Paillier columns in the outermost projection to the column itself,
+/// so the backend returns the ciphertexts for the gateway to fold. Unaliased calls are aliased
+/// to their own text so the result column keeps its name.
+pub struct FoldingWriter<'a> {
+    pub ast: &'a ASTNode,
+    pub aggregates: &'a Vec<Aggregate>
+}
+
+impl<'a> ExprWriter for FoldingWriter<'a> {
+    fn write(&self, writer: &Writer, builder: &mut String, node: &ASTNode) -> Result<bool, Box<ZeroError>> {
+        let items = match self.ast {
+            &ASTNode::SQLSelect{expr_list: box ASTNode::SQLExprList(ref items), ..} if !self.aggregates.is_empty() => items,
+            _ => return Ok(false)
+        };
+        // only the projected calls themselves, matched by identity
+        for item in items.iter() {
+            match item {
+                &ASTNode::SQLFunctionCall{identifier: box ASTNode::SQLIdentifier{ref id, ..}, ref args} if item as *const ASTNode == node as *const ASTNode => {
+                    let mut column = String::new();
+                    writer._write(&mut column, &args[0])?;
+                    builder.push_str(&column);
+                    write!(builder, " AS `{}({})`", id, column.trim().replace("`", "``")).unwrap();
+                    return Ok(true)
+                },
+                &ASTNode::SQLAlias{expr: box ref expr, ..} if expr as *const ASTNode == node as *const ASTNode => {
+                    if let &ASTNode::SQLFunctionCall{ref args, ..} = expr {
+                        writer._write(builder, &args[0])?;
+                        return Ok(true)
+                    }
+                },
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
+}
+
 pub struct LiteralReplacingWriter<'a> {
     pub encrypted_literals: &'a HashMap<u32, Vec<u8>>
 }
@@ -260,6 +398,304 @@ impl<'a> LiteralReplacingWriter<'a> {
     }
 }
 
+// the value a normalized column stores or compares in place of the plaintext
+pub fn normalized(value: Plaintext, plan: &EncryptionPlan) -> Plaintext {
+    match (value, &plan.normalization) {
+        (Plaintext::Text(v), &Some(ref n)) => Plaintext::Text(n.normalization().apply(&v)),
+        (v, _) => v
+    }
+}
+
+/// Rewrites expressions on indexed columns. Equality on blind indexed columns and LIKE on
+/// LIKE indexed columns move to the companion columns, and inserted or updated values also
+/// write their indexes, or the original values of normalized columns, to them.
+pub struct IndexWriter<'a> {
+    pub literals: &'a Vec<LiteralToken>,
+    pub literal_plans: &'a HashMap<usize, EncryptionPlan>,
+    pub param_plans: &'a HashMap<usize, EncryptionPlan>
+}
+
+// a companion column maintained alongside a stored value
+enum Companion<'b> {
+    Blind(&'b BlindIndex),
+    Like(&'b LikeIndex),
+    Display(&'b Normalization, &'b String)
+}
+
+impl<'b> Companion<'b> {
+    fn column(&self) -> &'b String {
+        match *self {
+            Companion::Blind(bi) => &bi.column,
+            Companion::Like(li) => &li.column,
+            Companion::Display(_, column) => column
+        }
+    }
+}
+
+impl<'a> ExprWriter for IndexWriter<'a> {
+    fn write(&self, writer: &Writer, builder: &mut String, node: &ASTNode) -> Result<bool, Box<ZeroError>> {
+        match node {
+            &ASTNode::SQLBinary{box ref left, op: Operator::LIKE, box ref right} => {
+                match (left, self.plan(right).and_then(|p| p.like_index.as_ref())) {
+                    (&ASTNode::SQLIdentifier{..}, Some(&LikeIndexUse::Match(ref li))) => {
+                        write!(builder, " MATCH ({}) AGAINST (", qualified(left, &li.column)).unwrap();
+                        match right {
+                            &ASTNode::SQLLiteral(i) => {
+                                let plan = self.literal_plans.get(&i).unwrap();
+                                let tokens = li.pattern_tokens(plan.key.as_ref().unwrap(), &self.text(i, plan)?)?;
+                                write!(builder, "'{}'", tokens).unwrap();
+                            },
+                            // the pattern's tokens are bound when the statement is executed
+                            _ => writer._write(builder, right)?
+                        }
+                        builder.push_str(" IN BOOLEAN MODE)");
+                        Ok(true)
+                    },
+                    _ => Ok(false)
+                }
+            },
+            &ASTNode::SQLBinary{box ref left, ref op, box ref right} => {
+                let op_text = match *op {
+                    Operator::EQ => " =",
+                    Operator::NEQ => " !=",
+                    _ => return Ok(false)
+                };
+                match (left, right) {
+                    (&ASTNode::SQLIdentifier{..}, value) => match self.plan(value).and_then(|p| p.blind_index.as_ref()) {
+                        Some(&BlindIndexUse::Compare(ref bi)) => {
+                            if let Some(indexes) = self.indexes(value, bi)? {
+                                write!(builder, " {} {}IN ({})", qualified(left, &bi.column), not_in(op), indexes.join(", ")).unwrap();
+                                return Ok(true)
+                            }
+                            write!(builder, " {}{}", qualified(left, &bi.column), op_text).unwrap();
+                            writer._write(builder, value)?;
+                            Ok(true)
+                        },
+                        _ => {
+                            if let Some(comparands) = self.comparands(value)? {
+                                writer._write(builder, left)?;
+                                write!(builder, " {}IN ({})", not_in(op), comparands.join(", ")).unwrap();
+                                return Ok(true)
+                            }
+                            let companions = self.companions(value);
+                            if companions.is_empty() {
+                                return Ok(false)
+                            }
+                            writer._write(builder, left)?;
+                            builder.push_str(op_text);
+                            writer._write(builder, value)?;
+                            for c in companions.iter() {
+                                write!(builder, ", {} =", qualified(left, c.column())).unwrap();
+                                self.write_companion(builder, value, c)?;
+                            }
+                            Ok(true)
+                        }
+                    },
+                    (value, &ASTNode::SQLIdentifier{..}) => match self.plan(value).and_then(|p| p.blind_index.as_ref()) {
+                        Some(&BlindIndexUse::Compare(ref bi)) => {
+                            if let Some(indexes) = self.indexes(value, bi)? {
+                                write!(builder, " {} {}IN ({})", qualified(right, &bi.column), not_in(op), indexes.join(", ")).unwrap();
+                                return Ok(true)
+                            }
+                            writer._write(builder, value)?;
+                            write!(builder, "{} {}", op_text, qualified(right, &bi.column)).unwrap();
+                            Ok(true)
+                        },
+                        _ => match self.comparands(value)? {
+                            Some(comparands) => {
+                                writer._write(builder, right)?;
+                                write!(builder, " {}IN ({})", not_in(op), comparands.join(", ")).unwrap();
+                                Ok(true)
+                            },
+                            None => Ok(false)
+                        }
+                    },
+                    _ => Ok(false)
+                }
+            },
+            &ASTNode::SQLInsert{box ref table, ref insert_mode, box ref column_list, ref values_list} => {
+                // positions of the inserted values with companions, the planner gives every row the same plans
+                let indexed = match values_list.first() {
+                    Some(&ASTNode::SQLExprList(ref values)) => values.iter().enumerate()
+                        .flat_map(|(i, v)| self.companions(v).into_iter().map(move |c| (i, c)))
+                        .collect::<Vec<(usize, Companion)>>(),
+                    _ => vec![]
+                };
+                if indexed.is_empty() {
+                    return Ok(false)
+                }
+
+                builder.push_str("INSERT ");
+                if *insert_mode == InsertMode::IGNORE {
+                    builder.push_str("IGNORE ");
+                }
+                builder.push_str("INTO");
+                writer._write(builder, table)?;
+                builder.push_str(" (");
+                writer._write(builder, column_list)?;
+                for &(_, ref c) in indexed.iter() {
+                    write!(builder, ", {}", c.column()).unwrap();
+                }
+                builder.push_str(") VALUES ");
+
+                let mut sep = "";
+                for values in values_list.iter() {
+                    builder.push_str(sep);
+                    builder.push_str("(");
+                    writer._write(builder, values)?;
+                    for &(i, ref c) in indexed.iter() {
+                        builder.push_str(",");
+                        match values {
+                            &ASTNode::SQLExprList(ref v) if v.len() > i => self.write_companion(builder, &v[i], c)?,
+                            _ => return Err(ZeroError::EncryptionError {
+                                    message: format!("Missing value for indexed column {}", c.column()).into(),
+                                    code: "1064".into()
+                                }.into())
+                        }
+                    }
+                    builder.push_str(")");
+                    sep = ", ";
+                }
+                Ok(true)
+            },
+            _ => Ok(false)
+        }
+    }
+}
+
+impl<'a> IndexWriter<'a> {
+    fn plan(&self, value: &ASTNode) -> Option<&EncryptionPlan> {
+        match value {
+            &ASTNode::SQLLiteral(i) => self.literal_plans.get(&i),
+            &ASTNode::SQLBoundParam(i) => self.param_plans.get(&(i as usize)),
+            _ => None
+        }
+    }
+
+    // the companion columns a stored value also writes
+    fn companions(&self, value: &ASTNode) -> Vec<Companion> {
+        let mut companions = Vec::new();
+        if let Some(plan) = self.plan(value) {
+            if let Some(BlindIndexUse::Store(ref bi)) = plan.blind_index {
+                companions.push(Companion::Blind(bi));
+            }
+            if let Some(LikeIndexUse::Store(ref li)) = plan.like_index {
+                companions.push(Companion::Like(li));
+            }
+            if let Some(NormalizationUse::Store(ref n)) = plan.normalization {
+                if let Some(ref column) = n.display {
+                    companions.push(Companion::Display(n, column));
+                }
+            }
+        }
+        companions
+    }
+
+    fn write_companion(&self, builder: &mut String, value: &ASTNode, companion: &Companion) -> Result<(), Box<ZeroError>> {
+        let i = match value {
+            &ASTNode::SQLLiteral(i) => i,
+            _ => return Err(ZeroError::EncryptionError {
+                    message: format!("Indexed column {} requires a literal value", companion.column()).into(),
+                    code: "1064".into()
+                }.into())
+        };
+        match (self.literals.get(i), self.literal_plans.get(&i)) {
+            (Some(&LiteralToken::LiteralNull(_)), _) => builder.push_str(" NULL"),
+            (Some(lit), Some(plan)) => {
+                let key = plan.key.as_ref().unwrap();
+                match *companion {
+                    Companion::Blind(bi) => {
+                        let index = literal_plaintext(lit, plan)?.blind_index(key, bi.length);
+                        write!(builder, " X'{}'", to_hex_string(&index)).unwrap();
+                    },
+                    Companion::Like(li) => write!(builder, " '{}'", li.tokens(key, &self.text(i, plan)?)).unwrap(),
+                    // the original value, as normalized columns only store the normalized value
+                    Companion::Display(n, _) => {
+                        let display = literal_plaintext(lit, plan)?.encrypt(&EncryptionType::AesGcm, &n.display_keys(key), &[])?;
+                        write!(builder, " X'{}'", to_hex_string(&display)).unwrap();
+                    }
+                }
+            },
+            _ => return Err(ZeroError::EncryptionError {
+                    message: format!("No plan for literal {}", i).into(),
+                    code: "1064".into()
+                }.into())
+        }
+        Ok(())
+    }
+
+    // a literal's index under every version of its key, while values written under older versions
+    // remain, None when the key has a single version and a plain comparison is written
+    fn indexes(&self, value: &ASTNode, bi: &BlindIndex) -> Result<Option<Vec<String>>, Box<ZeroError>> {
+        let i = match value {
+            &ASTNode::SQLLiteral(i) => i,
+            _ => return Ok(None)
+        };
+        let (lit, plan) = match (&self.literals[i], self.literal_plans.get(&i)) {
+            (&LiteralToken::LiteralNull(_), _) | (_, None) => return Ok(None),
+            (lit, Some(plan)) => (lit, plan)
+        };
+        let keys = plan.key.as_ref().unwrap();
+        if keys.versions().len() < 2 {
+            return Ok(None)
+        }
+        let value = normalized(literal_plaintext(lit, plan)?, plan);
+        Ok(Some(value.blind_indexes(keys, bi.length).iter().map(|b| format!("X'{}'", to_hex_string(b))).collect()))
+    }
+
+    // a literal compared with a deterministic column, encrypted under every version of its key
+    // while values written under older versions remain, None when a plain comparison matches them all
+    fn comparands(&self, value: &ASTNode) -> Result<Option<Vec<String>>, Box<ZeroError>> {
+        let i = match value {
+            &ASTNode::SQLLiteral(i) => i,
+            _ => return Ok(None)
+        };
+        let (lit, plan) = match (&self.literals[i], self.literal_plans.get(&i)) {
+            (&LiteralToken::LiteralNull(_), _) | (_, None) => return Ok(None),
+            (lit, Some(plan)) => (lit, plan)
+        };
+        if !plan.compare || plan.blind_index.is_some() || plan.like_index.is_some() ||
+            !(plan.encryption.is_deterministic() || plan.encryption == EncryptionType::Ope) {
+            return Ok(None)
+        }
+        let keys = plan.key.as_ref().unwrap();
+        if keys.versions().len() < 2 && keys.header() {
+            return Ok(None)
+        }
+        let aad = match plan.aad {
+            Some(ref aad) => aad.to_bytes(None),
+            None => vec![]
+        };
+        let comparands = normalized(literal_plaintext(lit, plan)?, plan).comparands(&plan.encryption, keys, &aad)?;
+        Ok(Some(comparands.iter().map(|c| format!("X'{}'", to_hex_string(c))).collect()))
+    }
+
+    fn text(&self, i: usize, plan: &EncryptionPlan) -> Result<String, Box<ZeroError>> {
+        match literal_plaintext(&self.literals[i], plan)? {
+            Plaintext::Text(s) => Ok(s),
+            v => Err(ZeroError::EncryptionError {
+                message: format!("Expected a string for LIKE indexed column, found {:?}", v).into(),
+                code: "1064".into()
+            }.into())
+        }
+    }
+}
+
+fn not_in(op: &Operator) -> &'static str {
+    match *op {
+        Operator::NEQ => "NOT ",
+        _ => ""
+    }
+}
+
+// a companion column, qualified like the identifier it accompanies
+fn qualified(identifier: &ASTNode, column: &String) -> String {
+    match identifier {
+        &ASTNode::SQLIdentifier{ref parts, ..} if parts.len() > 1 => format!("{}.{}", parts[..parts.len() - 1].join("."), column),
+        _ => column.clone()
+    }
+}
+
 pub struct CreateTranslatingWriter<'a> {
     pub config: &'a Config,
     pub schema: &'a String
@@ -309,7 +745,7 @@ impl<'a> ExprWriter for CreateTranslatingWriter<'a> {
                                     let encryption_type = &config.encryption;
                                     match encryption_type {
                                         &EncryptionType::NA => writer._write(builder, data_type)?,
-                                        _ => writer._write(builder, &self.translate_type(data_type, &config.encryption)?)?
+                                        _ => writer._write(builder, &self.translate_type(data_type, &config.encryption, &config.native_type, &config.padding)?)?
                                     }
 
 
@@ -331,6 +767,18 @@ impl<'a> ExprWriter for CreateTranslatingWriter<'a> {
                                         _=> {}
                                     }
 
+                                    // companion columns follow the column they index
+                                    if let Some(ref bi) = config.blind_index {
+                                        write!(builder, ", {} BINARY({})", bi.column, bi.length).unwrap();
+                                    }
+                                    if let Some(ref li) = config.like_index {
+                                        write!(builder, ", {} TEXT", li.column).unwrap();
+                                    }
+                                    if let Some(ref display) = config.normalization.as_ref().and_then(|n| n.display.as_ref()) {
+                                        write!(builder, ", {}", display).unwrap();
+                                        writer._write(builder, &self.translate_type(data_type, &EncryptionType::AesGcm, &config.native_type, &Padding::NA)?)?;
+                                    }
+
                                 },
                                 _ => return Err(ZeroError::ParseError{
                                         message: format!("Expected column definition, received {:?}", c).into(),
@@ -351,6 +799,21 @@ impl<'a> ExprWriter for CreateTranslatingWriter<'a> {
                     writer._write(builder, k)?;
                 }
 
+                let indexed = column_list.iter().filter_map(|c| match c {
+                    &ASTNode::MySQLColumnDef{column: box ASTNode::SQLIdentifier{ref id, ..}, ..} =>
+                        self.config.get_column_config(&self.schema, &table_name, id),
+                    _ => None
+                }).collect::<Vec<&ColumnConfig>>();
+                for c in indexed {
+                    if let Some(ref bi) = c.blind_index {
+                        write!(builder, "{}INDEX {} ({})", sep, bi.column, bi.column).unwrap();
+                    }
+                    // LIKE tokens are looked up with MATCH ... AGAINST
+                    if let Some(ref li) = c.like_index {
+                        write!(builder, "{}FULLTEXT INDEX {} ({})", sep, li.column, li.column).unwrap();
+                    }
+                }
+
                 builder.push_str(")");
 
                 for o in table_options.iter() {
@@ -366,30 +829,91 @@ impl<'a> ExprWriter for CreateTranslatingWriter<'a> {
 
 // TODO needs to do some real length/display math for different encryption types
 impl<'a> CreateTranslatingWriter<'a> {
-    fn translate_type(&self, data_type: &ASTNode, encryption: &EncryptionType) -> Result<ASTNode, Box<ZeroError>> {
+    fn translate_type(&self, data_type: &ASTNode, encryption: &EncryptionType, native_type: &NativeType, padding: &Padding) -> Result<ASTNode, Box<ZeroError>> {
         match (data_type, encryption) {
-            (&ASTNode::MySQLDataType(ref dt), &EncryptionType::Aes(_)) | (&ASTNode::MySQLDataType(ref dt), &EncryptionType::AesGcm) => match dt {
-                &Bit{..} | &TinyInt{..} |
-                &SmallInt{..} | &MediumInt{..} |
-                &Int{..} | &BigInt{..}  => {
-                    // TODO currently all are stored as 8 bytes
-                    Ok(ASTNode::MySQLDataType(Binary{length: Some(8 + 28)}))
-                },
-                &Bool => Ok(ASTNode::MySQLDataType(Binary{length: Some(1 + 28)})),
-                &Decimal{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(16 + 28)})),
-                &Float{..} | &Double{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(8 + 28)})),
-                &Char{ref length} | &NChar{ref length} => {
-                    let l = length.unwrap_or(1) + 28;
-                    Ok(ASTNode::MySQLDataType(VarBinary{length: Some(l)}))
-                },
-                &Varchar{ref length} | &NVarchar{ref length} => {
-                    Ok(ASTNode::MySQLDataType(VarBinary{length: Some(self.get_encrypted_string_length(length))}))
-                },
-                &Date | &DateTime{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(12 + 28)})),
-                _ => Err(ZeroError::EncryptionError{
-                        message: format!("Unsupported data type for AES translation {:?}", dt).into(),
-                        code: "1064".into()
-                    }.into())
+            (&ASTNode::MySQLDataType(ref dt), &EncryptionType::Aes(_)) |
+            (&ASTNode::MySQLDataType(ref dt), &EncryptionType::AesGcm) |
+            (&ASTNode::MySQLDataType(ref dt), &EncryptionType::AesSiv) |
+            (&ASTNode::MySQLDataType(ref dt), &EncryptionType::Ope) => {
+                let overhead = encryption.overhead();
+                match (dt, encryption) {
+                    (&Bool, &EncryptionType::Ope) | (&Char{..}, &EncryptionType::Ope) | (&NChar{..}, &EncryptionType::Ope) |
+                    (&Varchar{..}, &EncryptionType::Ope) | (&NVarchar{..}, &EncryptionType::Ope) |
+                    (&Binary{..}, &EncryptionType::Ope) | (&CharByte{..}, &EncryptionType::Ope) | (&VarBinary{..}, &EncryptionType::Ope) |
+                    (&TinyBlob, &EncryptionType::Ope) | (&TinyText, &EncryptionType::Ope) | (&Blob{..}, &EncryptionType::Ope) |
+                    (&Text{..}, &EncryptionType::Ope) | (&MediumBlob, &EncryptionType::Ope) | (&MediumText, &EncryptionType::Ope) |
+                    (&LongBlob, &EncryptionType::Ope) | (&LongText, &EncryptionType::Ope) |
+                    (&Enum{..}, &EncryptionType::Ope) | (&Set{..}, &EncryptionType::Ope) => return Err(ZeroError::EncryptionError{
+                            message: format!("Unsupported data type for OPE translation {:?}", dt).into(),
+                            code: "1064".into()
+                        }.into()),
+                    _ => {}
+                }
+                match dt {
+                    &Bit{..} | &TinyInt{..} |
+                    &SmallInt{..} | &MediumInt{..} |
+                    &Int{..} | &BigInt{..}  => {
+                        // TODO currently all are stored as 8 bytes
+                        Ok(ASTNode::MySQLDataType(Binary{length: Some(8 + overhead)}))
+                    },
+                    &Bool => Ok(ASTNode::MySQLDataType(Binary{length: Some(1 + overhead)})),
+                    &Decimal{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(16 + overhead)})),
+                    &Float{..} | &Double{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(8 + overhead)})),
+                    &Char{ref length} | &NChar{ref length} => {
+                        let l = padding.padded_len(length.unwrap_or(1)) + overhead;
+                        Ok(ASTNode::MySQLDataType(VarBinary{length: Some(l)}))
+                    },
+                    &Varchar{ref length} | &NVarchar{ref length} => {
+                        Ok(ASTNode::MySQLDataType(VarBinary{length: Some(self.get_encrypted_string_length(length, overhead, padding))}))
+                    },
+                    &Date | &DateTime{..} | &Timestamp{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(12 + overhead)})),
+                    &Time{..} | &Year{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(8 + overhead)})),
+                    &Binary{ref length} | &CharByte{ref length} => {
+                        let l = length.unwrap_or(1) + overhead;
+                        Ok(ASTNode::MySQLDataType(VarBinary{length: Some(l)}))
+                    },
+                    &VarBinary{ref length} => {
+                        Ok(ASTNode::MySQLDataType(VarBinary{length: Some(self.get_encrypted_string_length(length, overhead, &Padding::NA))}))
+                    },
+                    // the next larger blob, so a value of the largest size still fits once encrypted
+                    &TinyBlob | &TinyText => Ok(ASTNode::MySQLDataType(Blob{length: None})),
+                    &Blob{..} | &Text{..} => Ok(ASTNode::MySQLDataType(MediumBlob)),
+                    &MediumBlob | &MediumText | &LongBlob | &LongText => Ok(ASTNode::MySQLDataType(LongBlob)),
+                    // room for the longest member, or for every member of a set
+                    &Enum{..} | &Set{..} => {
+                        let length = match native_type {
+                            &NativeType::ENUM(ref members) => members.iter().map(|m| m.len() as u32).max().unwrap_or(0),
+                            &NativeType::SET(ref members) => members.iter().fold(0, |l, m| l + m.len() as u32 + 1),
+                            _ => 0
+                        };
+                        Ok(ASTNode::MySQLDataType(VarBinary{length: Some(length + overhead)}))
+                    },
+                }
+            },
+            // ciphertexts are the same size whatever the value
+            (&ASTNode::MySQLDataType(ref dt), &EncryptionType::Paillier) => {
+                match dt {
+                    &Bit{..} | &TinyInt{..} |
+                    &SmallInt{..} | &MediumInt{..} |
+                    &Int{..} | &BigInt{..} | &Decimal{..} => Ok(ASTNode::MySQLDataType(Binary{length: Some(encryption.overhead())})),
+                    _ => Err(ZeroError::EncryptionError{
+                            message: format!("Unsupported data type for Paillier translation {:?}", dt).into(),
+                            code: "1064".into()
+                        }.into())
+                }
+            },
+            // ciphertexts keep the length and characters of the value
+            (&ASTNode::MySQLDataType(ref dt), &EncryptionType::Fpe(_)) => {
+                match dt {
+                    &Char{ref length} => Ok(ASTNode::MySQLDataType(Char{length: *length})),
+                    &NChar{ref length} => Ok(ASTNode::MySQLDataType(NChar{length: *length})),
+                    &Varchar{ref length} => Ok(ASTNode::MySQLDataType(Varchar{length: *length})),
+                    &NVarchar{ref length} => Ok(ASTNode::MySQLDataType(NVarchar{length: *length})),
+                    _ => Err(ZeroError::EncryptionError{
+                            message: format!("Unsupported data type for FPE translation {:?}", dt).into(),
+                            code: "1064".into()
+                        }.into())
+                }
             },
             _ => Err(ZeroError::EncryptionError{
                     message: format!("Expected data type and encryption, received data_type: {:?}, encryption: {:?}", data_type, encryption).into(),
@@ -398,12 +922,11 @@ impl<'a> CreateTranslatingWriter<'a> {
                     }
     }
 
-    // TODO delegate to crypt module
-    fn get_encrypted_string_length(&self, len: &Option<u32>) -> u32 {
+    fn get_encrypted_string_length(&self, len: &Option<u32>, overhead: u32, padding: &Padding) -> u32 {
         if len.is_some() {
-            len.unwrap() + 28
+            padding.padded_len(len.unwrap()) + overhead
         } else {
-            1024 + 28
+            padding.padded_len(1024) + overhead
         }
     }
 }
@@ -411,10 +934,17 @@ impl<'a> CreateTranslatingWriter<'a> {
 #[cfg(test)]
 mod tests {
 
-    use super::{CreateTranslatingWriter};
-    use query::{Writer, SQLWriter, Tokenizer, Parser};
+    use super::{CreateTranslatingWriter, FoldingWriter, IndexWriter, LiteralEncryptionWriter, to_hex_string, literal_plaintext};
+    use query::{Writer, SQLWriter, Tokenizer, Parser, LiteralToken};
     use query::dialects::mysqlsql::*;
     use query::dialects::ansisql::*;
+    use proxy::physical_planner::{EncryptionPlan, BlindIndexUse, LikeIndexUse, NormalizationUse, Aggregate};
+    use encrypt::{BlindIndex, LikeIndex, LikeMode, EncryptionType, KeyRing, NativeType, Padding, Normalization, Plaintext, Encrypt};
+    use encrypt::{AadBinding, TenantKey, MissingKey, Decrypt, hex_decode};
+    use key_provider::{TenantKeys, write_keystore};
+    use secret::SecretKey;
+    use std::collections::HashMap;
+    use std::env;
     use config;
 
     #[test]
@@ -422,7 +952,7 @@ mod tests {
         let ansi = AnsiSQLDialect::new();
         let dialect = MySQLDialect::new(&ansi);
 
-        let config = config::parse_config("zero-config.xml");
+        let config = config::parse_config("zero-config.xml").unwrap();
         let schema = String::from("zero");
 
         let sql = String::from("CREATE TABLE user (
@@ -448,11 +978,11 @@ mod tests {
 
         let expected = "CREATE TABLE user (
             id INTEGER PRIMARY KEY,
-            first_name VARBINARY(78),
-            last_name VARBINARY(78),
-            ssn VARBINARY(78),
-            age BINARY(36),
-            sex VARBINARY(78)
+            first_name VARBINARY(83),
+            last_name VARBINARY(83),
+            ssn VARBINARY(83),
+            age BINARY(41),
+            sex VARBINARY(83)
         )";
 
         let rewritten = writer.write(&parsed).unwrap();
@@ -463,6 +993,526 @@ mod tests {
 
     }
 
+    #[test]
+    fn blind_index_companions() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let schema = String::from("zero");
+
+        let sql = String::from("CREATE TABLE indexed (id INTEGER PRIMARY KEY, ssn VARCHAR(50), age INTEGER)");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+
+        let translator = CreateTranslatingWriter {
+            config: &config,
+            schema: &schema
+        };
+        let mysql = MySQLWriter{};
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+
+        let expected = "CREATE TABLE indexed (id INTEGER PRIMARY KEY, ssn VARBINARY(83), ssn_bidx BINARY(16),
+            age BINARY(41), age_bidx BINARY(8), INDEX ssn_bidx (ssn_bidx), INDEX age_bidx (age_bidx))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+
+        let sql = String::from("CREATE TABLE searchable (id INTEGER PRIMARY KEY, last_name VARCHAR(50), email VARCHAR(50))");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+
+        let expected = "CREATE TABLE searchable (id INTEGER PRIMARY KEY, last_name VARBINARY(83), last_name_like TEXT,
+            email VARBINARY(83), email_bidx BINARY(16), email_like TEXT,
+            FULLTEXT INDEX last_name_like (last_name_like), INDEX email_bidx (email_bidx), FULLTEXT INDEX email_like (email_like))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+    }
+
+    #[test]
+    fn padded_create() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let schema = String::from("zero");
+
+        let sql = String::from("CREATE TABLE padded (first_name VARCHAR(20), bio VARCHAR(100), code CHAR(5))");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let translator = CreateTranslatingWriter {
+            config: &config,
+            schema: &schema
+        };
+        let mysql = MySQLWriter{};
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+        // room for the padded value, which is at least a byte longer than the longest value
+        let expected = "CREATE TABLE padded (first_name VARBINARY(54), bio VARBINARY(161), code VARBINARY(49))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+    }
+
+    #[test]
+    fn paillier_folding() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let schema = String::from("zero");
+
+        let sql = String::from("CREATE TABLE ledger (id INTEGER, amount DECIMAL(10,2), quantity BIGINT)");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let translator = CreateTranslatingWriter {
+            config: &config,
+            schema: &schema
+        };
+        let mysql = MySQLWriter{};
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+        let expected = "CREATE TABLE ledger (id INTEGER, amount BINARY(517), quantity BINARY(517))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+
+        let aggregates = vec![Aggregate::Sum, Aggregate::Avg];
+        let sql = String::from("SELECT SUM(amount), AVG(quantity) AS q FROM ledger WHERE id > 10");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let folder = FoldingWriter {
+            ast: &parsed,
+            aggregates: &aggregates
+        };
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&folder, &mysql, &ansi_writer]);
+        let expected = "SELECT amount AS `SUM(amount)`, quantity AS q FROM ledger WHERE id > 10";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+    }
+
+    #[test]
+    fn fpe_create() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let schema = String::from("zero");
+
+        let sql = String::from("CREATE TABLE formatted (id INTEGER, ssn VARCHAR(11), card CHAR(19))");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let translator = CreateTranslatingWriter {
+            config: &config,
+            schema: &schema
+        };
+        let mysql = MySQLWriter{};
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+        let expected = "CREATE TABLE formatted (id INTEGER, ssn VARCHAR(11), card CHAR(19))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+
+        let sql = String::from("CREATE TABLE formatted (id INTEGER, ssn INTEGER, card CHAR(19))");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+        assert!(writer.write(&parsed).is_err());
+    }
+
+    #[test]
+    fn binary_and_temporal_types() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let schema = String::from("zero");
+
+        let sql = String::from("CREATE TABLE documents (id INTEGER, notes TEXT, body LONGTEXT, attachment MEDIUMBLOB, digest BINARY(32),
+                                token VARBINARY(64), signed TIMESTAMP(3), duration TIME, filed YEAR)");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let translator = CreateTranslatingWriter {
+            config: &config,
+            schema: &schema
+        };
+        let mysql = MySQLWriter{};
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+        let expected = "CREATE TABLE documents (id INTEGER, notes MEDIUMBLOB, body LONGBLOB, attachment LONGBLOB, digest VARBINARY(53),
+                        token VARBINARY(97), signed BINARY(49), duration BINARY(29), filed BINARY(41))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+
+        let plan = |data_type: NativeType| EncryptionPlan {
+            data_type: data_type,
+            encryption: EncryptionType::AesGcm,
+            key: Some(KeyRing::new(0, SecretKey::new([3u8; 32]))),
+            aad: None,
+            aad_row: None,
+            tenant_row: None,
+            blind_index: None,
+            like_index: None,
+            padding: Padding::NA,
+            normalization: None,
+            compare: false
+        };
+        let string = |v: &str| LiteralToken::LiteralString(0, String::from(v));
+        assert_eq!(Plaintext::Bytes(vec![b'a', 0, b'b']), literal_plaintext(&string("a\0b"), &plan(NativeType::LONGBLOB(1 << 24))).unwrap());
+        assert_eq!(Plaintext::Time(-5400000000), literal_plaintext(&string("-01:30:00"), &plan(NativeType::TIME(0))).unwrap());
+        assert_eq!(Plaintext::U64(2016), literal_plaintext(&LiteralToken::LiteralLong(0, String::from("2016")), &plan(NativeType::YEAR(4))).unwrap());
+        assert!(literal_plaintext(&string("1900"), &plan(NativeType::YEAR(4))).is_err());
+        assert!(literal_plaintext(&string("25:61"), &plan(NativeType::TIME(0))).is_err());
+        assert_eq!(Plaintext::Text(String::from("notes")), literal_plaintext(&string("notes"), &plan(NativeType::LONGTEXT(1 << 32))).unwrap());
+    }
+
+    #[test]
+    fn enum_and_set_types() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let schema = String::from("zero");
+
+        let sql = String::from("CREATE TABLE profiles (id INTEGER, sex ENUM('M','F'), sports SET('chess','golf','rowing'))");
+        let tokens = sql.tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let translator = CreateTranslatingWriter {
+            config: &config,
+            schema: &schema
+        };
+        let mysql = MySQLWriter{};
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+        let expected = "CREATE TABLE profiles (id INTEGER, sex VARBINARY(22), sports VARBINARY(51))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+
+        let plan = |data_type: NativeType| EncryptionPlan {
+            data_type: data_type,
+            encryption: EncryptionType::AesSiv,
+            key: Some(KeyRing::new(0, SecretKey::new([3u8; 32]))),
+            aad: None,
+            aad_row: None,
+            tenant_row: None,
+            blind_index: None,
+            like_index: None,
+            padding: Padding::NA,
+            normalization: None,
+            compare: false
+        };
+        let sex = plan(NativeType::ENUM(vec![String::from("M"), String::from("F")]));
+        let sports = plan(NativeType::SET(vec![String::from("chess"), String::from("golf"), String::from("rowing")]));
+        let string = |v: &str| LiteralToken::LiteralString(0, String::from(v));
+        assert_eq!(Plaintext::Text(String::from("F")), literal_plaintext(&string("f"), &sex).unwrap());
+        assert_eq!(Plaintext::Text(String::from("M")), literal_plaintext(&LiteralToken::LiteralLong(0, String::from("1")), &sex).unwrap());
+        assert!(literal_plaintext(&string("X"), &sex).is_err());
+        assert_eq!(Plaintext::Text(String::from("chess,rowing")), literal_plaintext(&string("rowing,chess"), &sports).unwrap());
+        assert!(literal_plaintext(&string("chess,darts"), &sports).is_err());
+    }
+
+    #[test]
+    fn index_writer() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let bi = BlindIndex{column: String::from("ssn_bidx"), length: 16};
+        let keys = KeyRing::new(0, SecretKey::new([3u8; 32]));
+        let index = to_hex_string(&Plaintext::Text(String::from("123-45-6789")).blind_index(&keys, 16));
+        let li = LikeIndex{column: String::from("ssn_like"), mode: LikeMode::NGram, length: 3};
+        let like_tokens = li.tokens(&keys, "123-45-6789");
+        let pattern = li.pattern_tokens(&keys, "123%").unwrap();
+        let plan = |b: Option<BlindIndexUse>, l: Option<LikeIndexUse>| EncryptionPlan {
+            data_type: NativeType::Varchar(50),
+            encryption: EncryptionType::AesGcm,
+            key: Some(keys.clone()),
+            aad: None,
+            aad_row: None,
+            tenant_row: None,
+            blind_index: b,
+            like_index: l,
+            padding: Padding::NA,
+            normalization: None,
+            compare: false
+        };
+
+        let cases = vec![
+            ("SELECT id FROM indexed WHERE ssn = '123-45-6789'", Some(BlindIndexUse::Compare(bi.clone())), None,
+             format!("SELECT id FROM indexed WHERE ssn_bidx = X'{}'", index)),
+            ("SELECT i.id FROM indexed AS i WHERE '123-45-6789' <> i.ssn", Some(BlindIndexUse::Compare(bi.clone())), None,
+             format!("SELECT i.id FROM indexed AS i WHERE X'{}' != i.ssn_bidx", index)),
+            ("SELECT i.id FROM indexed AS i WHERE i.ssn LIKE '123%'", None, Some(LikeIndexUse::Match(li.clone())),
+             format!("SELECT i.id FROM indexed AS i WHERE MATCH (i.ssn_like) AGAINST ('{}' IN BOOLEAN MODE)", pattern)),
+            ("SELECT id FROM indexed WHERE ssn LIKE ?", None, Some(LikeIndexUse::Match(li.clone())),
+             String::from("SELECT id FROM indexed WHERE MATCH (ssn_like) AGAINST (? IN BOOLEAN MODE)")),
+            // stored values are randomized, so only the indexes are checked
+            ("INSERT INTO indexed (ssn, id) VALUES ('123-45-6789', 1)", Some(BlindIndexUse::Store(bi.clone())), Some(LikeIndexUse::Store(li.clone())),
+             String::from("(ssn, id, ssn_bidx, ssn_like) VALUES")),
+            ("INSERT INTO indexed (ssn, id) VALUES ('123-45-6789', 1)", Some(BlindIndexUse::Store(bi.clone())), Some(LikeIndexUse::Store(li.clone())),
+             format!(", 1, X'{}', '{}')", index, like_tokens)),
+            ("UPDATE indexed SET ssn = '123-45-6789' WHERE id = 1", Some(BlindIndexUse::Store(bi.clone())), None,
+             format!(", ssn_bidx = X'{}' WHERE id = 1", index)),
+            ("UPDATE indexed SET ssn = '123-45-6789' WHERE id = 1", None, Some(LikeIndexUse::Store(li.clone())),
+             format!(", ssn_like = '{}' WHERE id = 1", like_tokens)),
+        ];
+
+        let tenant_keys = TenantKeys::new(&config::KeyProviderConfig{props: HashMap::new()}).unwrap();
+        for (sql, b, l, expected) in cases {
+            let tokens = String::from(sql).tokenize(&dialect).unwrap();
+            let parsed = tokens.parse().unwrap();
+
+            let mut literal_plans = HashMap::new();
+            literal_plans.insert(0, plan(b.clone(), l.clone()));
+            let mut params = HashMap::new();
+            params.insert(0, plan(b, l));
+            let index_writer = IndexWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                param_plans: &params
+            };
+            let lit_writer = LiteralEncryptionWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                tenant_keys: &tenant_keys
+            };
+            let mysql = MySQLWriter{};
+            let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+            let writer = SQLWriter::new(vec![&index_writer, &lit_writer, &mysql, &ansi_writer]);
+
+            let rewritten = format_sql(&writer.write(&parsed).unwrap());
+            assert!(rewritten.contains(&format_sql(&expected)), "{} does not contain {}", rewritten, expected);
+        }
+
+        // with several key versions, literals are compared with the index under each
+        let mut rotated = keys.clone();
+        rotated.add(1, SecretKey::new([4u8; 32]));
+        let newest = to_hex_string(&Plaintext::Text(String::from("123-45-6789")).blind_index(&rotated, 16));
+        let cases = vec![
+            ("SELECT id FROM indexed WHERE ssn = '123-45-6789'",
+             format!("SELECT id FROM indexed WHERE ssn_bidx IN (X'{}', X'{}')", newest, index)),
+            ("SELECT i.id FROM indexed AS i WHERE '123-45-6789' <> i.ssn",
+             format!("SELECT i.id FROM indexed AS i WHERE i.ssn_bidx NOT IN (X'{}', X'{}')", newest, index)),
+        ];
+        for (sql, expected) in cases {
+            let tokens = String::from(sql).tokenize(&dialect).unwrap();
+            let parsed = tokens.parse().unwrap();
+
+            let mut literal_plans = HashMap::new();
+            literal_plans.insert(0, EncryptionPlan{key: Some(rotated.clone()), ..plan(Some(BlindIndexUse::Compare(bi.clone())), None)});
+            let params = HashMap::new();
+            let index_writer = IndexWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                param_plans: &params
+            };
+            let lit_writer = LiteralEncryptionWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                tenant_keys: &tenant_keys
+            };
+            let mysql = MySQLWriter{};
+            let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+            let writer = SQLWriter::new(vec![&index_writer, &lit_writer, &mysql, &ansi_writer]);
+
+            assert_eq!(format_sql(&expected), format_sql(&writer.write(&parsed).unwrap()));
+        }
+    }
+
+    #[test]
+    fn rotated_equality() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let mut keys = KeyRing::new(1, SecretKey::new([3u8; 32]));
+        keys.add(2, SecretKey::new([4u8; 32]));
+        let mut legacy = KeyRing::new(1, SecretKey::new([3u8; 32]));
+        legacy.set_header(false);
+        let hex = |k: &KeyRing| to_hex_string(&Plaintext::Text(String::from("Smith")).encrypt(&EncryptionType::AesSiv, k, &[]).unwrap());
+        let (v1, v2, bare) = (hex(&keys.at(1).unwrap()), hex(&keys.at(2).unwrap()), hex(&legacy));
+        let plan = |keys: &KeyRing, compare: bool| EncryptionPlan {
+            data_type: NativeType::Varchar(50),
+            encryption: EncryptionType::AesSiv,
+            key: Some(keys.clone()),
+            aad: None,
+            aad_row: None,
+            tenant_row: None,
+            blind_index: None,
+            like_index: None,
+            padding: Padding::NA,
+            normalization: None,
+            compare: compare
+        };
+
+        let cases = vec![
+            ("SELECT id FROM users WHERE last = 'Smith'", plan(&keys, true),
+             format!("SELECT id FROM users WHERE last IN (X'{}', X'{}')", v2, v1)),
+            ("SELECT u.id FROM users AS u WHERE 'Smith' <> u.last", plan(&keys, true),
+             format!("SELECT u.id FROM users AS u WHERE u.last NOT IN (X'{}', X'{}')", v2, v1)),
+            // columns still writing values without a header also match the values rotated to one
+            ("SELECT id FROM users WHERE last = 'Smith'", plan(&legacy, true),
+             format!("SELECT id FROM users WHERE last IN (X'{}', X'{}')", v1, bare)),
+            ("SELECT id FROM users WHERE last = 'Smith'", plan(&keys.at(2).unwrap(), true),
+             format!("SELECT id FROM users WHERE last = X'{}'", v2)),
+            // assignments write the newest version only
+            ("UPDATE users SET last = 'Smith' WHERE id = 1", plan(&keys, false),
+             format!("UPDATE users SET last = X'{}' WHERE id = 1", v2)),
+        ];
+
+        let tenant_keys = TenantKeys::new(&config::KeyProviderConfig{props: HashMap::new()}).unwrap();
+        for (sql, p, expected) in cases {
+            let tokens = String::from(sql).tokenize(&dialect).unwrap();
+            let parsed = tokens.parse().unwrap();
+
+            let mut literal_plans = HashMap::new();
+            literal_plans.insert(0, p);
+            let params = HashMap::new();
+            let index_writer = IndexWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                param_plans: &params
+            };
+            let lit_writer = LiteralEncryptionWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                tenant_keys: &tenant_keys
+            };
+            let mysql = MySQLWriter{};
+            let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+            let writer = SQLWriter::new(vec![&index_writer, &lit_writer, &mysql, &ansi_writer]);
+
+            assert_eq!(format_sql(&expected), format_sql(&writer.write(&parsed).unwrap()));
+        }
+    }
+
+    #[test]
+    fn normalized_columns() {
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let schema = String::from("zero");
+        let tokens = String::from("CREATE TABLE normalized (id INTEGER, c_last VARCHAR(16), c_first VARCHAR(16))").tokenize(&dialect).unwrap();
+        let parsed = tokens.parse().unwrap();
+        let translator = CreateTranslatingWriter {
+            config: &config,
+            schema: &schema
+        };
+        let mysql = MySQLWriter{};
+        let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+        let writer = SQLWriter::new(vec![&translator, &mysql, &ansi_writer]);
+        let expected = "CREATE TABLE normalized (id INTEGER, c_last VARBINARY(49), c_last_display VARBINARY(49), c_first VARBINARY(37))";
+        assert_eq!(format_sql(expected), format_sql(&writer.write(&parsed).unwrap()));
+
+        let n = Normalization{case_fold: true, nfc: true, trim: true, display: Some(String::from("c_last_display"))};
+        let keys = KeyRing::new(0, SecretKey::new([3u8; 32]));
+        let encryption = EncryptionType::Aes([0u8; 12]);
+        let plan = |u: NormalizationUse| EncryptionPlan {
+            data_type: NativeType::Varchar(16),
+            encryption: encryption.clone(),
+            key: Some(keys.clone()),
+            aad: None,
+            aad_row: None,
+            tenant_row: None,
+            blind_index: None,
+            like_index: None,
+            padding: Padding::NA,
+            normalization: Some(u),
+            compare: false
+        };
+        let smith = to_hex_string(&String::from("smith").encrypt(&encryption, &keys, &[]).unwrap());
+
+        // values are compared and stored normalized, and stored values keep their original in the display column
+        let cases = vec![
+            ("SELECT id FROM normalized WHERE c_last = 'SMITH '", 0, NormalizationUse::Compare(n.clone()),
+             format!("WHERE c_last = X'{}'", smith)),
+            ("UPDATE normalized SET c_last = 'Smith' WHERE id = 1", 0, NormalizationUse::Store(n.clone()),
+             format!("SET c_last = X'{}', c_last_display = X'", smith)),
+            ("INSERT INTO normalized (id, c_last) VALUES (1, 'Smith')", 1, NormalizationUse::Store(n.clone()),
+             format!("(id, c_last, c_last_display) VALUES (1, X'{}', X'", smith)),
+        ];
+
+        let tenant_keys = TenantKeys::new(&config::KeyProviderConfig{props: HashMap::new()}).unwrap();
+        for (sql, i, u, expected) in cases {
+            let tokens = String::from(sql).tokenize(&dialect).unwrap();
+            let parsed = tokens.parse().unwrap();
+
+            let mut literal_plans = HashMap::new();
+            literal_plans.insert(i, plan(u));
+            let params = HashMap::new();
+            let index_writer = IndexWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                param_plans: &params
+            };
+            let lit_writer = LiteralEncryptionWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                tenant_keys: &tenant_keys
+            };
+            let mysql = MySQLWriter{};
+            let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+            let writer = SQLWriter::new(vec![&index_writer, &lit_writer, &mysql, &ansi_writer]);
+
+            let rewritten = writer.write(&parsed).unwrap();
+            assert!(format_sql(&rewritten).contains(&format_sql(&expected)), "{} does not contain {}", rewritten, expected);
+            if rewritten.contains("c_last_display") {
+                let hex = rewritten.rsplit("X'").next().unwrap().split('\'').next().unwrap();
+                let display = String::decrypt(&hex_decode(hex).unwrap(), &EncryptionType::AesGcm, &n.display_keys(&keys), &[]).unwrap();
+                assert_eq!("Smith", display);
+            }
+        }
+    }
+
+    #[test]
+    fn tenant_keys() {
+        let path = env::temp_dir().join("zero-writers-tenants.keystore").to_str().unwrap().to_string();
+        let mut keys = HashMap::new();
+        keys.insert(String::from("tenant-7"), SecretKey::new([7u8; 32]));
+        write_keystore(&path, "tenants", 10, &keys).unwrap();
+        env::set_var("ZERO_WRITERS_TENANT_PASSPHRASE", "tenants");
+        let mut props = HashMap::new();
+        props.insert(String::from("type"), String::from("keystore"));
+        props.insert(String::from("path"), path);
+        props.insert(String::from("passphrase-env"), String::from("ZERO_WRITERS_TENANT_PASSPHRASE"));
+        let tenant_keys = TenantKeys::new(&config::KeyProviderConfig{props: props}).unwrap();
+
+        let aad = AadBinding{column: "zero.tickets.body".into(), row_key: None, row_key_type: NativeType::UNKNOWN, row_keys: false,
+                             tenant: Some(TenantKey{column: "tenant_id".into(), key_ref: "tenant-{}".into(), missing: MissingKey::Null})};
+        let mut literal_plans = HashMap::new();
+        literal_plans.insert(1, EncryptionPlan {
+            data_type: NativeType::Varchar(50),
+            encryption: EncryptionType::AesGcm,
+            key: Some(KeyRing::new(0, SecretKey::new([0u8; 32]))),
+            aad: Some(aad.clone()),
+            aad_row: None,
+            tenant_row: Some(0),
+            blind_index: None,
+            like_index: None,
+            padding: Padding::NA,
+            normalization: None,
+            compare: false
+        });
+
+        let ansi = AnsiSQLDialect::new();
+        let dialect = MySQLDialect::new(&ansi);
+        let write = |sql: &str| {
+            let tokens = String::from(sql).tokenize(&dialect).unwrap();
+            let parsed = tokens.parse().unwrap();
+            let lit_writer = LiteralEncryptionWriter {
+                literals: &tokens.literals,
+                literal_plans: &literal_plans,
+                tenant_keys: &tenant_keys
+            };
+            let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+            SQLWriter::new(vec![&lit_writer, &ansi_writer]).write(&parsed)
+        };
+
+        // encrypted under the key of the inserted tenant
+        let sql = write("INSERT INTO tickets (tenant_id, body) VALUES (7, 'hi')").unwrap();
+        let hex = sql.split("X'").nth(1).unwrap().split('\'').next().unwrap();
+        let tenant = aad.tenant_keys(&SecretKey::new([7u8; 32]));
+        assert_eq!("hi", String::decrypt(&hex_decode(hex).unwrap(), &EncryptionType::AesGcm, &tenant, &aad.to_bytes(None)).unwrap());
+
+        match write("INSERT INTO tickets (tenant_id, body) VALUES (8, 'hi')") {
+            Err(e) => assert_eq!("[1064] Column zero.tickets.body has no key for tenant 8", format!("{}", e)),
+            _ => panic!("TEST FAIL")
+        }
+    }
+
     fn format_sql(sql: &str) -> String {
 
         sql.to_uppercase()
@@ -24,7 +24,7 @@ use std::fmt::Write;
 // TODO need some way of unifying keywords between dialects
 static KEYWORDS: &'static [&'static str] = &["SELECT", "FROM", "WHERE", "AND", "OR", "UNION", "FROM", "AS",
     "WHERE", "ORDER", "BY", "HAVING", "GROUP", "ASC", "DESC", "JOIN", "INNER", "LEFT", "RIGHT", "CROSS",
-    "FULL", "ON", "INSERT", "UPDATE", "SET", "VALUES", "INTO", "DELETE"];
+    "FULL", "ON", "INSERT", "UPDATE", "SET", "VALUES", "INTO", "DELETE", "LIKE"];
 
 pub struct AnsiSQLDialect {
     bound_param_index: AtomicU32,
@@ -149,8 +149,8 @@ impl Dialect for AnsiSQLDialect {
 
                     } else if keywords.iter().position(|&r| r.eq_ignore_ascii_case(&text)).is_none() {
                         Ok(Some(Token::Identifier(text)))
-                    } else if "AND".eq_ignore_ascii_case(&text) || "OR".eq_ignore_ascii_case(&text) {
-                        Ok(Some(Token::Operator(text)))
+                    } else if "AND".eq_ignore_ascii_case(&text) || "OR".eq_ignore_ascii_case(&text) || "LIKE".eq_ignore_ascii_case(&text) {
+                        Ok(Some(Token::Operator(text.to_uppercase())))
                     } else {
                         Ok(Some(Token::Keyword(text.to_uppercase())))
                     }
@@ -279,7 +279,7 @@ impl Dialect for AnsiSQLDialect {
                     "<" | "<=" | ">" | ">=" | "<>" | "!=" => 20,
                     "-" | "+" => 33,
                     "*" | "/" => 40,
-                    "=" => 11,
+                    "=" | "LIKE" => 11,
                     "AND" => 9,
                     "OR" => 7,
 
@@ -579,6 +579,7 @@ impl AnsiSQLDialect {
                 "=" => Operator::EQ,
                 "AND" => Operator::AND,
                 "OR" => Operator::OR,
+                "LIKE" => Operator::LIKE,
                 _ => return Err(ZeroError::ParseError {
                      message: format!("Unsupported operator {}", t).into(),
                      code: "1064".into()
@@ -970,7 +971,8 @@ impl<'a> AnsiSQLWriter<'a> {
             Operator::EQ => "=",
             Operator::NEQ => "!=",
             Operator::OR => "OR",
-            Operator::AND  => "AND"
+            Operator::AND  => "AND",
+            Operator::LIKE => "LIKE"
         };
         write!(builder, " {}", op_text).unwrap();
     }
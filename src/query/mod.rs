@@ -351,7 +351,8 @@ pub enum Operator {
     EQ,
     NEQ,
     OR,
-    AND
+    AND,
+    LIKE
 }
 
 impl Operator {
@@ -370,6 +371,7 @@ impl Operator {
             Operator::NEQ => "!=".into(),
             Operator::OR => "OR".into(),
             Operator::AND => "AND".into(),
+            Operator::LIKE => "LIKE".into(),
         }
     }
 }
@@ -15,6 +15,13 @@
 use super::{ASTNode, Operator, JoinType, LiteralToken};
 use encrypt::EncryptionType;
 use encrypt::NativeType;
+use encrypt::AadBinding;
+use encrypt::KeyRing;
+use secret::SecretKey;
+use encrypt::BlindIndex;
+use encrypt::LikeIndex;
+use encrypt::Padding;
+use encrypt::Normalization;
 use error::ZeroError;
 use std::rc::Rc;
 use std::fmt;
@@ -33,7 +40,12 @@ pub struct ColumnMeta {
     pub name: String,
     pub native_type: NativeType,
     pub encryption: EncryptionType,
-    pub key: [u8; 32],
+    pub key: KeyRing,
+    pub aad: Option<AadBinding>,
+    pub blind_index: Option<BlindIndex>,
+    pub like_index: Option<LikeIndex>,
+    pub padding: Padding,
+    pub normalization: Option<Normalization>,
 }
 
 
@@ -58,7 +70,12 @@ impl TupleType {
 pub struct Element {
     pub name: String,
     pub encryption: EncryptionType,
-    pub key: [u8; 32],
+    pub key: KeyRing,
+    pub aad: Option<AadBinding>,
+    pub blind_index: Option<BlindIndex>,
+    pub like_index: Option<LikeIndex>,
+    pub padding: Padding,
+    pub normalization: Option<Normalization>,
     pub data_type: NativeType,
     pub relation: String,
     pub p_name: Option<String>,
@@ -109,6 +126,11 @@ impl Rex {
                     name: name.clone(),
                     encryption: e.encryption,
                     key: e.key,
+                    aad: e.aad,
+                    blind_index: e.blind_index,
+                    like_index: e.like_index,
+                    padding: e.padding,
+                    normalization: e.normalization,
                     data_type: e.data_type,
                     relation: e.relation,
                     p_name: Some(e.name),
@@ -121,7 +143,12 @@ impl Rex {
                 Ok(Element {
                     name : "Literal".into(), // TODO
                     encryption: EncryptionType::NA,
-                    key: [0_u8; 32],
+                    key: KeyRing::new(0, SecretKey::new([0u8; 32])),
+                    aad: None,
+                    blind_index: None,
+                    like_index: None,
+                    padding: Padding::NA,
+                    normalization: None,
                     data_type: NativeType::UNKNOWN, // TODO
                     relation: String::from("SYS"),
                     p_name: None,
@@ -131,7 +158,11 @@ impl Rex {
             },
             &Rex::RexFunctionCall{ref name, ref args} => {
                 match &name as &str {
-                    "MAX" | "SUM" | "MIN" | "COALESCE" => {
+                    // folded by the gateway, the result is encrypted like the column until then
+                    "SUM" | "AVG" if homomorphic_argument(name, args).is_some() => {
+                        Ok(homomorphic_argument(name, args).unwrap().clone())
+                    },
+                    "MAX" | "SUM" | "MIN" | "COALESCE" | "AVG" => {
                         let elements = args.iter().map(|a| {
                             let el = a.get_element()?;
                             if el.encryption != EncryptionType::NA {
@@ -151,7 +182,12 @@ impl Rex {
                     "COUNT" => Ok(Element {
                         name : name.clone(),
                         encryption: EncryptionType::NA,
-                        key: [0_u8; 32],
+                        key: KeyRing::new(0, SecretKey::new([0u8; 32])),
+                        aad: None,
+                        blind_index: None,
+                        like_index: None,
+                        padding: Padding::NA,
+                        normalization: None,
                         data_type: NativeType::U64,
                         relation: String::from("SYS"),
                         p_name: None,
@@ -208,6 +244,16 @@ impl Rex {
     }
 }
 
+/// The column of SUM(col) or AVG(col) over a Paillier column, whose ciphertexts can be
+/// added up without decrypting them
+pub fn homomorphic_argument<'a>(name: &str, args: &'a Vec<Rex>) -> Option<&'a Element> {
+    match (name, args.len(), args.get(0)) {
+        ("SUM", 1, Some(&Rex::Identifier{ref el, ..})) |
+        ("AVG", 1, Some(&Rex::Identifier{ref el, ..})) if el.encryption == EncryptionType::Paillier => Some(el),
+        _ => None
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Rel {
     Projection { project: Box<Rex>, input: Box<Rel> , tt: TupleType},
@@ -283,7 +329,12 @@ impl<'a> Planner<'a> {
                     let element = Element {
                         name : id.clone(),
                         encryption: EncryptionType::NA,
-                        key: [0_u8; 32],
+                        key: KeyRing::new(0, SecretKey::new([0u8; 32])),
+                        aad: None,
+                        blind_index: None,
+                        like_index: None,
+                        padding: Padding::NA,
+                        normalization: None,
                         data_type: NativeType::UNKNOWN,
                         relation: String::from("SYS"),
                         p_name: None,
@@ -554,7 +605,8 @@ impl<'a> Planner<'a> {
                 };
 
                 let tt = TupleType::new(input.tt().elements.iter().map(|e| Element{
-                    name: e.name.clone(), encryption: e.encryption.clone(), key: e.key.clone(),
+                    name: e.name.clone(), encryption: e.encryption.clone(), key: e.key.clone(), aad: e.aad.clone(),
+                    blind_index: e.blind_index.clone(), like_index: e.like_index.clone(), padding: e.padding.clone(), normalization: e.normalization.clone(),
                     data_type: e.data_type.clone(), relation: a.clone(),
                     p_name: e.p_name.clone(), p_relation: Some(e.relation.clone())
                 }).collect());
@@ -580,7 +632,8 @@ impl<'a> Planner<'a> {
                                 let tt = TupleType::new(
                                     meta.columns.iter()
                                         .map(|c| Element {
-                                            name: c.name.clone(), encryption: c.encryption.clone(), key: c.key.clone(),
+                                            name: c.name.clone(), encryption: c.encryption.clone(), key: c.key.clone(), aad: c.aad.clone(),
+                                            blind_index: c.blind_index.clone(), like_index: c.like_index.clone(), padding: c.padding.clone(), normalization: c.normalization.clone(),
                                             data_type: c.native_type.clone(), relation: table_name.clone(),
                                             p_name: None, p_relation: None
                                         })
@@ -674,7 +727,8 @@ mod tests {
     use query::{Tokenizer, Parser, ASTNode};
     use query::dialects::ansisql::*;
     use query::dialects::mysqlsql::*;
-    use encrypt::{NativeType, EncryptionType};
+    use encrypt::{NativeType, EncryptionType, KeyRing, Padding};
+    use secret::SecretKey;
     use std::rc::Rc;
     use super::{Planner, SchemaProvider, TableMeta, ColumnMeta, Rel};
     use error::ZeroError;
@@ -916,22 +970,22 @@ mod tests {
                         columns: vec![
                             ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
                                         encryption: EncryptionType::NA,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("first_name"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("last_name"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("ssn"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("age"), native_type: NativeType::U64,
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("sex"), native_type: NativeType::Varchar(50),
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                         ]
                     }))
                 },
@@ -940,16 +994,16 @@ mod tests {
                         columns: vec![
                             ColumnMeta {name: String::from("id"), native_type: NativeType::U64,
                                         encryption: EncryptionType::NA,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("user_id"), native_type: NativeType::U64,
                                         encryption: EncryptionType::NA,
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("item_code"), native_type: NativeType::U64,
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                             ColumnMeta {name: String::from("amount"), native_type: NativeType::F64,
                                         encryption: EncryptionType::Aes([0u8;12]),
-                                        key: [0u8; 32]},
+                                        key: KeyRing::new(0, SecretKey::new([0u8; 32])), aad: None, blind_index: None, like_index: None, padding: Padding::NA, normalization: None},
                         ]
                     }))
                 },
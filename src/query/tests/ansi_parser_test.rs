@@ -534,6 +534,43 @@ fn select_comparisons() {
     println!("Rewritten: {:?}", rewritten);
 
 }
+#[test]
+fn select_like() {
+    let dialect = AnsiSQLDialect::new();
+    let sql = String::from("SELECT * FROM foo WHERE a LIKE 'SMI%' AND b like ?");
+    let tokens = sql.tokenize(&dialect).unwrap();
+    let parsed = tokens.parse().unwrap();
+
+    assert_eq!(
+        SQLSelect {
+            expr_list: Box::new(SQLExprList(vec![id("*")])),
+            relation: Some(id_boxed("foo")),
+            selection: Some(Box::new(SQLBinary {
+                left: Box::new(SQLBinary {
+                    left: id_boxed("a"),
+                    op: LIKE,
+                    right: Box::new(SQLLiteral(0))
+                }),
+                op: AND,
+                right: Box::new(SQLBinary {
+                    left: id_boxed("b"),
+                    op: LIKE,
+                    right: Box::new(SQLBoundParam(0))
+                })
+            })),
+            order: None,
+            limit: None,
+            for_update: false
+        },
+        parsed
+    );
+
+    let ansi_writer = AnsiSQLWriter{literal_tokens: &tokens.literals};
+    let writer = SQLWriter::new(vec![&ansi_writer]);
+    let rewritten = writer.write(&parsed).unwrap();
+    assert_eq!(format_sql(&rewritten), format_sql(&sql));
+}
+
 #[test]
 fn insert() {
 
@@ -0,0 +1,375 @@
+// Copyright 2016 AgilData
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http:// www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Re-encrypts the values of a table under the newest version of each column key.
+//!
+//! Rows are walked in primary key order in batches, each batch is committed in its own
+//! transaction and followed by a checkpoint, so an interrupted run resumes where it stopped.
+//! Values go through the same `Encrypt`/`Decrypt` impls as the gateway, and updates only
+//! apply while the stored ciphertext is unchanged, so writes made through the gateway
+//! during a rotation are never overwritten. Blind indexes and LIKE tokens of rewritten
+//! values are recomputed under the newest key alongside them.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use mysql;
+use mysql::Value;
+
+use config::{Config, ColumnConfig, TConfig, TTableConfig};
+use encrypt::*;
+use error::ZeroError;
+use proxy::schema_provider::build_aad;
+
+pub struct RotateOpts {
+    pub batch_size: u32,
+    pub throttle: Duration,
+    pub checkpoint: String
+}
+
+/// A value rewritten under the newest key, and its recomputed companions
+#[derive(Debug, PartialEq)]
+pub struct Rotated {
+    pub value: Vec<u8>,
+    pub index: Option<Vec<u8>>,
+    pub like_tokens: Option<String>
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RotateStats {
+    pub rows: u64,
+    pub rewritten: u64,
+    pub conflicts: u64
+}
+
+/// What to read and rewrite for a table
+#[derive(Debug)]
+pub struct RotatePlan<'a> {
+    schema: String,
+    table: String,
+    pk: &'a ColumnConfig,
+    columns: Vec<(&'a ColumnConfig, Option<AadBinding>)>
+}
+
+pub fn plan<'a>(config: &'a Config, schema: &str, table: &str) -> Result<RotatePlan<'a>, Box<ZeroError>> {
+    let (schema, table) = (String::from(schema), String::from(table));
+    let table_config = match config.get_table_config(&schema, &table) {
+        Some(t) => t,
+        None => return Err(rotate_error(format!("Table {}.{} is not configured", schema, table)))
+    };
+    if table_config.tenant.is_some() {
+        return Err(rotate_error(format!("Table {}.{} is encrypted per tenant, rotate each tenant's key in the key provider", schema, table)))
+    }
+
+    let pks = table_config.column_map.values().filter(|c| c.pk_ordinal.is_some()).count();
+    let pk = match table_config.get_primary_key() {
+        Some(pk) if pks == 1 && pk.encryption == EncryptionType::NA => pk,
+        _ => return Err(rotate_error(format!("Table {}.{} requires a single unencrypted pkOrdinal column to be rotated", schema, table)))
+    };
+
+    let mut columns = table_config.column_map.values()
+        .filter(|c| c.encryption != EncryptionType::NA)
+        .map(|c| (c, build_aad(config, &schema, &table, c)))
+        .collect::<Vec<(&ColumnConfig, Option<AadBinding>)>>();
+    if columns.is_empty() {
+        return Err(rotate_error(format!("Table {}.{} has no encrypted columns", schema, table)))
+    }
+    columns.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+
+    Ok(RotatePlan{schema: schema, table: table, pk: pk, columns: columns})
+}
+
+impl<'a> RotatePlan<'a> {
+
+    fn select_sql(&self, resume: bool, batch_size: u32) -> String {
+        let columns = self.columns.iter().map(|&(c, _)| c.name.clone()).collect::<Vec<String>>().join(", ");
+        format!("SELECT {}, {} FROM {}.{}{} ORDER BY {} LIMIT {}",
+            self.pk.name, columns, self.schema, self.table,
+            if resume { format!(" WHERE {} > ?", self.pk.name) } else { String::new() },
+            self.pk.name, batch_size)
+    }
+
+    // updates the given columns only while they still hold the values that were read
+    fn update_sql(&self, changed: &[usize]) -> String {
+        let names = changed.iter().map(|&i| self.columns[i].0.name.clone()).collect::<Vec<String>>();
+        let mut set = Vec::new();
+        for &i in changed.iter() {
+            let column = self.columns[i].0;
+            set.push(format!("{} = ?", column.name));
+            if let Some(ref bi) = column.blind_index {
+                set.push(format!("{} = ?", bi.column));
+            }
+            if let Some(ref li) = column.like_index {
+                set.push(format!("{} = ?", li.column));
+            }
+        }
+        format!("UPDATE {}.{} SET {} WHERE {} = ? AND {}",
+            self.schema, self.table,
+            set.join(", "),
+            self.pk.name,
+            names.iter().map(|n| format!("{} = ?", n)).collect::<Vec<String>>().join(" AND "))
+    }
+
+    fn name(&self) -> String {
+        format!("{}.{}", self.schema, self.table)
+    }
+}
+
+/// Rotates a table, resuming from the checkpoint file if one exists
+pub fn rotate_table(pool: &mysql::Pool, plan: &RotatePlan, opts: &RotateOpts) -> Result<RotateStats, Box<ZeroError>> {
+    let mut stats = RotateStats{rows: 0, rewritten: 0, conflicts: 0};
+    let mut last_pk = match read_checkpoint(&opts.checkpoint, &plan.name())? {
+        Some((pk, rows)) => {
+            info!("Resuming rotation of {} after {} {} ({} rows done)", plan.name(), plan.pk.name, pk, rows);
+            stats.rows = rows;
+            Some(Value::from(pk))
+        },
+        None => None
+    };
+
+    let total = match pool.first_exec(format!("SELECT COUNT(*) FROM {}.{}", plan.schema, plan.table), ()) {
+        Ok(Some(row)) => mysql::from_row::<u64>(row),
+        Ok(None) => 0,
+        Err(e) => return Err(mysql_error(e))
+    };
+
+    loop {
+        let rows = {
+            let sql = plan.select_sql(last_pk.is_some(), opts.batch_size);
+            let params = match last_pk {
+                Some(ref pk) => vec![pk.clone()],
+                None => vec![]
+            };
+            let result = pool.prep_exec(sql, params).map_err(mysql_error)?;
+            let mut rows = Vec::new();
+            for row in result {
+                rows.push(row.map_err(mysql_error)?.unwrap());
+            }
+            rows
+        };
+        if rows.is_empty() {
+            break;
+        }
+
+        let mut tx = pool.start_transaction(false, None, None).map_err(mysql_error)?;
+        for row in rows.iter() {
+            let pk_text = value_text(&row[0])?;
+            let mut changed = Vec::new();
+            let mut new_values = Vec::new();
+            for (i, &(column, ref aad)) in plan.columns.iter().enumerate() {
+                let current = match row[i + 1] {
+                    Value::Bytes(ref b) => b,
+                    Value::NULL => continue,
+                    ref v => return Err(rotate_error(format!("Unexpected value {:?} in encrypted column {}", v, column.name)))
+                };
+                let (aad, keys) = match *aad {
+                    Some(ref a) => {
+                        let row = if a.row_key.is_some() { Some(&pk_text as &str) } else { None };
+                        (a.to_bytes(row), a.keys(&column.key, row))
+                    },
+                    None => (vec![], column.key.clone())
+                };
+                let rotated = reencrypt(column, &keys, &aad, current).map_err(|e| {
+                    rotate_error(format!("Failed to rotate {}.{} where {} = {}: {}", plan.name(), column.name, plan.pk.name, pk_text, e))
+                })?;
+                if let Some(r) = rotated {
+                    changed.push(i);
+                    new_values.push(Value::Bytes(r.value));
+                    if let Some(index) = r.index {
+                        new_values.push(Value::Bytes(index));
+                    }
+                    if let Some(tokens) = r.like_tokens {
+                        new_values.push(Value::Bytes(tokens.into_bytes()));
+                    }
+                }
+            }
+
+            if !changed.is_empty() {
+                let mut params = new_values;
+                params.push(row[0].clone());
+                for &i in changed.iter() {
+                    params.push(row[i + 1].clone());
+                }
+                let affected = tx.prep_exec(plan.update_sql(&changed), params).map_err(mysql_error)?.affected_rows();
+                if affected == 0 {
+                    // rewritten through the gateway since it was read, so already under the newest key
+                    stats.conflicts += 1;
+                } else {
+                    stats.rewritten += changed.len() as u64;
+                }
+            }
+            stats.rows += 1;
+        }
+        tx.commit().map_err(mysql_error)?;
+
+        let pk = rows.last().unwrap()[0].clone();
+        write_checkpoint(&opts.checkpoint, &plan.name(), &value_text(&pk)?, stats.rows)?;
+        last_pk = Some(pk);
+
+        info!("{}: {}/{} rows, {} values rewritten, {} changed concurrently", plan.name(), stats.rows, total, stats.rewritten, stats.conflicts);
+        if (rows.len() as u32) < opts.batch_size {
+            break;
+        }
+        thread::sleep(opts.throttle);
+    }
+
+    // completed, a later run starts from the beginning
+    let _ = fs::remove_file(&opts.checkpoint);
+    Ok(stats)
+}
+
+/// The value rewritten under the newest key, or None when it already uses it.
+/// keys are the column's, or those derived for the value's row, indexes always use the column's.
+pub fn reencrypt(column: &ColumnConfig, keys: &KeyRing, aad: &[u8], v: &[u8]) -> Result<Option<Rotated>, Box<ZeroError>> {
+    let scheme = &column.encryption;
+    if read_header(scheme, v) == Some(keys.current().0) {
+        return Ok(None)
+    }
+    // format preserving columns have a single key version
+    if let &EncryptionType::Fpe(_) = scheme {
+        return Ok(None)
+    }
+
+    let plaintext = match column.native_type {
+        NativeType::UNKNOWN => return Err(rotate_error(format!("Native type {:?} cannot be rotated", column.native_type))),
+        ref t => Plaintext::decrypt(t, v, scheme, keys, aad)?
+    };
+    let index = match column.blind_index {
+        Some(ref bi) => Some(plaintext.blind_index(&column.key, bi.length)),
+        None => None
+    };
+    let like_tokens = match (&column.like_index, &plaintext) {
+        (&Some(ref li), &Plaintext::Text(ref v)) => Some(li.tokens(&column.key, v)),
+        _ => None
+    };
+    Ok(Some(Rotated{value: plaintext.encrypt_padded(scheme, keys, aad, &column.padding)?, index: index, like_tokens: like_tokens}))
+}
+
+// the text the gateway sees for a primary key, as used in row bound aad
+fn value_text(v: &Value) -> Result<String, Box<ZeroError>> {
+    match *v {
+        Value::Int(i) => Ok(format!("{}", i)),
+        Value::UInt(u) => Ok(format!("{}", u)),
+        Value::Bytes(ref b) => match String::from_utf8(b.clone()) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(rotate_error("Primary key is not valid UTF-8".into()))
+        },
+        ref v => Err(rotate_error(format!("Unsupported primary key value {:?}", v)))
+    }
+}
+
+// checkpoint file: table, hex encoded last primary key and rows done
+fn read_checkpoint(path: &str, table: &str) -> Result<Option<(String, u64)>, Box<ZeroError>> {
+    let mut contents = String::new();
+    if File::open(path).and_then(|mut f| f.read_to_string(&mut contents)).is_err() {
+        return Ok(None)
+    }
+
+    let parts = contents.split_whitespace().collect::<Vec<&str>>();
+    match (parts.get(0), parts.get(1), parts.get(2).and_then(|r| r.parse::<u64>().ok())) {
+        (Some(t), Some(pk), Some(rows)) if *t == table => match String::from_utf8(hex_decode(pk)?) {
+            Ok(pk) => Ok(Some((pk, rows))),
+            Err(_) => Err(rotate_error(format!("Corrupt checkpoint {}", path)))
+        },
+        (Some(t), _, _) if *t != table => Err(rotate_error(format!("Checkpoint {} belongs to {}, not {}", path, t, table))),
+        _ => Err(rotate_error(format!("Corrupt checkpoint {}", path)))
+    }
+}
+
+fn write_checkpoint(path: &str, table: &str, pk: &str, rows: u64) -> Result<(), Box<ZeroError>> {
+    // written aside and renamed so a crash never leaves a partial checkpoint
+    let tmp = format!("{}.tmp", path);
+    match File::create(&tmp)
+        .and_then(|mut f| write!(f, "{} {} {}\n", table, hex_encode(pk.as_bytes()), rows).and_then(|_| f.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(rotate_error(format!("Unable to write checkpoint {}: {}", path, e)))
+    }
+}
+
+fn mysql_error(e: mysql::Error) -> Box<ZeroError> {
+    ZeroError::SchemaError {
+        message: format!("{}", e),
+        code: "1064".into()
+    }.into()
+}
+
+fn rotate_error(message: String) -> Box<ZeroError> {
+    ZeroError::EncryptionError {
+        message: message,
+        code: "1064".into()
+    }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{read_checkpoint, write_checkpoint};
+    use config;
+    use config::TConfig;
+    use std::env;
+
+    #[test]
+    fn test_rotate_plan() {
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let plan = plan(&config, "zero", "rotated").unwrap();
+
+        assert_eq!("SELECT id, a, b FROM zero.rotated ORDER BY id LIMIT 100", plan.select_sql(false, 100));
+        assert_eq!("SELECT id, a, b FROM zero.rotated WHERE id > ? ORDER BY id LIMIT 100", plan.select_sql(true, 100));
+        assert_eq!("UPDATE zero.rotated SET a = ?, b = ? WHERE id = ? AND a = ? AND b = ?", plan.update_sql(&[0, 1]));
+        assert_eq!("UPDATE zero.rotated SET b = ? WHERE id = ? AND b = ?", plan.update_sql(&[1]));
+
+        // blind indexes are rewritten with their column
+        let plan = super::plan(&config, "zero", "indexed").unwrap();
+        assert_eq!("UPDATE zero.indexed SET age = ?, age_bidx = ?, ssn = ?, ssn_bidx = ? WHERE id = ? AND age = ? AND ssn = ?", plan.update_sql(&[0, 1]));
+        let plan = super::plan(&config, "zero", "searchable").unwrap();
+        assert_eq!("UPDATE zero.searchable SET email = ?, email_bidx = ?, email_like = ? WHERE id = ? AND email = ?", plan.update_sql(&[0]));
+
+        // no primary key
+        assert!(super::plan(&config, "zero", "characters_siv").is_err());
+        assert!(super::plan(&config, "zero", "missing").is_err());
+    }
+
+    #[test]
+    fn test_reencrypt() {
+        let config = config::parse_config("src/test/test-zero-config.xml").unwrap();
+        let column = config.get_column_config(&"zero".into(), &"rotated".into(), &"a".into()).unwrap();
+        let aad = b"zero.rotated.a";
+
+        let v1 = KeyRing::new(1, column.key.get(1).unwrap().clone());
+        let old = String::from("alice@example.com").encrypt(&column.encryption, &v1, aad).unwrap();
+
+        let rotated = reencrypt(column, &column.key, aad, &old).unwrap().unwrap().value;
+        assert_eq!(Some(2), read_header(&column.encryption, &rotated));
+        assert_eq!("alice@example.com", String::decrypt(&rotated, &column.encryption, &column.key, aad).unwrap());
+
+        // already current
+        assert_eq!(None, reencrypt(column, &column.key, aad, &rotated).unwrap());
+
+        // wrong aad is an error rather than a silent skip
+        assert!(reencrypt(column, &column.key, b"zero.rotated.b", &old).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let path = env::temp_dir().join("zero-rotate-test.checkpoint").to_str().unwrap().to_string();
+        write_checkpoint(&path, "zero.rotated", "user 42", 1200).unwrap();
+
+        assert_eq!(Some((String::from("user 42"), 1200)), read_checkpoint(&path, "zero.rotated").unwrap());
+        assert!(read_checkpoint(&path, "zero.users").is_err());
+        assert_eq!(None, read_checkpoint(&format!("{}.missing", path), "zero.rotated").unwrap());
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2016 AgilData
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http:// www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key material kept out of swap, wiped once it is no longer referenced and never printed.
+//!
+//! Keys live in slots of chunks locked into memory with mlock. Locking each key separately
+//! would split the heap mapping at every key and unlocking one would unlock any other key on
+//! its page, so chunks stay locked for the life of the process and their slots are reused.
+
+use std::fmt;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{compiler_fence, Ordering};
+use backend::fixed_time_eq;
+
+// 4KB of keys per chunk
+const CHUNK_SLOTS: usize = 128;
+
+/// A 256 bit key. Clones share the one copy of the key, which is zeroed when the last clone
+/// is dropped.
+#[derive(Clone)]
+pub struct SecretKey(Arc<Slot>);
+
+struct Slot(*mut [u8; 32]);
+
+// a slot is only written before it is shared and when its last owner drops it
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+impl SecretKey {
+    /// Moves key into locked memory, wiping the copy passed in
+    pub fn new(mut key: [u8; 32]) -> SecretKey {
+        let slot = lock_arena().take();
+        unsafe { ptr::write(slot, key); }
+        wipe(&mut key);
+        SecretKey(Arc::new(Slot(slot)))
+    }
+
+    /// A key of exactly 32 bytes
+    pub fn from_slice(bytes: &[u8]) -> Option<SecretKey> {
+        if bytes.len() != 32 {
+            return None
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(bytes);
+        Some(SecretKey::new(key))
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { &*(self.0).0 }
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretKey(REDACTED)")
+    }
+}
+
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &SecretKey) -> bool {
+        fixed_time_eq(&**self, &**other)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        unsafe { wipe(&mut *self.0); }
+        lock_arena().free.push(self.0);
+    }
+}
+
+/// Overwrites buf with zeros in a way the compiler will not optimize away
+pub fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0); }
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+struct Arena {
+    chunks: Vec<Box<[[u8; 32]]>>,
+    free: Vec<*mut [u8; 32]>,
+    // whether the OS refused to lock a chunk, which is only logged once
+    unlocked: bool
+}
+
+unsafe impl Send for Arena {}
+
+static ARENA: Mutex<Arena> = Mutex::new(Arena{chunks: Vec::new(), free: Vec::new(), unlocked: false});
+
+// a panic while the arena is held cannot leave it inconsistent, so ignore poisoning
+fn lock_arena() -> ::std::sync::MutexGuard<'static, Arena> {
+    match ARENA.lock() {
+        Ok(arena) => arena,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+impl Arena {
+    fn take(&mut self) -> *mut [u8; 32] {
+        if self.free.is_empty() {
+            let mut chunk = vec![[0u8; 32]; CHUNK_SLOTS].into_boxed_slice();
+            if !mlock(chunk.as_ptr() as *const u8, CHUNK_SLOTS * 32) && !self.unlocked {
+                warn!("Unable to lock key memory, keys may be swapped to disk. Raise RLIMIT_MEMLOCK to allow it");
+                self.unlocked = true;
+            }
+            for slot in chunk.iter_mut() {
+                self.free.push(slot as *mut [u8; 32]);
+            }
+            self.chunks.push(chunk);
+        }
+        self.free.pop().unwrap()
+    }
+}
+
+#[cfg(unix)]
+fn mlock(addr: *const u8, len: usize) -> bool {
+    extern "C" {
+        fn mlock(addr: *const u8, len: usize) -> i32;
+    }
+    unsafe { mlock(addr, len) == 0 }
+}
+
+#[cfg(not(unix))]
+fn mlock(_addr: *const u8, _len: usize) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_secret_key() {
+        let key = SecretKey::new([7u8; 32]);
+        let copy = key.clone();
+        assert_eq!(&[7u8; 32][..], &*copy);
+        assert_eq!(key, SecretKey::new([7u8; 32]));
+        assert!(key != SecretKey::new([8u8; 32]));
+        assert_eq!("SecretKey(REDACTED)", format!("{:?}", key));
+        assert_eq!(None, SecretKey::from_slice(&[1u8; 16]));
+
+        // the slot is wiped once the last clone is gone
+        let slot = (key.0).0;
+        drop(key);
+        assert_eq!([7u8; 32], unsafe { *slot });
+        drop(copy);
+        // unless another test has already reused it
+        let arena = lock_arena();
+        if arena.free.contains(&slot) {
+            assert_eq!([0u8; 32], unsafe { *slot });
+        }
+    }
+}